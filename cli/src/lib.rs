@@ -0,0 +1,579 @@
+//! Reusable command line for rapido apps.  An app implements `AppCli` with
+//! structopt enums of its tx and query commands and gets the common commands:
+//! ```text
+//! cli keys add <name> [--recover]    create (or recover from a mnemonic) a key
+//! cli keys list
+//! cli keys show <name>
+//! cli tx sign --from <name> <app tx command>        print the signed tx as hex
+//! cli tx send --from <name> <app tx command>        sign and broadcast
+//! cli tx broadcast <hex> [--mode async|sync|commit]
+//! cli tx export --sender <account id> --out <file> <app tx command>
+//! cli tx sign-file --from <name> <file>          sign an exported tx, offline
+//! cli tx broadcast-file <file> [--mode async|sync|commit]
+//! cli query <app query command>
+//! cli status
+//! ```
+//! Global options: `--node` (default 'tcp://127.0.0.1:26657'), `--keystore`
+//! (default 'keys.json') and `--chain-id`.  The keystore password is read from
+//! `RAPIDO_KEYSTORE_PASSWORD` or prompted for.
+//!
+//! To sign on an offline machine, `tx export` writes the unsigned tx to a JSON
+//! file (see `SignedTransaction::to_json`), `tx sign-file` signs it in place on
+//! the machine with the keystore and `tx broadcast-file` sends it.
+//! ```ignore
+//! #[tokio::main]
+//! async fn main() {
+//!     if let Err(err) = rapido_cli::run::<CounterCli>().await {
+//!         eprintln!("{:?}", err);
+//!     }
+//! }
+//! ```
+use std::io::BufRead;
+
+use anyhow::bail;
+use borsh::BorshSerialize;
+use exonum_crypto::PublicKey;
+use structopt::clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use structopt::StructOpt;
+use tendermint_rpc::HttpClient;
+
+use rapido_account::Account;
+use rapido_client::{
+    broadcast, chain_info, keys, send_transaction_commit, AutoNonce, BroadcastMode, Keystore,
+};
+use rapido_core::{AccountId, SignedTransaction, TxBuilder};
+
+/// Environment variable with the keystore password
+pub const PASSWORD_ENV: &str = "RAPIDO_KEYSTORE_PASSWORD";
+const DEFAULT_NODE: &str = "tcp://127.0.0.1:26657";
+const DEFAULT_KEYSTORE: &str = "keys.json";
+
+/// The app specific part of the command line
+pub trait AppCli {
+    /// The app's tx commands
+    type TxCommand: StructOpt;
+    /// The app's query commands
+    type QueryCommand: StructOpt;
+    /// The app's messages
+    type Msg: BorshSerialize;
+
+    /// Name of the AppModule the txs are sent to
+    fn app_name() -> &'static str;
+
+    /// Convert a tx command to the message to send
+    fn msg(cmd: Self::TxCommand) -> Result<Self::Msg, anyhow::Error>;
+
+    /// Return the query path (under the app) and key of a query command
+    fn query(cmd: &Self::QueryCommand) -> Result<(String, Vec<u8>), anyhow::Error>;
+
+    /// Format the value returned by the query for display
+    fn format_query(cmd: &Self::QueryCommand, value: &[u8]) -> Result<String, anyhow::Error>;
+
+    /// The sender of the txs signed by a key.  Defaults to the account id of
+    /// the public key. See `rapido_account::Account`
+    fn sender(_name: &str, public_key: &PublicKey) -> AccountId {
+        Account::create(public_key, false).id()
+    }
+}
+
+fn mode_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("mode")
+        .long("mode")
+        .takes_value(true)
+        .possible_values(&["async", "sync", "commit"])
+        .default_value("commit")
+}
+
+fn from_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("from")
+        .long("from")
+        .takes_value(true)
+        .required(true)
+        .help("Name of the key to sign with")
+}
+
+// The app's tx command with the signing options
+fn tx_command<'a, 'b, A: AppCli>(name: &str) -> App<'a, 'b> {
+    nonce_command::<A>(name).arg(from_arg())
+}
+
+// The app's tx command with the export options
+fn export_command<'a, 'b, A: AppCli>() -> App<'a, 'b> {
+    nonce_command::<A>("export")
+        .arg(
+            Arg::with_name("sender")
+                .long("sender")
+                .takes_value(true)
+                .required(true)
+                .help("Account id of the sender, hex"),
+        )
+        .arg(
+            Arg::with_name("out")
+                .long("out")
+                .takes_value(true)
+                .required(true)
+                .help("File to write the unsigned tx to"),
+        )
+}
+
+fn nonce_command<'a, 'b, A: AppCli>(name: &str) -> App<'a, 'b> {
+    A::TxCommand::clap().name(name).arg(
+        Arg::with_name("nonce")
+            .long("nonce")
+            .takes_value(true)
+            .help("Defaults to the sender's next nonce, from the node"),
+    )
+}
+
+fn command<'a, 'b, A: AppCli>() -> App<'a, 'b> {
+    App::new(A::app_name())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("node")
+                .long("node")
+                .takes_value(true)
+                .global(true)
+                .default_value(DEFAULT_NODE),
+        )
+        .arg(
+            Arg::with_name("keystore")
+                .long("keystore")
+                .takes_value(true)
+                .global(true)
+                .default_value(DEFAULT_KEYSTORE),
+        )
+        .arg(
+            Arg::with_name("chain-id")
+                .long("chain-id")
+                .takes_value(true)
+                .global(true)
+                .help("The chain id of the txs. Required to build a tx"),
+        )
+        .subcommand(
+            SubCommand::with_name("keys")
+                .about("Manage the keys of the keystore")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .arg(Arg::with_name("name").required(true))
+                        .arg(
+                            Arg::with_name("recover")
+                                .long("recover")
+                                .help("Read the mnemonic from stdin"),
+                        ),
+                )
+                .subcommand(SubCommand::with_name("list"))
+                .subcommand(
+                    SubCommand::with_name("show").arg(Arg::with_name("name").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tx")
+                .about("Sign and send txs")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(tx_command::<A>("sign"))
+                .subcommand(tx_command::<A>("send"))
+                .subcommand(
+                    SubCommand::with_name("broadcast")
+                        .arg(
+                            Arg::with_name("tx")
+                                .required(true)
+                                .help("The signed tx as hex"),
+                        )
+                        .arg(mode_arg()),
+                )
+                .subcommand(export_command::<A>())
+                .subcommand(
+                    SubCommand::with_name("sign-file").arg(from_arg()).arg(
+                        Arg::with_name("file")
+                            .required(true)
+                            .help("The exported tx"),
+                    ),
+                )
+                .subcommand(
+                    SubCommand::with_name("broadcast-file")
+                        .arg(Arg::with_name("file").required(true).help("The signed tx"))
+                        .arg(mode_arg()),
+                ),
+        )
+        .subcommand(A::QueryCommand::clap().name("query"))
+        .subcommand(SubCommand::with_name("status").about("Show the node's status"))
+}
+
+/// Parse the command line and run the command
+pub async fn run<A: AppCli>() -> Result<(), anyhow::Error> {
+    run_with::<A>(&command::<A>().get_matches()).await
+}
+
+async fn run_with<A: AppCli>(matches: &ArgMatches<'_>) -> Result<(), anyhow::Error> {
+    let node = matches.value_of("node").unwrap_or(DEFAULT_NODE);
+    match matches.subcommand() {
+        ("keys", Some(m)) => run_keys(keystore_path(matches), m),
+        ("tx", Some(m)) => match m.subcommand() {
+            ("sign", Some(m)) => {
+                let tx = sign_tx::<A>(matches, m).await?;
+                println!("{}", tx.to_hex());
+                Ok(())
+            }
+            ("send", Some(m)) => {
+                let tx = sign_tx::<A>(matches, m).await?;
+                println!("{}", send_transaction_commit(&tx, &client(node)?).await?);
+                Ok(())
+            }
+            ("broadcast", Some(m)) => {
+                let tx = SignedTransaction::from_hex(m.value_of("tx").unwrap_or_default())?;
+                broadcast_tx(&tx, m, node).await
+            }
+            ("export", Some(m)) => {
+                let sender = match hex::decode(m.value_of("sender").unwrap_or_default()) {
+                    Ok(sender) => sender,
+                    Err(_) => bail!("expected the sender's account id as hex"),
+                };
+                let tx = build_tx::<A>(matches, m, sender).await?.build()?;
+                let out = m.value_of("out").unwrap_or_default();
+                write_tx_file(out, &tx)?;
+                println!("unsigned tx written to {}", out);
+                Ok(())
+            }
+            ("sign-file", Some(m)) => {
+                let file = m.value_of("file").unwrap_or_default();
+                let from = m.value_of("from").unwrap_or_default();
+                let mut tx = read_tx_file(file)?;
+                let keystore = open_keystore(keystore_path(matches))?;
+                match keystore.public_key(from) {
+                    Some(pk) if A::sender(from, &pk) == tx.sender() => {}
+                    Some(_) => bail!("the tx isn't from {}", from),
+                    None => bail!("no key named {}", from),
+                }
+                keystore.sign_with(from, &mut tx)?;
+                write_tx_file(file, &tx)?;
+                println!("signed tx {}", tx.id_hex());
+                Ok(())
+            }
+            ("broadcast-file", Some(m)) => {
+                let tx = read_tx_file(m.value_of("file").unwrap_or_default())?;
+                broadcast_tx(&tx, m, node).await
+            }
+            _ => bail!("unknown tx command"),
+        },
+        ("query", Some(m)) => {
+            let cmd = A::QueryCommand::from_clap(m);
+            let (path, key) = A::query(&cmd)?;
+            let app_path = format!("{}/{}", A::app_name(), path.trim_start_matches('/'));
+            let value =
+                rapido_client::query(app_path.trim_end_matches('/'), key, &client(node)?).await?;
+            println!("{}", A::format_query(&cmd, &value)?);
+            Ok(())
+        }
+        ("status", Some(_)) => {
+            let info = chain_info(&client(node)?).await?;
+            println!("chain id:    {}", info.chain_id);
+            println!("node:        {} ({})", info.moniker, info.node_version);
+            println!("height:      {}", info.height);
+            println!("app hash:    {}", hex::encode(&info.app_hash));
+            println!("block time:  {}", info.latest_block_time);
+            println!("catching up: {}", info.catching_up);
+            Ok(())
+        }
+        _ => bail!("unknown command"),
+    }
+}
+
+async fn broadcast_tx(
+    tx: &SignedTransaction,
+    matches: &ArgMatches<'_>,
+    node: &str,
+) -> Result<(), anyhow::Error> {
+    let mode = match matches.value_of("mode") {
+        Some("async") => BroadcastMode::Async,
+        Some("sync") => BroadcastMode::Sync,
+        _ => BroadcastMode::Commit,
+    };
+    let result = broadcast(tx, mode, &client(node)?).await?;
+    println!("tx hash: {}", result.hash);
+    println!("code:    {}", result.code);
+    println!("log:     {}", result.log);
+    if let Some(height) = result.height {
+        println!("height:  {}", height);
+    }
+    Ok(())
+}
+
+/// Write a tx to a JSON file. See `SignedTransaction::to_json`
+pub fn write_tx_file(path: &str, tx: &SignedTransaction) -> Result<(), anyhow::Error> {
+    std::fs::write(path, serde_json::to_string_pretty(&tx.to_json())?)?;
+    Ok(())
+}
+
+/// Read a tx from a JSON file written by `write_tx_file`
+pub fn read_tx_file(path: &str) -> Result<SignedTransaction, anyhow::Error> {
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(path)?)?;
+    SignedTransaction::from_json(&json)
+}
+
+fn client(node: &str) -> Result<HttpClient, anyhow::Error> {
+    Ok(HttpClient::new(node.parse()?)?)
+}
+
+fn keystore_path<'a>(matches: &'a ArgMatches) -> &'a str {
+    matches.value_of("keystore").unwrap_or(DEFAULT_KEYSTORE)
+}
+
+fn password() -> Result<String, anyhow::Error> {
+    if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        return Ok(password);
+    }
+    Ok(rpassword::read_password_from_tty(Some(
+        "Keystore password: ",
+    ))?)
+}
+
+// Open the keystore, creating it if it doesn't exist
+fn open_keystore(path: &str) -> Result<Keystore, anyhow::Error> {
+    let password = password()?;
+    if std::path::Path::new(path).exists() {
+        Keystore::open(path, &password)
+    } else {
+        Keystore::create(path, &password)
+    }
+}
+
+fn run_keys(path: &str, matches: &ArgMatches) -> Result<(), anyhow::Error> {
+    match matches.subcommand() {
+        ("add", Some(m)) => {
+            let name = m.value_of("name").unwrap_or_default();
+            let phrase = if m.is_present("recover") {
+                eprintln!("Enter the mnemonic:");
+                let mut line = String::new();
+                std::io::stdin().lock().read_line(&mut line)?;
+                line.trim().to_string()
+            } else {
+                keys::generate_mnemonic()
+            };
+            let key = keys::from_mnemonic(&phrase, keys::DEFAULT_PATH)?;
+            let mut keystore = open_keystore(path)?;
+            keystore.import(name, key.secret_key)?;
+            println!("public key: {}", hex::encode(key.public_key.as_ref()));
+            println!("account id: {}", String::from_utf8_lossy(&key.account_id));
+            if !m.is_present("recover") {
+                println!("mnemonic (write it down to recover the key):\n{}", phrase);
+            }
+            Ok(())
+        }
+        ("list", Some(_)) => {
+            for name in open_keystore(path)?.names() {
+                println!("{}", name);
+            }
+            Ok(())
+        }
+        ("show", Some(m)) => {
+            let name = m.value_of("name").unwrap_or_default();
+            match open_keystore(path)?.public_key(name) {
+                Some(pk) => println!("public key: {}", hex::encode(pk.as_ref())),
+                None => bail!("no key named {}", name),
+            }
+            Ok(())
+        }
+        _ => bail!("unknown keys command"),
+    }
+}
+
+// Build and sign a tx from the app's tx command
+async fn sign_tx<A: AppCli>(
+    global: &ArgMatches<'_>,
+    matches: &ArgMatches<'_>,
+) -> Result<SignedTransaction, anyhow::Error> {
+    let from = matches.value_of("from").unwrap_or_default();
+    let keystore = open_keystore(keystore_path(global))?;
+    let public_key = match keystore.public_key(from) {
+        Some(pk) => pk,
+        None => bail!("no key named {}", from),
+    };
+
+    let mut tx = build_tx::<A>(global, matches, A::sender(from, &public_key))
+        .await?
+        .build()?;
+    keystore.sign_with(from, &mut tx)?;
+    Ok(tx)
+}
+
+// Start a tx from the app's tx command, with the nonce set
+async fn build_tx<A: AppCli>(
+    global: &ArgMatches<'_>,
+    matches: &ArgMatches<'_>,
+    sender: AccountId,
+) -> Result<TxBuilder, anyhow::Error> {
+    let nonce: Option<u64> = match matches.value_of("nonce").map(|n| n.parse()) {
+        Some(Ok(nonce)) => Some(nonce),
+        Some(Err(_)) => bail!("bad nonce"),
+        None => None,
+    };
+    let chain_id = match global.value_of("chain-id") {
+        Some(chain_id) if !chain_id.is_empty() => chain_id,
+        _ => bail!("--chain-id is required"),
+    };
+    let msg = A::msg(A::TxCommand::from_clap(matches))?;
+    let builder = TxBuilder::new(A::app_name())
+        .sender(sender)
+        .msg(msg)
+        .chain_id(chain_id);
+    match nonce {
+        Some(nonce) => Ok(builder.nonce(nonce)),
+        None => {
+            let node = global.value_of("node").unwrap_or(DEFAULT_NODE);
+            builder.auto_nonce(&client(node)?).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshDeserialize;
+
+    #[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+    enum Msgs {
+        Add(u16),
+    }
+
+    #[derive(StructOpt, Debug)]
+    enum TxCommands {
+        Add { value: u16 },
+    }
+
+    #[derive(StructOpt, Debug)]
+    enum QueryCommands {
+        Count { name: String },
+    }
+
+    struct TestCli;
+    impl AppCli for TestCli {
+        type TxCommand = TxCommands;
+        type QueryCommand = QueryCommands;
+        type Msg = Msgs;
+
+        fn app_name() -> &'static str {
+            "counter"
+        }
+
+        fn msg(cmd: TxCommands) -> Result<Msgs, anyhow::Error> {
+            match cmd {
+                TxCommands::Add { value } => Ok(Msgs::Add(value)),
+            }
+        }
+
+        fn query(cmd: &QueryCommands) -> Result<(String, Vec<u8>), anyhow::Error> {
+            match cmd {
+                QueryCommands::Count { name } => Ok(("/".into(), name.as_bytes().to_vec())),
+            }
+        }
+
+        fn format_query(_cmd: &QueryCommands, value: &[u8]) -> Result<String, anyhow::Error> {
+            Ok(format!("{:?}", value))
+        }
+
+        fn sender(name: &str, _public_key: &PublicKey) -> AccountId {
+            name.as_bytes().to_vec()
+        }
+    }
+
+    #[test]
+    fn test_sign_tx() {
+        let path = std::env::temp_dir().join(format!("rapido-cli-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        std::env::set_var(PASSWORD_ENV, "secret");
+        let mut keystore = Keystore::create(&path, "secret").unwrap();
+        let pk = keystore.generate("dave").unwrap();
+
+        let keystore_arg = path.to_str().unwrap();
+        let matches = command::<TestCli>().get_matches_from(vec![
+            "counter",
+            "--keystore",
+            keystore_arg,
+            "--chain-id",
+            "test-chain",
+            "tx",
+            "sign",
+            "--from",
+            "dave",
+            "--nonce",
+            "3",
+            "add",
+            "5",
+        ]);
+        let (_, tx_matches) = matches.subcommand();
+        let (_, sign_matches) = tx_matches.unwrap().subcommand();
+        let tx = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap()
+            .block_on(sign_tx::<TestCli>(&matches, sign_matches.unwrap()))
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(b"dave".to_vec(), tx.sender());
+        assert_eq!(3, tx.nonce());
+        assert_eq!("test-chain", tx.chain_id());
+        assert_eq!(Msgs::Add(5), Msgs::try_from_slice(&tx.msg()).unwrap());
+        assert!(rapido_core::verify_tx_signature(&tx, &pk));
+
+        assert!(command::<TestCli>()
+            .get_matches_from_safe(vec!["counter", "tx", "sign", "add", "5"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_tx_file() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rapido-cli-export-{}.json", std::process::id()));
+        let tx_path = dir.join(format!("rapido-cli-tx-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut keystore = Keystore::create(&path, "secret").unwrap();
+        let pk = keystore.generate("dave").unwrap();
+
+        let out = tx_path.to_str().unwrap();
+        let matches = command::<TestCli>().get_matches_from(vec![
+            "counter",
+            "--chain-id",
+            "test-chain",
+            "tx",
+            "export",
+            "--sender",
+            &hex::encode("dave"),
+            "--nonce",
+            "2",
+            "--out",
+            out,
+            "add",
+            "5",
+        ]);
+        let (_, tx_matches) = matches.subcommand();
+        let (_, export_matches) = tx_matches.unwrap().subcommand();
+        let tx = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .build()
+            .unwrap()
+            .block_on(build_tx::<TestCli>(
+                &matches,
+                export_matches.unwrap(),
+                b"dave".to_vec(),
+            ))
+            .unwrap()
+            .build()
+            .unwrap();
+        write_tx_file(out, &tx).unwrap();
+
+        // Signed on another machine
+        let mut unsigned = read_tx_file(out).unwrap();
+        assert_eq!(tx.encode(), unsigned.encode());
+        keystore.sign_with("dave", &mut unsigned).unwrap();
+        write_tx_file(out, &unsigned).unwrap();
+
+        let signed = read_tx_file(out).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&tx_path).unwrap();
+        assert_eq!(2, signed.nonce());
+        assert_eq!("test-chain", signed.chain_id());
+        assert!(rapido_core::verify_tx_signature(&signed, &pk));
+    }
+}
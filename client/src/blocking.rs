@@ -0,0 +1,96 @@
+//! A blocking wrapper of `RapidoClient` for demos and simple command lines that
+//! don't want to set up an async runtime.  It runs the calls on its own
+//! (single threaded) tokio runtime, so don't use it from async code:
+//! ```ignore
+//! let mut client = BlockingClient::new("tcp://127.0.0.1:26657")?;
+//! client.send(APP_NAME, "dave", Msgs::Add(1), nonce, &secret_key)?;
+//! let counter: Counter = client.query_as(APP_NAME, "/", b"dave".to_vec())?;
+//! ```
+use borsh::{BorshDeserialize, BorshSerialize};
+use exonum_crypto::SecretKey;
+use serde::de::DeserializeOwned;
+use tokio::runtime::{Builder, Runtime};
+
+use rapido_core::{AccountId, SignedTransaction};
+
+use crate::{chain_info, ChainInfo, ClientConfig, ClientError, RapidoClient, TxResponse};
+
+/// `RapidoClient` with blocking calls
+pub struct BlockingClient {
+    runtime: Runtime,
+    client: RapidoClient,
+}
+
+impl BlockingClient {
+    /// Connect to the node's RPC address, e.g. 'tcp://127.0.0.1:26657'
+    pub fn new(address: &str) -> Result<Self, ClientError> {
+        let runtime = match Builder::new().basic_scheduler().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(err) => return Err(ClientError::Transport(err.into())),
+        };
+        Ok(Self {
+            runtime,
+            client: RapidoClient::new(address)?,
+        })
+    }
+
+    /// Set the timeouts and retries of the calls to the node
+    pub fn config(mut self, config: ClientConfig) -> Self {
+        self.client = self.client.config(config);
+        self
+    }
+
+    /// Set the chain id of the txs sent. Required to send txs
+    pub fn chain_id<C: Into<String>>(mut self, chain_id: C) -> Self {
+        self.client = self.client.chain_id(chain_id);
+        self
+    }
+
+    /// See `RapidoClient::send`
+    pub fn send<M: BorshSerialize>(
+        &mut self,
+        app: &str,
+        sender: impl Into<AccountId>,
+        msg: M,
+        nonce: u64,
+        private_key: &SecretKey,
+    ) -> Result<TxResponse, ClientError> {
+        let client = &self.client;
+        self.runtime
+            .block_on(client.send(app, sender, msg, nonce, private_key))
+    }
+
+    /// See `RapidoClient::send_tx`
+    pub fn send_tx(&mut self, tx: &SignedTransaction) -> Result<TxResponse, ClientError> {
+        let client = &self.client;
+        self.runtime.block_on(client.send_tx(tx))
+    }
+
+    /// See `RapidoClient::query_as`
+    pub fn query_as<T: BorshDeserialize>(
+        &mut self,
+        app: &str,
+        path: &str,
+        key: Vec<u8>,
+    ) -> Result<T, ClientError> {
+        let client = &self.client;
+        self.runtime.block_on(client.query_as(app, path, key))
+    }
+
+    /// See `RapidoClient::query_json`
+    pub fn query_json<T: DeserializeOwned>(
+        &mut self,
+        app: &str,
+        path: &str,
+        key: Vec<u8>,
+    ) -> Result<T, ClientError> {
+        let client = &self.client;
+        self.runtime.block_on(client.query_json(app, path, key))
+    }
+
+    /// See `chain_info`
+    pub fn chain_info(&mut self) -> Result<ChainInfo, ClientError> {
+        let client = self.client.inner();
+        self.runtime.block_on(chain_info(client))
+    }
+}
@@ -0,0 +1,282 @@
+//! Read the state of the chain: the node's status and the app's info, and the
+//! committed blocks and txs, decoded back into `SignedTransaction`s.
+//! ```ignore
+//! let info = chain_info(&client).await?;
+//! if info.catching_up {
+//!     println!("node is syncing, at height {}", info.height);
+//! }
+//! let page = search_txs("rapido.account.created.id='...'", 1, &client).await?;
+//! let block = block(10, &client).await?;
+//! ```
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use tendermint::abci::{transaction, DeliverTx, Transaction};
+use tendermint::block::Height;
+use tendermint_rpc::query::Query;
+use tendermint_rpc::{Client, HttpClient, Order};
+
+use rapido_core::SignedTransaction;
+
+use crate::events::{tx_event, TxEvent};
+use crate::{ClientConfig, ClientError};
+
+/// How many txs `search_txs` returns per page
+pub const TXS_PER_PAGE: u8 = 30;
+
+/// The chain as seen by the node and its app. See `chain_info`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainInfo {
+    pub chain_id: String,
+    /// The node's moniker
+    pub moniker: String,
+    /// The Tendermint version of the node
+    pub node_version: String,
+    /// Height of the last block committed by the app
+    pub height: u64,
+    /// App hash after the last block committed by the app
+    pub app_hash: Vec<u8>,
+    /// Time of the latest block, RFC 3339
+    pub latest_block_time: String,
+    /// Is the node still syncing with its peers?
+    pub catching_up: bool,
+}
+
+/// Return the chain's info from the node's `/status` and the app's `abci_info`.
+/// Useful for health checks.  Uses the default `ClientConfig`
+pub async fn chain_info(client: &HttpClient) -> Result<ChainInfo, ClientError> {
+    let config = ClientConfig::default();
+    let status = config.call(|| client.status()).await?;
+    let info = config.call(|| client.abci_info()).await?;
+    Ok(ChainInfo {
+        chain_id: status.node_info.network.to_string(),
+        moniker: status.node_info.moniker.to_string(),
+        node_version: status.node_info.version.to_string(),
+        height: info.last_block_height.value(),
+        app_hash: info.last_block_app_hash,
+        latest_block_time: status.sync_info.latest_block_time.to_rfc3339(),
+        catching_up: status.sync_info.catching_up,
+    })
+}
+
+/// A tx committed in a block, with the result of its deliver
+pub struct CommittedTx {
+    /// The Tendermint hash of the tx, hex
+    pub hash: String,
+    pub height: u64,
+    /// Position of the tx in the block
+    pub index: u32,
+    pub tx: SignedTransaction,
+    /// 0 if the tx succeeded
+    pub code: u32,
+    pub log: String,
+    pub events: Vec<TxEvent>,
+}
+
+impl CommittedTx {
+    // Decode a tx returned by the node
+    pub(crate) fn decode(
+        hash: String,
+        height: u64,
+        index: u32,
+        tx: &Transaction,
+        result: DeliverTx,
+    ) -> Result<Self, ClientError> {
+        let tx = match SignedTransaction::decode(tx.as_bytes()) {
+            Ok(tx) => tx,
+            Err(err) => return Err(ClientError::DecodeFailure(err)),
+        };
+        Ok(Self {
+            hash,
+            height,
+            index,
+            tx,
+            code: result.code.value(),
+            log: result.log.to_string(),
+            events: result
+                .events
+                .into_iter()
+                .map(|e| tx_event(height as i64, e))
+                .collect(),
+        })
+    }
+}
+
+/// A page of the txs found by `search_txs`
+pub struct TxPage {
+    /// How many txs match the query, on all pages
+    pub total: u32,
+    pub txs: Vec<CommittedTx>,
+}
+
+// Parse a query of 'key=value' conditions joined by 'AND'.  A value is a
+// quoted string or an integer
+fn parse_query(query: &str) -> Result<Query, ClientError> {
+    let mut parsed: Option<Query> = None;
+    for condition in query.split(" AND ") {
+        let (key, value) = match condition.find('=') {
+            Some(i) => (condition[..i].trim(), condition[i + 1..].trim()),
+            None => {
+                return Err(ClientError::InvalidInput(format!(
+                    "expected key=value in '{}'",
+                    condition.trim()
+                )))
+            }
+        };
+        if key.is_empty() || key.ends_with(|c| c == '<' || c == '>' || c == '!') || value.is_empty()
+        {
+            return Err(ClientError::InvalidInput(format!(
+                "expected key=value in '{}'",
+                condition.trim()
+            )));
+        }
+        let quoted = value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'');
+        parsed = Some(match (parsed, quoted) {
+            (None, true) => Query::eq(key, &value[1..value.len() - 1]),
+            (Some(q), true) => q.and_eq(key, &value[1..value.len() - 1]),
+            (parsed, false) => {
+                let number: i64 = match value.parse() {
+                    Ok(number) => number,
+                    Err(_) => {
+                        return Err(ClientError::InvalidInput(format!(
+                            "expected a quoted string or an integer: {}",
+                            value
+                        )))
+                    }
+                };
+                match parsed {
+                    None => Query::eq(key, number),
+                    Some(q) => q.and_eq(key, number),
+                }
+            }
+        });
+    }
+    match parsed {
+        Some(query) => Ok(query),
+        None => Err(ClientError::InvalidInput("empty query".into())),
+    }
+}
+
+/// Search the committed txs by their events with Tendermint's `/tx_search`, e.g.
+/// "rapido.account.created.id='...'" or "tx.height=5".  Conditions are joined with
+/// 'AND'.  `page` starts at 1; txs are returned oldest first, `TXS_PER_PAGE` per
+/// page.  The node must index the tx events (the default 'kv' indexer).  Uses the
+/// default `ClientConfig`
+pub async fn search_txs(
+    query: &str,
+    page: u32,
+    client: &HttpClient,
+) -> Result<TxPage, ClientError> {
+    let query = parse_query(query)?;
+    let resp = ClientConfig::default()
+        .call(|| client.tx_search(query.clone(), false, page, TXS_PER_PAGE, Order::Ascending))
+        .await?;
+    let mut txs = Vec::with_capacity(resp.txs.len());
+    for found in resp.txs {
+        txs.push(CommittedTx::decode(
+            found.hash.to_string(),
+            found.height.value(),
+            found.index,
+            &found.tx,
+            found.tx_result,
+        )?);
+    }
+    Ok(TxPage {
+        total: resp.total_count,
+        txs,
+    })
+}
+
+/// A committed block with its txs. See `block`
+pub struct CommittedBlock {
+    pub height: u64,
+    /// Time of the block, RFC 3339
+    pub time: String,
+    /// App hash after the previous block
+    pub app_hash: Vec<u8>,
+    pub txs: Vec<CommittedTx>,
+}
+
+/// Return the block at the height with its txs and the result of their
+/// delivers. Uses the default `ClientConfig`
+pub async fn block(height: u64, client: &HttpClient) -> Result<CommittedBlock, ClientError> {
+    let height = match Height::try_from(height) {
+        Ok(height) => height,
+        Err(_) => return Err(ClientError::InvalidInput(format!("bad height: {}", height))),
+    };
+    let config = ClientConfig::default();
+    let block = config.call(|| client.block(height)).await?.block;
+    let results = config
+        .call(|| client.block_results(height))
+        .await?
+        .txs_results
+        .unwrap_or_default();
+    if results.len() != block.data.iter().count() {
+        return Err(ClientError::DecodeFailure(anyhow!(
+            "expected {} tx results at height {}, got {}",
+            block.data.iter().count(),
+            height,
+            results.len()
+        )));
+    }
+
+    let mut txs = Vec::with_capacity(results.len());
+    for (index, (tx, result)) in block.data.iter().zip(results).enumerate() {
+        // The Tendermint hash of a tx is the SHA256 of its bytes
+        let hash = hex::encode_upper(exonum_crypto::hash(tx.as_bytes()).as_ref());
+        txs.push(CommittedTx::decode(
+            hash,
+            height.value(),
+            index as u32,
+            tx,
+            result,
+        )?);
+    }
+    Ok(CommittedBlock {
+        height: height.value(),
+        time: block.header.time.to_rfc3339(),
+        app_hash: block.header.app_hash.value(),
+        txs,
+    })
+}
+
+/// Return the committed tx with the hash, hex as returned by the send.  Uses
+/// the default `ClientConfig`
+pub async fn tx(hash: &str, client: &HttpClient) -> Result<CommittedTx, ClientError> {
+    let tx_hash = match transaction::Hash::from_str(hash) {
+        Ok(tx_hash) => tx_hash,
+        Err(_) => return Err(ClientError::InvalidInput(format!("bad tx hash: {}", hash))),
+    };
+    let resp = ClientConfig::default()
+        .call(|| client.tx(tx_hash, false))
+        .await?;
+    CommittedTx::decode(
+        resp.hash.to_string(),
+        resp.height.value(),
+        resp.index,
+        &resp.tx,
+        resp.tx_result,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            Query::eq("rapido.account.created.id", "abc"),
+            parse_query("rapido.account.created.id='abc'").unwrap()
+        );
+        assert_eq!(
+            Query::eq("rapido.bank.transfer.to", "bob").and_eq("tx.height", 5i64),
+            parse_query("rapido.bank.transfer.to = 'bob' AND tx.height=5").unwrap()
+        );
+        assert!(parse_query("").is_err());
+        assert!(parse_query("tx.height>5").is_err());
+        assert!(parse_query("tx.height>=5").is_err());
+        assert!(parse_query("rapido.account.created.id=abc").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Timeouts and retries of the RPC calls.  Each call to the node is limited to
+//! the timeout and a failed (or timed out) call is retried, waiting `backoff`
+//! before the first retry and doubling the wait for each one after.  Only
+//! failures to reach the node are retried: a tx rejected by the app is returned
+//! as is.  Note a `broadcast_tx_commit` that timed out may still be committed.
+//!
+//! TLS isn't supported by the tendermint-rpc HTTP client: use a local proxy to
+//! reach a node over https.
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tokio::time::{delay_for, timeout};
+
+use crate::ClientError;
+
+/// Timeouts and retries of the client. See `RapidoClient::config`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientConfig {
+    /// Timeout of each call to the node
+    pub timeout: Duration,
+    /// How many times a failed call is retried
+    pub retries: u32,
+    /// Wait before the first retry. Doubles for each retry after
+    pub backoff: Duration,
+}
+
+impl Default for ClientConfig {
+    /// 30 second timeout (a commit waits for the block), 2 retries, 500ms backoff
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            retries: 2,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl ClientConfig {
+    // The wait before the retry (from 0)
+    fn backoff_for(&self, retry: u32) -> Duration {
+        self.backoff * 2u32.saturating_pow(retry)
+    }
+
+    // Make the call with the timeout and retries
+    pub(crate) async fn call<T, E, F, Fut>(&self, f: F) -> Result<T, ClientError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut retry = 0;
+        loop {
+            let err = match timeout(self.timeout, f()).await {
+                Ok(Ok(value)) => return Ok(value),
+                Ok(Err(err)) => anyhow::Error::from(err),
+                Err(_) => anyhow!("timed out after {:?}", self.timeout),
+            };
+            if retry >= self.retries {
+                let err = err.context(format!("rpc failed after {} attempts", retry + 1));
+                return Err(ClientError::Transport(err));
+            }
+            delay_for(self.backoff_for(retry)).await;
+            retry += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn test_retries() {
+        let config = ClientConfig {
+            timeout: Duration::from_millis(100),
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        };
+        assert_eq!(Duration::from_millis(4), config.backoff_for(2));
+
+        // Fails twice, then succeeds
+        let calls = AtomicU32::new(0);
+        let result = block_on(config.call(|| async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(io::Error::new(io::ErrorKind::Other, "down")),
+                n => Ok(n),
+            }
+        }));
+        assert_eq!(2, result.unwrap());
+
+        // Gives up after the retries
+        let calls = AtomicU32::new(0);
+        let result: Result<u32, _> = block_on(config.call(|| async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(io::Error::new(io::ErrorKind::Other, "down"))
+        }));
+        assert!(result.is_err());
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        // Times out
+        let result = block_on(config.call(|| async {
+            delay_for(Duration::from_secs(5)).await;
+            Ok::<_, io::Error>(())
+        }));
+        assert!(result.is_err());
+    }
+}
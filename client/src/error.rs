@@ -0,0 +1,75 @@
+//! Errors of the client operations
+use std::fmt;
+
+/// Error of a client operation.  Tells a tx or query rejected by the app apart
+/// from a failure to reach the node, so tools can decide what to retry.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The tx failed the check and wasn't added to the mempool
+    CheckFailed { code: u32, log: String },
+    /// The tx was committed in a block but failed
+    DeliverFailed { code: u32, log: String },
+    /// The app returned an error for the query
+    QueryFailed { code: u32, log: String },
+    /// A bad argument, e.g. a malformed query path or tx hash
+    InvalidInput(String),
+    /// The node couldn't be reached, timed out or returned a bad response
+    Transport(anyhow::Error),
+    /// The value returned couldn't be decoded
+    DecodeFailure(anyhow::Error),
+    /// Nodes at the same height returned different app hashes. See `FailoverClient`
+    AppHashMismatch { height: u64 },
+}
+
+impl ClientError {
+    /// Was the tx or query rejected by the app?
+    pub fn is_rejected(&self) -> bool {
+        matches!(
+            self,
+            ClientError::CheckFailed { .. }
+                | ClientError::DeliverFailed { .. }
+                | ClientError::QueryFailed { .. }
+        )
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::CheckFailed { code, log } => write!(f, "check err ({}): {}", code, log),
+            ClientError::DeliverFailed { code, log } => {
+                write!(f, "deliver err ({}): {}", code, log)
+            }
+            ClientError::QueryFailed { code, log } => write!(f, "query err ({}): {}", code, log),
+            ClientError::InvalidInput(msg) => write!(f, "invalid input: {}", msg),
+            ClientError::Transport(error) => write!(f, "rpc error: {:#}", error),
+            ClientError::DecodeFailure(error) => write!(f, "problem decoding: {:#}", error),
+            ClientError::AppHashMismatch { height } => {
+                write!(f, "nodes disagree on the app hash at height {}", height)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_error() {
+        let err = ClientError::CheckFailed {
+            code: 1,
+            log: "nonce don't match".into(),
+        };
+        assert!(err.is_rejected());
+        assert_eq!("check err (1): nonce don't match", err.to_string());
+
+        let err = ClientError::Transport(anyhow::anyhow!("connection refused"));
+        assert!(!err.is_rejected());
+        // Converts to an anyhow error
+        let any: anyhow::Error = err.into();
+        assert!(any.downcast_ref::<ClientError>().is_some());
+    }
+}
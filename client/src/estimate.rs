@@ -0,0 +1,115 @@
+//! Estimate the gas of a tx before sending it, with the node's `rapido/simulate`
+//! query.  The tx is run against the latest committed state and discarded: it
+//! doesn't need to be signed or have the next nonce.
+//! ```ignore
+//! let tx = TxBuilder::new(APP_NAME).sender("dave").msg(Msgs::Add(1)).build()?;
+//! let estimate = estimate(&tx, &client).await?;
+//! let gas = estimate.gas_with_margin(DEFAULT_GAS_MULTIPLIER)?;
+//! ```
+use tendermint_rpc::HttpClient;
+
+use rapido_core::{SignedTransaction, SimulatedEvent, SimulationResult};
+
+use crate::{decode_borsh, query_encoded, ClientConfig, ClientError};
+
+/// Safety margin for `Estimate::gas_with_margin`. The state may change between
+/// the simulation and the tx being delivered
+pub const DEFAULT_GAS_MULTIPLIER: f64 = 1.2;
+
+/// The gas and events of a simulated tx. See `estimate`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    /// Gas consumed by the simulated tx
+    pub gas_used: u64,
+    /// Events the tx would emit, e.g. the transfer of its fee
+    pub events: Vec<SimulatedEvent>,
+}
+
+impl Estimate {
+    /// The gas used times the multiplier (at least 1), rounded up
+    pub fn gas_with_margin(&self, multiplier: f64) -> Result<u64, ClientError> {
+        if multiplier.is_nan() || multiplier < 1.0 {
+            return Err(ClientError::InvalidInput(format!(
+                "expected a gas multiplier of at least 1: {}",
+                multiplier
+            )));
+        }
+        Ok((self.gas_used as f64 * multiplier).ceil() as u64)
+    }
+
+    /// The fee of the gas, with the multiplier, at the price per unit of gas
+    pub fn fee(&self, gas_price: u64, multiplier: f64) -> Result<u64, ClientError> {
+        match self.gas_with_margin(multiplier)?.checked_mul(gas_price) {
+            Some(fee) => Ok(fee),
+            None => Err(ClientError::InvalidInput("fee overflows".into())),
+        }
+    }
+
+    /// Return the attribute of the first event of the kind, e.g. the amount of a
+    /// transfer
+    pub fn attribute(&self, kind: &str, key: &str) -> Option<&str> {
+        self.events
+            .iter()
+            .filter(|e| e.kind == kind)
+            .flat_map(|e| e.attributes.iter())
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// Run the rapido/simulate query
+pub(crate) async fn simulate(
+    tx: &SignedTransaction,
+    config: &ClientConfig,
+    client: &HttpClient,
+) -> Result<Estimate, ClientError> {
+    let encoded = query_encoded("rapido/simulate", tx.encode(), config, client).await?;
+    let result: SimulationResult = decode_borsh(encoded)?;
+    Ok(Estimate {
+        gas_used: result.gas_used,
+        events: result.events,
+    })
+}
+
+/// Simulate the tx on the node and return its gas and events.  A tx that
+/// would fail returns `ClientError::QueryFailed` with the app's log.
+/// Uses the default `ClientConfig`
+pub async fn estimate(
+    tx: &SignedTransaction,
+    client: &HttpClient,
+) -> Result<Estimate, ClientError> {
+    simulate(tx, &ClientConfig::default(), client).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let estimate = Estimate {
+            gas_used: 100,
+            events: vec![SimulatedEvent {
+                kind: "rapido.bank.transfer".into(),
+                attributes: vec![("amount".into(), "5".into())],
+            }],
+        };
+        assert_eq!(100, estimate.gas_with_margin(1.0).unwrap());
+        assert_eq!(
+            120,
+            estimate.gas_with_margin(DEFAULT_GAS_MULTIPLIER).unwrap()
+        );
+        assert_eq!(101, estimate.gas_with_margin(1.001).unwrap());
+        assert!(estimate.gas_with_margin(0.5).is_err());
+        assert!(estimate.gas_with_margin(f64::NAN).is_err());
+
+        assert_eq!(300, estimate.fee(2, 1.5).unwrap());
+        assert!(estimate.fee(u64::MAX, 1.0).is_err());
+
+        assert_eq!(
+            Some("5"),
+            estimate.attribute("rapido.bank.transfer", "amount")
+        );
+        assert_eq!(None, estimate.attribute("rapido.bank.transfer", "to"));
+    }
+}
@@ -0,0 +1,113 @@
+//! Subscribe to the events of committed txs over Tendermint's WebSocket.
+//! ```ignore
+//! let mut events = subscribe_events("rapido.account.created", "tcp://127.0.0.1:26657").await?;
+//! while let Some(event) = events.next().await {
+//!     println!("{:?}", event?);
+//! }
+//! ```
+use std::collections::VecDeque;
+
+use futures::stream::{self, Stream, StreamExt};
+use tendermint::abci;
+use tendermint_rpc::event::{Event, EventData};
+use tendermint_rpc::query::EventType;
+use tendermint_rpc::{SubscriptionClient, WebSocketClient};
+
+/// An event emitted by a committed tx
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxEvent {
+    /// Height of the block the tx was committed in
+    pub height: i64,
+    /// The event type, e.g. 'rapido.account.created'
+    pub kind: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+/// Does the event type match the filter?  A filter matches the event type or,
+/// ending in '.', every event type it prefixes.  An empty filter matches all.
+fn matches_filter(kind: &str, filter: &str) -> bool {
+    if filter.is_empty() || kind == filter {
+        return true;
+    }
+    filter.ends_with('.') && kind.starts_with(filter)
+}
+
+// Convert an event of a tx committed at the height
+pub(crate) fn tx_event(height: i64, event: abci::Event) -> TxEvent {
+    TxEvent {
+        height,
+        kind: event.type_str,
+        attributes: event
+            .attributes
+            .into_iter()
+            .map(|tag| (tag.key.to_string(), tag.value.to_string()))
+            .collect(),
+    }
+}
+
+// Decode the rapido events of a tx that match the filter
+fn decode_tx_events(event: Event, filter: &str) -> Vec<TxEvent> {
+    let tx = match event.data {
+        EventData::Tx { tx_result } => tx_result,
+        _ => return vec![],
+    };
+    tx.result
+        .events
+        .into_iter()
+        .filter(|e| matches_filter(&e.type_str, filter))
+        .map(|e| tx_event(tx.height, e))
+        .collect()
+}
+
+/// Connect to the node's WebSocket and return a stream of the events of committed
+/// txs matching the filter, e.g. "rapido.account.created" or "rapido.bank." for all
+/// the bank's events.  The stream ends when the connection closes.
+pub async fn subscribe_events(
+    filter: &str,
+    address: &str,
+) -> Result<impl Stream<Item = Result<TxEvent, anyhow::Error>>, anyhow::Error> {
+    let (client, driver) = WebSocketClient::new(address.parse()?).await?;
+    tokio::spawn(driver.run());
+    let subscription = client.subscribe(EventType::Tx.into()).await?;
+
+    // The client is kept with the subscription to keep the connection open
+    let state = (client, subscription, filter.to_string(), VecDeque::new());
+    Ok(stream::unfold(
+        state,
+        |(client, mut subscription, filter, mut pending)| async move {
+            loop {
+                if let Some(event) = pending.pop_front() {
+                    return Some((Ok(event), (client, subscription, filter, pending)));
+                }
+                match subscription.next().await {
+                    Some(Ok(event)) => pending.extend(decode_tx_events(event, &filter)),
+                    Some(Err(err)) => {
+                        return Some((Err(err.into()), (client, subscription, filter, pending)))
+                    }
+                    None => return None,
+                }
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_filter() {
+        assert!(matches_filter("rapido.account.created", ""));
+        assert!(matches_filter(
+            "rapido.account.created",
+            "rapido.account.created"
+        ));
+        assert!(matches_filter("rapido.account.created", "rapido.account."));
+        assert!(!matches_filter("rapido.account.created", "rapido.account"));
+        assert!(!matches_filter("rapido.bank.transfer", "rapido.account."));
+        assert!(!matches_filter(
+            "rapido.account.created",
+            "rapido.account.create"
+        ));
+    }
+}
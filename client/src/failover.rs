@@ -0,0 +1,258 @@
+//! A client of several nodes that fails over when one can't be reached or lags
+//! behind.  Before each call the nodes report their height (`abci_info`); nodes
+//! more than `max_lag` blocks behind the highest are skipped and the call goes
+//! to the node used last, else the next one, moving on while a node can't be
+//! reached.  A tx or query rejected by the app isn't retried on another node.
+//! ```ignore
+//! let client = FailoverClient::new(&["tcp://10.0.0.1:26657", "tcp://10.0.0.2:26657"])?
+//!     .max_lag(2)
+//!     .verify_app_hash(true);
+//! let balance: u64 = client.query_as("rapido.bank", "/", b"dave".to_vec()).await?;
+//! ```
+//! With `verify_app_hash`, the nodes at the highest height must report the same
+//! app hash, else the call fails with `ClientError::AppHashMismatch`.
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::anyhow;
+use borsh::BorshDeserialize;
+use futures::future::join_all;
+use serde::de::DeserializeOwned;
+use tendermint_rpc::{Client, HttpClient};
+
+use rapido_core::{QueryEncoding, SignedTransaction};
+
+use crate::{
+    app_path, broadcast_commit, decode_borsh, decode_json, query_encoded, ClientConfig,
+    ClientError, TxResponse,
+};
+
+// The height and app hash reported by a node, if it could be reached
+type Report = Option<(u64, Vec<u8>)>;
+
+// Return the nodes to try, in order, from their reports: the preferred node
+// first, then the ones after it.  Skips the nodes that can't be reached or lag
+// more than `max_lag` blocks behind the highest
+fn usable_nodes(
+    reports: &[Report],
+    preferred: usize,
+    max_lag: u64,
+    verify_app_hash: bool,
+) -> Result<Vec<usize>, ClientError> {
+    let highest = match reports.iter().flatten().map(|(h, _)| *h).max() {
+        Some(highest) => highest,
+        None => {
+            return Err(ClientError::Transport(anyhow!(
+                "none of the {} nodes can be reached",
+                reports.len()
+            )))
+        }
+    };
+
+    if verify_app_hash {
+        let mut at_highest = reports
+            .iter()
+            .flatten()
+            .filter(|(h, _)| *h == highest)
+            .map(|(_, hash)| hash);
+        let first = at_highest.next();
+        if at_highest.any(|hash| Some(hash) != first) {
+            return Err(ClientError::AppHashMismatch { height: highest });
+        }
+    }
+
+    let count = reports.len();
+    Ok((0..count)
+        .map(|i| (preferred + i) % count)
+        .filter(|i| match &reports[*i] {
+            Some((height, _)) => height + max_lag >= highest,
+            None => false,
+        })
+        .collect())
+}
+
+/// A client of several nodes of the same chain. See the module doc
+pub struct FailoverClient {
+    nodes: Vec<HttpClient>,
+    config: ClientConfig,
+    max_lag: u64,
+    verify_app_hash: bool,
+    // The node that answered the last call
+    preferred: AtomicUsize,
+}
+
+impl FailoverClient {
+    /// Connect to the nodes' RPC addresses, e.g. 'tcp://127.0.0.1:26657'
+    pub fn new(addresses: &[&str]) -> Result<Self, ClientError> {
+        if addresses.is_empty() {
+            return Err(ClientError::InvalidInput("no node addresses".into()));
+        }
+        let mut nodes = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let parsed = match address.parse() {
+                Ok(parsed) => parsed,
+                Err(_) => {
+                    return Err(ClientError::InvalidInput(format!(
+                        "bad address: {}",
+                        address
+                    )))
+                }
+            };
+            match HttpClient::new(parsed) {
+                Ok(client) => nodes.push(client),
+                Err(err) => return Err(ClientError::Transport(err.into())),
+            }
+        }
+        Ok(Self {
+            nodes,
+            config: ClientConfig::default(),
+            max_lag: 0,
+            verify_app_hash: false,
+            preferred: AtomicUsize::new(0),
+        })
+    }
+
+    /// Set the timeouts and retries of the calls to each node
+    pub fn config(mut self, config: ClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set how many blocks a node can be behind the highest and still be used.
+    /// Defaults to 0
+    pub fn max_lag(mut self, blocks: u64) -> Self {
+        self.max_lag = blocks;
+        self
+    }
+
+    /// Check the nodes at the highest height agree on the app hash. Defaults to false
+    pub fn verify_app_hash(mut self, verify: bool) -> Self {
+        self.verify_app_hash = verify;
+        self
+    }
+
+    // Ask each node for its height and app hash, once, with the timeout
+    async fn reports(&self) -> Vec<Report> {
+        let config = ClientConfig {
+            retries: 0,
+            ..self.config.clone()
+        };
+        let calls = self
+            .nodes
+            .iter()
+            .map(|node| config.call(move || node.abci_info()));
+        join_all(calls)
+            .await
+            .into_iter()
+            .map(|result| match result {
+                Ok(info) => Some((info.last_block_height.value(), info.last_block_app_hash)),
+                Err(_) => None,
+            })
+            .collect()
+    }
+
+    /// Make the call with the usable nodes, in turn, until one can be reached.
+    /// Used by the other calls of the client:
+    /// ```ignore
+    /// let info = client.call(|node| async move { chain_info(&node).await }).await?;
+    /// ```
+    pub async fn call<T, F, Fut>(&self, f: F) -> Result<T, ClientError>
+    where
+        F: Fn(HttpClient) -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        let reports = self.reports().await;
+        let preferred = self.preferred.load(Ordering::Relaxed);
+        let mut last_err = None;
+        for i in usable_nodes(&reports, preferred, self.max_lag, self.verify_app_hash)? {
+            match f(self.nodes[i].clone()).await {
+                Err(ClientError::Transport(err)) => last_err = Some(err),
+                result => {
+                    self.preferred.store(i, Ordering::Relaxed);
+                    return result;
+                }
+            }
+        }
+        let err = last_err.unwrap_or_else(|| anyhow!("no usable node"));
+        Err(ClientError::Transport(err.context("all nodes failed")))
+    }
+
+    /// Send a tx and wait for it to be committed in a block.  Note a tx that
+    /// timed out on a node may still be committed: sending it to the next node
+    /// then fails the nonce check
+    pub async fn send_tx(&self, tx: &SignedTransaction) -> Result<TxResponse, ClientError> {
+        let config = &self.config;
+        self.call(|node| async move {
+            TxResponse::from_commit(broadcast_commit(tx, config, &node).await?)
+        })
+        .await
+    }
+
+    // Query the path of an app
+    async fn query_encoded(
+        &self,
+        app: &str,
+        path: &str,
+        key: Vec<u8>,
+    ) -> Result<(QueryEncoding, Vec<u8>), ClientError> {
+        let path = app_path(app, path);
+        let config = &self.config;
+        self.call(|node| {
+            let (path, key) = (path.clone(), key.clone());
+            async move { query_encoded(&path, key, config, &node).await }
+        })
+        .await
+    }
+
+    /// Query the path of an app and decode the Borsh encoded value
+    pub async fn query_as<T: BorshDeserialize>(
+        &self,
+        app: &str,
+        path: &str,
+        key: Vec<u8>,
+    ) -> Result<T, ClientError> {
+        decode_borsh(self.query_encoded(app, path, key).await?)
+    }
+
+    /// Query the path of an app and decode the JSON encoded value
+    pub async fn query_json<T: DeserializeOwned>(
+        &self,
+        app: &str,
+        path: &str,
+        key: Vec<u8>,
+    ) -> Result<T, ClientError> {
+        decode_json(self.query_encoded(app, path, key).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usable_nodes() {
+        let hash = vec![1u8; 32];
+        let reports = vec![
+            Some((10, hash.clone())),
+            None,
+            Some((8, vec![2u8; 32])),
+            Some((10, hash.clone())),
+        ];
+        // Starts from the preferred node, skips the unreachable and lagging ones
+        assert_eq!(vec![3, 0], usable_nodes(&reports, 2, 0, true).unwrap());
+        assert_eq!(vec![2, 3, 0], usable_nodes(&reports, 2, 2, true).unwrap());
+        assert_eq!(vec![0, 3], usable_nodes(&reports, 0, 1, false).unwrap());
+
+        // Nodes at the highest height disagree
+        let forked = vec![Some((10, hash)), Some((10, vec![3u8; 32]))];
+        match usable_nodes(&forked, 0, 0, true) {
+            Err(ClientError::AppHashMismatch { height }) => assert_eq!(10, height),
+            _ => panic!("expected an app hash mismatch"),
+        }
+        assert_eq!(vec![1, 0], usable_nodes(&forked, 1, 0, false).unwrap());
+
+        assert!(usable_nodes(&[None, None], 0, 0, false).is_err());
+        assert!(FailoverClient::new(&[]).is_err());
+        assert!(FailoverClient::new(&["tcp://127.0.0.1:26657", "bad"]).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! A REST/JSON gateway to a node, so web apps can query and send txs without
+//! knowing Borsh or the Tendermint RPC.  Enable with the `gateway` feature:
+//! ```ignore
+//! serve_gateway(([127, 0, 0, 1], 8080).into(), "tcp://127.0.0.1:26657").await?;
+//! ```
+//! Routes:
+//! - `GET /<app>/<path>?key=<hex>` queries the app. A JSON value is returned as
+//!   is, other values as hex: `{"encoding": "borsh", "value": "<hex>"}`
+//! - `POST /txs` with `{"tx": "<hex>", "mode": "sync"}` broadcasts the encoded tx
+//!   (see `SignedTransaction::to_hex`) and returns its `BroadcastResult`. The mode
+//!   is one of 'async', 'sync' (the default) or 'commit'
+//!
+//! Errors are returned as `{"error": "..."}` with a 4xx status for a bad request
+//! or a query rejected by the app, and 502 if the node can't be reached.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use serde_json::{json, Value};
+use tendermint_rpc::HttpClient;
+use warp::http::StatusCode;
+use warp::path::Tail;
+use warp::Filter;
+
+use rapido_core::{QueryEncoding, SignedTransaction};
+
+use crate::{app_path, broadcast, query_encoded, BroadcastMode, ClientConfig, ClientError};
+
+// Decode a hex value, with or without a '0x' prefix
+fn decode_hex(value: &str) -> Result<Vec<u8>, ClientError> {
+    match hex::decode(value.trim_start_matches("0x")) {
+        Ok(raw) => Ok(raw),
+        Err(_) => Err(ClientError::InvalidInput(format!("bad hex: {}", value))),
+    }
+}
+
+fn parse_mode(mode: Option<&str>) -> Result<BroadcastMode, ClientError> {
+    match mode {
+        Some("async") => Ok(BroadcastMode::Async),
+        None | Some("sync") => Ok(BroadcastMode::Sync),
+        Some("commit") => Ok(BroadcastMode::Commit),
+        Some(other) => Err(ClientError::InvalidInput(format!(
+            "unknown mode: {}",
+            other
+        ))),
+    }
+}
+
+// The JSON of a query value
+fn query_value(encoding: QueryEncoding, value: &[u8]) -> Result<Value, ClientError> {
+    if encoding == QueryEncoding::Json {
+        return match serde_json::from_slice(value) {
+            Ok(value) => Ok(value),
+            Err(err) => Err(ClientError::DecodeFailure(err.into())),
+        };
+    }
+    Ok(json!({
+        "encoding": encoding.to_string(),
+        "value": hex::encode(value),
+    }))
+}
+
+fn error_status(err: &ClientError) -> StatusCode {
+    match err {
+        ClientError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+        ClientError::CheckFailed { .. }
+        | ClientError::DeliverFailed { .. }
+        | ClientError::QueryFailed { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        ClientError::Transport(_)
+        | ClientError::DecodeFailure(_)
+        | ClientError::AppHashMismatch { .. } => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn reply(result: Result<Value, ClientError>) -> warp::reply::WithStatus<warp::reply::Json> {
+    match result {
+        Ok(value) => warp::reply::with_status(warp::reply::json(&value), StatusCode::OK),
+        Err(err) => warp::reply::with_status(
+            warp::reply::json(&json!({ "error": err.to_string() })),
+            error_status(&err),
+        ),
+    }
+}
+
+async fn handle_query(
+    tail: Tail,
+    params: HashMap<String, String>,
+    client: HttpClient,
+) -> Result<Value, ClientError> {
+    let (app, path) = match tail.as_str().find('/') {
+        Some(i) => (&tail.as_str()[..i], &tail.as_str()[i..]),
+        None => (tail.as_str(), "/"),
+    };
+    let key = match params.get("key") {
+        Some(key) => decode_hex(key)?,
+        None => vec![],
+    };
+    let (encoding, value) =
+        query_encoded(&app_path(app, path), key, &ClientConfig::default(), &client).await?;
+    query_value(encoding, &value)
+}
+
+async fn handle_tx(body: Value, client: HttpClient) -> Result<Value, ClientError> {
+    let raw = match body["tx"].as_str() {
+        Some(raw) => decode_hex(raw)?,
+        None => return Err(ClientError::InvalidInput("missing 'tx'".into())),
+    };
+    let tx = match SignedTransaction::decode(&raw) {
+        Ok(tx) => tx,
+        Err(err) => return Err(ClientError::InvalidInput(err.to_string())),
+    };
+    let result = broadcast(&tx, parse_mode(body["mode"].as_str())?, &client).await?;
+    Ok(json!({
+        "hash": result.hash,
+        "code": result.code,
+        "log": result.log,
+        "height": result.height,
+    }))
+}
+
+/// Serve the gateway on the address, forwarding to the node's RPC address,
+/// e.g. 'tcp://127.0.0.1:26657'.  Runs until the process stops
+pub async fn serve_gateway(addr: SocketAddr, node: &str) -> Result<(), ClientError> {
+    let address = match node.parse() {
+        Ok(address) => address,
+        Err(_) => return Err(ClientError::InvalidInput(format!("bad address: {}", node))),
+    };
+    let client = match HttpClient::new(address) {
+        Ok(client) => client,
+        Err(err) => return Err(ClientError::Transport(err.into())),
+    };
+    let with_client = warp::any().map(move || client.clone());
+
+    let txs = warp::post()
+        .and(warp::path("txs"))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and(with_client.clone())
+        .and_then(|body, client| async move {
+            Ok::<_, warp::Rejection>(reply(handle_tx(body, client).await))
+        });
+    let queries = warp::get()
+        .and(warp::path::tail())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_client)
+        .and_then(|tail, params, client| async move {
+            Ok::<_, warp::Rejection>(reply(handle_query(tail, params, client).await))
+        });
+
+    warp::serve(txs.or(queries)).run(addr).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gateway_encoding() {
+        assert_eq!(vec![1, 2], decode_hex("0x0102").unwrap());
+        assert_eq!(b"dave".to_vec(), decode_hex("64617665").unwrap());
+        assert!(decode_hex("xyz").is_err());
+
+        assert_eq!(BroadcastMode::Sync, parse_mode(None).unwrap());
+        assert_eq!(BroadcastMode::Commit, parse_mode(Some("commit")).unwrap());
+        assert!(parse_mode(Some("later")).is_err());
+
+        assert_eq!(
+            json!({"count": 2}),
+            query_value(QueryEncoding::Json, br#"{"count":2}"#).unwrap()
+        );
+        assert_eq!(
+            json!({"encoding": "borsh", "value": "0200"}),
+            query_value(QueryEncoding::Borsh, &[2, 0]).unwrap()
+        );
+        assert!(query_value(QueryEncoding::Json, b"not json").is_err());
+
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            error_status(&ClientError::InvalidInput("bad".into()))
+        );
+        assert_eq!(
+            StatusCode::BAD_GATEWAY,
+            error_status(&ClientError::Transport(anyhow::anyhow!("down")))
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Deterministic keys from a BIP39 mnemonic.  Keys are derived from the
+//! mnemonic's seed with SLIP-0010 for ed25519, so the same phrase and path always
+//! give the same key and account id.  Only hardened paths are supported:
+//! ```ignore
+//! let phrase = keys::generate_mnemonic();
+//! let key = keys::from_mnemonic(&phrase, keys::DEFAULT_PATH)?;
+//! keystore.import("alice", key.secret_key)?;
+//! ```
+use anyhow::{bail, ensure};
+use bip39::{Language, Mnemonic, MnemonicType, Seed};
+use exonum_crypto::{gen_keypair_from_seed, PublicKey, SecretKey};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha512;
+
+use rapido_account::Account;
+use rapido_core::AccountId;
+
+/// Derivation path of the first account
+pub const DEFAULT_PATH: &str = "m/44'/118'/0'/0'/0'";
+
+const HARDENED: u32 = 0x8000_0000;
+// HMAC key of the master key of SLIP-0010 ed25519
+const ED25519_CURVE: &[u8] = b"ed25519 seed";
+
+/// A key derived from a mnemonic
+#[derive(Debug, Clone)]
+pub struct DerivedKey {
+    pub public_key: PublicKey,
+    pub secret_key: SecretKey,
+    /// The account id of the public key. See `rapido_account::Account`
+    pub account_id: AccountId,
+}
+
+/// Generate a new 24 word mnemonic
+pub fn generate_mnemonic() -> String {
+    Mnemonic::new(MnemonicType::Words24, Language::English).into_phrase()
+}
+
+/// Derive the key at the path, e.g. "m/44'/118'/0'/0'/0'", from the mnemonic
+pub fn from_mnemonic(phrase: &str, path: &str) -> Result<DerivedKey, anyhow::Error> {
+    let mnemonic = match Mnemonic::from_phrase(phrase, Language::English) {
+        Ok(mnemonic) => mnemonic,
+        Err(err) => bail!("bad mnemonic: {}", err),
+    };
+    let seed = Seed::new(&mnemonic, "");
+    let raw = derive(seed.as_bytes(), &parse_path(path)?);
+
+    let (public_key, secret_key) =
+        gen_keypair_from_seed(&exonum_crypto::Seed::from_slice(&raw).expect("32 byte seed"));
+    Ok(DerivedKey {
+        account_id: Account::create(&public_key, false).id(),
+        public_key,
+        secret_key,
+    })
+}
+
+// Parse a path of hardened indexes
+fn parse_path(path: &str) -> Result<Vec<u32>, anyhow::Error> {
+    let mut parts = path.split('/');
+    ensure!(
+        parts.next() == Some("m"),
+        "path must start with 'm': {}",
+        path
+    );
+    let mut indexes = Vec::new();
+    for part in parts {
+        let index = match part.strip_suffix('\'') {
+            Some(index) => index,
+            None => bail!("only hardened indexes are supported: {}", path),
+        };
+        let index: u32 = match index.parse() {
+            Ok(index) => index,
+            Err(_) => bail!("bad index '{}' in {}", part, path),
+        };
+        ensure!(index < HARDENED, "index out of range in {}", path);
+        indexes.push(index | HARDENED);
+    }
+    Ok(indexes)
+}
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    let mut mac = Hmac::<Sha512>::new_varkey(key).expect("hmac takes any key length");
+    mac.update(data);
+    let mut out = [0u8; 64];
+    out.copy_from_slice(&mac.finalize().into_bytes());
+    out
+}
+
+// SLIP-0010 private key derivation for ed25519
+fn derive(seed: &[u8], path: &[u32]) -> [u8; 32] {
+    let mut node = hmac_sha512(ED25519_CURVE, seed);
+    for index in path {
+        let mut data = vec![0u8];
+        data.extend_from_slice(&node[..32]);
+        data.extend_from_slice(&index.to_be_bytes());
+        node = hmac_sha512(&node[32..], &data);
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&node[..32]);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    #[test]
+    fn test_slip10_vectors() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        assert_eq!(
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7",
+            hex::encode(derive(&seed, &[]))
+        );
+        assert_eq!(
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3",
+            hex::encode(derive(&seed, &parse_path("m/0'").unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_from_mnemonic() {
+        let key = from_mnemonic(PHRASE, DEFAULT_PATH).unwrap();
+        let again = from_mnemonic(PHRASE, DEFAULT_PATH).unwrap();
+        assert_eq!(key.public_key, again.public_key);
+        assert_eq!(key.account_id, again.account_id);
+
+        let other = from_mnemonic(PHRASE, "m/44'/118'/0'/0'/1'").unwrap();
+        assert_ne!(key.public_key, other.public_key);
+
+        assert!(from_mnemonic("abandon abandon", DEFAULT_PATH).is_err());
+        assert!(from_mnemonic(PHRASE, "m/44'/118'/0").is_err());
+        assert!(from_mnemonic(PHRASE, "44'/118'").is_err());
+
+        let phrase = generate_mnemonic();
+        assert_eq!(24, phrase.split_whitespace().count());
+        assert!(from_mnemonic(&phrase, DEFAULT_PATH).is_ok());
+    }
+}
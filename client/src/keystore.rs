@@ -0,0 +1,277 @@
+//! Encrypted file keystore.  Keeps named ed25519 keys in a JSON file, each
+//! encrypted (ChaCha20-Poly1305) with a key derived (scrypt) from the keystore's
+//! password.  Use it to sign txs instead of generating or hardcoding keys:
+//! ```ignore
+//! let mut keystore = Keystore::create("keys.json", "password")?;
+//! keystore.generate("alice")?;
+//! ...
+//! let keystore = Keystore::open("keys.json", "password")?;
+//! let mut tx = TxBuilder::new(APP_NAME).sender("alice").msg(Msgs::Create).build()?;
+//! keystore.sign_with("alice", &mut tx)?;
+//! ```
+//! Only ed25519 keys are supported as rapido txs are signed with them.
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use exonum_crypto::{gen_keypair, PublicKey, SecretKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::{json, Value};
+
+use rapido_core::SignedTransaction;
+
+const KEYSTORE_VERSION: u64 = 1;
+// Encrypted with the key to check the password
+const CHECK_TEXT: &[u8] = b"rapido.keystore";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+// scrypt cost.  Lower in tests as it's slow without optimizations
+#[cfg(not(test))]
+const SCRYPT_LOG_N: u8 = 15;
+#[cfg(test)]
+const SCRYPT_LOG_N: u8 = 8;
+
+// Derive the encryption key from the password
+fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; 32], anyhow::Error> {
+    let params = match scrypt::ScryptParams::new(SCRYPT_LOG_N, 8, 1) {
+        Ok(params) => params,
+        Err(_) => bail!("bad scrypt params"),
+    };
+    let mut key = [0u8; 32];
+    if scrypt::scrypt(password.as_bytes(), salt, &params, &mut key).is_err() {
+        bail!("deriving the keystore key");
+    }
+    Ok(key)
+}
+
+// An ed25519 secret key is the seed followed by the public key
+fn public_key_of(sk: &SecretKey) -> PublicKey {
+    PublicKey::from_slice(&sk.as_ref()[32..]).expect("public key of a secret key")
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+// Encrypt with a random nonce.  Returns (nonce, ciphertext)
+fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), anyhow::Error> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = random_bytes(NONCE_LENGTH);
+    match cipher.encrypt(Nonce::from_slice(&nonce), plaintext) {
+        Ok(ciphertext) => Ok((nonce, ciphertext)),
+        Err(_) => bail!("encrypting a key"),
+    }
+}
+
+fn decrypt(key: &[u8; 32], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    ensure!(nonce.len() == NONCE_LENGTH, "bad nonce length");
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    match cipher.decrypt(Nonce::from_slice(nonce), ciphertext) {
+        Ok(plaintext) => Ok(plaintext),
+        Err(_) => bail!("wrong password or corrupted keystore"),
+    }
+}
+
+// Read a hex field of a JSON object
+fn hex_field(value: &Value, field: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let encoded = match value[field].as_str() {
+        Some(encoded) => encoded,
+        None => bail!("keystore is missing '{}'", field),
+    };
+    match hex::decode(encoded) {
+        Ok(raw) => Ok(raw),
+        Err(_) => bail!("bad hex in '{}'", field),
+    }
+}
+
+/// Named keys encrypted in a file.  Changes are written to the file immediately.
+pub struct Keystore {
+    path: PathBuf,
+    salt: Vec<u8>,
+    key: [u8; 32],
+    keys: BTreeMap<String, (PublicKey, SecretKey)>,
+}
+
+impl Keystore {
+    /// Create a new, empty keystore at the path. Returns an error if the file exists
+    pub fn create<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref().to_path_buf();
+        ensure!(!path.exists(), "{} already exists", path.display());
+        let salt = random_bytes(SALT_LENGTH);
+        let keystore = Self {
+            key: derive_key(password, &salt)?,
+            path,
+            salt,
+            keys: BTreeMap::new(),
+        };
+        keystore.save()?;
+        Ok(keystore)
+    }
+
+    /// Open an existing keystore. Returns an error if the password is wrong
+    pub fn open<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref().to_path_buf();
+        let json: Value = serde_json::from_slice(&fs::read(&path)?)?;
+        ensure!(
+            json["version"].as_u64() == Some(KEYSTORE_VERSION),
+            "unsupported keystore version: {}",
+            json["version"]
+        );
+        let salt = hex_field(&json, "salt")?;
+        let key = derive_key(password, &salt)?;
+        let check = decrypt(
+            &key,
+            &hex_field(&json["check"], "nonce")?,
+            &hex_field(&json["check"], "ciphertext")?,
+        )?;
+        ensure!(check == CHECK_TEXT, "wrong password or corrupted keystore");
+
+        let mut keys = BTreeMap::new();
+        if let Some(entries) = json["keys"].as_object() {
+            for (name, entry) in entries {
+                let raw = decrypt(
+                    &key,
+                    &hex_field(entry, "nonce")?,
+                    &hex_field(entry, "ciphertext")?,
+                )?;
+                let sk = match SecretKey::from_slice(&raw) {
+                    Some(sk) => sk,
+                    None => bail!("bad secret key for {}", name),
+                };
+                keys.insert(name.clone(), (public_key_of(&sk), sk));
+            }
+        }
+        Ok(Self {
+            path,
+            salt,
+            key,
+            keys,
+        })
+    }
+
+    /// Generate a new key with the name. Returns its public key
+    pub fn generate(&mut self, name: &str) -> Result<PublicKey, anyhow::Error> {
+        let (pk, sk) = gen_keypair();
+        self.import(name, sk)?;
+        Ok(pk)
+    }
+
+    /// Add an existing secret key with the name
+    pub fn import(&mut self, name: &str, sk: SecretKey) -> Result<(), anyhow::Error> {
+        ensure!(!name.is_empty(), "missing the key name");
+        ensure!(!self.keys.contains_key(name), "key {} already exists", name);
+        self.keys.insert(name.into(), (public_key_of(&sk), sk));
+        self.save()
+    }
+
+    /// Remove the key with the name
+    pub fn remove(&mut self, name: &str) -> Result<(), anyhow::Error> {
+        ensure!(self.keys.remove(name).is_some(), "no key named {}", name);
+        self.save()
+    }
+
+    /// The names of the keys, sorted
+    pub fn names(&self) -> Vec<String> {
+        self.keys.keys().cloned().collect()
+    }
+
+    /// The public key of the name
+    pub fn public_key(&self, name: &str) -> Option<PublicKey> {
+        self.keys.get(name).map(|(pk, _)| *pk)
+    }
+
+    /// Sign the tx with the key of the name. The tx must have a chain id
+    pub fn sign_with(&self, name: &str, tx: &mut SignedTransaction) -> Result<(), anyhow::Error> {
+        match self.keys.get(name) {
+            Some((_, sk)) => tx.sign(sk),
+            None => bail!("no key named {}", name),
+        }
+    }
+
+    // Write the keystore to its file, readable only by the owner
+    fn save(&self) -> Result<(), anyhow::Error> {
+        let (nonce, ciphertext) = encrypt(&self.key, CHECK_TEXT)?;
+        let mut keys = serde_json::Map::new();
+        for (name, (pk, sk)) in self.keys.iter() {
+            let (nonce, ciphertext) = encrypt(&self.key, sk.as_ref())?;
+            keys.insert(
+                name.clone(),
+                json!({
+                    "public_key": hex::encode(pk.as_ref()),
+                    "nonce": hex::encode(nonce),
+                    "ciphertext": hex::encode(ciphertext),
+                }),
+            );
+        }
+        let json = json!({
+            "version": KEYSTORE_VERSION,
+            "salt": hex::encode(&self.salt),
+            "check": {
+                "nonce": hex::encode(nonce),
+                "ciphertext": hex::encode(ciphertext),
+            },
+            "keys": keys,
+        });
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&self.path)?;
+        file.write_all(serde_json::to_string_pretty(&json)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{verify_tx_signature, TxBuilder};
+
+    #[test]
+    fn test_keystore() {
+        let path =
+            std::env::temp_dir().join(format!("rapido-keystore-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut keystore = Keystore::create(&path, "secret").unwrap();
+        assert!(Keystore::create(&path, "secret").is_err());
+        let pk = keystore.generate("alice").unwrap();
+        assert!(keystore.generate("alice").is_err());
+        keystore.generate("bob").unwrap();
+        keystore.remove("bob").unwrap();
+
+        assert!(Keystore::open(&path, "wrong").is_err());
+        let keystore = Keystore::open(&path, "secret").unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(vec!["alice".to_string()], keystore.names());
+        assert_eq!(Some(pk), keystore.public_key("alice"));
+
+        let mut tx = TxBuilder::new("counter")
+            .sender("alice")
+            .msg(1u8)
+            .build()
+            .unwrap();
+        assert!(keystore.sign_with("alice", &mut tx).is_err());
+        let mut tx = TxBuilder::new("counter")
+            .sender("alice")
+            .msg(1u8)
+            .chain_id("test-chain")
+            .build()
+            .unwrap();
+        keystore.sign_with("alice", &mut tx).unwrap();
+        assert!(verify_tx_signature(&tx, &pk));
+        assert!(keystore.sign_with("bob", &mut tx).is_err());
+    }
+}
@@ -1,71 +1,667 @@
 //! Simple RPC helper functions to interact with a Tendermint node.
 //! ideal for command line applications.
 //! Currently supports sending transactions and querying the app.
+//! `RapidoClient` wraps these with typed helpers:
+//! ```ignore
+//! let client = RapidoClient::new("tcp://127.0.0.1:26657")?.chain_id("my-chain");
+//! let resp = client.send(APP_NAME, "dave", Msgs::Add(1), nonce, &secret_key).await?;
+//! let counter: Counter = client.query_as(APP_NAME, "/", b"dave".to_vec()).await?;
+//! ```
+//! Calls return a `ClientError`.  See `blocking` for a client without async.
+use std::future::Future;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use anyhow::ensure;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::de::DeserializeOwned;
 
-use tendermint::abci::Transaction;
+use exonum_crypto::SecretKey;
+use tendermint::abci::{transaction, Transaction};
 use tendermint_rpc::{endpoint::broadcast, Client, HttpClient};
 
-use rapido_core::SignedTransaction;
+use rapido_core::{AccountId, QueryEncoding, SignedTransaction, TxBuilder};
 
-fn parse_tx_commit_response(resp: broadcast::tx_commit::Response) -> Result<String, anyhow::Error> {
-    ensure!(
-        resp.check_tx.code.is_ok(),
-        "check err: {:}",
-        resp.check_tx.log
-    );
-    ensure!(
-        resp.deliver_tx.code.is_ok(),
-        "deliver err: {:}",
-        resp.check_tx.log
-    );
+pub mod blocking;
+mod chain;
+mod config;
+mod error;
+mod estimate;
+mod events;
+mod failover;
+#[cfg(feature = "gateway")]
+mod gateway;
+pub mod keys;
+mod keystore;
+mod uri;
+mod verify;
 
+pub use chain::{
+    block, chain_info, search_txs, tx, ChainInfo, CommittedBlock, CommittedTx, TxPage, TXS_PER_PAGE,
+};
+pub use config::ClientConfig;
+pub use error::ClientError;
+pub use estimate::{estimate, Estimate, DEFAULT_GAS_MULTIPLIER};
+pub use events::{subscribe_events, TxEvent};
+pub use failover::FailoverClient;
+#[cfg(feature = "gateway")]
+pub use gateway::serve_gateway;
+pub use keystore::Keystore;
+pub use uri::{broadcast_hex, broadcast_path};
+pub use verify::{query_with_proof, verify_query, ProvenQuery};
+
+// Return the error of a failed check or deliver of a committed tx
+fn commit_error(resp: &broadcast::tx_commit::Response) -> Option<ClientError> {
+    if resp.check_tx.code.is_err() {
+        return Some(ClientError::CheckFailed {
+            code: resp.check_tx.code.value(),
+            log: resp.check_tx.log.to_string(),
+        });
+    }
+    if resp.deliver_tx.code.is_err() {
+        return Some(ClientError::DeliverFailed {
+            code: resp.deliver_tx.code.value(),
+            log: resp.deliver_tx.log.to_string(),
+        });
+    }
+    None
+}
+
+fn parse_tx_commit_response(resp: broadcast::tx_commit::Response) -> Result<String, ClientError> {
+    if let Some(err) = commit_error(&resp) {
+        return Err(err);
+    }
     Ok(format!("success!  tx hash: {:}", resp.hash.to_string()))
 }
 
-fn parse_tx_sync_response(resp: broadcast::tx_sync::Response) -> Result<String, anyhow::Error> {
-    ensure!(resp.code.is_ok(), resp.log);
+fn parse_tx_sync_response(resp: broadcast::tx_sync::Response) -> Result<String, ClientError> {
+    if resp.code.is_err() {
+        return Err(ClientError::CheckFailed {
+            code: resp.code.value(),
+            log: resp.log.to_string(),
+        });
+    }
     Ok(format!("success!  tx hash: {:}", resp.hash.to_string()))
 }
 
+async fn broadcast_commit(
+    tx: &SignedTransaction,
+    config: &ClientConfig,
+    client: &HttpClient,
+) -> Result<broadcast::tx_commit::Response, ClientError> {
+    let encoded = tx.encode();
+    config
+        .call(|| client.broadcast_tx_commit(Transaction::from(encoded.clone())))
+        .await
+}
+
 /// Send a tx and wait for its inclusion in a block.  Returns the
-/// results of both the check and deliver.
+/// results of both the check and deliver. Uses the default `ClientConfig`
 pub async fn send_transaction_commit(
     tx: &SignedTransaction,
     client: &HttpClient,
-) -> Result<String, anyhow::Error> {
-    let resp = client
-        .broadcast_tx_commit(Transaction::from(tx.encode()))
-        .await?;
-    parse_tx_commit_response(resp)
+) -> Result<String, ClientError> {
+    parse_tx_commit_response(broadcast_commit(tx, &ClientConfig::default(), client).await?)
 }
 
 /// Send a transaction and only return the results of the check.
+/// Uses the default `ClientConfig`
 pub async fn send_transaction_sync(
     tx: &SignedTransaction,
     client: &HttpClient,
-) -> Result<String, anyhow::Error> {
-    let resp = client
-        .broadcast_tx_sync(Transaction::from(tx.encode()))
+) -> Result<String, ClientError> {
+    let encoded = tx.encode();
+    let resp = ClientConfig::default()
+        .call(|| client.broadcast_tx_sync(Transaction::from(encoded.clone())))
         .await?;
     parse_tx_sync_response(resp)
 }
 
+/// Send a transaction without waiting for the check.  Returns the tx hash.
+/// Uses the default `ClientConfig`
+pub async fn send_transaction_async(
+    tx: &SignedTransaction,
+    client: &HttpClient,
+) -> Result<String, ClientError> {
+    let resp = broadcast(tx, BroadcastMode::Async, client).await?;
+    Ok(format!("success!  tx hash: {:}", resp.hash))
+}
+
+/// How to wait for a tx sent with `broadcast`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BroadcastMode {
+    /// Return right away, without the result of the check
+    Async,
+    /// Wait for the check
+    Sync,
+    /// Wait for the tx to be committed in a block
+    Commit,
+}
+
+/// The result of a tx sent with `broadcast`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BroadcastResult {
+    pub hash: String,
+    /// The code of the check, or of the deliver if the check passed and
+    /// the mode is `Commit`. 0 if the tx succeeded
+    pub code: u32,
+    pub log: String,
+    /// Height of the block. Only set with `Commit`
+    pub height: Option<u64>,
+}
+
+impl BroadcastResult {
+    /// Did the tx succeed?
+    pub fn is_ok(&self) -> bool {
+        self.code == 0
+    }
+}
+
+/// Send a tx with the mode.  Unlike the `send_transaction_*` functions, a tx
+/// rejected by the app isn't an error: check the code of the result. Uses the
+/// default `ClientConfig`
+pub async fn broadcast(
+    tx: &SignedTransaction,
+    mode: BroadcastMode,
+    client: &HttpClient,
+) -> Result<BroadcastResult, ClientError> {
+    let config = ClientConfig::default();
+    let encoded = tx.encode();
+    match mode {
+        BroadcastMode::Async => {
+            let resp = config
+                .call(|| client.broadcast_tx_async(Transaction::from(encoded.clone())))
+                .await?;
+            Ok(BroadcastResult {
+                hash: resp.hash.to_string(),
+                code: resp.code.value(),
+                log: resp.log.to_string(),
+                height: None,
+            })
+        }
+        BroadcastMode::Sync => {
+            let resp = config
+                .call(|| client.broadcast_tx_sync(Transaction::from(encoded.clone())))
+                .await?;
+            Ok(BroadcastResult {
+                hash: resp.hash.to_string(),
+                code: resp.code.value(),
+                log: resp.log.to_string(),
+                height: None,
+            })
+        }
+        BroadcastMode::Commit => {
+            let resp = broadcast_commit(tx, &config, client).await?;
+            let result = if resp.check_tx.code.is_err() {
+                resp.check_tx
+            } else {
+                resp.deliver_tx
+            };
+            Ok(BroadcastResult {
+                hash: resp.hash.to_string(),
+                code: result.code.value(),
+                log: result.log.to_string(),
+                height: Some(resp.height.value()),
+            })
+        }
+    }
+}
+
+/// How often `wait_for_tx` checks for the tx
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The result of a committed tx. See `wait_for_tx`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxStatus {
+    pub hash: String,
+    pub height: u64,
+    /// 0 if the tx succeeded
+    pub code: u32,
+    pub log: String,
+    pub events: Vec<TxEvent>,
+}
+
+/// Wait for a tx sent with `send_transaction_sync` to be committed in a block,
+/// polling the node until it's found or the timeout passes.  The hash is hex, as
+/// returned by the send.  A tx that failed in the block is returned with its code.
+pub async fn wait_for_tx(
+    hash: &str,
+    timeout: Duration,
+    client: &HttpClient,
+) -> Result<TxStatus, ClientError> {
+    let tx_hash = match transaction::Hash::from_str(hash) {
+        Ok(tx_hash) => tx_hash,
+        Err(_) => return Err(ClientError::InvalidInput(format!("bad tx hash: {}", hash))),
+    };
+    let start = Instant::now();
+    loop {
+        match client.tx(tx_hash, false).await {
+            Ok(resp) => {
+                let height = resp.height.value();
+                return Ok(TxStatus {
+                    hash: hash.into(),
+                    height,
+                    code: resp.tx_result.code.value(),
+                    log: resp.tx_result.log.to_string(),
+                    events: resp
+                        .tx_result
+                        .events
+                        .into_iter()
+                        .map(|e| events::tx_event(height as i64, e))
+                        .collect(),
+                });
+            }
+            Err(err) => {
+                if start.elapsed() >= timeout {
+                    return Err(ClientError::Transport(anyhow!(
+                        "timed out waiting for tx {}: {}",
+                        hash,
+                        err
+                    )));
+                }
+                tokio::time::delay_for(WAIT_POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Send a batch of transactions from the same sender. Nonces are assigned
+/// automatically, starting at `start_nonce` and incrementing for each tx, and
+/// a copy of each tx is signed with the given key.  If `wait` is true, each tx is sent
+/// via `broadcast_tx_commit` and waits for inclusion in a block, otherwise only
+/// the results of the check are returned. Returns a result for each tx sent, in order.
+/// Stops at the first failure, as the nonces of the txs after it would be wrong:
+/// the last result is the error.
+pub async fn send_transactions(
+    txs: &[SignedTransaction],
+    start_nonce: u64,
+    private_key: &SecretKey,
+    wait: bool,
+    client: &HttpClient,
+) -> Vec<Result<String, ClientError>> {
+    let txs = match sign_batch(txs, start_nonce, private_key) {
+        Ok(txs) => txs,
+        Err(err) => return vec![Err(err)],
+    };
+    send_until_failure(txs, |tx| async move {
+        if wait {
+            send_transaction_commit(&tx, client).await
+        } else {
+            send_transaction_sync(&tx, client).await
+        }
+    })
+    .await
+}
+
+// Copy the txs with nonces from `start_nonce`, signed with the key
+fn sign_batch(
+    txs: &[SignedTransaction],
+    start_nonce: u64,
+    private_key: &SecretKey,
+) -> Result<Vec<SignedTransaction>, ClientError> {
+    txs.iter()
+        .enumerate()
+        .map(|(i, tx)| {
+            let mut tx = tx.clone();
+            tx.set_nonce(start_nonce + i as u64);
+            tx.sign(private_key)
+                .map_err(|err| ClientError::InvalidInput(err.to_string()))?;
+            Ok(tx)
+        })
+        .collect()
+}
+
+// Send each tx in order until one fails
+async fn send_until_failure<F, Fut>(
+    txs: Vec<SignedTransaction>,
+    mut send: F,
+) -> Vec<Result<String, ClientError>>
+where
+    F: FnMut(SignedTransaction) -> Fut,
+    Fut: Future<Output = Result<String, ClientError>>,
+{
+    let mut results = Vec::with_capacity(txs.len());
+    for tx in txs {
+        let result = send(tx).await;
+        let failed = result.is_err();
+        results.push(result);
+        if failed {
+            break;
+        }
+    }
+    results
+}
+
 /// Query a particular application (by its registered name). Returns the
 /// result as a Vec<u8>.  It's up to the consuming application to determine
-/// how to code the value.
+/// how to code the value. See `query_as` and `query_json` to decode it.
+/// Uses the default `ClientConfig`
 pub async fn query(
     app_path: &str,
     key: Vec<u8>,
     client: &HttpClient,
-) -> Result<Vec<u8>, anyhow::Error> {
-    let p = tendermint::abci::Path::from_str(app_path);
-    ensure!(p.is_ok(), "problem parsing app name (path)");
-    let resp = client
-        .abci_query(Some(p.unwrap()), key, None, false)
+) -> Result<Vec<u8>, ClientError> {
+    Ok(
+        query_encoded(app_path, key, &ClientConfig::default(), client)
+            .await?
+            .1,
+    )
+}
+
+// Query and return the value with its encoding
+async fn query_encoded(
+    app_path: &str,
+    key: Vec<u8>,
+    config: &ClientConfig,
+    client: &HttpClient,
+) -> Result<(QueryEncoding, Vec<u8>), ClientError> {
+    let path = match tendermint::abci::Path::from_str(app_path) {
+        Ok(path) => path,
+        Err(_) => {
+            return Err(ClientError::InvalidInput(
+                "problem parsing app name (path)".into(),
+            ))
+        }
+    };
+    let resp = config
+        .call(|| client.abci_query(Some(path.clone()), key.clone(), None, false))
         .await?;
-    ensure!(resp.code.is_ok(), "query err: {:}", resp.log);
-    Ok(resp.value)
+    if resp.code.is_err() {
+        return Err(ClientError::QueryFailed {
+            code: resp.code.value(),
+            log: resp.log.to_string(),
+        });
+    }
+    Ok((QueryEncoding::from_info(&resp.info), resp.value))
+}
+
+fn decode_borsh<T: BorshDeserialize>(
+    (encoding, value): (QueryEncoding, Vec<u8>),
+) -> Result<T, ClientError> {
+    if encoding == QueryEncoding::Json {
+        return Err(ClientError::DecodeFailure(anyhow!(
+            "expected a borsh value, got {}",
+            encoding
+        )));
+    }
+    T::try_from_slice(&value).map_err(|err| ClientError::DecodeFailure(err.into()))
+}
+
+fn decode_json<T: DeserializeOwned>(
+    (encoding, value): (QueryEncoding, Vec<u8>),
+) -> Result<T, ClientError> {
+    if encoding != QueryEncoding::Json {
+        return Err(ClientError::DecodeFailure(anyhow!(
+            "expected a json value, got {}",
+            encoding
+        )));
+    }
+    serde_json::from_slice(&value).map_err(|err| ClientError::DecodeFailure(err.into()))
+}
+
+/// Query an application and decode the Borsh encoded value.
+/// Uses the default `ClientConfig`
+pub async fn query_as<T: BorshDeserialize>(
+    app_path: &str,
+    key: Vec<u8>,
+    client: &HttpClient,
+) -> Result<T, ClientError> {
+    decode_borsh(query_encoded(app_path, key, &ClientConfig::default(), client).await?)
+}
+
+/// Query an application and decode the JSON encoded value.
+/// Uses the default `ClientConfig`
+pub async fn query_json<T: DeserializeOwned>(
+    app_path: &str,
+    key: Vec<u8>,
+    client: &HttpClient,
+) -> Result<T, ClientError> {
+    decode_json(query_encoded(app_path, key, &ClientConfig::default(), client).await?)
+}
+
+/// Return the next nonce of the account on the lane, as tracked by the
+/// `NonceStore` (used by the account module). Uses the default `ClientConfig`
+pub async fn account_nonce(
+    account: &[u8],
+    lane: u8,
+    client: &HttpClient,
+) -> Result<u64, ClientError> {
+    query_as(&format!("rapido/nonce/{}", lane), account.to_vec(), client).await
+}
+
+/// Set the nonce of a `TxBuilder` from the node
+#[async_trait]
+pub trait AutoNonce: Sized {
+    /// Set the nonce to the sender's next nonce on the tx's lane. Call it
+    /// after setting the sender and lane:
+    /// ```ignore
+    /// let tx = TxBuilder::new(APP_NAME)
+    ///     .sender(account_id)
+    ///     .msg(Msgs::Create)
+    ///     .auto_nonce(&client)
+    ///     .await?
+    ///     .sign(&secret_key)?;
+    /// ```
+    async fn auto_nonce(self, client: &HttpClient) -> Result<Self, anyhow::Error>;
+}
+
+#[async_trait]
+impl AutoNonce for TxBuilder {
+    async fn auto_nonce(self, client: &HttpClient) -> Result<Self, anyhow::Error> {
+        let tx = self.clone().build()?;
+        let nonce = account_nonce(&tx.sender(), tx.lane(), client).await?;
+        Ok(self.nonce(nonce))
+    }
+}
+
+/// The result of a transaction committed in a block
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxResponse {
+    /// The tx hash (hex)
+    pub hash: String,
+    /// Height of the block the tx was committed in
+    pub height: u64,
+    pub log: String,
+    pub gas_used: u64,
+}
+
+impl TxResponse {
+    fn from_commit(resp: broadcast::tx_commit::Response) -> Result<Self, ClientError> {
+        if let Some(err) = commit_error(&resp) {
+            return Err(err);
+        }
+        Ok(Self {
+            hash: resp.hash.to_string(),
+            height: resp.height.value(),
+            log: resp.deliver_tx.log.to_string(),
+            gas_used: resp.deliver_tx.gas_used.value(),
+        })
+    }
+}
+
+// Join an app name and a query path into the app path of a query
+fn app_path(app: &str, path: &str) -> String {
+    match path.trim_start_matches('/') {
+        "" => app.into(),
+        rest => format!("{}/{}", app, rest),
+    }
+}
+
+/// Typed client for a rapido node.  Builds, signs and sends txs from messages
+/// and decodes query values, so applications don't deal with the encoding.
+pub struct RapidoClient {
+    client: HttpClient,
+    chain_id: String,
+    config: ClientConfig,
+}
+
+impl RapidoClient {
+    /// Connect to the node's RPC address, e.g. 'tcp://127.0.0.1:26657'
+    pub fn new(address: &str) -> Result<Self, ClientError> {
+        let address = match address.parse() {
+            Ok(address) => address,
+            Err(_) => {
+                return Err(ClientError::InvalidInput(format!(
+                    "bad address: {}",
+                    address
+                )))
+            }
+        };
+        match HttpClient::new(address) {
+            Ok(client) => Ok(Self::from_client(client)),
+            Err(err) => Err(ClientError::Transport(err.into())),
+        }
+    }
+
+    /// Use an existing tendermint client
+    pub fn from_client(client: HttpClient) -> Self {
+        Self {
+            client,
+            chain_id: String::new(),
+            config: ClientConfig::default(),
+        }
+    }
+
+    /// Set the timeouts and retries of the calls to the node
+    pub fn config(mut self, config: ClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the chain id of the txs sent. Required to send txs
+    pub fn chain_id<C: Into<String>>(mut self, chain_id: C) -> Self {
+        self.chain_id = chain_id.into();
+        self
+    }
+
+    /// The underlying tendermint client
+    pub fn inner(&self) -> &HttpClient {
+        &self.client
+    }
+
+    /// Build a tx with the message, sign it, and send it to the app. Waits for
+    /// the tx to be committed in a block.
+    pub async fn send<M: BorshSerialize>(
+        &self,
+        app: &str,
+        sender: impl Into<AccountId>,
+        msg: M,
+        nonce: u64,
+        private_key: &SecretKey,
+    ) -> Result<TxResponse, ClientError> {
+        let tx = TxBuilder::new(app)
+            .sender(sender)
+            .msg(msg)
+            .nonce(nonce)
+            .chain_id(self.chain_id.clone())
+            .sign(private_key)
+            .map_err(|err| ClientError::InvalidInput(err.to_string()))?;
+        self.send_tx(&tx).await
+    }
+
+    /// Send a tx and wait for it to be committed in a block
+    pub async fn send_tx(&self, tx: &SignedTransaction) -> Result<TxResponse, ClientError> {
+        TxResponse::from_commit(broadcast_commit(tx, &self.config, &self.client).await?)
+    }
+
+    /// Simulate the tx on the node to estimate its gas. See `estimate`
+    pub async fn estimate(&self, tx: &SignedTransaction) -> Result<Estimate, ClientError> {
+        estimate::simulate(tx, &self.config, &self.client).await
+    }
+
+    /// Query the path of an app and decode the Borsh encoded value.
+    /// For example: `client.query_as::<u64>("rapido.bank", "/", b"bob".to_vec())`
+    pub async fn query_as<T: BorshDeserialize>(
+        &self,
+        app: &str,
+        path: &str,
+        key: Vec<u8>,
+    ) -> Result<T, ClientError> {
+        let path = app_path(app, path);
+        decode_borsh(query_encoded(&path, key, &self.config, &self.client).await?)
+    }
+
+    /// Query the path of an app and decode the JSON encoded value
+    pub async fn query_json<T: DeserializeOwned>(
+        &self,
+        app: &str,
+        path: &str,
+        key: Vec<u8>,
+    ) -> Result<T, ClientError> {
+        let path = app_path(app, path);
+        decode_json(query_encoded(&path, key, &self.config, &self.client).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_app_path() {
+        assert_eq!("rapido.bank", app_path("rapido.bank", ""));
+        assert_eq!("rapido.bank", app_path("rapido.bank", "/"));
+        assert_eq!(
+            "rapido.bank/allowance/bob/alice",
+            app_path("rapido.bank", "/allowance/bob/alice")
+        );
+        assert_eq!("counter/count", app_path("counter", "count"));
+    }
+
+    #[test]
+    fn test_send_transactions() {
+        let (pk, sk) = exonum_crypto::gen_keypair();
+        let txs: Vec<SignedTransaction> = (0..3)
+            .map(|i| {
+                TxBuilder::new("counter")
+                    .sender("bob")
+                    .msg(i as u8)
+                    .chain_id("test-chain")
+                    .build()
+                    .unwrap()
+            })
+            .collect();
+
+        let signed = sign_batch(&txs, 5, &sk).unwrap();
+        assert_eq!(
+            vec![5, 6, 7],
+            signed.iter().map(|tx| tx.nonce()).collect::<Vec<_>>()
+        );
+        assert!(signed
+            .iter()
+            .all(|tx| rapido_core::verify_tx_signature(tx, &pk)));
+        // the txs passed in aren't changed
+        assert!(txs.iter().all(|tx| tx.nonce() == 0));
+
+        // txs without a chain id can't be signed
+        let unset = TxBuilder::new("counter")
+            .sender("bob")
+            .msg(0u8)
+            .build()
+            .unwrap();
+        match sign_batch(&[unset], 0, &sk) {
+            Err(ClientError::InvalidInput(msg)) => assert!(msg.contains("chain id")),
+            _ => panic!("expected a missing chain id error"),
+        }
+
+        // stops at the first failure
+        let mut sent = Vec::new();
+        let results = futures::executor::block_on(send_until_failure(signed, |tx| {
+            sent.push(tx.nonce());
+            let result = if tx.nonce() == 6 {
+                Err(ClientError::CheckFailed {
+                    code: 1,
+                    log: "bad nonce".into(),
+                })
+            } else {
+                Ok(tx.id_hex())
+            };
+            async move { result }
+        }));
+        assert_eq!(vec![5, 6], sent);
+        assert_eq!(2, results.len());
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
 }
@@ -0,0 +1,175 @@
+//! Broadcast txs over Tendermint's URI (GET) interface, with the tx as a 0x hex
+//! payload: `http://127.0.0.1:26657/broadcast_tx_commit?tx=0x...`.  Useful to send
+//! a tx encoded with `SignedTransaction::to_hex` from another tool:
+//! ```ignore
+//! let result = broadcast_hex(&encoded, BroadcastMode::Sync, "tcp://127.0.0.1:26657").await?;
+//! ```
+use std::io;
+
+use anyhow::anyhow;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use rapido_core::SignedTransaction;
+
+use crate::{BroadcastMode, BroadcastResult, ClientConfig, ClientError};
+
+// The host:port of a node's RPC address, e.g. 'tcp://127.0.0.1:26657'
+fn host_of(address: &str) -> Result<String, ClientError> {
+    let rest = address
+        .strip_prefix("tcp://")
+        .or_else(|| address.strip_prefix("http://"))
+        .unwrap_or(address);
+    let host = rest.trim_end_matches('/');
+    if host.is_empty() || host.contains('/') || !host.contains(':') {
+        return Err(ClientError::InvalidInput(format!(
+            "expected a host:port address: {}",
+            address
+        )));
+    }
+    Ok(host.into())
+}
+
+/// Return the GET path to broadcast the tx with the mode
+pub fn broadcast_path(tx: &SignedTransaction, mode: BroadcastMode) -> String {
+    let endpoint = match mode {
+        BroadcastMode::Async => "broadcast_tx_async",
+        BroadcastMode::Sync => "broadcast_tx_sync",
+        BroadcastMode::Commit => "broadcast_tx_commit",
+    };
+    format!("/{}?tx={}", endpoint, tx.to_hex())
+}
+
+// Code and log of a check or deliver result
+fn result_of(value: &Value) -> (u32, String) {
+    (
+        value["code"].as_u64().unwrap_or_default() as u32,
+        value["log"].as_str().unwrap_or_default().into(),
+    )
+}
+
+// Parse the JSON-RPC response of a broadcast
+fn parse_response(mode: BroadcastMode, body: &[u8]) -> Result<BroadcastResult, ClientError> {
+    let json: Value = match serde_json::from_slice(body) {
+        Ok(json) => json,
+        Err(err) => return Err(ClientError::DecodeFailure(err.into())),
+    };
+    if !json["error"].is_null() {
+        return Err(ClientError::Transport(anyhow!(
+            "{} {}",
+            json["error"]["message"].as_str().unwrap_or_default(),
+            json["error"]["data"].as_str().unwrap_or_default()
+        )));
+    }
+    let result = &json["result"];
+    let hash = match result["hash"].as_str() {
+        Some(hash) => hash.to_string(),
+        None => return Err(ClientError::DecodeFailure(anyhow!("missing the tx hash"))),
+    };
+    let (code, log, height) = match mode {
+        BroadcastMode::Commit => {
+            let (code, log) = match result_of(&result["check_tx"]) {
+                (0, _) => result_of(&result["deliver_tx"]),
+                failed => failed,
+            };
+            // Heights are encoded as strings
+            let height = result["height"].as_str().and_then(|h| h.parse().ok());
+            (code, log, height)
+        }
+        _ => {
+            let (code, log) = result_of(result);
+            (code, log, None)
+        }
+    };
+    Ok(BroadcastResult {
+        hash,
+        code,
+        log,
+        height,
+    })
+}
+
+// GET the path and return the body.  Uses HTTP/1.0 so the body isn't chunked
+async fn http_get(host: &str, path: &str) -> Result<Vec<u8>, io::Error> {
+    let mut stream = TcpStream::connect(host).await?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    match response.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(i) => Ok(response[i + 4..].to_vec()),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad http response",
+        )),
+    }
+}
+
+/// Broadcast a tx encoded with `SignedTransaction::to_hex` over the node's GET
+/// interface.  As `broadcast`, a tx rejected by the app isn't an error: check
+/// the code of the result.  Uses the default `ClientConfig`
+pub async fn broadcast_hex(
+    encoded: &str,
+    mode: BroadcastMode,
+    address: &str,
+) -> Result<BroadcastResult, ClientError> {
+    let tx = match SignedTransaction::from_hex(encoded) {
+        Ok(tx) => tx,
+        Err(err) => return Err(ClientError::InvalidInput(err.to_string())),
+    };
+    let host = host_of(address)?;
+    let path = broadcast_path(&tx, mode);
+    let body = ClientConfig::default()
+        .call(|| http_get(&host, &path))
+        .await?;
+    parse_response(mode, &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::TxBuilder;
+
+    #[test]
+    fn test_broadcast_hex() {
+        assert_eq!("127.0.0.1:26657", host_of("tcp://127.0.0.1:26657").unwrap());
+        assert_eq!(
+            "localhost:26657",
+            host_of("http://localhost:26657/").unwrap()
+        );
+        assert!(host_of("tcp://127.0.0.1").is_err());
+
+        let tx = TxBuilder::new("counter")
+            .sender("bob")
+            .msg(1u8)
+            .build()
+            .unwrap();
+        let path = broadcast_path(&tx, BroadcastMode::Sync);
+        assert_eq!(format!("/broadcast_tx_sync?tx={}", tx.to_hex()), path);
+        // The node decodes the same tx
+        let encoded = path.split("tx=").nth(1).unwrap();
+        assert_eq!(
+            tx.encode(),
+            SignedTransaction::from_hex(encoded).unwrap().encode()
+        );
+
+        let sync =
+            br#"{"jsonrpc":"2.0","id":-1,"result":{"code":1,"log":"bad nonce","hash":"AB"}}"#;
+        let result = parse_response(BroadcastMode::Sync, sync).unwrap();
+        assert_eq!(
+            ("AB", 1, "bad nonce"),
+            (&*result.hash, result.code, &*result.log)
+        );
+
+        let commit = br#"{"result":{"check_tx":{"code":0},"deliver_tx":{"code":2,"log":"failed"},"hash":"CD","height":"7"}}"#;
+        let result = parse_response(BroadcastMode::Commit, commit).unwrap();
+        assert_eq!((2, Some(7)), (result.code, result.height));
+
+        let error = br#"{"error":{"code":-32603,"message":"Internal error","data":"tx already exists in cache"}}"#;
+        assert!(parse_response(BroadcastMode::Sync, error).is_err());
+    }
+}
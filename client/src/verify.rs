@@ -0,0 +1,109 @@
+//! Verify query results from an untrusted node against a trusted apphash.
+//! A query to a path returning a proof (see `Store::query_proof`) returns the
+//! proof of the value in the store and of the store in the app state.  The
+//! apphash of the state at height `h` is in the header of block `h + 1`.
+//! ```ignore
+//! let resp = query_with_proof("rapido.anchor/proof", hash.to_vec(), &client).await?;
+//! let anchor: Option<Anchor> = BorshDeserialize::try_from_slice(&resp.value)?;
+//! let stored = anchor.map(|a| a.try_to_vec()).transpose()?;
+//! verify_query(ANCHOR_STORE_NAME, hash.to_vec(), stored.as_deref(), &resp.proof, &trusted_apphash)?;
+//! ```
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use exonum_crypto::Hash;
+use tendermint::merkle::proof::Proof;
+use tendermint_rpc::{Client, HttpClient};
+
+use crate::ClientError;
+use rapido_core::{verify_query_proof, PROOF_OP_TYPE, STATE_PROOF_OP_TYPE};
+
+/// A query value with its proof
+#[derive(Debug, Clone)]
+pub struct ProvenQuery {
+    pub value: Vec<u8>,
+    pub proof: Proof,
+    /// Height of the state queried
+    pub height: u64,
+}
+
+/// Query an application and return the value with its proof.  Returns an error if
+/// the query path doesn't return a proof.
+pub async fn query_with_proof(
+    app_path: &str,
+    key: Vec<u8>,
+    client: &HttpClient,
+) -> Result<ProvenQuery, ClientError> {
+    let path = match tendermint::abci::Path::from_str(app_path) {
+        Ok(path) => path,
+        Err(_) => {
+            return Err(ClientError::InvalidInput(
+                "problem parsing app name (path)".into(),
+            ))
+        }
+    };
+    let resp = match client.abci_query(Some(path), key, None, true).await {
+        Ok(resp) => resp,
+        Err(err) => return Err(ClientError::Transport(err.into())),
+    };
+    if resp.code.is_err() {
+        return Err(ClientError::QueryFailed {
+            code: resp.code.value(),
+            log: resp.log.to_string(),
+        });
+    }
+    let proof = match resp.proof {
+        Some(proof) => proof,
+        None => {
+            return Err(ClientError::DecodeFailure(anyhow!(
+                "no proof returned for {}",
+                app_path
+            )))
+        }
+    };
+    Ok(ProvenQuery {
+        value: resp.value,
+        proof,
+        height: resp.height.value(),
+    })
+}
+
+// Return the data of the proof op of the type
+fn proof_op<'a>(proof: &'a Proof, op_type: &str) -> Result<&'a [u8], anyhow::Error> {
+    match proof.ops.iter().find(|op| op.field_type == op_type) {
+        Some(op) => Ok(&op.data),
+        None => bail!("missing proof op {}", op_type),
+    }
+}
+
+/// Verify the proof of a query against a trusted apphash. The proof must be of
+/// `key` in the store named `store_name`.  `value` is the value as stored, usually
+/// Borsh encoded, or `None` to verify its absence.
+pub fn verify_query<K>(
+    store_name: &str,
+    key: K,
+    value: Option<&[u8]>,
+    proof: &Proof,
+    apphash: &[u8],
+) -> Result<(), anyhow::Error>
+where
+    K: BorshSerialize + BorshDeserialize,
+{
+    let apphash = match Hash::from_slice(apphash) {
+        Some(hash) => hash,
+        None => bail!("bad apphash length: {}", apphash.len()),
+    };
+    let proven = verify_query_proof(
+        store_name,
+        key,
+        proof_op(proof, PROOF_OP_TYPE)?,
+        proof_op(proof, STATE_PROOF_OP_TYPE)?,
+        &apphash,
+    )?;
+    ensure!(
+        proven.as_deref() == value,
+        "value doesn't match the proven value"
+    );
+    Ok(())
+}
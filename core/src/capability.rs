@@ -0,0 +1,100 @@
+//! Capabilities mediate what an AppModule can ask another AppModule to do via
+//! `Context::dispatch`.  A capability is named `app::name`, e.g. `bank::mint`, and
+//! is granted to an AppModule when wiring the app:
+//! ```ignore
+//! AppBuilder::new()
+//!     .with_app(BankModule::new())
+//!     .with_app(GovModule::new())
+//!     .grant("gov", "bank::mint")
+//! ```
+//! The bank then guards the privileged message with:
+//! ```ignore
+//! ctx.require_capability("mint")?;
+//! ```
+//! which fails unless the message was dispatched by an AppModule holding `bank::mint`.
+use std::collections::HashSet;
+
+use anyhow::{bail, ensure};
+
+/// Separates the app and name of a capability
+pub const CAPABILITY_SEPARATOR: &str = "::";
+
+// Granted capabilities as (grantee, capability)
+#[derive(Debug, Default)]
+pub(crate) struct Capabilities {
+    grants: HashSet<(String, String)>,
+}
+
+impl Capabilities {
+    pub(crate) fn grant(&mut self, grantee: &str, capability: &str) {
+        self.grants.insert((grantee.into(), capability.into()));
+    }
+
+    // Does the grantee hold the capability of the app?
+    pub(crate) fn has(&self, grantee: &str, app: &str, name: &str) -> bool {
+        let capability = format!("{}{}{}", app, CAPABILITY_SEPARATOR, name);
+        self.grants.contains(&(grantee.into(), capability))
+    }
+
+    // Check each grant refers to registered AppModules
+    pub(crate) fn validate<F: Fn(&str) -> bool>(
+        &self,
+        is_registered: F,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        for (grantee, capability) in &self.grants {
+            ensure!(
+                is_registered(grantee),
+                "capability {} granted to unknown AppModule {}",
+                capability,
+                grantee
+            );
+            let (app, _) = split_capability(capability)?;
+            ensure!(
+                is_registered(app),
+                "capability {} is for unknown AppModule {}",
+                capability,
+                app
+            );
+        }
+        Ok(())
+    }
+}
+
+// Split 'app::name'
+pub(crate) fn split_capability(capability: &str) -> anyhow::Result<(&str, &str), anyhow::Error> {
+    match capability.rfind(CAPABILITY_SEPARATOR) {
+        Some(i) if i > 0 && i + CAPABILITY_SEPARATOR.len() < capability.len() => Ok((
+            &capability[..i],
+            &capability[i + CAPABILITY_SEPARATOR.len()..],
+        )),
+        _ => bail!(
+            "malformed capability '{}', expected 'app::name'",
+            capability
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let mut caps = Capabilities::default();
+        caps.grant("gov", "bank::mint");
+        assert!(caps.has("gov", "bank", "mint"));
+        assert!(!caps.has("gov", "bank", "burn"));
+        assert!(!caps.has("other", "bank", "mint"));
+
+        assert!(caps.validate(|n| n == "gov" || n == "bank").is_ok());
+        assert!(caps.validate(|n| n == "gov").is_err());
+
+        assert_eq!(
+            ("rapido.bank", "mint"),
+            split_capability("rapido.bank::mint").unwrap()
+        );
+        assert!(split_capability("bank").is_err());
+        assert!(split_capability("::mint").is_err());
+        assert!(split_capability("bank::").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Recurring jobs for AppModules.  A job sends a message to an AppModule every N
+//! blocks.  Jobs are persisted in the store and executed by the framework during
+//! end_block, after scheduled messages, in the order they were registered.
+//! The message is passed to the AppModule's `handle_tx` with an empty sender.
+//! Example, from `initialize` or `handle_tx`:
+//! ```ignore
+//! Cron::new().register("myapp", "rebalance", 100, Msgs::Rebalance, view)?;
+//! ```
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{Store, StoreValue, StoreView};
+
+const CRON_STORE_NAME: &str = "rapido.cron";
+const JOBS_KEY: &str = "jobs";
+
+/// A message sent to an AppModule every `every` blocks
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct CronJob {
+    /// The name of the AppModule to receive the message
+    pub app: String,
+    /// Name of the job, unique per AppModule
+    pub name: String,
+    /// Run at every height that's a multiple of this value
+    pub every: u64,
+    /// The encoded message
+    pub msg: Vec<u8>,
+}
+
+impl CronJob {
+    /// Is the job due at the given height?
+    pub fn is_due(&self, height: i64) -> bool {
+        height > 0 && height as u64 % self.every == 0
+    }
+}
+
+// All jobs in registration order
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq, Default)]
+pub(crate) struct CronJobs(Vec<CronJob>);
+
+struct CronStore;
+impl Store for CronStore {
+    type Key = String;
+    type Value = CronJobs;
+
+    fn name(&self) -> String {
+        CRON_STORE_NAME.into()
+    }
+}
+
+/// Register and remove recurring jobs. See the module docs.
+pub struct Cron;
+
+impl Cron {
+    pub fn new() -> Self {
+        Cron {}
+    }
+
+    /// Register a job to send `msg` to `app` every `every` blocks. Registering
+    /// a job with the same app and name replaces it, keeping its place in the order.
+    pub fn register<M: BorshSerialize>(
+        &self,
+        app: &str,
+        name: &str,
+        every: u64,
+        msg: M,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        ensure!(every > 0, "a cron job must run at least every block");
+
+        let job = CronJob {
+            app: app.into(),
+            name: name.into(),
+            every,
+            msg: msg.try_to_vec()?,
+        };
+        let mut jobs = self.load(view);
+        match jobs.0.iter().position(|j| j.app == app && j.name == name) {
+            Some(i) => jobs.0[i] = job,
+            None => jobs.0.push(job),
+        }
+        CronStore {}.put(JOBS_KEY.into(), jobs, view);
+        Ok(())
+    }
+
+    /// Remove a job
+    pub fn remove(
+        &self,
+        app: &str,
+        name: &str,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let mut jobs = self.load(view);
+        let index = match jobs.0.iter().position(|j| j.app == app && j.name == name) {
+            Some(i) => i,
+            None => bail!("cron job {} not found for {}", name, app),
+        };
+        jobs.0.remove(index);
+        CronStore {}.put(JOBS_KEY.into(), jobs, view);
+        Ok(())
+    }
+
+    /// Return all jobs in the order they run
+    pub fn jobs(&self, view: &StoreView) -> Vec<CronJob> {
+        self.load(view).0
+    }
+
+    // Return the jobs to run at the height. Called by the framework in end_block
+    pub(crate) fn due(&self, height: i64, view: &StoreView) -> Vec<CronJob> {
+        self.jobs(view)
+            .into_iter()
+            .filter(|j| j.is_due(height))
+            .collect()
+    }
+
+    fn load(&self, view: &StoreView) -> CronJobs {
+        CronStore {}.get(JOBS_KEY.into(), view).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cron_jobs() {
+        let db: Box<dyn exonum_merkledb::Database> = Box::new(exonum_merkledb::TemporaryDB::new());
+        let snap = db.snapshot();
+        let mut view = StoreView::wrap(&snap, Default::default());
+        let cron = Cron::new();
+
+        assert!(cron.register("app", "never", 0, 1u8, &mut view).is_err());
+        cron.register("app", "ten", 10, 1u8, &mut view).unwrap();
+        cron.register("other", "five", 5, 2u8, &mut view).unwrap();
+        // Replace keeps the order
+        cron.register("app", "ten", 10, 3u8, &mut view).unwrap();
+
+        let jobs = cron.jobs(&view);
+        assert_eq!(2, jobs.len());
+        assert_eq!(vec![3u8], jobs[0].msg);
+
+        assert!(cron.due(0, &view).is_empty());
+        assert_eq!(1, cron.due(5, &view).len());
+        let due = cron.due(20, &view);
+        assert_eq!(
+            vec!["ten", "five"],
+            due.iter().map(|j| j.name.as_str()).collect::<Vec<_>>()
+        );
+
+        assert!(cron.remove("app", "missing", &mut view).is_err());
+        cron.remove("app", "ten", &mut view).unwrap();
+        assert_eq!(1, cron.jobs(&view).len());
+    }
+}
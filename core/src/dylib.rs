@@ -0,0 +1,364 @@
+//! Load AppModules from shared libraries built separately from the node.
+//! Enabled with the `dylib` feature. Rust trait objects don't have a stable ABI,
+//! so the library and node talk through a versioned C ABI: the library exports
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn rapido_module() -> ModuleApi
+//! ```
+//! and calls back into the node with the `HostApi` passed to its handlers.
+//! Keys are scoped to the module.  Example:
+//! ```ignore
+//! AppBuilder::new().with_dylib("./libmod_bank.so")
+//! ```
+//! The node may call the handlers from any thread, and concurrently (e.g. a
+//! query while a block is delivered).  Since ABI version 2 a library must make
+//! its handlers thread safe: any state it keeps must be synchronized.  Callbacks
+//! return -1 for a `Bytes` with a null `ptr` and a non-zero `len`.
+use std::ffi::c_void;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, ensure};
+use libloading::{Library, Symbol};
+
+use crate::{AppModule, Context, QueryResponse, QueryView, Store, StoreView};
+
+/// Version of the C ABI. A library must return it in `ModuleApi::abi_version`.
+/// Version 2 requires the handlers to be thread safe
+pub const RAPIDO_DYLIB_ABI_VERSION: u32 = 2;
+/// Name of the symbol exported by the library
+pub const RAPIDO_DYLIB_ENTRY: &[u8] = b"rapido_module";
+
+const DYLIB_DATA_STORE_NAME: &str = "rapido.dylib.data";
+
+/// Borrowed bytes passed across the ABI
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Bytes {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl Bytes {
+    pub fn new(value: &[u8]) -> Self {
+        Self {
+            ptr: value.as_ptr(),
+            len: value.len(),
+        }
+    }
+
+    /// Return the bytes, or `None` if `ptr` is null and `len` isn't 0
+    ///
+    /// # Safety
+    /// `ptr` must be valid for `len` bytes while `self` is borrowed
+    pub unsafe fn as_slice(&self) -> Option<&[u8]> {
+        if self.len == 0 {
+            return Some(&[]);
+        }
+        if self.ptr.is_null() {
+            return None;
+        }
+        Some(std::slice::from_raw_parts(self.ptr, self.len))
+    }
+}
+
+/// Callbacks into the node, passed to the library's handlers. `host` is passed
+/// back as the first argument of each callback.
+#[repr(C)]
+pub struct HostApi {
+    pub host: *mut c_void,
+    /// Copy up to `cap` bytes of the value to `out` and return its length, or -1 if not found
+    pub store_get: extern "C" fn(host: *mut c_void, key: Bytes, out: *mut u8, cap: usize) -> i64,
+    /// Returns -1 in queries
+    pub store_set: extern "C" fn(host: *mut c_void, key: Bytes, value: Bytes) -> i32,
+    /// Returns -1 in queries
+    pub store_remove: extern "C" fn(host: *mut c_void, key: Bytes) -> i32,
+    /// Like `store_get` for the tx sender. Returns -1 in queries
+    pub sender: extern "C" fn(host: *mut c_void, out: *mut u8, cap: usize) -> i64,
+    pub height: extern "C" fn(host: *mut c_void) -> i64,
+    /// Attributes are a Borsh encoded `Vec<(String, String)>`. Returns -1 if invalid
+    pub emit_event: extern "C" fn(host: *mut c_void, event_type: Bytes, attrs: Bytes) -> i32,
+    /// Set the query value, or the error message when a handler returns non-zero
+    pub set_result: extern "C" fn(host: *mut c_void, value: Bytes),
+}
+
+/// Returned by the library's `rapido_module`. The handlers must be thread safe:
+/// they may be called concurrently from different threads
+#[repr(C)]
+pub struct ModuleApi {
+    /// Must be `RAPIDO_DYLIB_ABI_VERSION`
+    pub abi_version: u32,
+    /// Name of the AppModule. Must be valid for the life of the library
+    pub name: Bytes,
+    /// Return 0 on success
+    pub handle_tx: extern "C" fn(api: *const HostApi, msg: Bytes) -> i32,
+    /// Return 0 on success and set the value with `set_result`
+    pub handle_query: extern "C" fn(api: *const HostApi, path: Bytes, key: Bytes) -> i32,
+}
+
+// Data of each library AppModule keyed by (name, key)
+struct DylibDataStore;
+impl Store for DylibDataStore {
+    type Key = (String, Vec<u8>);
+    type Value = Vec<u8>;
+
+    fn name(&self) -> String {
+        DYLIB_DATA_STORE_NAME.into()
+    }
+}
+
+/// An AppModule loaded from a shared library. See `AppBuilder::with_dylib`
+pub struct DylibModule {
+    name: String,
+    api: ModuleApi,
+    // Keep the library loaded while the module is in use
+    _library: Library,
+}
+
+// The ABI version checked in `load` requires the library's handlers to be
+// thread safe, and the module only holds the library and its function pointers
+unsafe impl Send for DylibModule {}
+unsafe impl Sync for DylibModule {}
+
+impl DylibModule {
+    /// Load the library and check its ABI version
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        // Loading runs the library's initializers. Only load trusted libraries
+        let library = unsafe { Library::new(path) }
+            .map_err(|e| anyhow!("problem loading {}: {}", path.display(), e))?;
+        let api = unsafe {
+            let entry: Symbol<unsafe extern "C" fn() -> ModuleApi> = library
+                .get(RAPIDO_DYLIB_ENTRY)
+                .map_err(|e| anyhow!("{} is not a rapido module: {}", path.display(), e))?;
+            entry()
+        };
+        ensure!(
+            api.abi_version == RAPIDO_DYLIB_ABI_VERSION,
+            "{} uses ABI version {}, expected {}",
+            path.display(),
+            api.abi_version,
+            RAPIDO_DYLIB_ABI_VERSION
+        );
+        let name = match unsafe { api.name.as_slice() } {
+            Some(name) => String::from_utf8(name.to_vec())?,
+            None => bail!("{} returned a null module name", path.display()),
+        };
+        Ok(Self {
+            name,
+            api,
+            _library: library,
+        })
+    }
+}
+
+impl AppModule for DylibModule {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let mut host = Host {
+            app: self.name.clone(),
+            view: view as *mut StoreView<'_> as *mut c_void,
+            writable: true,
+            ctx: Some(ctx),
+            result: Vec::new(),
+        };
+        let api = host.api();
+        let code = (self.api.handle_tx)(&api, Bytes::new(&ctx.msg));
+        ensure!(
+            code == 0,
+            "{} handle_tx returned {}: {}",
+            self.name,
+            code,
+            String::from_utf8_lossy(&host.result)
+        );
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        let view: &StoreView = view;
+        let mut host = Host {
+            app: self.name.clone(),
+            view: view as *const StoreView<'_> as *mut c_void,
+            writable: false,
+            ctx: None,
+            result: Vec::new(),
+        };
+        let api = host.api();
+        let code = (self.api.handle_query)(&api, Bytes::new(path.as_bytes()), Bytes::new(&key));
+        ensure!(
+            code == 0,
+            "{} handle_query returned {}: {}",
+            self.name,
+            code,
+            String::from_utf8_lossy(&host.result)
+        );
+        Ok(std::mem::take(&mut host.result).into())
+    }
+}
+
+// State behind `HostApi::host`. Only used while a handler runs, within the
+// lifetime of the view and context it borrows.
+struct Host<'a> {
+    app: String,
+    // *mut StoreView. Only written if `writable`
+    view: *mut c_void,
+    writable: bool,
+    ctx: Option<&'a Context>,
+    result: Vec<u8>,
+}
+
+impl<'a> Host<'a> {
+    fn api(&mut self) -> HostApi {
+        HostApi {
+            host: self as *mut Host<'a> as *mut c_void,
+            store_get: host_store_get,
+            store_set: host_store_set,
+            store_remove: host_store_remove,
+            sender: host_sender,
+            height: host_height,
+            emit_event: host_emit_event,
+            set_result: host_set_result,
+        }
+    }
+}
+
+unsafe fn host<'a>(ptr: *mut c_void) -> &'a mut Host<'a> {
+    &mut *(ptr as *mut Host<'a>)
+}
+
+unsafe fn view<'a>(host: &Host) -> &'a mut StoreView<'a> {
+    &mut *(host.view as *mut StoreView<'a>)
+}
+
+// Copy up to `cap` bytes of the value and return its length
+unsafe fn copy_out(value: &[u8], out: *mut u8, cap: usize) -> i64 {
+    let n = value.len().min(cap);
+    std::ptr::copy_nonoverlapping(value.as_ptr(), out, n);
+    value.len() as i64
+}
+
+extern "C" fn host_store_get(ptr: *mut c_void, key: Bytes, out: *mut u8, cap: usize) -> i64 {
+    unsafe {
+        let host = host(ptr);
+        let key = match key.as_slice() {
+            Some(key) => (host.app.clone(), key.to_vec()),
+            None => return -1,
+        };
+        let value = DylibDataStore {}.get(key, view(host));
+        match value {
+            Some(value) => copy_out(&value, out, cap),
+            None => -1,
+        }
+    }
+}
+
+extern "C" fn host_store_set(ptr: *mut c_void, key: Bytes, value: Bytes) -> i32 {
+    unsafe {
+        let host = host(ptr);
+        if !host.writable {
+            return -1;
+        }
+        let (key, value) = match (key.as_slice(), value.as_slice()) {
+            (Some(key), Some(value)) => ((host.app.clone(), key.to_vec()), value.to_vec()),
+            _ => return -1,
+        };
+        DylibDataStore {}.put(key, value, view(host));
+        0
+    }
+}
+
+extern "C" fn host_store_remove(ptr: *mut c_void, key: Bytes) -> i32 {
+    unsafe {
+        let host = host(ptr);
+        if !host.writable {
+            return -1;
+        }
+        let key = match key.as_slice() {
+            Some(key) => (host.app.clone(), key.to_vec()),
+            None => return -1,
+        };
+        DylibDataStore {}.remove(key, view(host));
+        0
+    }
+}
+
+extern "C" fn host_sender(ptr: *mut c_void, out: *mut u8, cap: usize) -> i64 {
+    unsafe {
+        match host(ptr).ctx {
+            Some(ctx) => copy_out(&ctx.sender(), out, cap),
+            None => -1,
+        }
+    }
+}
+
+extern "C" fn host_height(ptr: *mut c_void) -> i64 {
+    unsafe { host(ptr).ctx.map_or(0, |ctx| ctx.height()) }
+}
+
+extern "C" fn host_emit_event(ptr: *mut c_void, event_type: Bytes, attrs: Bytes) -> i32 {
+    use borsh::BorshDeserialize;
+    unsafe {
+        let ctx = match host(ptr).ctx {
+            Some(ctx) => ctx,
+            None => return -1,
+        };
+        let event_type = match event_type.as_slice().map(std::str::from_utf8) {
+            Some(Ok(t)) => t,
+            _ => return -1,
+        };
+        let attrs = match attrs
+            .as_slice()
+            .map(<Vec<(String, String)>>::try_from_slice)
+        {
+            Some(Ok(a)) => a,
+            _ => return -1,
+        };
+        let pairs: Vec<(&str, &str)> = attrs
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        match ctx.dispatch_event(event_type, &pairs) {
+            Ok(()) => 0,
+            Err(_) => -1,
+        }
+    }
+}
+
+extern "C" fn host_set_result(ptr: *mut c_void, value: Bytes) {
+    unsafe {
+        host(ptr).result = value.as_slice().unwrap_or_default().to_vec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_missing_library() {
+        assert!(DylibModule::load("./missing/libmod_none.so").is_err());
+    }
+
+    #[test]
+    fn test_bytes_as_slice() {
+        let value = b"abc".to_vec();
+        assert_eq!(Some(&b"abc"[..]), unsafe { Bytes::new(&value).as_slice() });
+        let null = Bytes {
+            ptr: std::ptr::null(),
+            len: 3,
+        };
+        assert!(unsafe { null.as_slice() }.is_none());
+        let empty = Bytes {
+            ptr: std::ptr::null(),
+            len: 0,
+        };
+        assert_eq!(Some(&[][..]), unsafe { empty.as_slice() });
+    }
+}
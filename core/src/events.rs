@@ -0,0 +1,201 @@
+//! Typed events.  Derive `RapidoEvent` on a struct and emit it with `Context::emit`
+//! instead of assembling `(&str, &str)` pairs by hand. Field values are converted to
+//! attribute values via `EventValue`.  Example:
+//! ```ignore
+//! #[derive(RapidoEvent)]
+//! pub struct TransferEvent {
+//!     from: AccountId,
+//!     to: AccountId,
+//!     amount: u64,
+//! }
+//!
+//! // Emits a 'bank.transfer' event with the attributes 'from', 'to' and 'amount'
+//! ctx.emit(TransferEvent { from, to, amount })?;
+//! ```
+//! The event type is the snake_case name of the struct without an 'Event' suffix.
+//! Use `#[event(name = "...")]` on the struct to set it, and `#[event(skip)]` to
+//! leave a field out of the attributes.
+//!
+//! Events are validated before they are recorded. See `validate_event`.
+use anyhow::{bail, ensure};
+use exonum_crypto::Hash;
+
+/// Maximum number of events a tx can emit, including nested dispatches
+pub const MAX_EVENTS_PER_TX: usize = 64;
+/// Maximum length of an event type, not including the appname prefix
+pub const MAX_EVENT_TYPE_LENGTH: usize = 64;
+/// Maximum size in bytes of an attribute key or value
+pub const MAX_EVENT_ATTRIBUTE_SIZE: usize = 1024;
+
+/// Check an event emitted by a module. The event type may only contain
+/// ascii alphanumerics, '_' and '-'.  No '.', so a module can't emit an event
+/// in another module's namespace.
+pub(crate) fn validate_event(
+    event_type: &str,
+    pairs: &[(&str, &str)],
+) -> anyhow::Result<(), anyhow::Error> {
+    ensure!(!event_type.is_empty(), "event type is empty");
+    ensure!(
+        event_type.len() <= MAX_EVENT_TYPE_LENGTH,
+        "event type '{}' exceeds {} characters",
+        event_type,
+        MAX_EVENT_TYPE_LENGTH
+    );
+    if let Some(c) = event_type
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-'))
+    {
+        bail!(
+            "event type '{}' contains invalid character '{}'",
+            event_type,
+            c
+        );
+    }
+    for (k, v) in pairs {
+        ensure!(
+            !k.is_empty(),
+            "event '{}': attribute key is empty",
+            event_type
+        );
+        ensure!(
+            k.len() <= MAX_EVENT_ATTRIBUTE_SIZE && v.len() <= MAX_EVENT_ATTRIBUTE_SIZE,
+            "event '{}': attribute '{}' exceeds {} bytes",
+            event_type,
+            k,
+            MAX_EVENT_ATTRIBUTE_SIZE
+        );
+    }
+    Ok(())
+}
+
+/// An event that can be emitted via `Context::emit`. Usually derived.
+pub trait RapidoEvent {
+    /// The event type. The Context prefixes it with the appname
+    fn event_type(&self) -> String;
+
+    /// The attributes as key/value pairs
+    fn attributes(&self) -> Vec<(String, String)>;
+}
+
+/// Convert a value to the string used in an event attribute
+pub trait EventValue {
+    fn to_event_value(&self) -> String;
+}
+
+macro_rules! impl_event_value_display {
+    ($( $type:ty ),*) => {
+        $(
+            impl EventValue for $type {
+                fn to_event_value(&self) -> String {
+                    self.to_string()
+                }
+            }
+        )*
+    };
+}
+
+impl_event_value_display!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, bool, char, String, str
+);
+
+// Bytes are usually text ids (e.g. an AccountId). Fallback to hex
+impl EventValue for Vec<u8> {
+    fn to_event_value(&self) -> String {
+        match std::str::from_utf8(self) {
+            Ok(s) => s.into(),
+            Err(_) => hex::encode(self),
+        }
+    }
+}
+
+impl EventValue for Hash {
+    fn to_event_value(&self) -> String {
+        hex::encode(self.as_ref())
+    }
+}
+
+// None is an empty value
+impl<T: EventValue> EventValue for Option<T> {
+    fn to_event_value(&self) -> String {
+        match self {
+            Some(v) => v.to_event_value(),
+            None => String::new(),
+        }
+    }
+}
+
+impl<T: EventValue + ?Sized> EventValue for &T {
+    fn to_event_value(&self) -> String {
+        (**self).to_event_value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RapidoEvent, SignedTransaction};
+
+    #[derive(RapidoEvent)]
+    struct TransferEvent {
+        from: Vec<u8>,
+        amount: u64,
+        memo: Option<String>,
+        #[event(skip)]
+        _internal: u8,
+    }
+
+    #[derive(RapidoEvent)]
+    #[event(name = "minted")]
+    struct Mint {
+        amount: u64,
+    }
+
+    #[test]
+    fn test_typed_events() {
+        let transfer = TransferEvent {
+            from: b"bob".to_vec(),
+            amount: 10,
+            memo: None,
+            _internal: 1,
+        };
+        assert_eq!("transfer", transfer.event_type());
+        assert_eq!(
+            vec![
+                ("from".to_string(), "bob".to_string()),
+                ("amount".to_string(), "10".to_string()),
+                ("memo".to_string(), "".to_string()),
+            ],
+            transfer.attributes()
+        );
+        assert_eq!("minted", Mint { amount: 1 }.event_type());
+
+        let tx = SignedTransaction::create("bob", "bank", 1u8, 0u64);
+        let ctx = tx.into_context();
+        ctx.emit(transfer).unwrap();
+        let events = ctx.get_events();
+        assert_eq!("bank.transfer", events[0].get_field_type());
+        assert_eq!(3, events[0].get_attributes().len());
+    }
+
+    #[test]
+    fn test_event_validation() {
+        let tx = SignedTransaction::create("bob", "bank", 1u8, 0u64);
+        let ctx = tx.into_context();
+
+        assert!(ctx.dispatch_event("transfer_v2", &[("a", "b")]).is_ok());
+        // Can't escape the module's namespace
+        assert!(ctx.dispatch_event("account.create", &[("a", "b")]).is_err());
+        assert!(ctx.dispatch_event("", &[]).is_err());
+        assert!(ctx.dispatch_event("bad type", &[]).is_err());
+        let big = "x".repeat(MAX_EVENT_ATTRIBUTE_SIZE + 1);
+        assert!(ctx.dispatch_event("transfer", &[("a", &big)]).is_err());
+        assert!(ctx.dispatch_event("transfer", &[("", "b")]).is_err());
+        assert_eq!(1, ctx.get_events().len());
+
+        for _ in 1..MAX_EVENTS_PER_TX {
+            assert!(ctx.dispatch_event("transfer", &[]).is_ok());
+        }
+        assert!(ctx.dispatch_event("transfer", &[]).is_err());
+        assert_eq!(MAX_EVENTS_PER_TX, ctx.get_events().len());
+    }
+}
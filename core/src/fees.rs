@@ -0,0 +1,164 @@
+//! Fee grants. Allow an account (the granter) to sponsor the fees of another
+//! account (the grantee).  A transaction signed by the grantee can set the
+//! granter as its `fee_payer`.  Grants are managed via the `FeeGrantModule`.
+//! Fee middleware charges the fee payer with `Context::charge_fee`, which deducts
+//! the fee from the allowance.
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    AccountId, AppModule, Context, QueryResponse, QueryView, SignedTransaction, Store, StoreValue,
+    StoreView,
+};
+
+/// Name of the fee grant AppModule
+pub const FEE_GRANT_APP_NAME: &str = "rapido.fees";
+const FEE_GRANT_STORE_NAME: &str = "rapido.fees.grants";
+
+/// An allowance the granter has given the grantee to spend on fees
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq, Default)]
+pub struct FeeGrant {
+    pub granter: AccountId,
+    pub grantee: AccountId,
+    pub allowance: u64,
+}
+
+/// Store of fee grants keyed by (granter, grantee)
+pub struct FeeGrantStore;
+impl Store for FeeGrantStore {
+    type Key = (AccountId, AccountId);
+    type Value = FeeGrant;
+
+    fn name(&self) -> String {
+        FEE_GRANT_STORE_NAME.into()
+    }
+}
+
+impl FeeGrantStore {
+    pub fn new() -> Self {
+        FeeGrantStore {}
+    }
+
+    /// Create or replace a grant
+    pub fn grant(
+        &self,
+        granter: AccountId,
+        grantee: AccountId,
+        allowance: u64,
+        view: &mut StoreView,
+    ) {
+        let grant = FeeGrant {
+            granter: granter.clone(),
+            grantee: grantee.clone(),
+            allowance,
+        };
+        self.put((granter, grantee), grant, view)
+    }
+
+    /// Remove a grant
+    pub fn revoke(&self, granter: AccountId, grantee: AccountId, view: &mut StoreView) {
+        self.remove((granter, grantee), view)
+    }
+
+    /// Deduct `amount` from the allowance of a grant. Called by `Context::charge_fee`
+    /// when charging fees to a fee payer on behalf of the sender.
+    pub fn use_grant(
+        &self,
+        granter: AccountId,
+        grantee: AccountId,
+        amount: u64,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let key = (granter, grantee);
+        let grant = match self.get(key.clone(), view) {
+            Some(g) => g,
+            None => bail!("fee grant not found"),
+        };
+        ensure!(grant.allowance >= amount, "fee grant allowance exceeded");
+
+        let updated = FeeGrant {
+            allowance: grant.allowance - amount,
+            ..grant
+        };
+        self.put(key, updated, view);
+        Ok(())
+    }
+}
+
+/// Return the account responsible for paying the fees of the transaction. This is
+/// either the `fee_payer` (if set) or the sender.  Returns an error if the fee payer
+/// hasn't granted an allowance to the sender.
+pub fn resolve_fee_payer(
+    tx: &SignedTransaction,
+    view: &StoreView,
+) -> anyhow::Result<AccountId, anyhow::Error> {
+    match tx.fee_payer() {
+        Some(payer) if payer != tx.sender() => {
+            let store = FeeGrantStore::new();
+            ensure!(
+                store.get((payer.clone(), tx.sender()), view).is_some(),
+                "fee payer has not granted an allowance to the sender"
+            );
+            Ok(payer)
+        }
+        _ => Ok(tx.sender()),
+    }
+}
+
+/// Messages used to manage grants. The sender of the transaction is the granter.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum FeeGrantMsgs {
+    Grant { grantee: AccountId, allowance: u64 },
+    Revoke { grantee: AccountId },
+}
+
+/// AppModule to manage fee grants. Add it via `AppBuilder::with_app` to let
+/// accounts sponsor the fees of others.
+pub struct FeeGrantModule;
+impl AppModule for FeeGrantModule {
+    fn name(&self) -> String {
+        FEE_GRANT_APP_NAME.into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let msg: FeeGrantMsgs = ctx.decode_msg()?;
+        let store = FeeGrantStore::new();
+        match msg {
+            FeeGrantMsgs::Grant { grantee, allowance } => {
+                ensure!(
+                    grantee != ctx.sender(),
+                    "cannot grant an allowance to yourself"
+                );
+                store.grant(ctx.sender(), grantee, allowance, view);
+                Ok(())
+            }
+            FeeGrantMsgs::Revoke { grantee } => {
+                store.revoke(ctx.sender(), grantee, view);
+                Ok(())
+            }
+        }
+    }
+
+    // Query a grant where the key is the Borsh encoded (granter, grantee)
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        match path {
+            "/" => {
+                let k = <(AccountId, AccountId)>::try_from_slice(&key)?;
+                match FeeGrantStore::new().query(k, view) {
+                    Some(grant) => Ok(grant.try_to_vec()?.into()),
+                    None => bail!("fee grant not found"),
+                }
+            }
+            _ => bail!("{:} not found", path),
+        }
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/".into()]
+    }
+}
@@ -0,0 +1,84 @@
+//! Gas metering for transactions. Each transaction gets a `GasMeter` with the
+//! limit set via `AppBuilder::gas_limit`.  AppModules charge gas through
+//! `Context::gas_meter()`, for example per byte of a stored blob, and the tx
+//! fails if it runs out. Gas used is reported in the deliver_tx response.
+use std::cell::Cell;
+
+use anyhow::ensure;
+
+/// Tracks the gas consumed by a transaction against its limit
+#[derive(Debug)]
+pub struct GasMeter {
+    limit: u64,
+    consumed: Cell<u64>,
+}
+
+impl GasMeter {
+    /// Create a meter with the given limit
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            consumed: Cell::new(0),
+        }
+    }
+
+    /// Create a meter without a limit
+    pub fn unlimited() -> Self {
+        Self::new(u64::MAX)
+    }
+
+    /// Charge `amount` of gas. `descriptor` is included in the error to explain
+    /// what ran out of gas.  Returns an error, without consuming, if the amount
+    /// exceeds the remaining gas.
+    pub fn consume(&self, amount: u64, descriptor: &str) -> anyhow::Result<(), anyhow::Error> {
+        ensure!(
+            amount <= self.remaining(),
+            "out of gas: {} needs {}, remaining {}",
+            descriptor,
+            amount,
+            self.remaining()
+        );
+        self.consumed.set(self.consumed.get() + amount);
+        Ok(())
+    }
+
+    /// Return the gas consumed so far
+    pub fn consumed(&self) -> u64 {
+        self.consumed.get()
+    }
+
+    /// Return the remaining gas
+    pub fn remaining(&self) -> u64 {
+        self.limit - self.consumed.get()
+    }
+
+    /// Return the limit
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+}
+
+impl Default for GasMeter {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gas_meter() {
+        let meter = GasMeter::new(100);
+        assert!(meter.consume(60, "first").is_ok());
+        assert_eq!(40, meter.remaining());
+
+        // Too much. Nothing consumed
+        assert!(meter.consume(50, "second").is_err());
+        assert_eq!(60, meter.consumed());
+
+        assert!(meter.consume(40, "third").is_ok());
+        assert_eq!(0, meter.remaining());
+    }
+}
@@ -5,12 +5,32 @@
 //! * Deterministic message serialization via [Borsh](http://borsh.io/)
 //!
 //! See [Counter](https://github.com/davebryson/rapido/blob/master/examples/counter/src/lib.rs) for a complete example application
+// Allow the derive macros to refer to `::rapido_core` from within this crate
+extern crate self as rapido_core;
+
 #[macro_use]
 mod macros;
+mod capability;
+mod cron;
+#[cfg(feature = "dylib")]
+pub mod dylib;
+mod events;
+pub mod fees;
+mod gas;
+mod logger;
+mod nonce;
+mod openapi;
+mod panic;
+pub mod params;
+mod query;
+mod ratelimit;
+mod scheduler;
 mod schema;
+pub mod sink;
 mod store;
 mod testkit;
 mod types;
+pub mod upgrade;
 
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -19,24 +39,68 @@ use std::sync::Arc;
 #[macro_use]
 extern crate log;
 
+use crate::capability::{split_capability, Capabilities};
+use crate::panic::{catch_panic, error_code};
 use crate::schema::RapidoSchema;
 use abci::*;
 use anyhow::{bail, ensure};
+use borsh::BorshSerialize;
 use env_logger::Env;
-use exonum_merkledb::{Database, DbOptions, Fork, ObjectHash, RocksDB, SystemSchema, TemporaryDB};
+use exonum_merkledb::{
+    Database, DbOptions, Fork, ObjectHash, RocksDB, Snapshot, SystemSchema, TemporaryDB,
+};
 use protobuf::RepeatedField;
 
 // Re-export
 pub use self::{
-    store::{Store, StoreView},
-    testkit::{testing_keypair, TestKit},
+    capability::CAPABILITY_SEPARATOR,
+    cron::{Cron, CronJob},
+    events::{
+        EventValue, RapidoEvent, MAX_EVENTS_PER_TX, MAX_EVENT_ATTRIBUTE_SIZE, MAX_EVENT_TYPE_LENGTH,
+    },
+    gas::GasMeter,
+    logger::Logger,
+    nonce::NonceStore,
+    panic::{HandlerPanic, PANIC_ERROR_CODE},
+    query::{Json, QueryEncode, QueryEncoding, QueryParams, QueryRouter},
+    ratelimit::{RateLimitStore, RateLimiter, TxCount},
+    scheduler::{ScheduledTx, Scheduler},
+    schema::ChainState,
+    store::{proof_root, verify_query_proof, QueryView, Store, StoreView},
+    testkit::{
+        assert_proof_valid, testing_keypair, BlockResult, CommitResult, GenesisConfig, Invariant,
+        QueryProof, StateSnapshot, TestKit, TxFailure, TxOutcome, TESTKIT_BLOCK_INTERVAL,
+        TESTKIT_CHAIN_ID, TESTKIT_GENESIS_TIME,
+    },
     types::{
-        sign_transaction, verify_tx_signature, AccountId, AppModule, Authenticator, Context,
-        SignedTransaction,
+        sign_transaction, verify_tx_signature, AccountId, AppModule, AuthResult, Authenticator,
+        BlockInfo, ChainContext, Context, InitError, Misbehavior, ModuleClient, ModuleInfo,
+        QueryResponse, SignedTransaction, SimulatedEvent, SimulationResult, TxBuilder,
+        TxMiddleware, UnsignedPolicy, ValidatorPower, VoteInfo, MAX_DISPATCH_DEPTH,
     },
 };
+pub use rapido_derive::{RapidoEvent, RapidoMessages, StoreValue};
+
+#[cfg(feature = "proptest-harness")]
+pub use self::testkit::prop;
+
+// Used by the derive macros
+#[doc(hidden)]
+pub mod __private {
+    pub use anyhow;
+    pub use borsh;
+    pub use exonum_crypto;
+    pub use exonum_merkledb;
+}
 
 const NAME: &str = "rapido_v3";
+// Codespace of errors returned by the framework. AppModule errors use the appname
+const RAPIDO_CODESPACE: &str = "rapido";
+/// Type of the ProofOp of a value returned with a query proof. See `Store::query_proof`
+pub const PROOF_OP_TYPE: &str = "rapido:map";
+/// Type of the ProofOp of the store's root in the app state, returned with a
+/// query proof. See `verify_query_proof`
+pub const STATE_PROOF_OP_TYPE: &str = "rapido:state";
 const RESERVED_APP_NAME: &str = "rapido";
 const RAPIDO_HOME: &str = ".rapido";
 const RAPIDO_STATE_DIR: &str = "state";
@@ -49,6 +113,22 @@ fn dbdir() -> PathBuf {
     dir
 }
 
+/// How check_tx processes a transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckMode {
+    /// Only run the Authenticator (the default)
+    Stateless,
+    /// Run the Authenticator and then execute the tx via the AppModule's `handle_tx`
+    /// against the check cache.  Keeps obviously failing txs out of blocks.
+    Execute,
+}
+
+impl Default for CheckMode {
+    fn default() -> Self {
+        CheckMode::Stateless
+    }
+}
+
 /// Assemble your app.
 /// Example:
 /// ```ignore
@@ -58,6 +138,16 @@ pub struct AppBuilder {
     db: Arc<dyn Database>,
     appmodules: Vec<Box<dyn AppModule>>,
     validate_tx_handler: Option<Box<dyn Authenticator>>,
+    unsigned_policy: Option<Box<dyn UnsignedPolicy>>,
+    middleware: Vec<Box<dyn TxMiddleware>>,
+    capabilities: Capabilities,
+    check_mode: CheckMode,
+    registry: Option<Vec<String>>,
+    order: Option<Vec<String>>,
+    app_version: u64,
+    gas_limit: u64,
+    upgrade_handlers: HashMap<String, upgrade::UpgradeHandler>,
+    event_sinks: Vec<Box<dyn sink::EventSink>>,
     use_rocks_db: bool,
 }
 
@@ -67,6 +157,16 @@ impl AppBuilder {
             db: Arc::new(TemporaryDB::new()),
             appmodules: Vec::new(),
             validate_tx_handler: None,
+            unsigned_policy: None,
+            middleware: Vec::new(),
+            capabilities: Capabilities::default(),
+            check_mode: CheckMode::default(),
+            registry: None,
+            order: None,
+            app_version: 0,
+            gas_limit: u64::MAX,
+            upgrade_handlers: HashMap::new(),
+            event_sinks: Vec::new(),
             use_rocks_db: false,
         }
     }
@@ -85,31 +185,153 @@ impl AppBuilder {
         self
     }
 
+    /// Set a policy to accept unsigned transactions. See the `UnsignedPolicy` trait.
+    /// If a policy is not set, all unsigned transactions are rejected.
+    pub fn set_unsigned_policy(mut self, policy: impl Into<Box<dyn UnsignedPolicy>>) -> Self {
+        self.unsigned_policy = Some(policy.into());
+        self
+    }
+
+    /// Add middleware called around each tx. See the `TxMiddleware` trait.
+    /// Call more than once to add more; they're called in the order added.
+    pub fn with_middleware(mut self, middleware: impl Into<Box<dyn TxMiddleware>>) -> Self {
+        self.middleware.push(middleware.into());
+        self
+    }
+
+    /// Grant the AppModule `grantee` a capability named `app::name`, e.g. `bank::mint`.
+    /// The AppModule `app` checks it with `Context::require_capability` when
+    /// `grantee` dispatches a message to it. The node will fail to start if either
+    /// AppModule is not registered.
+    pub fn grant(mut self, grantee: &str, capability: &str) -> Self {
+        if let Err(err) = split_capability(capability) {
+            panic!("{}", err);
+        }
+        self.capabilities.grant(grantee, capability);
+        self
+    }
+
+    /// Set how check_tx processes transactions. See `CheckMode`.
+    /// The default is `CheckMode::Stateless`.
+    pub fn check_mode(mut self, mode: CheckMode) -> Self {
+        self.check_mode = mode;
+        self
+    }
+
+    /// Set the registry of expected AppModule routes, usually `NAMES` from an
+    /// enum generated by `register_modules!`.  The node will fail to start if
+    /// a route in the registry doesn't have an AppModule, or an AppModule is not
+    /// in the registry.
+    pub fn with_registry(mut self, routes: &[&str]) -> Self {
+        self.registry = Some(routes.iter().map(|r| r.to_string()).collect());
+        self
+    }
+
+    /// Set the version of the application reported to Tendermint and available
+    /// to AppModules via `Context::app_version`. Defaults to 0.
+    pub fn app_version(mut self, version: u64) -> Self {
+        self.app_version = version;
+        self
+    }
+
+    /// Set the maximum gas a transaction can consume. See `GasMeter`.
+    /// By default there is no limit.
+    pub fn gas_limit(mut self, limit: u64) -> Self {
+        self.gas_limit = limit;
+        self
+    }
+
+    /// Add the handler for the named upgrade. It's run in begin_block at the
+    /// height of the upgrade plan. See the `upgrade` module.
+    pub fn with_upgrade_handler<F>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(&mut StoreView) -> anyhow::Result<(), anyhow::Error> + Send + Sync + 'static,
+    {
+        self.upgrade_handlers.insert(name.into(), Box::new(handler));
+        self
+    }
+
+    /// Forward the events of each committed block to the sink, e.g. a webhook.
+    /// Call more than once to add more.  See the `sink` module.
+    pub fn with_event_sink(mut self, sink: impl sink::EventSink + 'static) -> Self {
+        self.event_sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Set the order AppModules are called in `init_chain`, `begin_block` and `end_block`
+    /// by name. It must include every AppModule.  By default, AppModules are called in
+    /// the order they were added with `with_app`.  Either way, an AppModule is always
+    /// called after its `dependencies`.
+    pub fn with_order(mut self, names: &[&str]) -> Self {
+        self.order = Some(names.iter().map(|n| n.to_string()).collect());
+        self
+    }
+
     /// Call this one or more times to add AppModules to the overall App.
+    /// AppModules are initialized and called on each block in the order they're added.
+    /// Each AppModule must have a unique name.
     pub fn with_app(mut self, app: impl Into<Box<dyn AppModule>>) -> Self {
         self.appmodules.push(app.into());
         self
     }
 
+    /// Add an AppModule under the given name instead of its `name()`. The name is
+    /// used to route txs and queries, and for its genesis section. Use to add
+    /// more than one instance of the same AppModule type.
+    /// Example: `.with_app_as("bank", BankModule::new(..))`
+    pub fn with_app_as(mut self, name: &str, app: impl Into<Box<dyn AppModule>>) -> Self {
+        self.appmodules
+            .push(Box::new(types::AppAlias::new(name, app.into())));
+        self
+    }
+
+    /// Add an AppModule loaded from a shared library. Panics if the library can't
+    /// be loaded or was built for another ABI version. See the `dylib` module.
+    #[cfg(feature = "dylib")]
+    pub fn with_dylib<P: AsRef<std::path::Path>>(self, path: P) -> Self {
+        match dylib::DylibModule::load(path) {
+            Ok(module) => self.with_app(module),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
     /// Call to return a configured node with a temp/in-memory db
     /// Use to directly interact with ABCI calls during development.
     pub fn node(self) -> Node {
         if self.appmodules.len() == 0 {
             panic!("No appmodules configured!");
         }
+        self.check_registry();
         Node::new(self)
     }
 
+    // Panic if the registered AppModules don't match the registry (if set)
+    fn check_registry(&self) {
+        if let Some(registry) = &self.registry {
+            let names: Vec<String> = self.appmodules.iter().map(|app| app.name()).collect();
+            for route in registry {
+                if !names.contains(route) {
+                    panic!("No AppModule registered for route: {}", route);
+                }
+            }
+            for name in &names {
+                if !registry.contains(name) {
+                    panic!("AppModule {} is missing from the registry", name);
+                }
+            }
+        }
+    }
+
     /// Called last to start the application via rust-abci.  This will start
     /// the application and connect to Tendermint.
     pub fn run(mut self) {
-        env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-            .try_init()
-            .expect("logger");
+        // Use the logger already installed by the host application, if any
+        let _ = env_logger::Builder::from_env(Env::default().default_filter_or("info")).try_init();
 
         if self.appmodules.len() == 0 {
             panic!("No appmodules configured!");
         }
+        self.check_registry();
 
         if self.use_rocks_db {
             let db = RocksDB::open(dbdir(), &DbOptions::default()).expect("create rocks db");
@@ -127,20 +349,53 @@ impl AppBuilder {
 /// Returns Ok for any Tx. and does not increment a nonce.
 pub struct DefaultAuthenticator;
 impl Authenticator for DefaultAuthenticator {
-    fn validate(&self, _tx: &SignedTransaction, _view: &StoreView) -> Result<(), anyhow::Error> {
-        Ok(())
+    fn validate(
+        &self,
+        _ctx: &Context,
+        _tx: &SignedTransaction,
+        _view: &StoreView,
+    ) -> Result<AuthResult, anyhow::Error> {
+        Ok(AuthResult::new())
     }
 }
 
+// Result of running a tx through check/deliver
+#[derive(Default)]
+struct TxResponse {
+    events: Vec<Event>,
+    log: String,
+    gas_used: u64,
+}
+
 #[doc(hidden)]
 /// Node provides functionality to execute appmodules and manage storage.  
 /// You should use the `AppBuilder` to create a Node.
 pub struct Node {
     db: Arc<dyn Database>,
-    appmodules: HashMap<String, Box<dyn AppModule>>,
+    appmodules: types::Router,
+    // AppModule names in the order they're called on init and each block
+    order: Vec<String>,
     authenticator: Box<dyn Authenticator>,
+    unsigned_policy: Option<Box<dyn UnsignedPolicy>>,
+    middleware: Vec<Box<dyn TxMiddleware>>,
+    capabilities: Arc<Capabilities>,
+    check_mode: CheckMode,
     check_cache: Option<store::Cache>,
     deliver_cache: Option<store::Cache>,
+    block: BlockInfo,
+    chain: ChainContext,
+    gas_limit: u64,
+    // AppModule versions are checked once, in the first begin_block
+    versions_checked: bool,
+    // AppModule versions to save on the next commit
+    pending_versions: Vec<(String, u32)>,
+    // Validator updates of the block, saved on the next commit
+    pending_validators: Vec<ValidatorPower>,
+    upgrade_handlers: HashMap<String, upgrade::UpgradeHandler>,
+    event_sink: Option<sink::SinkRunner>,
+    // Events of the current block for the event sink, and the index of the next tx
+    block_events: Vec<sink::SinkEvent>,
+    tx_index: usize,
 }
 
 impl Node {
@@ -150,16 +405,37 @@ impl Node {
 
         // setup route mapping
         let mut service_map = HashMap::new();
+        let mut order = Vec::new();
         for s in config.appmodules {
             let route = s.name();
             // Rapido is a reserved app/route name
             if route == RESERVED_APP_NAME {
                 panic!("The AppModule name 'rapido' is reserved for internal use");
             }
-            // First come, first serve...
-            if !service_map.contains_key(&route) {
-                service_map.insert(route, s);
+            if service_map.contains_key(&route) {
+                panic!(
+                    "The AppModule name '{}' is registered more than once",
+                    route
+                );
+            }
+            order.push(route.clone());
+            service_map.insert(route, s);
+        }
+
+        // Use an explicit order if set. It must include every AppModule
+        if let Some(explicit) = config.order {
+            if explicit.len() != order.len() || !order.iter().all(|n| explicit.contains(n)) {
+                panic!("The AppModule order must include every AppModule once");
             }
+            order = explicit;
+        }
+        let order = sort_by_dependencies(order, &service_map);
+
+        if let Err(err) = config
+            .capabilities
+            .validate(|name| service_map.contains_key(name))
+        {
+            panic!("{}", err);
         }
 
         // Use the default authenticator if one is not set.
@@ -168,12 +444,164 @@ impl Node {
             None => Box::new(DefaultAuthenticator),
         };
 
+        // The chain id is saved on init_chain. Load it on a restart
+        let snapshot = db.snapshot();
+        let schema = RapidoSchema::new(&snapshot);
+        let chain = ChainContext {
+            chain_id: schema.get_chain_id().unwrap_or_default(),
+            app_version: config.app_version,
+        };
+
+        // Refuse to start if an AppModule is older than the stored version or can't
+        // migrate from it
+        for name in &order {
+            let app = service_map.get(name).expect("app module");
+            if let Some(stored) = schema.get_module_version(name) {
+                if stored > app.version() {
+                    panic!(
+                        "AppModule {} version {} is older than the stored version {}",
+                        name,
+                        app.version(),
+                        stored
+                    );
+                }
+                if stored < app.version() && !app.can_migrate_from(stored) {
+                    panic!(
+                        "AppModule {} version {} cannot migrate from the stored version {}",
+                        name,
+                        app.version(),
+                        stored
+                    );
+                }
+            }
+        }
+
+        // Refuse to start if an upgrade is due and the binary doesn't have its handler
+        let next_height = schema.get_chain_state().unwrap_or_default().height + 1;
+        let plan = upgrade::current_plan(&store::StoreView::wrap_snapshot(&snapshot));
+        if let Some(plan) = &plan {
+            let has_handler = config.upgrade_handlers.contains_key(&plan.name);
+            if upgrade::upgrade_action(Some(plan), next_height, has_handler)
+                == upgrade::UpgradeAction::Halt
+            {
+                panic!(
+                    "UPGRADE \"{}\" NEEDED at height {}: {}",
+                    plan.name, plan.height, plan.info
+                );
+            }
+        }
+
         Self {
             db: db.clone(),
-            appmodules: service_map,
+            appmodules: Arc::new(service_map),
+            order,
             authenticator: auth,
+            unsigned_policy: config.unsigned_policy,
+            middleware: config.middleware,
+            capabilities: Arc::new(config.capabilities),
+            check_mode: config.check_mode,
             check_cache: Some(Default::default()),
             deliver_cache: Some(Default::default()),
+            block: Default::default(),
+            chain,
+            gas_limit: config.gas_limit,
+            versions_checked: false,
+            pending_versions: Vec::new(),
+            pending_validators: Vec::new(),
+            upgrade_handlers: config.upgrade_handlers,
+            event_sink: if config.event_sinks.is_empty() {
+                None
+            } else {
+                Some(sink::SinkRunner::start(config.event_sinks))
+            },
+            block_events: Vec::new(),
+            tx_index: 0,
+        }
+    }
+
+    // Keep the events for the event sink, if there is one
+    fn collect_events(&mut self, source: sink::EventSource, events: &[Event]) {
+        if self.event_sink.is_some() {
+            self.block_events
+                .extend(events.iter().map(|e| sink::SinkEvent::from_abci(source, e)));
+        }
+    }
+
+    /// Export the committed state of all AppModules as a genesis `app_state`: a JSON
+    /// object keyed by AppModule name. See `AppModule::export_genesis`.
+    pub fn export_app_state(&self) -> anyhow::Result<Vec<u8>, anyhow::Error> {
+        let snapshot = self.db.snapshot();
+        let view = store::StoreView::wrap_snapshot(&snapshot);
+
+        let mut app_state = serde_json::Map::new();
+        for name in &self.order {
+            let app = self.appmodules.get(name).expect("app module");
+            let section = app.export_genesis(&view)?;
+            if section.is_empty() {
+                continue;
+            }
+            let value: serde_json::Value = serde_json::from_slice(&section)?;
+            app_state.insert(name.clone(), value);
+        }
+        Ok(serde_json::to_vec_pretty(&serde_json::Value::Object(
+            app_state,
+        ))?)
+    }
+
+    // Add the current block, chain, AppModules and capabilities to a context
+    fn prepare_context(&self, ctx: Context) -> Context {
+        ctx.with_block(self.block.clone())
+            .with_chain(self.chain.clone())
+            .with_router(self.appmodules.clone())
+            .with_capabilities(self.capabilities.clone())
+    }
+
+    // Unsigned txs skip the authenticator and must pass the unsigned policy.
+    // They're rejected if a policy is not set.
+    fn check_unsigned(
+        &self,
+        tx: &SignedTransaction,
+        view: &StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        match &self.unsigned_policy {
+            Some(policy) => policy.allow(tx, view),
+            None => bail!("unsigned transactions are not accepted"),
+        }
+    }
+
+    // Run a tx through the middleware and the AppModule's handle_tx
+    fn execute_tx(
+        &self,
+        app: &dyn AppModule,
+        ctx: &Context,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        for m in &self.middleware {
+            m.before(ctx, view)?;
+        }
+        // The changes of a failed tx are undone, except those of the middleware
+        // before it, e.g. the fee paid
+        let checkpoint = view.checkpoint();
+        let mut result = catch_panic(ctx.appname(), || app.handle_tx(ctx, view));
+        if result.is_err() {
+            view.rollback_to(checkpoint);
+        }
+        for m in self.middleware.iter().rev() {
+            let after = m.after(ctx, &result, view);
+            if result.is_ok() && after.is_err() {
+                view.rollback_to(checkpoint);
+                result = after;
+            }
+        }
+        result
+    }
+
+    // The codespace of a failed tx: the AppModule it's routed to, or 'rapido'
+    // if it can't be routed
+    fn tx_codespace(&self, raw_tx: &[u8]) -> String {
+        match SignedTransaction::decode(raw_tx) {
+            Ok(tx) if self.appmodules.contains_key(tx.appname()) => tx.appname().into(),
+            _ => RAPIDO_CODESPACE.into(),
         }
     }
 
@@ -182,10 +610,19 @@ impl Node {
         &mut self,
         is_check: bool,
         raw_tx: Vec<u8>,
-    ) -> anyhow::Result<RepeatedField<Event>, anyhow::Error> {
+    ) -> anyhow::Result<TxResponse, anyhow::Error> {
         // Decode the incoming transaction
         let tx = SignedTransaction::decode(&raw_tx[..])?;
 
+        // Return err if the tx is intended for another chain. A tx without a chain
+        // id could be replayed on any chain
+        ensure!(!tx.chain_id().is_empty(), "missing chain id");
+        ensure!(
+            tx.chain_id() == self.chain.chain_id,
+            "wrong chain id: {}",
+            tx.chain_id()
+        );
+
         // Return err if there are no appmodules matching the route
         if !self.appmodules.contains_key(tx.appname()) {
             bail!(format!(
@@ -199,18 +636,63 @@ impl Node {
             let snap = self.db.snapshot();
             let mut cache = store::StoreView::wrap(&snap, self.check_cache.take().unwrap());
 
-            let resp = match self.authenticator.validate(&tx, &cache) {
-                Ok(()) => Ok(RepeatedField::<Event>::new()),
+            // Return err if the fee payer hasn't sponsored the sender
+            if let Err(r) = fees::resolve_fee_payer(&tx, &cache) {
+                self.check_cache.replace(cache.into_cache());
+                return Err(r);
+            }
+
+            if tx.is_unsigned() {
+                let resp = self
+                    .check_unsigned(&tx, &cache)
+                    .map(|_| TxResponse::default());
+                self.check_cache.replace(cache.into_cache());
+                return resp;
+            }
+
+            let ctx = self
+                .prepare_context(tx.into_context())
+                .with_gas_limit(self.gas_limit);
+            let resp = self
+                .authenticator
+                .validate(&ctx, &tx, &cache)
+                .and_then(|auth| {
+                    // Increment the nonce for a sender in the checkTx cache
+                    // this is to ensure multiple txs from a user are tracked
+                    // this doesn't affect the nonce count in deliver_tx
+                    ensure!(
+                        self.authenticator
+                            .increment_nonce(&ctx, &tx, &mut cache)
+                            .is_ok(),
+                        "check tx : inc nonce error"
+                    );
+                    Ok(auth)
+                });
+
+            // Stateful check: run the tx through the AppModule with the check cache
+            let resp = match resp {
+                Ok(auth) => {
+                    let mut events = auth.events;
+                    let mut result = Ok(());
+                    if self.check_mode == CheckMode::Execute {
+                        let app = self.appmodules.get(tx.appname()).expect("app module");
+                        result = self.execute_tx(app.as_ref(), &ctx, &mut cache);
+                        events.extend(ctx.get_events().into_vec());
+                    }
+                    result.map(|_| TxResponse {
+                        events,
+                        log: auth.log,
+                        gas_used: ctx.gas_meter().consumed(),
+                    })
+                }
                 Err(r) => Err(r),
             };
 
-            // Increment the nonce for a sender in the checkTx cache
-            // this is to ensure multiple txs from a user are tracked
-            // this doesn't affect the nonce count in deliver_tx
-            ensure!(
-                self.authenticator.increment_nonce(&tx, &mut cache).is_ok(),
-                "check tx : inc nonce error"
-            );
+            // A failed tx leaves nothing in the cache, including its nonce, so
+            // the sender's next tx is checked against the same state
+            if resp.is_err() {
+                cache.rollback();
+            }
 
             // Refresh the cache
             self.check_cache.replace(cache.into_cache());
@@ -225,41 +707,431 @@ impl Node {
         let snap = self.db.snapshot();
         let mut cache = store::StoreView::wrap(&snap, self.deliver_cache.take().unwrap());
 
-        let ctx = tx.into_context();
-        let resp = match app.handle_tx(&ctx, &mut cache) {
+        if let Err(r) = fees::resolve_fee_payer(&tx, &cache) {
+            self.deliver_cache.replace(cache.into_cache());
+            return Err(r);
+        }
+
+        // A proposer may include txs that never went through check_tx
+        if tx.is_unsigned() {
+            if let Err(r) = self.check_unsigned(&tx, &cache) {
+                self.deliver_cache.replace(cache.into_cache());
+                return Err(r);
+            }
+        }
+
+        let ctx = self
+            .prepare_context(tx.into_context())
+            .with_gas_limit(self.gas_limit);
+        let resp = match self.execute_tx(app.as_ref(), &ctx, &mut cache) {
             Ok(()) => {
-                let events = ctx.get_events();
-                Ok(events)
+                let mut events = ctx.get_events().into_vec();
+                events.push(tx_event(&tx));
+                Ok(TxResponse {
+                    events,
+                    log: String::new(),
+                    gas_used: ctx.gas_meter().consumed(),
+                })
             }
             Err(r) => Err(r),
         };
 
-        // Increment the nonce for a sender
-        ensure!(
-            self.authenticator.increment_nonce(&tx, &mut cache).is_ok(),
-            "deliver tx nonce error"
-        );
+        // Increment the nonce for a sender. Unsigned txs don't use nonces
+        if !tx.is_unsigned() {
+            ensure!(
+                self.authenticator
+                    .increment_nonce(&ctx, &tx, &mut cache)
+                    .is_ok(),
+                "deliver tx nonce error"
+            );
+        }
 
         self.deliver_cache.replace(cache.into_cache());
         resp
     }
 
-    // Called by abci.commit() below
-    fn update_state(&mut self, fork: &Fork) -> Vec<u8> {
+    // Compare the stored version of each AppModule to its current version and call
+    // `on_upgrade` when it increased. Changes are made to the deliver cache so they
+    // are committed with the block.  Returns the error of a failed upgrade
+    fn run_upgrades(&mut self) -> anyhow::Result<(), anyhow::Error> {
+        let snap = self.db.snapshot();
+        let schema = RapidoSchema::new(&snap);
+        let mut cache = store::StoreView::wrap(&snap, self.deliver_cache.take().unwrap());
+
+        for name in &self.order {
+            let app = self.appmodules.get(name).expect("app module");
+            let version = app.version();
+            match schema.get_module_version(name) {
+                // Downgrades are refused on start
+                Some(stored) if stored >= version => continue,
+                Some(stored) => {
+                    info!("upgrading {} from version {} to {}", name, stored, version);
+                    if let Err(err) = app.on_upgrade(stored, &mut cache) {
+                        self.deliver_cache.replace(cache.into_cache());
+                        return Err(err.context(format!(
+                            "problem upgrading {} from version {} to {}",
+                            name, stored, version
+                        )));
+                    }
+                }
+                // New AppModule
+                None => {}
+            }
+            self.pending_versions.push((name.clone(), version));
+        }
+        self.deliver_cache.replace(cache.into_cache());
+        Ok(())
+    }
+
+    // Apply the scheduled upgrade plan at its height, or halt if this binary
+    // doesn't have its handler. See the `upgrade` module
+    fn run_upgrade_plan(&mut self) {
+        let snap = self.db.snapshot();
+        let mut cache = store::StoreView::wrap(&snap, self.deliver_cache.take().unwrap());
+        let plan = upgrade::current_plan(&cache);
+        let handler = plan
+            .as_ref()
+            .and_then(|p| self.upgrade_handlers.get(&p.name));
+        let height = self.block.height;
+
+        match upgrade::upgrade_action(plan.as_ref(), height, handler.is_some()) {
+            upgrade::UpgradeAction::Continue => {}
+            upgrade::UpgradeAction::Apply => {
+                let plan = plan.expect("upgrade plan");
+                info!("applying upgrade {} at height {}", plan.name, height);
+                if let Err(err) = handler.expect("upgrade handler")(&mut cache) {
+                    panic!("problem applying upgrade {}: {}", plan.name, err);
+                }
+                upgrade::mark_applied(&plan, height, &mut cache);
+            }
+            upgrade::UpgradeAction::Halt => {
+                let plan = plan.expect("upgrade plan");
+                panic!(
+                    "UPGRADE \"{}\" NEEDED at height {}: {}",
+                    plan.name, plan.height, plan.info
+                );
+            }
+            upgrade::UpgradeAction::Early => {
+                let plan = plan.expect("upgrade plan");
+                panic!(
+                    "binary has the handler for upgrade \"{}\" before its height {}",
+                    plan.name, plan.height
+                );
+            }
+        }
+        self.deliver_cache.replace(cache.into_cache());
+    }
+
+    // Call the begin or end block hook of each AppModule in order. The changes of a
+    // failed or panicking hook are undone. Returns the events
+    fn run_block_hooks(&mut self, begin: bool) -> Vec<Event> {
+        let snap = self.db.snapshot();
+        let mut cache = store::StoreView::wrap(&snap, self.deliver_cache.take().unwrap());
+
+        let mut events = Vec::new();
+        for name in &self.order {
+            let app = self.appmodules.get(name).expect("app module");
+            let mut ctx = self.prepare_context(Context::from_parts(Vec::new(), name, Vec::new()));
+            if !begin {
+                ctx = ctx.with_validator_updates();
+            }
+            let checkpoint = cache.checkpoint();
+            let result = catch_panic(name, || {
+                if begin {
+                    app.begin_block(&ctx, &mut cache)
+                } else {
+                    app.end_block(&ctx, &mut cache)
+                }
+            });
+            match result {
+                Ok(()) => {
+                    events.extend(ctx.get_events().into_vec());
+                    self.pending_validators.extend(ctx.take_validator_updates());
+                }
+                Err(err) => {
+                    cache.rollback_to(checkpoint);
+                    warn!("block hook for {} failed: {}", name, err);
+                }
+            }
+        }
+        self.deliver_cache.replace(cache.into_cache());
+        events
+    }
+
+    // Called by abci.commit() below with a fork of the block's changes. The chain
+    // state is added to the fork and everything is merged as a single patch, so a
+    // block is never half applied.  Returns the new apphash
+    fn update_state(&mut self, fork: Fork) -> Vec<u8> {
         // Use the root aggregator from Exonum.
         // Probably don't really need this as we're using 1
-        // tree for all...
-        let aggregator = SystemSchema::new(fork).state_aggregator();
+        // tree for all...  The aggregator is updated when the fork
+        // is turned into a patch, so read it from the patch
+        let patch = fork.into_patch();
+        let aggregator = SystemSchema::new(&patch as &dyn Snapshot).state_aggregator();
         let statehash = aggregator.object_hash().as_bytes().to_vec();
 
-        // Update the Rapido chain state
-        let mut rapidostate = RapidoSchema::new(fork);
+        // Update the Rapido chain state. These entries aren't part of the aggregator
+        let fork = Fork::from(patch);
+        let mut rapidostate = RapidoSchema::new(&fork);
         let laststate = rapidostate.get_chain_state().unwrap_or_default();
         let new_height = laststate.height + 1;
         rapidostate.save_chain_state(new_height, statehash.clone());
+        for (name, version) in self.pending_versions.drain(..) {
+            rapidostate.save_module_version(&name, version);
+        }
+        for validator in self.pending_validators.drain(..) {
+            rapidostate.save_validator(&validator);
+        }
+        self.db.merge(fork.into_patch()).expect("abci:commit");
         // Return the new apphash
-        statehash.clone()
+        statehash
     }
+
+    /// Run init_chain, returning an error naming the AppModule that failed.
+    /// The ABCI `init_chain` panics on an error.
+    pub fn try_init_chain(
+        &mut self,
+        req: &RequestInitChain,
+    ) -> Result<ResponseInitChain, InitError> {
+        // Signed txs are bound to the chain id
+        if req.get_chain_id().is_empty() {
+            return Err(InitError::EmptyChainId);
+        }
+        let mut sections = split_app_state(&req.app_state_bytes).map_err(InitError::AppState)?;
+        for name in sections.keys() {
+            if !self.appmodules.contains_key(name) {
+                return Err(InitError::UnknownModule(name.clone()));
+            }
+        }
+
+        let snap = self.db.snapshot();
+        let mut cache = store::StoreView::wrap(&snap, Default::default());
+        for name in &self.order {
+            let app = self.appmodules.get(name).expect("app module");
+            let genesis = sections.remove(name).unwrap_or_default();
+            if let Err(error) = app.initialize(&genesis, &mut cache) {
+                return Err(InitError::Module {
+                    name: name.clone(),
+                    error,
+                });
+            }
+        }
+
+        let fork = self.db.fork();
+        cache.commit(&fork);
+
+        // Save the chain id, the validators and the initial version of each AppModule
+        let mut schema = RapidoSchema::new(&fork);
+        schema.save_chain_id(req.get_chain_id());
+        for validator in req.get_validators() {
+            schema.save_validator(&ValidatorPower::from_abci(validator));
+        }
+        self.chain.chain_id = req.get_chain_id().into();
+        for name in &self.order {
+            let app = self.appmodules.get(name).expect("app module");
+            schema.save_module_version(name, app.version());
+        }
+        self.db.merge(fork.into_patch()).expect("init_chain:commit");
+
+        // Tendermint keeps the genesis validators when the response has none
+        Ok(ResponseInitChain::new())
+    }
+
+    // The latest committed state
+    pub(crate) fn snapshot(&self) -> Box<dyn Snapshot> {
+        self.db.snapshot()
+    }
+
+    // Copy the committed state. See `TestKit::snapshot`
+    pub(crate) fn save_state(&self) -> schema::SavedState {
+        let snapshot = self.db.snapshot();
+        schema::save_state(&snapshot)
+    }
+
+    // Replace the committed state with the saved state, dropping the changes
+    // of the current block
+    pub(crate) fn restore_state(&mut self, state: &schema::SavedState) {
+        let fork = self.db.fork();
+        schema::restore_state(&fork, state);
+        self.db.merge(fork.into_patch()).expect("restore state");
+
+        self.chain.chain_id = state.chain_id().unwrap_or_default().into();
+        self.check_cache.replace(Default::default());
+        self.deliver_cache.replace(Default::default());
+        self.pending_versions.clear();
+        self.pending_validators.clear();
+        self.block_events.clear();
+        self.tx_index = 0;
+    }
+
+    // Describe the registered AppModules, in order
+    fn module_infos(&self) -> Vec<ModuleInfo> {
+        self.order
+            .iter()
+            .map(|name| {
+                let app = self.appmodules.get(name).expect("app module");
+                ModuleInfo {
+                    name: name.clone(),
+                    version: app.version(),
+                    query_paths: app.query_paths(),
+                }
+            })
+            .collect()
+    }
+
+    // Run a tx as deliver_tx would, against the committed state, and discard
+    // the changes. The authenticator isn't called, so the tx doesn't need to be
+    // signed or have the next nonce: estimate the gas before signing
+    fn simulate(
+        &self,
+        tx: &SignedTransaction,
+        snapshot: &Box<dyn Snapshot>,
+    ) -> anyhow::Result<SimulationResult, anyhow::Error> {
+        ensure!(!tx.chain_id().is_empty(), "missing chain id");
+        ensure!(
+            tx.chain_id() == self.chain.chain_id,
+            "wrong chain id: {}",
+            tx.chain_id()
+        );
+        let app = match self.appmodules.get(tx.appname()) {
+            Some(app) => app,
+            None => bail!("No registered AppModule found for name: {}", tx.appname()),
+        };
+
+        let mut view = store::StoreView::wrap_snapshot(snapshot);
+        fees::resolve_fee_payer(tx, &view)?;
+        if tx.is_unsigned() {
+            self.check_unsigned(tx, &view)?;
+        }
+        let ctx = self
+            .prepare_context(tx.into_context())
+            .with_gas_limit(self.gas_limit);
+        self.execute_tx(app.as_ref(), &ctx, &mut view)?;
+
+        let mut events = ctx.get_events().into_vec();
+        events.push(tx_event(tx));
+        Ok(SimulationResult {
+            gas_used: ctx.gas_meter().consumed(),
+            events: events.iter().map(SimulatedEvent::from_abci).collect(),
+        })
+    }
+
+    // Handle the queries reserved for the framework: 'rapido/{path}'
+    fn rapido_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        snapshot: &Box<dyn Snapshot>,
+    ) -> anyhow::Result<QueryResponse, anyhow::Error> {
+        // The next nonce of the account (key) on a lane: '/nonce' (lane 0) or '/nonce/<lane>'
+        if path == "/nonce" || path.starts_with("/nonce/") {
+            let lane: u8 = match path.strip_prefix("/nonce/") {
+                Some(lane) => lane.parse()?,
+                None => 0,
+            };
+            ensure!(!key.is_empty(), "missing the account");
+            let view = store::StoreView::wrap_snapshot(snapshot);
+            let nonce = NonceStore::new()
+                .query((key, lane), &view)
+                .unwrap_or_default();
+            return Ok(nonce.try_to_vec()?.into());
+        }
+        match path {
+            // The latest committed height and apphash
+            "/apphash" => {
+                let state = RapidoSchema::new(snapshot)
+                    .get_chain_state()
+                    .unwrap_or_default();
+                Ok(QueryResponse::new(state.try_to_vec()?).with_height(state.height))
+            }
+            // The validators and their power
+            "/validators" => Ok(RapidoSchema::new(snapshot)
+                .get_validators()
+                .try_to_vec()?
+                .into()),
+            // The registered AppModules
+            "/modules" => Ok(self.module_infos().try_to_vec()?.into()),
+            // OpenAPI description of the AppModules' query routes
+            "/openapi" => {
+                QueryResponse::encode(&Json(openapi::openapi_document(&self.module_infos())))
+            }
+            // Run the tx (key) without committing it
+            "/simulate" => {
+                let tx = SignedTransaction::decode(&key)?;
+                Ok(self.simulate(&tx, snapshot)?.try_to_vec()?.into())
+            }
+            _ => bail!("{:} not found", path),
+        }
+    }
+}
+
+// Sort AppModule names so each comes after its dependencies, otherwise keeping the
+// given order. Panics if a dependency is missing or there's a cycle
+fn sort_by_dependencies(
+    order: Vec<String>,
+    appmodules: &HashMap<String, Box<dyn AppModule>>,
+) -> Vec<String> {
+    let mut remaining: Vec<(String, Vec<String>)> = Vec::new();
+    for name in order {
+        let deps = appmodules.get(&name).expect("app module").dependencies();
+        for dep in &deps {
+            if !appmodules.contains_key(dep) {
+                panic!("AppModule {} depends on missing AppModule {}", name, dep);
+            }
+        }
+        remaining.push((name, deps));
+    }
+
+    let mut sorted: Vec<String> = Vec::new();
+    while !remaining.is_empty() {
+        let next = remaining
+            .iter()
+            .position(|(_, deps)| deps.iter().all(|d| sorted.contains(d)));
+        match next {
+            Some(i) => sorted.push(remaining.remove(i).0),
+            None => panic!("AppModule dependencies have a cycle"),
+        }
+    }
+    sorted
+}
+
+// Split the genesis app_state, a JSON object keyed by AppModule name, into
+// the JSON encoded section for each AppModule. An empty app_state has no sections
+fn split_app_state(app_state: &[u8]) -> anyhow::Result<HashMap<String, Vec<u8>>, anyhow::Error> {
+    let mut sections = HashMap::new();
+    if app_state.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(sections);
+    }
+
+    let value: serde_json::Value = serde_json::from_slice(app_state)?;
+    let object = match value {
+        serde_json::Value::Object(o) => o,
+        serde_json::Value::Null => return Ok(sections),
+        _ => bail!("expected the app_state to be a JSON object"),
+    };
+    for (name, section) in object {
+        sections.insert(name, serde_json::to_vec(&section)?);
+    }
+    Ok(sections)
+}
+
+// Standard 'rapido_tx' event added to every successful deliver_tx so explorers
+// can index transactions. Not 'tx': Tendermint indexes 'tx.hash' and 'tx.height'
+// itself
+fn tx_event(tx: &SignedTransaction) -> Event {
+    let hash = tx.id_hex();
+    // Account ids are usually text (e.g. base58), fallback to hex
+    let sender = String::from_utf8(tx.sender()).unwrap_or_else(|e| hex::encode(e.into_bytes()));
+    let nonce = tx.nonce().to_string();
+    types::create_event(
+        "rapido_tx".into(),
+        &[
+            ("hash", &hash),
+            ("sender", &sender),
+            ("app", tx.appname()),
+            ("nonce", &nonce),
+        ],
+    )
 }
 
 // Parse a query route:  It expects query routes to be in the
@@ -300,35 +1172,20 @@ impl abci::Application for Node {
         let mut resp = ResponseInfo::new();
         resp.set_data(String::from(NAME));
         resp.set_version(String::from(req.get_version()));
+        resp.set_app_version(self.chain.app_version);
         resp.set_last_block_height(state.height);
         resp.set_last_block_app_hash(state.apphash.clone());
         resp
     }
 
     // Ran once on the initial (genesis) of the application.
-    // AppModules can implement `initialize` to load their own initial state.
-    fn init_chain(&mut self, _req: &RequestInitChain) -> ResponseInitChain {
-        let snap = self.db.snapshot();
-        let mut cache = store::StoreView::wrap(&snap, self.deliver_cache.take().unwrap());
-
-        for (_, app) in &self.appmodules {
-            let result = app.initialize(&mut cache);
-
-            if result.is_err() {
-                panic!("problem initializing chain with genesis data");
-            }
+    // AppModules can implement `initialize` to load their own initial state
+    // from their section of the genesis app_state
+    fn init_chain(&mut self, req: &RequestInitChain) -> ResponseInitChain {
+        match self.try_init_chain(req) {
+            Ok(resp) => resp,
+            Err(err) => panic!("{}", err),
         }
-
-        let fork = self.db.fork();
-        cache.commit(&fork);
-        //let aggregator = SystemSchema::new(&fork).state_aggregator();
-        //let statehash = aggregator.object_hash().as_bytes().to_vec();
-        //let resp = ResponseInitChain::new();
-        self.db.merge(fork.into_patch()).expect("init_chain:commit");
-
-        // TODO: Put validators in state
-        self.deliver_cache.replace(Default::default());
-        ResponseInitChain::new()
     }
 
     // handle rpc queries
@@ -341,6 +1198,7 @@ impl abci::Application for Node {
             Some(tuple) => tuple,
             None => {
                 response.code = 1u32;
+                response.codespace = RAPIDO_CODESPACE.into();
                 response.key = req.data.clone();
                 response.log = "Malformed query path".into();
                 return response;
@@ -349,35 +1207,62 @@ impl abci::Application for Node {
 
         let snapshot = self.db.snapshot();
         let cache = store::StoreView::wrap_snapshot(&snapshot);
+        let height = RapidoSchema::new(&snapshot)
+            .get_chain_state()
+            .unwrap_or_default()
+            .height;
+        response.height = height;
 
-        // TODO: Add rapdio reserved queries:
-        // /rapido/apphash
-        // /rapido/validators
+        // Reserved rapido queries, else call the AppModule
+        let (codespace, result) = if appname == RESERVED_APP_NAME {
+            (
+                RAPIDO_CODESPACE,
+                self.rapido_query(query_path, key, &snapshot),
+            )
+        } else {
+            // Check if a app exists for this name
+            if !self.appmodules.contains_key(appname) {
+                response.code = 1u32;
+                response.codespace = RAPIDO_CODESPACE.into();
+                response.log = format!("Query: cannot find appname: {}", appname);
+                return response;
+            }
 
-        // Check if a app exists for this name
-        if !self.appmodules.contains_key(appname) {
-            response.code = 1u32;
-            response.log = format!("Query: cannot find appname: {}", appname);
-            return response;
-        }
+            // Call AppModule handle_query
+            // we unwrap here, because we already checked for it above.
+            let app = self.appmodules.get(appname).unwrap();
+            let view = QueryView::new(&cache).with_height(height);
+            let result = catch_panic(appname, || app.handle_query(query_path, key, &view));
+            (appname, result)
+        };
 
-        // Call AppModule handle_query
         // We return 0 if all is bueno, else 1
-        match self
-            .appmodules
-            .get(appname)
-            .unwrap() // <= we unwrap here, because we already checked for it above.
-            // So, panic here if something else occurs
-            .handle_query(query_path, key, &cache)
-        {
-            Ok(value) => {
+        match result {
+            Ok(result) => {
                 response.code = 0;
-                response.value = value;
+                response.value = result.value;
                 response.key = req.data.clone();
+                response.log = result.log;
+                response.info = result.encoding.as_str().into();
+                response.height = result.height.unwrap_or(height);
+                if let Some(data) = result.proof {
+                    let mut op = ProofOp::new();
+                    op.set_field_type(PROOF_OP_TYPE.into());
+                    op.set_key(req.data.clone());
+                    op.set_data(data);
+                    response.mut_proof().mut_ops().push(op);
+
+                    let state_proof = schema::get_state_proof(&snapshot);
+                    let mut op = ProofOp::new();
+                    op.set_field_type(STATE_PROOF_OP_TYPE.into());
+                    op.set_data(serde_json::to_vec(&state_proof).expect("encoding state proof"));
+                    response.mut_proof().mut_ops().push(op);
+                }
                 response
             }
             Err(msg) => {
-                response.code = 1u32;
+                response.code = error_code(&msg);
+                response.codespace = codespace.into();
                 response.key = req.data.clone();
                 response.set_log(msg.to_string());
                 response
@@ -389,12 +1274,16 @@ impl abci::Application for Node {
     fn check_tx(&mut self, req: &RequestCheckTx) -> ResponseCheckTx {
         let mut resp = ResponseCheckTx::new();
         match self.run_tx(true, req.tx.clone()) {
-            Ok(_) => {
+            Ok(result) => {
                 resp.set_code(0);
+                resp.set_log(result.log);
+                resp.set_gas_used(result.gas_used as i64);
+                resp.events = RepeatedField::from_vec(result.events);
                 resp
             }
             Err(msg) => {
-                resp.set_code(1u32);
+                resp.set_code(error_code(&msg));
+                resp.set_codespace(self.tx_codespace(&req.tx));
                 resp.set_log(msg.to_string());
                 resp
             }
@@ -404,27 +1293,144 @@ impl abci::Application for Node {
     // Well you made is this far, let's see if you can influence app state.
     fn deliver_tx(&mut self, req: &RequestDeliverTx) -> ResponseDeliverTx {
         let mut resp = ResponseDeliverTx::new();
+        let index = self.tx_index;
+        self.tx_index += 1;
         match self.run_tx(false, req.tx.clone()) {
-            Ok(events) => {
+            Ok(result) => {
+                self.collect_events(sink::EventSource::Tx(index), &result.events);
                 resp.set_code(0);
-                resp.events = events;
+                resp.set_log(result.log);
+                resp.set_gas_used(result.gas_used as i64);
+                resp.events = RepeatedField::from_vec(result.events);
                 resp
             }
             Err(msg) => {
-                resp.set_code(1u32);
+                resp.set_code(error_code(&msg));
+                resp.set_codespace(self.tx_codespace(&req.tx));
                 resp.set_log(msg.to_string());
                 resp
             }
         }
     }
 
-    fn begin_block(&mut self, _req: &RequestBeginBlock) -> ResponseBeginBlock {
-        ResponseBeginBlock::new()
+    fn begin_block(&mut self, req: &RequestBeginBlock) -> ResponseBeginBlock {
+        let header = req.get_header();
+        self.block = BlockInfo {
+            height: header.get_height(),
+            time: header.get_time().get_seconds(),
+            hash: req.get_hash().to_vec(),
+            proposer: header.get_proposer_address().to_vec(),
+            votes: req
+                .get_last_commit_info()
+                .get_votes()
+                .iter()
+                .map(|v| VoteInfo {
+                    address: v.get_validator().get_address().to_vec(),
+                    power: v.get_validator().get_power(),
+                    signed: v.get_signed_last_block(),
+                })
+                .collect(),
+            evidence: req
+                .get_byzantine_validators()
+                .iter()
+                .map(|e| Misbehavior {
+                    kind: e.get_field_type().into(),
+                    address: e.get_validator().get_address().to_vec(),
+                    power: e.get_validator().get_power(),
+                    height: e.get_height(),
+                })
+                .collect(),
+        };
+
+        // A failed upgrade leaves the state inconsistent: halt the node
+        if !self.versions_checked {
+            if let Err(err) = self.run_upgrades() {
+                panic!("{:#}", err);
+            }
+            self.versions_checked = true;
+        }
+        self.run_upgrade_plan();
+
+        let events = self.run_block_hooks(true);
+        self.collect_events(sink::EventSource::BeginBlock, &events);
+        let mut resp = ResponseBeginBlock::new();
+        resp.events = RepeatedField::from_vec(events);
+        resp
     }
 
-    fn end_block(&mut self, _req: &RequestEndBlock) -> ResponseEndBlock {
-        // do validator updates
-        ResponseEndBlock::new()
+    fn end_block(&mut self, req: &RequestEndBlock) -> ResponseEndBlock {
+        let snap = self.db.snapshot();
+        let mut cache = store::StoreView::wrap(&snap, self.deliver_cache.take().unwrap());
+
+        // Dispatch any txs scheduled for this height
+        let mut events = Vec::new();
+        for scheduled in Scheduler::new().take(req.height, &mut cache) {
+            let app = match self.appmodules.get(&scheduled.app) {
+                Some(app) => app,
+                None => {
+                    warn!(
+                        "scheduled tx {}: no AppModule {}",
+                        scheduled.id, scheduled.app
+                    );
+                    continue;
+                }
+            };
+            let ctx = self
+                .prepare_context(Context::from_parts(
+                    scheduled.sender,
+                    &scheduled.app,
+                    scheduled.msg,
+                ))
+                .with_gas_limit(self.gas_limit);
+            let checkpoint = cache.checkpoint();
+            match self.execute_tx(app.as_ref(), &ctx, &mut cache) {
+                Ok(()) => events.extend(ctx.get_events().into_vec()),
+                Err(err) => {
+                    cache.rollback_to(checkpoint);
+                    warn!("scheduled tx {} failed: {}", scheduled.id, err);
+                }
+            }
+        }
+
+        // Run the recurring jobs due at this height
+        for job in Cron::new().due(req.height, &cache) {
+            let app = match self.appmodules.get(&job.app) {
+                Some(app) => app,
+                None => {
+                    warn!("cron job {}: no AppModule {}", job.name, job.app);
+                    continue;
+                }
+            };
+            let ctx = self
+                .prepare_context(Context::from_parts(Vec::new(), &job.app, job.msg))
+                .with_gas_limit(self.gas_limit);
+            let checkpoint = cache.checkpoint();
+            match self.execute_tx(app.as_ref(), &ctx, &mut cache) {
+                Ok(()) => events.extend(ctx.get_events().into_vec()),
+                Err(err) => {
+                    cache.rollback_to(checkpoint);
+                    warn!("cron job {} for {} failed: {}", job.name, job.app, err);
+                }
+            }
+        }
+        self.deliver_cache.replace(cache.into_cache());
+
+        // Call each AppModule
+        events.extend(self.run_block_hooks(false));
+        self.collect_events(sink::EventSource::EndBlock, &events);
+
+        // Tendermint rejects a validator updated twice in the block: keep the last update
+        let mut updates: Vec<&ValidatorPower> = Vec::new();
+        for update in self.pending_validators.iter().rev() {
+            if !updates.iter().any(|u| u.pub_key == update.pub_key) {
+                updates.push(update);
+            }
+        }
+        let mut resp = ResponseEndBlock::new();
+        resp.events = RepeatedField::from_vec(events);
+        resp.validator_updates =
+            RepeatedField::from_vec(updates.into_iter().rev().map(|u| u.to_abci()).collect());
+        resp
     }
 
     // Commit the txs and update app state
@@ -436,17 +1442,116 @@ impl abci::Application for Node {
         cache.commit(&fork);
 
         // new state root hash!
-        let apphash = self.update_state(&fork);
-        self.db
-            .merge(fork.into_patch())
-            .expect("abci:commit appstate");
+        let apphash = self.update_state(fork);
 
         // Refresh the caches
         self.deliver_cache.replace(Default::default());
         self.check_cache.replace(Default::default());
 
+        // Forward the block's events, off the consensus path
+        self.tx_index = 0;
+        let events = std::mem::take(&mut self.block_events);
+        if let Some(sink) = &self.event_sink {
+            sink.send(sink::CommittedEvents {
+                height: self.block.height,
+                app_hash: apphash.clone(),
+                events,
+            });
+        }
+
         let mut resp = ResponseCommit::new();
         resp.set_data(apphash);
         resp
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    type Upgrades = Arc<Mutex<Vec<u32>>>;
+
+    // Records the versions it's upgraded from
+    struct VersionApp {
+        version: u32,
+        fail: bool,
+        upgrades: Upgrades,
+    }
+
+    impl AppModule for VersionApp {
+        fn name(&self) -> String {
+            "versioned".into()
+        }
+
+        fn version(&self) -> u32 {
+            self.version
+        }
+
+        fn on_upgrade(&self, from_version: u32, _view: &mut StoreView) -> anyhow::Result<()> {
+            ensure!(!self.fail, "bad migration");
+            self.upgrades.lock().unwrap().push(from_version);
+            Ok(())
+        }
+
+        fn handle_tx(&self, _ctx: &Context, _view: &mut StoreView) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn handle_query(
+            &self,
+            _path: &str,
+            _key: Vec<u8>,
+            _view: &QueryView,
+        ) -> anyhow::Result<QueryResponse> {
+            bail!("no queries")
+        }
+    }
+
+    // Run a block with the version of the app on the db, after starting the chain
+    // if `init`
+    fn run_block(db: &Arc<dyn Database>, init: bool, version: u32, fail: bool) -> Upgrades {
+        let upgrades = Upgrades::default();
+        let mut builder = AppBuilder::new().with_app(VersionApp {
+            version,
+            fail,
+            upgrades: upgrades.clone(),
+        });
+        builder.db = db.clone();
+        let mut node = builder.node();
+        if init {
+            let mut req = RequestInitChain::new();
+            req.set_chain_id("test-chain".into());
+            node.init_chain(&req);
+        }
+        node.begin_block(&RequestBeginBlock::new());
+        node.end_block(&RequestEndBlock::new());
+        node.commit(&RequestCommit::new());
+        upgrades
+    }
+
+    #[test]
+    fn test_upgrade_only_when_the_version_increases() {
+        let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+        assert!(run_block(&db, true, 1, false).lock().unwrap().is_empty());
+        assert!(run_block(&db, false, 1, false).lock().unwrap().is_empty());
+        assert_eq!(vec![1], *run_block(&db, false, 2, false).lock().unwrap());
+        assert!(run_block(&db, false, 2, false).lock().unwrap().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "AppModule versioned version 1 is older than the stored version 2")]
+    fn test_downgrade_refused() {
+        let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+        run_block(&db, true, 2, false);
+        run_block(&db, false, 1, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "problem upgrading versioned from version 1 to 2: bad migration")]
+    fn test_failed_upgrade_halts() {
+        let db: Arc<dyn Database> = Arc::new(TemporaryDB::new());
+        run_block(&db, true, 1, false);
+        run_block(&db, false, 2, true);
+    }
+}
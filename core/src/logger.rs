@@ -0,0 +1,65 @@
+//! Logger scoped to an AppModule.  Log records use the AppModule name as the
+//! target, so operators can filter per app, e.g. `RUST_LOG=counter=debug`.
+//! Each record includes the block height and, when processing a tx, its hash.
+use std::fmt::Display;
+
+use log::Level;
+
+/// Returned by `Context::logger()`
+#[derive(Debug, Clone)]
+pub struct Logger {
+    target: String,
+    height: i64,
+    tx_hash: Option<String>,
+}
+
+impl Logger {
+    pub(crate) fn new(target: &str, height: i64, tx_hash: Option<String>) -> Self {
+        Self {
+            target: target.into(),
+            height,
+            tx_hash,
+        }
+    }
+
+    /// Log at the given level
+    pub fn log<M: Display>(&self, level: Level, msg: M) {
+        match &self.tx_hash {
+            Some(hash) => log!(
+                target: self.target.as_str(),
+                level,
+                "height={} tx={} {}",
+                self.height,
+                hash,
+                msg
+            ),
+            None => log!(
+                target: self.target.as_str(),
+                level,
+                "height={} {}",
+                self.height,
+                msg
+            ),
+        }
+    }
+
+    pub fn error<M: Display>(&self, msg: M) {
+        self.log(Level::Error, msg)
+    }
+
+    pub fn warn<M: Display>(&self, msg: M) {
+        self.log(Level::Warn, msg)
+    }
+
+    pub fn info<M: Display>(&self, msg: M) {
+        self.log(Level::Info, msg)
+    }
+
+    pub fn debug<M: Display>(&self, msg: M) {
+        self.log(Level::Debug, msg)
+    }
+
+    pub fn trace<M: Display>(&self, msg: M) {
+        self.log(Level::Trace, msg)
+    }
+}
@@ -2,6 +2,8 @@
 /// Implements BinaryValue and ObjectHash for the type.
 /// Note: The type to this macro must derive BorshSerialize/Deserialize.
 /// This is an adaptation of the macro from Exonum.
+/// Prefer `#[derive(StoreValue)]`, which also supports generic types and doesn't
+/// require the caller to depend on exonum.
 #[macro_export]
 macro_rules! impl_store_values {
     ($( $type:ty ),*) => {
@@ -25,3 +27,71 @@ macro_rules! impl_store_values {
         )*
     };
 }
+
+/// Generate an enum-based registry of the AppModule routes used by an application,
+/// so routes are checked by the compiler instead of compared as free-form strings.
+/// The string route is only used at the wire boundary (the `app` in a SignedTransaction).
+/// Example:
+/// ```ignore
+/// register_modules! {
+///     pub enum Routes {
+///         Counter => counter::APP_NAME,
+///         Accounts => "rapido.account",
+///     }
+/// }
+///
+/// let tx = TxBuilder::new(Routes::Counter).sender(id).msg(Msgs::Create).build()?;
+///
+/// // Fail fast on startup if the registered AppModules don't match the registry
+/// AppBuilder::new()
+///     .with_registry(Routes::NAMES)
+///     .with_app(CounterHandler {})
+///     .with_app(AccountModule::new(genesis))
+///     .run();
+/// ```
+#[macro_export]
+macro_rules! register_modules {
+    ($vis:vis enum $name:ident { $( $variant:ident => $route:expr ),* $(,)? }) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        $vis enum $name {
+            $( $variant ),*
+        }
+
+        impl $name {
+            /// The route names of all registered modules
+            pub const NAMES: &'static [&'static str] = &[ $( $route ),* ];
+
+            /// All registered modules
+            pub const ALL: &'static [$name] = &[ $( $name::$variant ),* ];
+
+            /// Return the route name used on the wire
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $( $name::$variant => $route ),*
+                }
+            }
+
+            /// Lookup a module by its route name
+            pub fn from_route(route: &str) -> Option<Self> {
+                $(
+                    if route == $route {
+                        return Some($name::$variant);
+                    }
+                )*
+                None
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.as_str())
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(route: $name) -> String {
+                route.as_str().into()
+            }
+        }
+    };
+}
@@ -0,0 +1,53 @@
+//! Nonce tracking for authenticators.  Each account has up to 256 independent
+//! nonce sequences ('lanes').  A tx selects its lane via `SignedTransaction::lane()`,
+//! so wallets can submit txs in parallel on different lanes without
+//! the mempool rejecting them for being out of order.
+use anyhow::ensure;
+
+use crate::{AccountId, SignedTransaction, Store, StoreView};
+
+const NONCE_STORE_NAME: &str = "rapido.nonce";
+
+/// Store of the next expected nonce keyed by (account, lane)
+pub struct NonceStore;
+impl Store for NonceStore {
+    type Key = (AccountId, u8);
+    type Value = u64;
+
+    fn name(&self) -> String {
+        NONCE_STORE_NAME.into()
+    }
+}
+
+impl NonceStore {
+    pub fn new() -> Self {
+        NonceStore {}
+    }
+
+    /// Return the next expected nonce for the account on the given lane
+    pub fn current(&self, account: AccountId, lane: u8, view: &StoreView) -> u64 {
+        self.get((account, lane), view).unwrap_or_default()
+    }
+
+    /// Check the nonce of the transaction matches the expected nonce for its lane
+    pub fn check(
+        &self,
+        tx: &SignedTransaction,
+        view: &StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let expected = self.current(tx.sender(), tx.lane(), view);
+        ensure!(
+            expected == tx.nonce(),
+            "nonce don't match. expected {} on lane {}",
+            expected,
+            tx.lane()
+        );
+        Ok(())
+    }
+
+    /// Increment the nonce for the sender on the lane used by the transaction
+    pub fn increment(&self, tx: &SignedTransaction, view: &mut StoreView) {
+        let next = self.current(tx.sender(), tx.lane(), view) + 1;
+        self.put((tx.sender(), tx.lane()), next, view)
+    }
+}
@@ -0,0 +1,186 @@
+//! OpenAPI description of the registered query routes, returned (JSON) by the
+//! `rapido/openapi` query.  It describes the routes as served by the client's
+//! REST gateway: `GET /<app>/<path>?key=<hex>` for each of an AppModule's
+//! `query_paths`, with ':name' parameters as path parameters, and `POST /txs`.
+//! Values are described generically: the encoding of a value is only known when
+//! it's returned.
+use serde_json::{json, Map, Value};
+
+use crate::ModuleInfo;
+
+const OPENAPI_VERSION: &str = "3.0.3";
+
+// Convert a path pattern to an OpenAPI path and its parameters:
+// '/orders/:market/:id' -> ('/orders/{market}/{id}', ['market', 'id'])
+fn openapi_path(app: &str, pattern: &str) -> (String, Vec<String>) {
+    let mut path = format!("/{}", app);
+    let mut params = Vec::new();
+    for segment in pattern.split('/').filter(|s| !s.is_empty()) {
+        match segment.strip_prefix(':') {
+            Some(name) => {
+                path.push_str(&format!("/{{{}}}", name));
+                params.push(name.to_string());
+            }
+            None => {
+                path.push('/');
+                path.push_str(segment);
+            }
+        }
+    }
+    (path, params)
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Error"}}},
+    })
+}
+
+fn query_operation(app: &str, pattern: &str, params: &[String]) -> Value {
+    let mut parameters: Vec<Value> = params
+        .iter()
+        .map(|name| {
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": {"type": "string"},
+            })
+        })
+        .collect();
+    parameters.push(json!({
+        "name": "key",
+        "in": "query",
+        "required": false,
+        "description": "The query key, hex encoded",
+        "schema": {"type": "string"},
+    }));
+    json!({
+        "tags": [app],
+        "summary": format!("Query {} {}", app, pattern),
+        "parameters": parameters,
+        "responses": {
+            "200": {
+                "description": "The value. JSON values are returned as is",
+                "content": {"application/json": {"schema": {"oneOf": [
+                    {"$ref": "#/components/schemas/EncodedValue"},
+                    {}
+                ]}}},
+            },
+            "400": error_response("Bad request"),
+            "422": error_response("Rejected by the app, e.g. not found"),
+            "502": error_response("The node can't be reached"),
+        },
+    })
+}
+
+fn txs_operation() -> Value {
+    let request = json!({
+        "type": "object",
+        "required": ["tx"],
+        "properties": {
+            "tx": {"type": "string", "description": "The signed transaction, hex encoded"},
+            "mode": {"type": "string", "enum": ["async", "sync", "commit"], "default": "sync"},
+        },
+    });
+    let result = json!({
+        "type": "object",
+        "properties": {
+            "hash": {"type": "string"},
+            "code": {"type": "integer"},
+            "log": {"type": "string"},
+            "height": {"type": "integer", "nullable": true},
+        },
+    });
+    json!({
+        "post": {
+            "summary": "Broadcast a transaction",
+            "requestBody": {
+                "required": true,
+                "content": {"application/json": {"schema": request}},
+            },
+            "responses": {
+                "200": {
+                    "description": "The result of the broadcast. A code other than 0 is a failed tx",
+                    "content": {"application/json": {"schema": result}},
+                },
+                "400": error_response("Bad request"),
+                "502": error_response("The node can't be reached"),
+            },
+        },
+    })
+}
+
+/// Generate the OpenAPI document of the AppModules' query routes
+pub(crate) fn openapi_document(modules: &[ModuleInfo]) -> Value {
+    let mut paths = Map::new();
+    paths.insert("/txs".into(), txs_operation());
+    for module in modules {
+        for pattern in &module.query_paths {
+            let (path, params) = openapi_path(&module.name, pattern);
+            paths.insert(
+                path,
+                json!({ "get": query_operation(&module.name, pattern, &params) }),
+            );
+        }
+    }
+    let tags: Vec<Value> = modules
+        .iter()
+        .map(|m| json!({"name": m.name, "description": format!("Version {}", m.version)}))
+        .collect();
+
+    let encoded_value = json!({
+        "type": "object",
+        "properties": {
+            "encoding": {"type": "string", "enum": ["borsh", "raw"]},
+            "value": {"type": "string", "description": "The value, hex encoded"},
+        },
+    });
+    let error = json!({
+        "type": "object",
+        "properties": {"error": {"type": "string"}},
+    });
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {"title": "rapido", "version": env!("CARGO_PKG_VERSION")},
+        "tags": tags,
+        "paths": paths,
+        "components": {"schemas": {"EncodedValue": encoded_value, "Error": error}},
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_document() {
+        assert_eq!(
+            (
+                "/orders/{market}/{id}".to_string(),
+                vec!["market".to_string(), "id".to_string()]
+            ),
+            openapi_path("orders", "/:market/:id")
+        );
+        assert_eq!(
+            ("/counter".to_string(), vec![]),
+            openapi_path("counter", "/")
+        );
+
+        let doc = openapi_document(&[ModuleInfo {
+            name: "orders".into(),
+            version: 2,
+            query_paths: vec!["/".into(), "/book/:market".into()],
+        }]);
+        assert_eq!(OPENAPI_VERSION, doc["openapi"]);
+        assert!(doc["paths"]["/txs"]["post"].is_object());
+        assert!(doc["paths"]["/orders"]["get"].is_object());
+        let params = &doc["paths"]["/orders/book/{market}"]["get"]["parameters"];
+        assert_eq!("market", params[0]["name"]);
+        assert_eq!("path", params[0]["in"]);
+        assert_eq!("key", params[1]["name"]);
+        assert_eq!("Version 2", doc["tags"][0]["description"]);
+    }
+}
@@ -0,0 +1,111 @@
+//! Catch panics in AppModule handlers.  A panic in a handler would otherwise
+//! kill the ABCI process and halt the node. Instead, the panic is logged with a
+//! backtrace and returned as a `HandlerPanic` error. Responses use `PANIC_ERROR_CODE`.
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+/// Response code of a tx or query that failed because the handler panicked
+pub const PANIC_ERROR_CODE: u32 = 2;
+
+/// Error returned when an AppModule handler panics
+#[derive(Debug)]
+pub struct HandlerPanic {
+    /// The AppModule that panicked
+    pub app: String,
+    /// The panic message
+    pub message: String,
+}
+
+impl fmt::Display for HandlerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AppModule {} panicked: {}", self.app, self.message)
+    }
+}
+
+impl std::error::Error for HandlerPanic {}
+
+thread_local! {
+    // > 0 while running a handler via `catch_panic`
+    static CATCHING: Cell<usize> = Cell::new(0);
+    // Backtrace of the last caught panic, set by the hook
+    static BACKTRACE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+static HOOK: Once = Once::new();
+
+// Wrap the current panic hook to capture the backtrace of panics we catch.
+// Other panics go to the original hook
+fn install_hook() {
+    HOOK.call_once(|| {
+        let default = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.with(|c| c.get()) > 0 {
+                let trace = Backtrace::force_capture().to_string();
+                BACKTRACE.with(|b| *b.borrow_mut() = Some(trace));
+            } else {
+                default(info)
+            }
+        }));
+    });
+}
+
+/// Run an AppModule handler, converting a panic into a `HandlerPanic` error
+pub(crate) fn catch_panic<T, F>(app: &str, f: F) -> anyhow::Result<T, anyhow::Error>
+where
+    F: FnOnce() -> anyhow::Result<T, anyhow::Error>,
+{
+    install_hook();
+    CATCHING.with(|c| c.set(c.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|c| c.set(c.get() - 1));
+
+    result.unwrap_or_else(|payload| {
+        let message = if let Some(s) = payload.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+        let trace = BACKTRACE
+            .with(|b| b.borrow_mut().take())
+            .unwrap_or_default();
+        error!("AppModule {} panicked: {}\n{}", app, message, trace);
+        Err(HandlerPanic {
+            app: app.into(),
+            message,
+        }
+        .into())
+    })
+}
+
+/// The response code for a failed tx or query
+pub(crate) fn error_code(err: &anyhow::Error) -> u32 {
+    if err.downcast_ref::<HandlerPanic>().is_some() {
+        PANIC_ERROR_CODE
+    } else {
+        1u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_panic() {
+        assert_eq!(5, catch_panic("ok", || Ok(5)).unwrap());
+
+        let err = catch_panic::<(), _>("bad", || panic!("boom")).unwrap_err();
+        assert_eq!(PANIC_ERROR_CODE, error_code(&err));
+        let p = err.downcast_ref::<HandlerPanic>().unwrap();
+        assert_eq!("bad", p.app);
+        assert_eq!("boom", p.message);
+
+        let err = catch_panic::<(), _>("bad", || anyhow::bail!("nope")).unwrap_err();
+        assert_eq!(1, error_code(&err));
+    }
+}
@@ -0,0 +1,211 @@
+//! On-chain parameters. AppModules keep tunable values (fee minimums, rate limits,
+//! inflation, ...) in the `Params` store, namespaced by AppModule name, instead of
+//! compiling them into the binary. Values are Borsh encoded.
+//! Example, in an AppModule:
+//! ```ignore
+//! let min_fee: u64 = Params::new(APP_NAME).get_or("min_fee", 10, view)?;
+//! ```
+//! Values are changed via a `ParamsMsgs::Set` to the `ParamsModule`, which only
+//! accepts it from the configured authority, e.g. a governance AppModule.
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{AccountId, AppModule, Context, QueryResponse, QueryView, Store, StoreView};
+
+/// Name of the params AppModule
+pub const PARAMS_APP_NAME: &str = "rapido.params";
+const PARAMS_STORE_NAME: &str = "rapido.params.store";
+
+/// Store of encoded parameter values keyed by (module, key)
+pub struct ParamsStore;
+impl Store for ParamsStore {
+    type Key = (String, String);
+    type Value = Vec<u8>;
+
+    fn name(&self) -> String {
+        PARAMS_STORE_NAME.into()
+    }
+}
+
+impl ParamsStore {
+    pub fn new() -> Self {
+        ParamsStore {}
+    }
+}
+
+/// Typed access to the parameters of an AppModule
+pub struct Params {
+    module: String,
+}
+
+impl Params {
+    /// Access the parameters of the named AppModule
+    pub fn new(module: &str) -> Self {
+        Self {
+            module: module.into(),
+        }
+    }
+
+    /// Return the value of the parameter, if set
+    pub fn get<T: BorshDeserialize>(
+        &self,
+        key: &str,
+        view: &StoreView,
+    ) -> anyhow::Result<Option<T>, anyhow::Error> {
+        match ParamsStore::new().get((self.module.clone(), key.into()), view) {
+            Some(raw) => Ok(Some(T::try_from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Return the value of the parameter or `default` if it's not set
+    pub fn get_or<T: BorshDeserialize>(
+        &self,
+        key: &str,
+        default: T,
+        view: &StoreView,
+    ) -> anyhow::Result<T, anyhow::Error> {
+        Ok(self.get(key, view)?.unwrap_or(default))
+    }
+
+    /// Set the value of the parameter. Usually called in `initialize` to set defaults
+    pub fn set<T: BorshSerialize>(
+        &self,
+        key: &str,
+        value: &T,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        self.set_raw(key, value.try_to_vec()?, view);
+        Ok(())
+    }
+
+    fn set_raw(&self, key: &str, value: Vec<u8>, view: &mut StoreView) {
+        ParamsStore::new().put((self.module.clone(), key.into()), value, view)
+    }
+}
+
+/// Messages to the `ParamsModule`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum ParamsMsgs {
+    /// Set the Borsh encoded `value` of a parameter
+    Set {
+        module: String,
+        key: String,
+        value: Vec<u8>,
+    },
+}
+
+// Who can change parameters. Also used by the `UpgradeModule`
+pub(crate) enum Authority {
+    Account(AccountId),
+    Module(String),
+}
+
+impl Authority {
+    // Is the tx from the account, or dispatched by the AppModule?
+    pub(crate) fn allows(&self, ctx: &Context) -> bool {
+        match self {
+            Authority::Account(account) => ctx.caller().is_none() && ctx.sender() == *account,
+            Authority::Module(app) => ctx.caller() == Some(app.as_str()),
+        }
+    }
+}
+
+/// AppModule to change parameters. Add it via `AppBuilder::with_app`.
+pub struct ParamsModule {
+    authority: Authority,
+}
+
+impl ParamsModule {
+    /// Only accept changes sent by the account
+    pub fn with_authority(account: impl Into<AccountId>) -> Self {
+        Self {
+            authority: Authority::Account(account.into()),
+        }
+    }
+
+    /// Only accept changes dispatched by the AppModule, e.g. governance.
+    /// See `Context::dispatch`
+    pub fn governed_by(app: &str) -> Self {
+        Self {
+            authority: Authority::Module(app.into()),
+        }
+    }
+
+    fn is_authorized(&self, ctx: &Context) -> bool {
+        self.authority.allows(ctx)
+    }
+}
+
+impl AppModule for ParamsModule {
+    fn name(&self) -> String {
+        PARAMS_APP_NAME.into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        ensure!(self.is_authorized(ctx), "not authorized to change params");
+        match ctx.decode_msg()? {
+            ParamsMsgs::Set { module, key, value } => {
+                Params::new(&module).set_raw(&key, value, view);
+                Ok(())
+            }
+        }
+    }
+
+    // Query the encoded value of a parameter: 'rapido.params/{module}/{key}'
+    fn handle_query(
+        &self,
+        path: &str,
+        _key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        if parts.len() != 2 {
+            bail!("{:} not found", path);
+        }
+        match ParamsStore::new().query((parts[0].into(), parts[1].into()), view) {
+            Some(raw) => Ok(raw.into()),
+            None => bail!("param not found: {}", path),
+        }
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/:module/:key".into()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SignedTransaction;
+
+    #[test]
+    fn test_params() {
+        let db: Box<dyn exonum_merkledb::Database> = Box::new(exonum_merkledb::TemporaryDB::new());
+        let snap = db.snapshot();
+        let mut view = StoreView::wrap(&snap, Default::default());
+
+        let bank = Params::new("bank");
+        assert_eq!(None, bank.get::<u64>("min_fee", &view).unwrap());
+        assert_eq!(5, bank.get_or("min_fee", 5u64, &view).unwrap());
+        bank.set("min_fee", &10u64, &mut view).unwrap();
+        assert_eq!(Some(10u64), bank.get("min_fee", &view).unwrap());
+        // Namespaced by module
+        assert_eq!(
+            None,
+            Params::new("other").get::<u64>("min_fee", &view).unwrap()
+        );
+
+        let module = ParamsModule::with_authority("gov");
+        let set = ParamsMsgs::Set {
+            module: "bank".into(),
+            key: "min_fee".into(),
+            value: 20u64.try_to_vec().unwrap(),
+        };
+        let ctx = SignedTransaction::create("bob", PARAMS_APP_NAME, set.clone(), 0).into_context();
+        assert!(module.handle_tx(&ctx, &mut view).is_err());
+        let ctx = SignedTransaction::create("gov", PARAMS_APP_NAME, set, 0).into_context();
+        assert!(module.handle_tx(&ctx, &mut view).is_ok());
+        assert_eq!(Some(20u64), bank.get("min_fee", &view).unwrap());
+    }
+}
@@ -0,0 +1,266 @@
+//! Route queries to handlers by path pattern.  Patterns are made of literal
+//! segments and named parameters prefixed with ':', for example `/accounts/:id`
+//! or `/orders/:market/:id`.  Handlers get the parsed parameters and return an
+//! optional value that the router encodes with Borsh. A missing route or value
+//! results in a standard 'not found' error.
+//! Example, in `AppModule::handle_query`:
+//! ```ignore
+//! QueryRouter::new()
+//!     .add("/", |params, view| Ok(MyStore::new().query(params.key().to_vec(), view)))
+//!     .add("/orders/:market/:id", |params, view| {
+//!         let market = params.require("market")?;
+//!         let id: u64 = params.require("id")?.parse()?;
+//!         Ok(OrderStore::new().query((market.into(), id), view))
+//!     })
+//!     .route(path, key, view)
+//! ```
+//! Values are encoded with Borsh. Wrap a value in `Json` to return JSON instead.
+//! The encoding is sent to the client in the response `info` field.
+use std::collections::HashMap;
+use std::fmt;
+
+use anyhow::{anyhow, bail};
+use borsh::BorshSerialize;
+use serde::Serialize;
+
+use crate::{QueryResponse, StoreView};
+
+/// The encoding of a query value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryEncoding {
+    /// Bytes set directly by the AppModule. The client must know the format
+    Raw,
+    Borsh,
+    Json,
+}
+
+impl Default for QueryEncoding {
+    fn default() -> Self {
+        QueryEncoding::Raw
+    }
+}
+
+impl QueryEncoding {
+    /// Value sent in the query response `info`. Empty for `Raw`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueryEncoding::Raw => "",
+            QueryEncoding::Borsh => "borsh",
+            QueryEncoding::Json => "json",
+        }
+    }
+
+    /// Parse the encoding from the query response `info`
+    pub fn from_info(info: &str) -> Self {
+        match info {
+            "borsh" => QueryEncoding::Borsh,
+            "json" => QueryEncoding::Json,
+            _ => QueryEncoding::Raw,
+        }
+    }
+}
+
+impl fmt::Display for QueryEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryEncoding::Raw => write!(f, "raw"),
+            _ => write!(f, "{}", self.as_str()),
+        }
+    }
+}
+
+/// A value that can be returned from a query.  Implemented with Borsh for any
+/// `BorshSerialize` value. Wrap a value in `Json` to encode it as JSON.
+pub trait QueryEncode {
+    fn encode_query(&self) -> anyhow::Result<QueryResponse, anyhow::Error>;
+}
+
+impl<T: BorshSerialize> QueryEncode for T {
+    fn encode_query(&self) -> anyhow::Result<QueryResponse, anyhow::Error> {
+        let mut resp = QueryResponse::new(self.try_to_vec()?);
+        resp.encoding = QueryEncoding::Borsh;
+        Ok(resp)
+    }
+}
+
+impl QueryEncode for QueryResponse {
+    fn encode_query(&self) -> anyhow::Result<QueryResponse, anyhow::Error> {
+        Ok(self.clone())
+    }
+}
+
+/// Encode a query value as JSON: `Ok(Some(Json(value)))`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> QueryEncode for Json<T> {
+    fn encode_query(&self) -> anyhow::Result<QueryResponse, anyhow::Error> {
+        let mut resp = QueryResponse::new(serde_json::to_vec(&self.0)?);
+        resp.encoding = QueryEncoding::Json;
+        Ok(resp)
+    }
+}
+
+type Handler = Box<dyn Fn(&QueryParams, &StoreView) -> anyhow::Result<QueryResponse> + Send + Sync>;
+
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+/// Parameters parsed from the query path, plus the key sent with the query
+#[derive(Debug, Default)]
+pub struct QueryParams {
+    path: String,
+    params: HashMap<String, String>,
+    key: Vec<u8>,
+}
+
+impl QueryParams {
+    /// Return the value of a named parameter
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|v| v.as_str())
+    }
+
+    /// Return the value of a named parameter or an error if it's missing
+    pub fn require(&self, name: &str) -> anyhow::Result<&str, anyhow::Error> {
+        self.get(name)
+            .ok_or_else(|| anyhow!("missing query parameter: {}", name))
+    }
+
+    /// Return the key sent with the query
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Return the path that was queried
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// Maps path patterns to query handlers. Routes are matched in the order they're added.
+#[derive(Default)]
+pub struct QueryRouter {
+    routes: Vec<(String, Vec<Segment>, Handler)>,
+}
+
+impl QueryRouter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a handler for the path pattern. The handler returns `None` if the
+    /// value is not found. See `QueryEncode` for how the value is encoded.
+    pub fn add<R, F>(mut self, pattern: &str, handler: F) -> Self
+    where
+        R: QueryEncode,
+        F: Fn(&QueryParams, &StoreView) -> anyhow::Result<Option<R>> + Send + Sync + 'static,
+    {
+        let segments = split_path(pattern)
+            .map(|s| match s.strip_prefix(':') {
+                Some(name) => Segment::Param(name.into()),
+                None => Segment::Literal(s.into()),
+            })
+            .collect();
+        let wrapped: Handler = Box::new(move |params, view| match handler(params, view)? {
+            Some(value) => value.encode_query(),
+            None => bail!("not found: {}", params.path()),
+        });
+        self.routes.push((pattern.into(), segments, wrapped));
+        self
+    }
+
+    /// Return the registered path patterns. Use in `AppModule::query_paths`
+    pub fn paths(&self) -> Vec<String> {
+        self.routes.iter().map(|(p, _, _)| p.clone()).collect()
+    }
+
+    /// Call the handler matching the path. Returns an error if no route matches
+    pub fn route(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &StoreView,
+    ) -> anyhow::Result<QueryResponse, anyhow::Error> {
+        let parts: Vec<&str> = split_path(path).collect();
+        for (_, segments, handler) in &self.routes {
+            if let Some(params) = match_path(segments, &parts) {
+                let query = QueryParams {
+                    path: path.into(),
+                    params,
+                    key,
+                };
+                return handler(&query, view);
+            }
+        }
+        bail!("query path not found: {}", path)
+    }
+}
+
+// Split a path into its non-empty segments
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+// Return the parameters if the path matches the segments
+fn match_path(segments: &[Segment], parts: &[&str]) -> Option<HashMap<String, String>> {
+    if segments.len() != parts.len() {
+        return None;
+    }
+    let mut params = HashMap::new();
+    for (segment, part) in segments.iter().zip(parts) {
+        match segment {
+            Segment::Literal(l) if l == part => {}
+            Segment::Literal(_) => return None,
+            Segment::Param(name) => {
+                params.insert(name.clone(), part.to_string());
+            }
+        }
+    }
+    Some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_router() {
+        let db: Box<dyn exonum_merkledb::Database> = Box::new(exonum_merkledb::TemporaryDB::new());
+        let snap = db.snapshot();
+        let view = StoreView::wrap_snapshot(&snap);
+
+        let router = QueryRouter::new()
+            .add("/", |params, _| Ok(Some(params.key().to_vec())))
+            .add("/orders/:market/:id", |params, _| {
+                let market = params.require("market")?;
+                let id: u64 = params.require("id")?.parse()?;
+                Ok(Some((market.to_string(), id)))
+            })
+            .add("/missing/:id", |_, _| Ok(None::<u8>))
+            .add("/json/:name", |params, _| {
+                Ok(Some(Json(params.require("name")?.to_string())))
+            });
+
+        let root = router.route("/", vec![1, 2], &view).unwrap();
+        assert_eq!(vec![1, 2].try_to_vec().unwrap(), root.value);
+
+        let order = router.route("/orders/btc/5", vec![], &view).unwrap();
+        assert_eq!(("btc".to_string(), 5u64).try_to_vec().unwrap(), order.value);
+        assert_eq!(QueryEncoding::Borsh, order.encoding);
+
+        let json = router.route("/json/bob", vec![], &view).unwrap();
+        assert_eq!(b"\"bob\"".to_vec(), json.value);
+        assert_eq!(QueryEncoding::Json, json.encoding);
+
+        assert!(router.route("/orders/btc/abc", vec![], &view).is_err());
+        assert!(router.route("/missing/1", vec![], &view).is_err());
+        assert!(router.route("/other", vec![], &view).is_err());
+
+        assert_eq!(
+            vec!["/", "/orders/:market/:id", "/missing/:id", "/json/:name"],
+            router.paths()
+        );
+    }
+}
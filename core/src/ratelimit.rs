@@ -0,0 +1,116 @@
+//! Per-sender rate limiting.  `RateLimiter` wraps an `Authenticator` and limits the
+//! number of txs a sender can submit in a window of blocks, so public devnets can
+//! resist spam even without fees.  Example, at most 5 txs per sender every 10 blocks:
+//! ```ignore
+//! AppBuilder::new()
+//!     .set_authenticator(RateLimiter::new(AccountAuthenticator {}, 5).per_blocks(10))
+//! ```
+//! The limit is checked by `validate`, keeping spam out of the mempool. Counts are
+//! kept in a core store and updated with the nonce, so check_tx counts the txs in
+//! the mempool and deliver_tx the txs committed in the window.
+use anyhow::ensure;
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    AccountId, AuthResult, Authenticator, Context, SignedTransaction, Store, StoreValue, StoreView,
+};
+
+const RATE_LIMIT_STORE_NAME: &str = "rapido.ratelimit";
+
+/// Number of txs sent by an account in a window
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq, Default)]
+pub struct TxCount {
+    pub window: u64,
+    pub count: u32,
+}
+
+/// Store of the tx count in the current window keyed by sender
+pub struct RateLimitStore;
+impl Store for RateLimitStore {
+    type Key = AccountId;
+    type Value = TxCount;
+
+    fn name(&self) -> String {
+        RATE_LIMIT_STORE_NAME.into()
+    }
+}
+
+impl RateLimitStore {
+    pub fn new() -> Self {
+        RateLimitStore {}
+    }
+
+    /// Return the number of txs sent by the account in the window
+    pub fn count(&self, account: AccountId, window: u64, view: &StoreView) -> u32 {
+        match self.get(account, view) {
+            Some(c) if c.window == window => c.count,
+            _ => 0,
+        }
+    }
+
+    /// Add a tx to the count of the account in the window
+    pub fn increment(&self, account: AccountId, window: u64, view: &mut StoreView) {
+        let count = self.count(account.clone(), window, view) + 1;
+        self.put(account, TxCount { window, count }, view)
+    }
+}
+
+/// Authenticator that limits the txs per sender. See the module docs.
+pub struct RateLimiter {
+    inner: Box<dyn Authenticator>,
+    max_txs: u32,
+    blocks: u64,
+}
+
+impl RateLimiter {
+    /// Wrap the authenticator, allowing `max_txs` per sender per block
+    pub fn new(inner: impl Into<Box<dyn Authenticator>>, max_txs: u32) -> Self {
+        Self {
+            inner: inner.into(),
+            max_txs,
+            blocks: 1,
+        }
+    }
+
+    /// Set the size of the window in blocks. Defaults to 1
+    pub fn per_blocks(mut self, blocks: u64) -> Self {
+        assert!(blocks > 0, "the rate limit window must be at least 1 block");
+        self.blocks = blocks;
+        self
+    }
+
+    // Windows are aligned on the height: height / blocks
+    fn window(&self, ctx: &Context) -> u64 {
+        ctx.height().max(0) as u64 / self.blocks
+    }
+}
+
+impl Authenticator for RateLimiter {
+    fn validate(
+        &self,
+        ctx: &Context,
+        tx: &SignedTransaction,
+        view: &StoreView,
+    ) -> anyhow::Result<AuthResult, anyhow::Error> {
+        let result = self.inner.validate(ctx, tx, view)?;
+        let count = RateLimitStore::new().count(tx.sender(), self.window(ctx), view);
+        ensure!(
+            count < self.max_txs,
+            "rate limit: max of {} txs per {} blocks",
+            self.max_txs,
+            self.blocks
+        );
+        Ok(result)
+    }
+
+    fn increment_nonce(
+        &self,
+        ctx: &Context,
+        tx: &SignedTransaction,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        self.inner.increment_nonce(ctx, tx, view)?;
+        RateLimitStore::new().increment(tx.sender(), self.window(ctx), view);
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+//! Schedule messages to be dispatched to an AppModule at a future height.
+//! Scheduled messages are persisted in the store and executed by the framework
+//! during end_block of the target height, in the order they were scheduled.
+//! The message is passed to the AppModule's `handle_tx` with the sender that
+//! scheduled it.
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{AccountId, Context, Store, StoreValue, StoreView};
+
+const SCHEDULE_STORE_NAME: &str = "rapido.schedule";
+const SCHEDULE_SEQ_STORE_NAME: &str = "rapido.schedule.seq";
+const SEQ_KEY: &str = "seq";
+
+/// A message scheduled for a future height
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct ScheduledTx {
+    /// Unique id returned when scheduled. Used to cancel
+    pub id: u64,
+    /// The account that scheduled the message
+    pub sender: AccountId,
+    /// The name of the AppModule to receive the message
+    pub app: String,
+    /// The encoded message
+    pub msg: Vec<u8>,
+}
+
+// All messages scheduled for a given height
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq, Default)]
+pub(crate) struct Schedule(Vec<ScheduledTx>);
+
+struct ScheduleStore;
+impl Store for ScheduleStore {
+    type Key = i64;
+    type Value = Schedule;
+
+    fn name(&self) -> String {
+        SCHEDULE_STORE_NAME.into()
+    }
+}
+
+// Sequence used to generate ids
+struct ScheduleSeqStore;
+impl Store for ScheduleSeqStore {
+    type Key = String;
+    type Value = u64;
+
+    fn name(&self) -> String {
+        SCHEDULE_SEQ_STORE_NAME.into()
+    }
+}
+
+/// Schedule and cancel messages.  Example, from within `handle_tx`:
+/// ```ignore
+/// let id = Scheduler::new().schedule(ctx, ctx.height() + 10, "myapp", Msgs::Expire, view)?;
+/// ```
+pub struct Scheduler;
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {}
+    }
+
+    /// Schedule `msg` to be sent to `app` at the given height on behalf of the
+    /// sender in the context.  The height must be after the current height.
+    /// Returns the id of the scheduled message.
+    pub fn schedule<M: BorshSerialize>(
+        &self,
+        ctx: &Context,
+        height: i64,
+        app: &str,
+        msg: M,
+        view: &mut StoreView,
+    ) -> anyhow::Result<u64, anyhow::Error> {
+        ensure!(
+            height > ctx.height(),
+            "can only schedule for a future height"
+        );
+
+        let seq = ScheduleSeqStore {};
+        let id = seq.get(SEQ_KEY.into(), view).unwrap_or_default();
+        seq.put(SEQ_KEY.into(), id + 1, view);
+
+        let store = ScheduleStore {};
+        let mut schedule = store.get(height, view).unwrap_or_default();
+        schedule.0.push(ScheduledTx {
+            id,
+            sender: ctx.sender(),
+            app: app.into(),
+            msg: msg.try_to_vec()?,
+        });
+        store.put(height, schedule, view);
+        Ok(id)
+    }
+
+    /// Cancel a scheduled message. Only the original sender can cancel.
+    pub fn cancel(
+        &self,
+        ctx: &Context,
+        height: i64,
+        id: u64,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let store = ScheduleStore {};
+        let mut schedule = store.get(height, view).unwrap_or_default();
+        let index = match schedule.0.iter().position(|s| s.id == id) {
+            Some(i) => i,
+            None => bail!("scheduled tx not found"),
+        };
+        ensure!(
+            schedule.0[index].sender == ctx.sender(),
+            "only the sender can cancel a scheduled tx"
+        );
+
+        schedule.0.remove(index);
+        if schedule.0.is_empty() {
+            store.remove(height, view);
+        } else {
+            store.put(height, schedule, view);
+        }
+        Ok(())
+    }
+
+    /// Return the messages scheduled for the given height
+    pub fn scheduled(&self, height: i64, view: &StoreView) -> Vec<ScheduledTx> {
+        let store = ScheduleStore {};
+        store.get(height, view).unwrap_or_default().0
+    }
+
+    // Remove and return the messages for the height. Called by the framework in end_block
+    pub(crate) fn take(&self, height: i64, view: &mut StoreView) -> Vec<ScheduledTx> {
+        let scheduled = self.scheduled(height, view);
+        if !scheduled.is_empty() {
+            ScheduleStore {}.remove(height, view);
+        }
+        scheduled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SignedTransaction;
+
+    #[test]
+    fn test_schedule_and_cancel() {
+        let db: Box<dyn exonum_merkledb::Database> = Box::new(exonum_merkledb::TemporaryDB::new());
+        let snap = db.snapshot();
+        let mut view = StoreView::wrap(&snap, Default::default());
+
+        let tx = SignedTransaction::create("bob", "example", 1u8, 0u64);
+        let ctx = tx.into_context();
+        let scheduler = Scheduler::new();
+
+        // Must be in the future
+        assert!(scheduler
+            .schedule(&ctx, 0, "example", 1u8, &mut view)
+            .is_err());
+
+        let id1 = scheduler
+            .schedule(&ctx, 5, "example", 1u8, &mut view)
+            .unwrap();
+        let id2 = scheduler
+            .schedule(&ctx, 5, "example", 2u8, &mut view)
+            .unwrap();
+        assert_ne!(id1, id2);
+        assert_eq!(2, scheduler.scheduled(5, &view).len());
+
+        // Only the sender can cancel
+        let other = SignedTransaction::create("alice", "example", 1u8, 0u64).into_context();
+        assert!(scheduler.cancel(&other, 5, id1, &mut view).is_err());
+        assert!(scheduler.cancel(&ctx, 5, id1, &mut view).is_ok());
+
+        let remaining = scheduler.take(5, &mut view);
+        assert_eq!(1, remaining.len());
+        assert_eq!(id2, remaining[0].id);
+        assert!(scheduler.scheduled(5, &view).is_empty());
+    }
+}
@@ -2,27 +2,34 @@
 use std::convert::AsRef;
 
 use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{StoreValue, ValidatorPower};
 use exonum_crypto::Hash;
 use exonum_merkledb::{
-    access::{Access, AccessExt, RawAccessMut},
-    ProofMapIndex,
+    access::{Access, AccessExt, RawAccess, RawAccessMut},
+    Fork, MapProof, ProofMapIndex, SystemSchema,
 };
 
 // 2 separate rockdb columns
 const RAPIDO_CHAIN_STATE: &str = "rapido.app.state";
-const RAPIDO_CORE_MAP: &'static str = "rapido.core.map";
+pub(crate) const RAPIDO_CORE_MAP: &'static str = "rapido.core.map";
+// Chain id from InitChain
+const RAPIDO_CHAIN_ID: &str = "rapido.chain.id";
+// Versions of AppModules keyed by name. Not part of the state root hash
+const RAPIDO_MODULE_VERSIONS: &str = "rapido.module.versions";
+// Voting power by validator public key
+const RAPIDO_VALIDATORS: &str = "rapido.validators";
 
-// Holds the chain state information used by Tendermint to sync with the node.
-#[derive(Debug, BorshSerialize, BorshDeserialize, Clone, PartialEq, Default)]
-pub(crate) struct ChainState {
-    // Last height
+/// Holds the chain state information used by Tendermint to sync with the node.
+/// Returned (Borsh encoded) by the `rapido/apphash` query.
+#[derive(Debug, BorshSerialize, BorshDeserialize, StoreValue, Clone, PartialEq, Default)]
+pub struct ChainState {
+    /// Last height
     pub height: i64,
-    // Last accumulated application root hash
+    /// Last accumulated application root hash
     pub apphash: Vec<u8>,
 }
 
-impl_store_values!(ChainState);
-
 // Simple entry storage for chain state that doesn't affect overall state root hash
 #[derive(Debug)]
 pub(crate) struct RapidoSchema<T: Access> {
@@ -37,6 +44,24 @@ impl<T: Access> RapidoSchema<T> {
     pub fn get_chain_state(&self) -> Option<ChainState> {
         self.access.get_entry(RAPIDO_CHAIN_STATE).get()
     }
+
+    pub fn get_chain_id(&self) -> Option<String> {
+        self.access.get_entry(RAPIDO_CHAIN_ID).get()
+    }
+
+    pub fn get_module_version(&self, name: &str) -> Option<u32> {
+        self.access
+            .get_map::<_, String, u32>(RAPIDO_MODULE_VERSIONS)
+            .get(&name.to_string())
+    }
+
+    pub fn get_validators(&self) -> Vec<ValidatorPower> {
+        self.access
+            .get_map::<_, Vec<u8>, i64>(RAPIDO_VALIDATORS)
+            .iter()
+            .map(|(pub_key, power)| ValidatorPower { pub_key, power })
+            .collect()
+    }
 }
 
 impl<T: Access> RapidoSchema<T>
@@ -48,9 +73,100 @@ where
             .get_entry(RAPIDO_CHAIN_STATE)
             .set(ChainState { height, apphash });
     }
+
+    pub fn save_chain_id(&mut self, chain_id: &str) {
+        self.access
+            .get_entry(RAPIDO_CHAIN_ID)
+            .set(chain_id.to_string());
+    }
+
+    pub fn save_module_version(&mut self, name: &str, version: u32) {
+        self.access
+            .get_map::<_, String, u32>(RAPIDO_MODULE_VERSIONS)
+            .put(&name.to_string(), version);
+    }
+
+    // A power of 0 removes the validator
+    pub fn save_validator(&mut self, validator: &ValidatorPower) {
+        let mut validators = self.access.get_map::<_, Vec<u8>, i64>(RAPIDO_VALIDATORS);
+        if validator.power == 0 {
+            validators.remove(&validator.pub_key);
+        } else {
+            validators.put(&validator.pub_key, validator.power);
+        }
+    }
 }
 
 // Helper to access the app state merkle tree
 pub(crate) fn get_store<T: Access>(access: T) -> ProofMapIndex<T::Base, Hash, Vec<u8>> {
     access.get_proof_map(RAPIDO_CORE_MAP)
 }
+
+// Proof of the app state merkle tree's root in the state aggregator, whose
+// hash is the apphash
+pub(crate) fn get_state_proof<T: RawAccess>(access: T) -> MapProof<String, Hash> {
+    SystemSchema::new(access)
+        .state_aggregator()
+        .get_proof(RAPIDO_CORE_MAP.to_string())
+}
+
+// A copy of the committed state: the app state merkle tree and the chain state
+#[derive(Debug, Clone)]
+pub(crate) struct SavedState {
+    entries: Vec<(Hash, Vec<u8>)>,
+    chain_state: Option<ChainState>,
+    chain_id: Option<String>,
+    versions: Vec<(String, u32)>,
+    validators: Vec<ValidatorPower>,
+}
+
+impl SavedState {
+    pub fn chain_id(&self) -> Option<&str> {
+        self.chain_id.as_deref()
+    }
+}
+
+// Copy the committed state
+pub(crate) fn save_state<T: Access + Copy>(access: T) -> SavedState {
+    let schema = RapidoSchema::new(access);
+    SavedState {
+        entries: get_store(access).iter().collect(),
+        chain_state: schema.get_chain_state(),
+        chain_id: schema.get_chain_id(),
+        versions: access
+            .get_map::<_, String, u32>(RAPIDO_MODULE_VERSIONS)
+            .iter()
+            .collect(),
+        validators: schema.get_validators(),
+    }
+}
+
+// Replace the state with the saved state
+pub(crate) fn restore_state(fork: &Fork, state: &SavedState) {
+    let mut store = get_store(fork);
+    store.clear();
+    for (key, value) in &state.entries {
+        store.put(key, value.clone());
+    }
+
+    let mut chain_state = fork.get_entry(RAPIDO_CHAIN_STATE);
+    match &state.chain_state {
+        Some(saved) => chain_state.set(saved.clone()),
+        None => chain_state.remove(),
+    }
+    let mut chain_id = fork.get_entry(RAPIDO_CHAIN_ID);
+    match &state.chain_id {
+        Some(saved) => chain_id.set(saved.clone()),
+        None => chain_id.remove(),
+    }
+    let mut versions = fork.get_map::<_, String, u32>(RAPIDO_MODULE_VERSIONS);
+    versions.clear();
+    for (name, version) in &state.versions {
+        versions.put(name, *version);
+    }
+    fork.get_map::<_, Vec<u8>, i64>(RAPIDO_VALIDATORS).clear();
+    let mut schema = RapidoSchema::new(fork);
+    for validator in &state.validators {
+        schema.save_validator(validator);
+    }
+}
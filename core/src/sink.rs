@@ -0,0 +1,286 @@
+//! Event sinks.  Forward the events of each committed block to an `EventSink`,
+//! e.g. to POST them to a webhook or append them to a local file that another
+//! process tails, without writing a Tendermint WebSocket consumer.  Sinks are not
+//! part of consensus: they run on their own thread after commit and a failing sink
+//! is logged and doesn't affect the node.  Only events of successful txs and the
+//! begin/end block hooks are forwarded.
+//! Example:
+//! ```ignore
+//! AppBuilder::new()
+//!     .with_app(MyModule {})
+//!     .with_event_sink(HttpSink::new("http://localhost:8080/events"))
+//!     .with_event_sink(FileSink::new("/var/rapido/events.jsonl"))
+//! ```
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use abci::Event;
+use anyhow::{bail, ensure};
+use log::warn;
+use serde_json::{json, Value};
+
+/// Where in the block an event was emitted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventSource {
+    BeginBlock,
+    /// A tx, with its index in the block
+    Tx(usize),
+    EndBlock,
+}
+
+/// An event of a committed block
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinkEvent {
+    pub source: EventSource,
+    /// The event type, e.g. 'rapido.bank.transfer'
+    pub kind: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl SinkEvent {
+    pub(crate) fn from_abci(source: EventSource, event: &Event) -> Self {
+        Self {
+            source,
+            kind: event.get_field_type().into(),
+            attributes: event
+                .get_attributes()
+                .iter()
+                .map(|p| {
+                    (
+                        String::from_utf8_lossy(p.get_key()).into_owned(),
+                        String::from_utf8_lossy(p.get_value()).into_owned(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let (source, tx) = match self.source {
+            EventSource::BeginBlock => ("begin_block", None),
+            EventSource::Tx(index) => ("tx", Some(index)),
+            EventSource::EndBlock => ("end_block", None),
+        };
+        let attributes: serde_json::Map<String, Value> = self
+            .attributes
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect();
+        json!({
+            "source": source,
+            "tx": tx,
+            "type": self.kind,
+            "attributes": attributes,
+        })
+    }
+}
+
+/// The events of a committed block
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CommittedEvents {
+    pub height: i64,
+    /// The app hash after the block
+    pub app_hash: Vec<u8>,
+    pub events: Vec<SinkEvent>,
+}
+
+impl CommittedEvents {
+    /// Encode as JSON: `{"height": 1, "app_hash": "<hex>", "events": [...]}`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "height": self.height,
+            "app_hash": hex::encode(&self.app_hash),
+            "events": self.events.iter().map(|e| e.to_json()).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Receives the events of each committed block. See `AppBuilder::with_event_sink`
+pub trait EventSink: Send {
+    fn publish(&mut self, block: &CommittedEvents) -> anyhow::Result<(), anyhow::Error>;
+}
+
+/// Append the events of each block as a line of JSON to a file
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl EventSink for FileSink {
+    fn publish(&mut self, block: &CommittedEvents) -> anyhow::Result<(), anyhow::Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", block.to_json())?;
+        Ok(())
+    }
+}
+
+/// POST the events of each block as JSON to a URL.  Only plain 'http://' URLs are
+/// supported: use a local proxy for TLS.  Blocks without events are skipped
+pub struct HttpSink {
+    url: String,
+    timeout: Duration,
+}
+
+impl HttpSink {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.into(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Timeout of connecting and of each read and write. Default: 5 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+// Split an 'http://host:port/path' URL into the host:port and the path
+fn split_url(url: &str) -> anyhow::Result<(String, String), anyhow::Error> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None => bail!("only http:// URLs are supported: {}", url),
+    };
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    ensure!(!host.is_empty(), "URL has no host: {}", url);
+    let host = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+    Ok((host, path.into()))
+}
+
+impl EventSink for HttpSink {
+    fn publish(&mut self, block: &CommittedEvents) -> anyhow::Result<(), anyhow::Error> {
+        if block.events.is_empty() {
+            return Ok(());
+        }
+        let (host, path) = split_url(&self.url)?;
+        let body = block.to_json().to_string();
+
+        let addr = match host.to_socket_addrs()?.next() {
+            Some(addr) => addr,
+            None => bail!("can't resolve {}", host),
+        };
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            host,
+            body.len(),
+            body
+        )?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let status = response.split_whitespace().nth(1).unwrap_or_default();
+        ensure!(
+            status.starts_with('2'),
+            "{} returned status {}",
+            self.url,
+            status
+        );
+        Ok(())
+    }
+}
+
+// Runs the sinks on their own thread so a slow sink doesn't hold up the node
+pub(crate) struct SinkRunner {
+    // In a Mutex to keep the Node Sync
+    sender: Mutex<Sender<CommittedEvents>>,
+}
+
+impl SinkRunner {
+    pub fn start(mut sinks: Vec<Box<dyn EventSink>>) -> Self {
+        let (sender, receiver) = channel::<CommittedEvents>();
+        thread::spawn(move || {
+            for block in receiver {
+                for sink in sinks.iter_mut() {
+                    if let Err(err) = sink.publish(&block) {
+                        warn!("event sink failed at height {}: {}", block.height, err);
+                    }
+                }
+            }
+        });
+        Self {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    pub fn send(&self, block: CommittedEvents) {
+        let sent = match self.sender.lock() {
+            Ok(sender) => sender.send(block).is_ok(),
+            Err(_) => false,
+        };
+        if !sent {
+            warn!("event sink thread stopped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::create_event;
+
+    #[test]
+    fn test_split_url() {
+        assert_eq!(
+            ("localhost:8080".to_string(), "/events".to_string()),
+            split_url("http://localhost:8080/events").unwrap()
+        );
+        assert_eq!(
+            ("example.com:80".to_string(), "/".to_string()),
+            split_url("http://example.com").unwrap()
+        );
+        assert!(split_url("https://example.com").is_err());
+    }
+
+    #[test]
+    fn test_file_sink() {
+        let event = create_event("rapido.bank.transfer".into(), &[("amount", "10")]);
+        let block = CommittedEvents {
+            height: 3,
+            app_hash: vec![1, 2],
+            events: vec![SinkEvent::from_abci(EventSource::Tx(0), &event)],
+        };
+
+        let path = std::env::temp_dir().join(format!("rapido-sink-{}.jsonl", std::process::id()));
+        let mut sink = FileSink::new(&path);
+        sink.publish(&block).unwrap();
+        sink.publish(&block).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(2, lines.len());
+        let json: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(3, json["height"]);
+        assert_eq!("0102", json["app_hash"]);
+        assert_eq!("rapido.bank.transfer", json["events"][0]["type"]);
+        assert_eq!(0, json["events"][0]["tx"]);
+        assert_eq!("10", json["events"][0]["attributes"]["amount"]);
+    }
+}
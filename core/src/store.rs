@@ -1,10 +1,12 @@
 //! Storage caches and trait(s)
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ops::Deref;
 
+use anyhow::{bail, ensure};
 use borsh::{BorshDeserialize, BorshSerialize};
 use exonum_crypto::Hash;
-use exonum_merkledb::{BinaryValue, Fork, Snapshot};
+use exonum_merkledb::{BinaryValue, Fork, MapProof, Snapshot};
 
 use crate::schema;
 
@@ -56,6 +58,8 @@ where
 pub struct StoreView<'a> {
     cache: Cache,
     access: &'a Box<dyn Snapshot>,
+    // The previous entry of each key changed in the view, to roll back changes
+    journal: Vec<(Hash, Option<ViewChange>)>,
 }
 
 impl<'a> StoreView<'a> {
@@ -64,6 +68,7 @@ impl<'a> StoreView<'a> {
         StoreView {
             access: db,
             cache: cache,
+            journal: Vec::new(),
         }
     }
 
@@ -72,6 +77,7 @@ impl<'a> StoreView<'a> {
         StoreView {
             access: db,
             cache: Default::default(),
+            journal: Vec::new(),
         }
     }
 
@@ -97,14 +103,44 @@ impl<'a> StoreView<'a> {
         schema::get_store(self.access).get(&key)
     }
 
+    /// Return a proof of the key (or its absence) in the last committed state
+    pub fn get_proof(&self, key: &Hash) -> MapProof<Hash, Vec<u8>> {
+        schema::get_store(self.access).get_proof(*key)
+    }
+
     /// Put a new view change into the cache
     pub fn put(&mut self, key: Hash, value: impl BinaryValue) {
-        self.cache.insert(key, ViewChange::Add(value.to_bytes()));
+        let previous = self.cache.insert(key, ViewChange::Add(value.to_bytes()));
+        self.journal.push((key, previous));
     }
 
     /// Remove an item
     pub fn remove(&mut self, key: Hash) {
-        self.cache.insert(key, ViewChange::Remove);
+        let previous = self.cache.insert(key, ViewChange::Remove);
+        self.journal.push((key, previous));
+    }
+
+    /// Mark the changes made so far, to undo the changes made after it with
+    /// `rollback_to`.  Used by AppModules to discard the changes of a step that
+    /// failed without failing the rest, e.g. one of the proposals ending in end_block
+    pub fn checkpoint(&self) -> usize {
+        self.journal.len()
+    }
+
+    /// Undo the changes made since the checkpoint
+    pub fn rollback_to(&mut self, checkpoint: usize) {
+        while self.journal.len() > checkpoint {
+            let (key, previous) = self.journal.pop().expect("journal entry");
+            match previous {
+                Some(change) => self.cache.insert(key, change),
+                None => self.cache.remove(&key),
+            };
+        }
+    }
+
+    // Undo all the changes made with the view
+    pub(crate) fn rollback(&mut self) {
+        self.rollback_to(0)
     }
 
     /// Called on abci.commit to write all changes to the merkle store.
@@ -120,6 +156,40 @@ impl<'a> StoreView<'a> {
     }
 }
 
+/// Read-only view of the store passed to `AppModule::handle_query`. It derefs to
+/// `StoreView` for reads, so it can be passed to `Store::query` and friends, but
+/// can't be used to change state.
+pub struct QueryView<'a, 'b> {
+    view: &'a StoreView<'b>,
+    height: i64,
+}
+
+impl<'a, 'b> QueryView<'a, 'b> {
+    pub fn new(view: &'a StoreView<'b>) -> Self {
+        Self { view, height: 0 }
+    }
+
+    /// Set the height of the state being queried
+    pub fn with_height(mut self, height: i64) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// Return the height of the state being queried: the last committed block
+    /// for client queries, or the current block for `Context::module` queries
+    pub fn height(&self) -> i64 {
+        self.height
+    }
+}
+
+impl<'a, 'b> Deref for QueryView<'a, 'b> {
+    type Target = StoreView<'b>;
+
+    fn deref(&self) -> &Self::Target {
+        self.view
+    }
+}
+
 /// Implement this trait to create a store for your application.
 /// Common operations such as put, get, etc... are provided.
 /// Primarily all you need to do is set the Key,Value type and return
@@ -130,7 +200,7 @@ pub trait Store: Sync + Send {
     type Key: BorshSerialize + BorshDeserialize;
 
     /// Specify what will be stored.  The value must fulfill the
-    /// BinaryValue trait.  Use the macro: `impl_store_values()` to do so.
+    /// BinaryValue trait.  Use `#[derive(StoreValue)]` to do so.
     type Value: BinaryValue;
 
     /// Return a unique name for the store.  Recommend using  'appname.name'.
@@ -149,11 +219,12 @@ pub trait Store: Sync + Send {
     fn get(&self, key: Self::Key, view: &StoreView) -> Option<Self::Value> {
         let hash = StoreKey::create(self.name(), key).hash();
 
-        // Check the cache first
-        if let Some(v) = view.get(&hash) {
-            return match Self::Value::from_bytes(Cow::Owned(v.clone())) {
-                Ok(r) => Some(r),
-                _ => None,
+        // Check the cache first. A value removed in the cache is gone,
+        // even if it's still in the committed store
+        if view.exists(&hash) {
+            return match view.get(&hash) {
+                Some(v) => Self::Value::from_bytes(Cow::Owned(v.clone())).ok(),
+                None => None,
             };
         }
 
@@ -186,18 +257,111 @@ pub trait Store: Sync + Send {
         view.remove(hash)
     }
 
-    /// Does the give key exists?
+    /// Does the give key exists? Checks the cache and then the committed store
     fn contains_key(&self, key: Self::Key, view: &StoreView) -> bool {
         let hash = StoreKey::create(self.name(), key).hash();
-        view.exists(&hash)
+        if view.exists(&hash) {
+            return view.get(&hash).is_some();
+        }
+        view.get_from_store(&hash).is_some()
+    }
+
+    /// Return a JSON encoded Merkle proof of the value (or its absence) in the
+    /// latest committed data. Return it with a query via `QueryResponse::with_proof`
+    fn query_proof(&self, key: Self::Key, view: &StoreView) -> anyhow::Result<Vec<u8>> {
+        let hash = StoreKey::create(self.name(), key).hash();
+        Ok(serde_json::to_vec(&view.get_proof(&hash))?)
+    }
+
+    /// Verify a proof from `query_proof` against a root hash of the Merkle tree,
+    /// e.g. a root of another chain's state. Return the proven value, or `None` if
+    /// it's a proof of absence.  See `proof_root`
+    fn verify_proof(
+        &self,
+        key: Self::Key,
+        proof: &[u8],
+        root: &Hash,
+    ) -> anyhow::Result<Option<Self::Value>> {
+        let hash = StoreKey::create(self.name(), key).hash();
+        let proof: MapProof<Hash, Vec<u8>> = serde_json::from_slice(proof)?;
+        let checked = match proof.check_against_hash(*root) {
+            Ok(checked) => checked,
+            Err(err) => bail!("invalid proof: {}", err),
+        };
+        if let Some((_, value)) = checked.entries().find(|(k, _)| **k == hash) {
+            return Ok(Some(Self::Value::from_bytes(Cow::Owned(value.clone()))?));
+        }
+        ensure!(
+            checked.missing_keys().any(|k| *k == hash),
+            "proof is not of the key"
+        );
+        Ok(None)
     }
+}
 
-    // TODO:
-    //fn get_proof(&self, key: &Vec<u8>);
+/// Return the root hash of the Merkle tree a proof from `Store::query_proof` was
+/// made against
+pub fn proof_root(proof: &[u8]) -> anyhow::Result<Hash> {
+    let proof: MapProof<Hash, Vec<u8>> = serde_json::from_slice(proof)?;
+    match proof.check() {
+        Ok(checked) => Ok(checked.index_hash()),
+        Err(err) => bail!("invalid proof: {}", err),
+    }
+}
+
+/// Verify the proof of a query against a trusted apphash.  `proof` is the proof
+/// of the value in the store (see `Store::query_proof`) and `state_proof` the
+/// proof of the store's root in the app state, as returned with the query in the
+/// `PROOF_OP_TYPE` and `STATE_PROOF_OP_TYPE` ProofOps.  The proof must be of
+/// `key` in the store named `store_name`.  Return the proven value, as stored, or
+/// `None` if it's a proof of absence
+pub fn verify_query_proof<K>(
+    store_name: &str,
+    key: K,
+    proof: &[u8],
+    state_proof: &[u8],
+    apphash: &Hash,
+) -> anyhow::Result<Option<Vec<u8>>>
+where
+    K: BorshSerialize + BorshDeserialize,
+{
+    let hash = StoreKey::create(store_name, key).hash();
+    let proof: MapProof<Hash, Vec<u8>> = serde_json::from_slice(proof)?;
+    let checked = match proof.check() {
+        Ok(checked) => checked,
+        Err(err) => bail!("invalid proof: {}", err),
+    };
+    let state_proof: MapProof<String, Hash> = serde_json::from_slice(state_proof)?;
+    let state = match state_proof.check_against_hash(*apphash) {
+        Ok(state) => state,
+        Err(err) => bail!("invalid state proof: {}", err),
+    };
+    let root = match state
+        .entries()
+        .find(|(k, _)| **k == schema::RAPIDO_CORE_MAP)
+    {
+        Some((_, root)) => *root,
+        None => bail!("state proof is not of the app state"),
+    };
+    ensure!(
+        checked.index_hash() == root,
+        "proof doesn't match the app state"
+    );
+
+    if let Some((_, value)) = checked.entries().find(|(k, _)| **k == hash) {
+        return Ok(Some(value.clone()));
+    }
+    ensure!(
+        checked.missing_keys().any(|k| *k == hash),
+        "proof is not of the key"
+    );
+    Ok(None)
 }
 
 mod tests {
     use super::*;
+    use crate::StoreValue;
+    use exonum_merkledb::ObjectHash;
 
     #[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, Default)]
     pub struct Person {
@@ -207,6 +371,13 @@ mod tests {
 
     impl_store_values!(Person);
 
+    // Generic values use the derive
+    #[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize, StoreValue)]
+    pub enum Versioned<T> {
+        V1(T),
+        V2(T, u8),
+    }
+
     // Add BinaryKey as a type also?
     pub struct MyStore;
     impl Store for MyStore {
@@ -250,4 +421,155 @@ mod tests {
         let t = c1.into_cache();
         println!("{:?}", t);
     }
+
+    #[test]
+    fn test_store_rollback() {
+        let db: Box<dyn exonum_merkledb::Database> = Box::new(exonum_merkledb::TemporaryDB::new());
+        let snap = db.snapshot();
+        let mut view = StoreView::wrap(&snap, Default::default());
+        let store = MyStore {};
+        let person = |age| Person {
+            name: "bob".into(),
+            age,
+        };
+
+        store.put("bob".into(), person(1), &mut view);
+        let checkpoint = view.checkpoint();
+        store.put("bob".into(), person(2), &mut view);
+        store.put("carl".into(), person(3), &mut view);
+        store.remove("bob".into(), &mut view);
+        assert!(store.get("bob".into(), &view).is_none());
+
+        view.rollback_to(checkpoint);
+        assert_eq!(Some(person(1)), store.get("bob".into(), &view));
+        assert!(!store.contains_key("carl".into(), &view));
+
+        view.rollback();
+        assert!(view.into_cache().is_empty());
+    }
+
+    #[test]
+    fn test_store_remove_committed() {
+        let db: Box<dyn exonum_merkledb::Database> = Box::new(exonum_merkledb::TemporaryDB::new());
+        let snap = db.snapshot();
+        let mut c1 = StoreView::wrap(&snap, Default::default());
+        let store = MyStore {};
+        let bob = Person {
+            name: "bob".into(),
+            age: 1u8,
+        };
+        store.put("bob".into(), bob.clone(), &mut c1);
+
+        let fork = db.fork();
+        c1.commit(&fork);
+        db.merge(fork.into_patch()).unwrap();
+
+        // A committed value is found when it's not in the cache
+        let snap = db.snapshot();
+        let mut c2 = StoreView::wrap(&snap, Default::default());
+        assert!(store.contains_key("bob".into(), &c2));
+        assert_eq!(Some(bob), store.get("bob".into(), &c2));
+
+        // and it's gone once removed in the cache, before the commit
+        store.remove("bob".into(), &mut c2);
+        assert!(!store.contains_key("bob".into(), &c2));
+        assert!(store.get("bob".into(), &c2).is_none());
+    }
+
+    #[test]
+    fn test_store_value_derive() {
+        let value = Versioned::V2("bob".to_string(), 1);
+        let back = Versioned::<String>::from_bytes(Cow::Owned(value.to_bytes())).unwrap();
+        assert_eq!(value, back);
+    }
+
+    #[test]
+    fn test_store_proof() {
+        let db: Box<dyn exonum_merkledb::Database> = Box::new(exonum_merkledb::TemporaryDB::new());
+        let snap = db.snapshot();
+        let mut c1 = StoreView::wrap(&snap, Default::default());
+
+        let store = MyStore {};
+        let bob = Person {
+            name: "bob".into(),
+            age: 1u8,
+        };
+        store.put("bob".into(), bob.clone(), &mut c1);
+
+        let fork = db.fork();
+        c1.commit(&fork);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snap = db.snapshot();
+        let view = StoreView::wrap_snapshot(&snap);
+        let raw = store.query_proof("bob".into(), &view).unwrap();
+        let proof: MapProof<Hash, Vec<u8>> = serde_json::from_slice(&raw).unwrap();
+        let checked = proof.check().unwrap();
+        let entries: Vec<_> = checked.entries().collect();
+        assert_eq!(1, entries.len());
+        assert_eq!(bob.to_bytes(), *entries[0].1);
+
+        let root = proof_root(&raw).unwrap();
+        assert_eq!(
+            Some(bob),
+            store.verify_proof("bob".into(), &raw, &root).unwrap()
+        );
+        // Not the key of the proof
+        assert!(store.verify_proof("alice".into(), &raw, &root).is_err());
+        // Proof of absence
+        let absent = store.query_proof("alice".into(), &view).unwrap();
+        assert_eq!(
+            None,
+            store.verify_proof("alice".into(), &absent, &root).unwrap()
+        );
+        // Wrong root
+        assert!(store
+            .verify_proof("bob".into(), &raw, &exonum_crypto::hash(b"other"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_verify_query_proof() {
+        let db: Box<dyn exonum_merkledb::Database> = Box::new(exonum_merkledb::TemporaryDB::new());
+        let snap = db.snapshot();
+        let mut c1 = StoreView::wrap(&snap, Default::default());
+
+        let store = MyStore {};
+        let bob = Person {
+            name: "bob".into(),
+            age: 1u8,
+        };
+        store.put("bob".into(), bob.clone(), &mut c1);
+        let fork = db.fork();
+        c1.commit(&fork);
+        db.merge(fork.into_patch()).unwrap();
+
+        let snap = db.snapshot();
+        let apphash = exonum_merkledb::SystemSchema::new(&snap)
+            .state_aggregator()
+            .object_hash();
+        let view = StoreView::wrap_snapshot(&snap);
+        let state_proof = serde_json::to_vec(&schema::get_state_proof(&snap)).unwrap();
+
+        let name = store.name();
+        let verify = |key: &str, proof: &[u8], apphash: &Hash| {
+            verify_query_proof(&name, key.to_string(), proof, &state_proof, apphash)
+        };
+        let raw = store.query_proof("bob".into(), &view).unwrap();
+        assert_eq!(Some(bob.to_bytes()), verify("bob", &raw, &apphash).unwrap());
+        let absent = store.query_proof("alice".into(), &view).unwrap();
+        assert_eq!(None, verify("alice", &absent, &apphash).unwrap());
+        // Untrusted apphash
+        assert!(verify("bob", &raw, &exonum_crypto::hash(b"other")).is_err());
+
+        // A proof of another key is rejected, whether of a value or an absence
+        assert!(verify("alice", &raw, &apphash).is_err());
+        assert!(verify("bob", &absent, &apphash).is_err());
+        let carol = store.query_proof("carol".into(), &view).unwrap();
+        assert!(verify("alice", &carol, &apphash).is_err());
+        // Or of the key in another store
+        assert!(
+            verify_query_proof("other", "bob".to_string(), &raw, &state_proof, &apphash).is_err()
+        );
+    }
 }
@@ -1,69 +1,563 @@
 //! TestKit is a simple tool to test your Application without running a Tendermint node.
-use crate::{AppBuilder, Node, SignedTransaction};
+//!
+//! `commit_tx` delivers and commits txs.  To test logic that depends on the height
+//! or time, `produce_block` runs a full block: begin_block, the txs, end_block and
+//! commit.  Each block is `TESTKIT_BLOCK_INTERVAL` seconds after the last one,
+//! unless the time is changed with `set_block_time` or `advance_time`.
+//! `advance_blocks` produces empty blocks, e.g. to reach the end of an
+//! unbonding period or the height of a scheduled tx.
+//!
+//! By default the chain has no validators and the id `TESTKIT_CHAIN_ID`.  Txs
+//! without a chain id or a signature are sent with the chain's id.  Use
+//! `create_with_genesis` to start it like a real network:
+//! ```ignore
+//! let genesis = GenesisConfig::new("my-chain")
+//!     .validator(ValidatorPower::new(pk.as_ref(), 10))
+//!     .app_state_json(r#"{"rapido.bank": {"bob": 100}}"#);
+//! let mut tester = TestKit::create_with_genesis(builder, genesis);
+//! tester.start();
+//! ```
+//!
+//! The validators vote on each block produced: `set_absent` marks a validator as
+//! not signing, as seen in `Context::votes`.  The validator updates made in
+//! end_block are in the `BlockResult` and, once committed, in `validators`.
+//!
+//! `read_store` and `dump_state` read the committed state directly, for state
+//! an AppModule has no query for.
+//!
+//! `snapshot` copies the committed state so an expensive genesis setup can be
+//! reused: `restore` it before each scenario.
+//!
+//! Invariants added with `add_invariant` are checked against the committed state
+//! after each `commit_tx` and `produce_block`. With the `proptest-harness`
+//! feature, the `prop` module generates random txs and blocks to run through them.
+use crate::schema::{self, SavedState};
+use crate::{
+    verify_query_proof, AppBuilder, InitError, Node, QueryEncoding, SignedTransaction, Store,
+    StoreView, ValidatorPower, PROOF_OP_TYPE, STATE_PROOF_OP_TYPE,
+};
+use std::fmt;
+
+#[cfg(feature = "proptest-harness")]
+pub mod prop;
+
 use abci::*;
 use anyhow::{bail, ensure};
-use exonum_crypto::{hash, PublicKey, SecretKey, Seed};
+use borsh::{BorshDeserialize, BorshSerialize};
+use exonum_crypto::{hash, Hash, PublicKey, SecretKey, Seed};
+use protobuf::well_known_types::Timestamp;
+use protobuf::RepeatedField;
+use serde::de::DeserializeOwned;
+
+/// The chain id used by the TestKit
+pub const TESTKIT_CHAIN_ID: &str = "rapido-testkit";
+
+/// Time of the first block produced by the TestKit, in seconds since the unix
+/// epoch (2020-01-01)
+pub const TESTKIT_GENESIS_TIME: i64 = 1_577_836_800;
+
+/// Seconds between the blocks produced by the TestKit
+pub const TESTKIT_BLOCK_INTERVAL: i64 = 5;
+
+/// The genesis of the chain, passed to init_chain by `start`. See `TestKit::create_with_genesis`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenesisConfig {
+    pub chain_id: String,
+    pub validators: Vec<ValidatorPower>,
+    /// JSON object keyed by AppModule name. See `AppModule::initialize`
+    pub app_state_json: String,
+}
+
+impl GenesisConfig {
+    pub fn new<S: Into<String>>(chain_id: S) -> Self {
+        Self {
+            chain_id: chain_id.into(),
+            validators: Vec::new(),
+            app_state_json: String::new(),
+        }
+    }
+
+    /// Add a genesis validator
+    pub fn validator(mut self, validator: ValidatorPower) -> Self {
+        self.validators.push(validator);
+        self
+    }
+
+    /// Set the genesis app_state
+    pub fn app_state_json<S: Into<String>>(mut self, app_state: S) -> Self {
+        self.app_state_json = app_state.into();
+        self
+    }
+}
+
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        Self::new(TESTKIT_CHAIN_ID)
+    }
+}
+
+/// The result of a tx delivered by the TestKit
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxOutcome {
+    /// 0 if the tx succeeded
+    pub code: u32,
+    /// The AppModule of a failed tx, or 'rapido' if it couldn't be routed
+    pub codespace: String,
+    pub log: String,
+    /// Events emitted by the tx, with the type prefixed by the appname: 'hello.transfer'
+    pub events: Vec<Event>,
+    pub data: Vec<u8>,
+}
+
+impl TxOutcome {
+    fn from_deliver(resp: ResponseDeliverTx) -> Self {
+        Self {
+            code: resp.code,
+            codespace: resp.codespace,
+            log: resp.log,
+            events: resp.events.into_vec(),
+            data: resp.data,
+        }
+    }
+
+    fn from_check(resp: ResponseCheckTx) -> Self {
+        Self {
+            code: resp.code,
+            codespace: resp.codespace,
+            log: resp.log,
+            events: resp.events.into_vec(),
+            data: resp.data,
+        }
+    }
+
+    // Return the failure if the tx failed
+    fn into_result(self) -> Result<Self, TxFailure> {
+        if self.is_ok() {
+            return Ok(self);
+        }
+        Err(TxFailure {
+            code: self.code,
+            codespace: self.codespace,
+            log: self.log,
+        })
+    }
+
+    /// Did the tx succeed?
+    pub fn is_ok(&self) -> bool {
+        self.code == 0
+    }
+
+    /// Return the first event of the type, e.g. 'hello.transfer'
+    pub fn event(&self, event_type: &str) -> Option<&Event> {
+        self.events.iter().find(|e| e.field_type == event_type)
+    }
+
+    /// Return the value of the attribute of the first event of the type
+    pub fn attribute(&self, event_type: &str, key: &str) -> Option<String> {
+        self.event(event_type)?
+            .attributes
+            .iter()
+            .find(|p| p.key == key.as_bytes())
+            .map(|p| String::from_utf8_lossy(&p.value).into_owned())
+    }
+}
+
+/// A check of the committed state. See `TestKit::add_invariant`
+pub type Invariant = Box<dyn Fn(&StoreView) -> anyhow::Result<(), anyhow::Error>>;
+
+/// A tx rejected by `check_tx` or `commit_tx`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxFailure {
+    pub code: u32,
+    /// The AppModule of the tx, or 'rapido' if it couldn't be routed
+    pub codespace: String,
+    pub log: String,
+}
+
+impl fmt::Display for TxFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} code {}: {}", self.codespace, self.code, self.log)
+    }
+}
+
+impl std::error::Error for TxFailure {}
+
+/// The result of `commit_tx`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitResult {
+    /// The application state hash after the commit
+    pub app_hash: Vec<u8>,
+    /// The result of each tx, in order
+    pub txs: Vec<TxOutcome>,
+}
+
+/// The result of `produce_block`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockResult {
+    pub height: i64,
+    /// Block time in seconds since the unix epoch
+    pub time: i64,
+    /// The application state hash after the block
+    pub app_hash: Vec<u8>,
+    /// The result of each tx, in order
+    pub txs: Vec<TxOutcome>,
+    /// The validator updates returned by end_block
+    pub validator_updates: Vec<ValidatorPower>,
+}
+
+/// A query value with its proof. See `TestKit::query_with_proof`
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryProof {
+    pub value: Vec<u8>,
+    /// Proof of the value in the store. See `Store::query_proof`
+    pub proof: Vec<u8>,
+    /// Proof of the store in the app state
+    pub state_proof: Vec<u8>,
+    /// Height of the state queried
+    pub height: i64,
+}
+
+/// A copy of the committed state and block time. See `TestKit::snapshot`
+#[derive(Debug, Clone)]
+pub struct StateSnapshot {
+    state: SavedState,
+    has_init: bool,
+    block_time: i64,
+}
+
+/// Assert the proof is of `key` in the store named `store_name` and valid against
+/// the apphash, as a light client would check it. Returns the proven value, as
+/// stored, or `None` for a proof of absence
+pub fn assert_proof_valid<K>(
+    store_name: &str,
+    key: K,
+    proof: &QueryProof,
+    apphash: &[u8],
+) -> Option<Vec<u8>>
+where
+    K: BorshSerialize + BorshDeserialize,
+{
+    let apphash = match Hash::from_slice(apphash) {
+        Some(hash) => hash,
+        None => panic!("bad apphash length: {}", apphash.len()),
+    };
+    match verify_query_proof(store_name, key, &proof.proof, &proof.state_proof, &apphash) {
+        Ok(value) => value,
+        Err(err) => panic!("invalid query proof: {}", err),
+    }
+}
 
 /// TestKit for testing an application without running Tendermint.
 pub struct TestKit {
     node: Node,
+    genesis: GenesisConfig,
     has_init: bool,
+    // Time of the next block produced
+    block_time: i64,
+    // Public keys of the validators that don't sign the blocks produced
+    absent: Vec<Vec<u8>>,
+    // Checked after each commit, by name
+    invariants: Vec<(String, Invariant)>,
 }
 
 impl TestKit {
     /// Create the kit from the AppBuilder
     pub fn create(builder: AppBuilder) -> Self {
+        Self::create_with_genesis(builder, GenesisConfig::default())
+    }
+
+    /// Create the kit with the chain id, validators and app_state used by `start`
+    pub fn create_with_genesis(builder: AppBuilder, genesis: GenesisConfig) -> Self {
         Self {
             node: builder.node(),
+            genesis,
             has_init: false,
+            block_time: TESTKIT_GENESIS_TIME,
+            absent: Vec::new(),
+            invariants: Vec::new(),
         }
     }
 
     /// Must call start first and only once.  This simulates Tendermint's
     /// call to initialize genesis data in the application state store.
     pub fn start(&mut self) {
-        self.node.init_chain(&RequestInitChain::new());
+        let app_state = self.genesis.app_state_json.clone();
+        self.start_with_genesis(&app_state);
+    }
+
+    /// Use instead of `start` to initialize with the given genesis `app_state`,
+    /// in place of the one of the `GenesisConfig`.
+    /// The app_state is a JSON object keyed by AppModule name. See `AppModule::initialize`.
+    pub fn start_with_genesis(&mut self, app_state: &str) {
+        if let Err(err) = self.try_start_with_genesis(app_state) {
+            panic!("{}", err);
+        }
+    }
+
+    /// Like `start_with_genesis`, but returns an error naming the AppModule
+    /// that failed to initialize instead of panicking.
+    pub fn try_start_with_genesis(&mut self, app_state: &str) -> Result<(), InitError> {
+        let mut req = RequestInitChain::new();
+        req.set_chain_id(self.genesis.chain_id.clone());
+        req.set_validators(RepeatedField::from_vec(
+            self.genesis
+                .validators
+                .iter()
+                .map(|v| v.to_abci())
+                .collect(),
+        ));
+        req.set_app_state_bytes(app_state.as_bytes().to_vec());
+        self.node.try_init_chain(&req)?;
         self.has_init = true;
+        Ok(())
+    }
+
+    // Encode the tx. A tx without a chain id or a signature is sent with the
+    // chain id of the TestKit, so tests don't have to set it
+    fn encode_tx(&self, tx: &SignedTransaction) -> Vec<u8> {
+        if !tx.chain_id().is_empty() || !tx.signature().is_empty() {
+            return tx.encode();
+        }
+        let mut tx = tx.clone();
+        tx.set_chain_id(self.genesis.chain_id.clone());
+        tx.encode()
     }
 
     /// Run transactions through the authentication handler. This simulates
     /// how Tendermint checks transactions for inclusion in the mempool.
-    pub fn check_tx(&mut self, txs: &[&SignedTransaction]) -> anyhow::Result<(), anyhow::Error> {
-        ensure!(self.has_init, "Must first call the start method");
+    /// Returns the result of each tx, or the first failure.
+    pub fn check_tx(&mut self, txs: &[&SignedTransaction]) -> Result<Vec<TxOutcome>, TxFailure> {
+        assert!(self.has_init, "Must first call the start method");
 
+        let mut outcomes = Vec::with_capacity(txs.len());
         for tx in txs {
             let mut req = RequestCheckTx::new();
-            req.set_tx(tx.encode());
-            let resp = self.node.check_tx(&req);
+            req.set_tx(self.encode_tx(tx));
+            let outcome = TxOutcome::from_check(self.node.check_tx(&req));
+            outcomes.push(outcome.into_result()?);
+        }
+        Ok(outcomes)
+    }
+
+    /// Run transactions and commit to state if there are no failures. Will return the updated
+    /// application state hash used as part of the consensus process in Tendermint, and
+    /// the result of each tx with its events.  Returns the first failure otherwise.
+    pub fn commit_tx(&mut self, txs: &[&SignedTransaction]) -> Result<CommitResult, TxFailure> {
+        assert!(self.has_init, "Must first call the start method");
+
+        let mut outcomes = Vec::with_capacity(txs.len());
+        for tx in txs {
+            let mut req = RequestDeliverTx::new();
+            req.set_tx(self.encode_tx(tx));
+            let outcome = TxOutcome::from_deliver(self.node.deliver_tx(&req));
+            outcomes.push(outcome.into_result()?);
+        }
+
+        // Commit and return the new apphash
+        let commit_resp = self.node.commit(&RequestCommit::new());
+        self.assert_invariants();
+        Ok(CommitResult {
+            app_hash: commit_resp.data,
+            txs: outcomes,
+        })
+    }
+
+    /// Set the time (seconds since the unix epoch) of the next block produced
+    pub fn set_block_time(&mut self, time: i64) {
+        self.block_time = time;
+    }
+
+    /// Move the time of the next block produced forward by the seconds
+    pub fn advance_time(&mut self, seconds: i64) {
+        self.block_time += seconds;
+    }
+
+    /// Return the time of the next block produced
+    pub fn block_time(&self) -> i64 {
+        self.block_time
+    }
+
+    /// Return the height of the last committed block
+    pub fn height(&mut self) -> i64 {
+        self.node.info(&RequestInfo::new()).last_block_height
+    }
+
+    /// Return the application state hash of the last committed block
+    pub fn app_hash(&mut self) -> Vec<u8> {
+        self.node.info(&RequestInfo::new()).last_block_app_hash
+    }
 
-            if resp.code != 0 {
-                bail!("reason: {:}", resp.log);
+    /// Return the committed validator set
+    pub fn validators(&mut self) -> Vec<ValidatorPower> {
+        match self.query_as("rapido/validators", vec![]) {
+            Ok(validators) => validators,
+            Err(err) => panic!("query validators: {}", err),
+        }
+    }
+
+    /// Set whether the validator signs the blocks produced. Validators sign by default
+    pub fn set_absent(&mut self, pub_key: &[u8], absent: bool) {
+        self.absent.retain(|k| k != pub_key);
+        if absent {
+            self.absent.push(pub_key.to_vec());
+        }
+    }
+
+    /// Assert the validator has the voting power in the committed validator set
+    pub fn assert_validator_power(&mut self, pub_key: &[u8], power: i64) {
+        match self.validators().iter().find(|v| v.pub_key == pub_key) {
+            Some(validator) => assert_eq!(
+                power,
+                validator.power,
+                "power of validator {}",
+                hex::encode(pub_key)
+            ),
+            None => panic!("no validator {}", hex::encode(pub_key)),
+        }
+    }
+
+    /// Assert the validator isn't in the committed validator set
+    pub fn assert_validator_removed(&mut self, pub_key: &[u8]) {
+        if self.validators().iter().any(|v| v.pub_key == pub_key) {
+            panic!("validator {} wasn't removed", hex::encode(pub_key));
+        }
+    }
+
+    /// Add a check of the committed state, e.g. that the balances add up to the
+    /// supply. It's run after each commit: an error fails the test
+    /// ```ignore
+    /// tester.add_invariant("no negative counts", |view| {
+    ///     ensure!(CountStore.query("total".into(), view).unwrap_or(0) >= 0, "negative total");
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn add_invariant<S, F>(&mut self, name: S, check: F)
+    where
+        S: Into<String>,
+        F: Fn(&StoreView) -> anyhow::Result<(), anyhow::Error> + 'static,
+    {
+        self.invariants.push((name.into(), Box::new(check)));
+    }
+
+    /// Run the invariants against the committed state. Panics on the first that fails
+    pub fn assert_invariants(&self) {
+        let snapshot = self.node.snapshot();
+        let view = StoreView::wrap_snapshot(&snapshot);
+        for (name, check) in &self.invariants {
+            if let Err(err) = check(&view) {
+                panic!("invariant '{}' failed: {}", name, err);
             }
         }
-        Ok(())
     }
 
-    /// Run transactions and commit to state if there are no failures. Will return the updated
-    /// application state hash used as part of the consensus process in Tendermint.
-    pub fn commit_tx(
+    /// Read a value of the store from the committed state, outside of the query path
+    pub fn read_store<S: Store>(&self, store: &S, key: S::Key) -> Option<S::Value> {
+        let snapshot = self.node.snapshot();
+        store.query(key, &StoreView::wrap_snapshot(&snapshot))
+    }
+
+    /// Return every entry of the committed app state, in key order. Keys are the
+    /// hash of the store name and key. See `Store`
+    pub fn dump_state(&self) -> Vec<(Hash, Vec<u8>)> {
+        let snapshot = self.node.snapshot();
+        schema::get_store(&snapshot).iter().collect()
+    }
+
+    /// Copy the committed state, with the time of the next block
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            state: self.node.save_state(),
+            has_init: self.has_init,
+            block_time: self.block_time,
+        }
+    }
+
+    /// Roll the state and block time back to the snapshot.  Snapshots can be
+    /// restored any number of times, e.g. before each scenario
+    pub fn restore(&mut self, snapshot: &StateSnapshot) {
+        self.node.restore_state(&snapshot.state);
+        self.has_init = snapshot.has_init;
+        self.block_time = snapshot.block_time;
+    }
+
+    /// Run a block with the txs, as Tendermint would: begin_block at the next
+    /// height and block time, deliver each tx, end_block and commit.  A failed tx
+    /// doesn't stop the block: check the outcome of each tx in the result.
+    pub fn produce_block(
         &mut self,
         txs: &[&SignedTransaction],
-    ) -> anyhow::Result<Vec<u8>, anyhow::Error> {
+    ) -> anyhow::Result<BlockResult, anyhow::Error> {
         ensure!(self.has_init, "Must first call the start method");
 
-        for tx in txs {
-            let mut req = RequestDeliverTx::new();
-            req.set_tx(tx.encode());
-            let resp = self.node.deliver_tx(&req);
+        let height = self.height() + 1;
+        let time = self.block_time;
+        let mut timestamp = Timestamp::new();
+        timestamp.set_seconds(time);
+        let mut header = Header::new();
+        header.set_chain_id(self.genesis.chain_id.clone());
+        header.set_height(height);
+        header.set_time(timestamp);
 
-            if resp.code != 0 {
-                bail!("reason: {:}", resp.log);
+        // The validators take turns to propose, and vote on the last block
+        let validators = self.validators();
+        if !validators.is_empty() {
+            let proposer = &validators[(height as usize - 1) % validators.len()];
+            header.set_proposer_address(proposer.address());
+        }
+        let mut votes = Vec::new();
+        if height > 1 {
+            for v in &validators {
+                let mut validator = Validator::new();
+                validator.set_address(v.address());
+                validator.set_power(v.power);
+                let mut vote = VoteInfo::new();
+                vote.set_validator(validator);
+                vote.set_signed_last_block(!self.absent.contains(&v.pub_key));
+                votes.push(vote);
             }
         }
+        let mut last_commit = LastCommitInfo::new();
+        last_commit.set_votes(RepeatedField::from_vec(votes));
 
-        // Commit and return the new apphash
+        let mut begin = RequestBeginBlock::new();
+        begin.set_header(header);
+        begin.set_last_commit_info(last_commit);
+        self.node.begin_block(&begin);
+
+        let mut outcomes = Vec::with_capacity(txs.len());
+        for tx in txs {
+            let mut req = RequestDeliverTx::new();
+            req.set_tx(self.encode_tx(tx));
+            outcomes.push(TxOutcome::from_deliver(self.node.deliver_tx(&req)));
+        }
+
+        let mut end = RequestEndBlock::new();
+        end.set_height(height);
+        let end_resp = self.node.end_block(&end);
         let commit_resp = self.node.commit(&RequestCommit::new());
-        Ok(commit_resp.data)
+        self.assert_invariants();
+
+        self.block_time += TESTKIT_BLOCK_INTERVAL;
+        Ok(BlockResult {
+            height,
+            time,
+            app_hash: commit_resp.data,
+            txs: outcomes,
+            validator_updates: end_resp
+                .get_validator_updates()
+                .iter()
+                .map(ValidatorPower::from_abci)
+                .collect(),
+        })
+    }
+
+    /// Produce `n` empty blocks. Returns the result of each block, in order
+    pub fn advance_blocks(&mut self, n: u64) -> anyhow::Result<Vec<BlockResult>, anyhow::Error> {
+        let mut blocks = Vec::new();
+        for _ in 0..n {
+            blocks.push(self.produce_block(&[])?);
+        }
+        Ok(blocks)
     }
 
     /// Query the latest committed state of an application.
@@ -76,18 +570,109 @@ impl TestKit {
         path: &str,
         key: K,
     ) -> anyhow::Result<Vec<u8>, anyhow::Error> {
+        // return the query value
+        Ok(self.query_response(path, key)?.value)
+    }
+
+    /// Query and decode a Borsh encoded value. See `QueryEncode`
+    pub fn query_as<K: Into<Vec<u8>>, T: BorshDeserialize>(
+        &mut self,
+        path: &str,
+        key: K,
+    ) -> anyhow::Result<T, anyhow::Error> {
+        let resp = self.query_response(path, key)?;
+        let encoding = QueryEncoding::from_info(&resp.info);
+        ensure!(
+            encoding != QueryEncoding::Json,
+            "expected a borsh value, got {}",
+            encoding
+        );
+        Ok(T::try_from_slice(&resp.value)?)
+    }
+
+    /// Query and decode a JSON encoded value. See `Json`
+    pub fn query_json<K: Into<Vec<u8>>, T: DeserializeOwned>(
+        &mut self,
+        path: &str,
+        key: K,
+    ) -> anyhow::Result<T, anyhow::Error> {
+        let resp = self.query_response(path, key)?;
+        let encoding = QueryEncoding::from_info(&resp.info);
+        ensure!(
+            encoding == QueryEncoding::Json,
+            "expected a json value, got {}",
+            encoding
+        );
+        Ok(serde_json::from_slice(&resp.value)?)
+    }
+
+    /// Query a path returning a proof, e.g. with `Store::query_proof`. Returns an
+    /// error if the query doesn't return a proof. Check it with `assert_proof_valid`:
+    /// ```ignore
+    /// let proof = tester.query_with_proof("anchor/proof", hash.to_vec())?;
+    /// let apphash = tester.app_hash();
+    /// let stored = assert_proof_valid(ANCHOR_STORE_NAME, hash.to_vec(), &proof, &apphash);
+    /// ```
+    pub fn query_with_proof<K: Into<Vec<u8>>>(
+        &mut self,
+        path: &str,
+        key: K,
+    ) -> anyhow::Result<QueryProof, anyhow::Error> {
+        let resp = self.query_request(path, key, true)?;
+        let op = |op_type: &str| -> anyhow::Result<Vec<u8>, anyhow::Error> {
+            match resp
+                .get_proof()
+                .get_ops()
+                .iter()
+                .find(|op| op.get_field_type() == op_type)
+            {
+                Some(op) => Ok(op.get_data().to_vec()),
+                None => bail!("no proof returned for {}", path),
+            }
+        };
+        Ok(QueryProof {
+            proof: op(PROOF_OP_TYPE)?,
+            state_proof: op(STATE_PROOF_OP_TYPE)?,
+            value: resp.value.clone(),
+            height: resp.height,
+        })
+    }
+
+    // Run the query, returning an error if it failed
+    fn query_response<K: Into<Vec<u8>>>(
+        &mut self,
+        path: &str,
+        key: K,
+    ) -> anyhow::Result<ResponseQuery, anyhow::Error> {
+        self.query_request(path, key, false)
+    }
+
+    fn query_request<K: Into<Vec<u8>>>(
+        &mut self,
+        path: &str,
+        key: K,
+        prove: bool,
+    ) -> anyhow::Result<ResponseQuery, anyhow::Error> {
         ensure!(self.has_init, "Must first call the start method");
 
         let mut query = RequestQuery::new();
         query.path = path.into();
         query.data = key.into();
+        query.prove = prove;
         let resp = self.node.query(&query);
 
         if resp.code != 0 {
             bail!("reason: {:}", resp.log);
         }
-        // return the query value
-        Ok(resp.value)
+        Ok(resp)
+    }
+
+    /// Export the committed state of all AppModules as a genesis app_state.
+    /// Can be passed to `start_with_genesis` on a new kit.
+    pub fn export_app_state(&self) -> anyhow::Result<String, anyhow::Error> {
+        ensure!(self.has_init, "Must first call the start method");
+        let raw = self.node.export_app_state()?;
+        Ok(String::from_utf8(raw)?)
     }
 }
 
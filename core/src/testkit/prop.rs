@@ -0,0 +1,111 @@
+//! Generators for property tests with `proptest`.  Build random txs from a
+//! module's message enum, and run them in blocks through the TestKit, which
+//! checks its invariants after each block:
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn supply_is_constant(
+//!         blocks in arb_blocks(arb_tx::<BankMsgs>("bank", &["bob", "alice"]), 1..10, 0..5)
+//!     ) {
+//!         let mut tester = TestKit::create(app());
+//!         tester.start();
+//!         tester.add_invariant("supply", check_supply);
+//!         run_blocks(&mut tester, &blocks);
+//!     }
+//! }
+//! ```
+//! The message enum needs a `Strategy`: derive `Arbitrary` with `proptest-derive`
+//! or pass one to `arb_tx_from`.  Requires the `proptest-harness` feature.
+use std::fmt::Debug;
+use std::ops::Range;
+
+use borsh::BorshSerialize;
+use proptest::prelude::*;
+
+use super::{BlockResult, TestKit};
+use crate::{SignedTransaction, TxBuilder};
+
+/// Txs to the app from one of the senders with any message. Panics if there
+/// are no senders
+pub fn arb_tx<M>(app: &str, senders: &[&str]) -> impl Strategy<Value = SignedTransaction>
+where
+    M: Arbitrary + BorshSerialize,
+{
+    arb_tx_from(app, senders, any::<M>())
+}
+
+/// Txs to the app from one of the senders with the messages of the strategy,
+/// e.g. `prop_oneof![Just(Msgs::Inc), any::<u8>().prop_map(Msgs::Create)]`
+pub fn arb_tx_from<M, S>(
+    app: &str,
+    senders: &[&str],
+    msgs: S,
+) -> impl Strategy<Value = SignedTransaction>
+where
+    M: BorshSerialize + Debug,
+    S: Strategy<Value = M>,
+{
+    let app = app.to_string();
+    let senders: Vec<Vec<u8>> = senders.iter().map(|s| s.as_bytes().to_vec()).collect();
+    (prop::sample::select(senders), msgs).prop_map(move |(sender, msg)| {
+        TxBuilder::new(app.clone())
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .expect("building tx")
+    })
+}
+
+/// Blocks of txs: a number of blocks in the range, each with a number of txs in
+/// the range
+pub fn arb_blocks<S>(
+    txs: S,
+    blocks: Range<usize>,
+    txs_per_block: Range<usize>,
+) -> impl Strategy<Value = Vec<Vec<SignedTransaction>>>
+where
+    S: Strategy<Value = SignedTransaction>,
+{
+    prop::collection::vec(prop::collection::vec(txs, txs_per_block), blocks)
+}
+
+/// Produce a block with each list of txs. A failed tx doesn't stop the run:
+/// check the results.  The TestKit's invariants are checked after each block
+pub fn run_blocks(tester: &mut TestKit, blocks: &[Vec<SignedTransaction>]) -> Vec<BlockResult> {
+    blocks
+        .iter()
+        .map(|txs| {
+            let txs: Vec<&SignedTransaction> = txs.iter().collect();
+            match tester.produce_block(&txs) {
+                Ok(result) => result,
+                Err(err) => panic!("produce block: {}", err),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshDeserialize;
+
+    #[derive(Debug, BorshSerialize, BorshDeserialize)]
+    enum Msgs {
+        Add(u8),
+    }
+
+    proptest! {
+        #[test]
+        fn test_arb_blocks(
+            blocks in arb_blocks(arb_tx_from("counter", &["bob", "alice"], any::<u8>().prop_map(Msgs::Add)), 1..4, 0..3)
+        ) {
+            prop_assert!(!blocks.is_empty() && blocks.len() < 4);
+            for tx in blocks.iter().flatten() {
+                prop_assert_eq!("counter", tx.appname());
+                prop_assert!(tx.sender() == b"bob" || tx.sender() == b"alice");
+                let Msgs::Add(value) = Msgs::try_from_slice(&tx.msg()).unwrap();
+                prop_assert_eq!(vec![0, value], tx.msg());
+            }
+        }
+    }
+}
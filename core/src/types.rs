@@ -1,16 +1,83 @@
 /// Core types used by the framework
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
 
-use abci::{Event, Pair};
-use anyhow::{anyhow, Result};
+use abci::{Event, Pair, PubKey, ValidatorUpdate};
+use anyhow::{anyhow, bail, ensure, Result};
 use borsh::{BorshDeserialize, BorshSerialize};
-use exonum_crypto::{Hash, PublicKey, SecretKey, Signature};
+use exonum_crypto::{Hash, PublicKey, SecretKey, Signature, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
 use protobuf::RepeatedField;
+use serde_json::{json, Value};
 
-use crate::store::StoreView;
+use crate::capability::Capabilities;
+use crate::events::{validate_event, RapidoEvent, MAX_EVENTS_PER_TX};
+use crate::fees::FeeGrantStore;
+use crate::gas::GasMeter;
+use crate::logger::Logger;
+use crate::query::{QueryEncode, QueryEncoding};
+use crate::store::{QueryView, StoreView};
 
 pub type AccountId = Vec<u8>;
 
+// Registered AppModules by name. Shared with the Context for cross-module dispatch
+pub(crate) type Router = Arc<HashMap<String, Box<dyn AppModule>>>;
+
+/// Maximum depth of nested `Context::dispatch` calls
+pub const MAX_DISPATCH_DEPTH: usize = 8;
+
+/// Information about the current block, set by the framework in begin_block
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BlockInfo {
+    /// Height of the block
+    pub height: i64,
+    /// Block time in seconds since the unix epoch
+    pub time: i64,
+    /// Hash of the block
+    pub hash: Vec<u8>,
+    /// Address of the validator that proposed the block
+    pub proposer: Vec<u8>,
+    /// Votes of the validators on the last block
+    pub votes: Vec<VoteInfo>,
+    /// Evidence of validator misbehavior included in the block
+    pub evidence: Vec<Misbehavior>,
+}
+
+/// The vote of a validator on the last block, from begin_block's `last_commit_info`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VoteInfo {
+    /// Address of the validator
+    pub address: Vec<u8>,
+    /// Voting power of the validator
+    pub power: i64,
+    /// Did the validator sign the last block?
+    pub signed: bool,
+}
+
+/// Evidence of validator misbehavior, e.g. double signing, from begin_block
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Misbehavior {
+    /// The kind of misbehavior as reported by Tendermint, e.g. 'duplicate/vote'
+    pub kind: String,
+    /// Address of the validator
+    pub address: Vec<u8>,
+    /// Voting power of the validator at the height
+    pub power: i64,
+    /// Height of the misbehavior
+    pub height: i64,
+}
+
+/// Information about the chain the application is running on
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChainContext {
+    /// The chain id from Tendermint's genesis
+    pub chain_id: String,
+    /// The application version. See `AppBuilder::app_version`
+    pub app_version: u64,
+}
+
 /// Context is passed to handlers from the framework automatically.
 /// It wraps information that can be used to process transactions
 /// such as the sender of the tx, the encoded msg to process and
@@ -23,26 +90,434 @@ pub struct Context {
     //event_manager: RefCell<EventManager>,
     events: RefCell<Vec<Event>>,
     appname: String,
+    block: BlockInfo,
+    chain: ChainContext,
+    gas: Rc<GasMeter>,
+    tx_hash: Option<String>,
+    rand_counter: Rc<Cell<u64>>,
+    // Number of events emitted by the tx, including nested dispatches
+    event_count: Rc<Cell<usize>>,
+    router: Option<Router>,
+    capabilities: Option<Arc<Capabilities>>,
+    caller: Option<String>,
+    depth: usize,
+    // The account paying the fees, if not the sender. See `fees`
+    fee_payer: Option<AccountId>,
+    // Has the fee of the tx been charged with `charge_fee`?
+    fee_charged: Rc<Cell<bool>>,
+    // Validator updates, only collected in end_block
+    validator_updates: Option<RefCell<Vec<ValidatorPower>>>,
 }
 
 impl Context {
     /// Create automatically by the framework for each incoming tx.
     pub fn new(tx: &SignedTransaction) -> Self {
+        let mut ctx = Self::from_parts(tx.sender(), tx.appname(), tx.msg());
+        ctx.tx_hash = Some(tx.id_hex());
+        ctx.fee_payer = tx.fee_payer().filter(|payer| *payer != tx.sender());
+        ctx
+    }
+
+    // Create a context without a tx. Used by the framework for scheduled txs
+    pub(crate) fn from_parts(sender: AccountId, appname: &str, msg: Vec<u8>) -> Self {
         Self {
-            sender: tx.sender(),
-            msg: tx.msg(),
+            sender,
+            msg,
             //event_manager: RefCell::new(EventManager::new(tx.appname().into())),
             events: RefCell::new(Vec::new()),
-            appname: tx.appname().into(),
+            appname: appname.into(),
+            block: Default::default(),
+            chain: Default::default(),
+            gas: Default::default(),
+            tx_hash: None,
+            rand_counter: Default::default(),
+            event_count: Default::default(),
+            router: None,
+            capabilities: None,
+            caller: None,
+            depth: 0,
+            fee_payer: None,
+            fee_charged: Default::default(),
+            validator_updates: None,
         }
     }
 
+    // Set the current block information
+    pub(crate) fn with_block(mut self, block: BlockInfo) -> Self {
+        self.block = block;
+        self
+    }
+
+    // Set the chain information
+    pub(crate) fn with_chain(mut self, chain: ChainContext) -> Self {
+        self.chain = chain;
+        self
+    }
+
+    // Set the gas limit for processing the message
+    pub(crate) fn with_gas_limit(mut self, limit: u64) -> Self {
+        self.gas = Rc::new(GasMeter::new(limit));
+        self
+    }
+
+    // Set the AppModules available to `dispatch`
+    pub(crate) fn with_router(mut self, router: Router) -> Self {
+        self.router = Some(router);
+        self
+    }
+
+    // Set the capabilities granted to AppModules
+    pub(crate) fn with_capabilities(mut self, capabilities: Arc<Capabilities>) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    // Collect validator updates. Used for end_block
+    pub(crate) fn with_validator_updates(mut self) -> Self {
+        self.validator_updates = Some(RefCell::new(Vec::new()));
+        self
+    }
+
+    // Return the validator updates made with `update_validator`
+    pub(crate) fn take_validator_updates(&self) -> Vec<ValidatorPower> {
+        match &self.validator_updates {
+            Some(updates) => updates.replace(Vec::new()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return the height of the current block
+    pub fn height(&self) -> i64 {
+        self.block.height
+    }
+
+    /// Return the time of the current block in seconds since the unix epoch
+    pub fn block_time(&self) -> i64 {
+        self.block.time
+    }
+
+    /// Return the address of the validator that proposed the current block
+    pub fn proposer(&self) -> &[u8] {
+        &self.block.proposer
+    }
+
+    /// Return the votes of the validators on the last block.  Empty outside of
+    /// begin_block and end_block
+    pub fn votes(&self) -> &[VoteInfo] {
+        &self.block.votes
+    }
+
+    /// Return the evidence of validator misbehavior included in the current block
+    pub fn evidence(&self) -> &[Misbehavior] {
+        &self.block.evidence
+    }
+
+    /// Set the voting power of a validator, adding it if it's new. A power of 0
+    /// removes the validator.  Only available in `AppModule::end_block`: the
+    /// updates are returned to Tendermint, which applies them 2 blocks later
+    pub fn update_validator(&self, validator: ValidatorPower) -> anyhow::Result<(), anyhow::Error> {
+        let updates = match &self.validator_updates {
+            Some(updates) => updates,
+            None => bail!("validator updates are only available in end_block"),
+        };
+        ensure!(
+            validator.pub_key.len() == PUBLIC_KEY_LENGTH,
+            "expected an ed25519 public key of {} bytes",
+            PUBLIC_KEY_LENGTH
+        );
+        ensure!(validator.power >= 0, "negative validator power");
+        updates.borrow_mut().push(validator);
+        Ok(())
+    }
+
+    /// Return the chain id. Use it to build chain specific identifiers
+    pub fn chain_id(&self) -> &str {
+        &*self.chain.chain_id
+    }
+
+    /// Return the application version
+    pub fn app_version(&self) -> u64 {
+        self.chain.app_version
+    }
+
+    /// Return information about the chain
+    pub fn chain(&self) -> &ChainContext {
+        &self.chain
+    }
+
+    /// Return the gas meter for the transaction. Use it to charge for expensive
+    /// operations and to check the remaining gas before starting large loops:
+    /// ```ignore
+    /// ctx.gas_meter().consume(blob.len() as u64 * GAS_PER_BYTE, "store blob")?;
+    /// ```
+    /// The meter is shared with messages sent via `dispatch`.
+    pub fn gas_meter(&self) -> &GasMeter {
+        &self.gas
+    }
+
+    /// Return a logger scoped to the AppModule. Records include the height
+    /// and tx hash. Example:
+    /// ```ignore
+    /// ctx.logger().debug(format!("created {}", name));
+    /// ```
+    pub fn logger(&self) -> Logger {
+        Logger::new(&self.appname, self.block.height, self.tx_hash.clone())
+    }
+
+    /// Return a deterministic pseudo-random seed derived from the block hash, the
+    /// tx hash, the sender, the AppModule and a counter incremented on each call.
+    /// Each account and AppModule gets its own sequence, including in the block
+    /// hooks where there's no tx.  Every validator computes the same sequence of
+    /// seeds, so it's safe to use for tie-breaking or sampling.
+    /// It is NOT unpredictable: the block proposer can influence the block hash.
+    pub fn rand_seed(&self) -> Hash {
+        let counter = self.rand_counter.get();
+        self.rand_counter.set(counter + 1);
+
+        let mut data = self.block.hash.clone();
+        if let Some(tx_hash) = &self.tx_hash {
+            data.extend_from_slice(tx_hash.as_bytes());
+        }
+        // Length prefixed, so the sender and app name can't run into each other
+        for part in &[&self.sender[..], self.appname.as_bytes()] {
+            data.extend_from_slice(&(part.len() as u32).to_le_bytes());
+            data.extend_from_slice(part);
+        }
+        data.extend_from_slice(&counter.to_le_bytes());
+        exonum_crypto::hash(&data)
+    }
+
     /// get the tx sender
     pub fn sender(&self) -> AccountId {
         // Hmmm... this is ugly
         self.sender.clone()
     }
 
+    /// Return the account paying the fees of the tx: the tx's fee payer, or the
+    /// sender if it's not set. See `fees`
+    pub fn fee_payer(&self) -> AccountId {
+        self.fee_payer.clone().unwrap_or_else(|| self.sender())
+    }
+
+    /// Charge the fee of the tx: `msg` is dispatched to `app` with the fee payer as
+    /// the sender, e.g. a bank transfer of `amount`.  If the fee payer isn't the
+    /// sender, `amount` is deducted from its `FeeGrant` to the sender and the tx fails
+    /// when the allowance is exceeded.  For a `TxMiddleware` charging fees: it can
+    /// only be called once per tx, before any dispatch.
+    pub fn charge_fee<M: BorshSerialize>(
+        &self,
+        amount: u64,
+        app: &str,
+        msg: M,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        ensure!(
+            self.depth == 0 && !self.fee_charged.get(),
+            "the fee of the tx has already been charged"
+        );
+        self.fee_charged.set(true);
+        if let Some(payer) = &self.fee_payer {
+            FeeGrantStore::new().use_grant(payer.clone(), self.sender(), amount, view)?;
+        }
+        self.dispatch_with_sender(self.fee_payer(), app, msg.try_to_vec()?, view)
+    }
+
+    /// Return the name of the AppModule processing the message
+    pub fn appname(&self) -> &str {
+        &*self.appname
+    }
+
+    /// Return the name of the AppModule that dispatched the message via
+    /// `dispatch`. Returns None if the message came from a transaction.
+    pub fn caller(&self) -> Option<&str> {
+        self.caller.as_deref()
+    }
+
+    /// Return an error unless the message was dispatched by an AppModule granted
+    /// the capability `{appname}::{name}`. See `AppBuilder::grant`.
+    /// Example, in the bank AppModule: `ctx.require_capability("mint")?;`
+    pub fn require_capability(&self, name: &str) -> anyhow::Result<(), anyhow::Error> {
+        let caller = match self.caller() {
+            Some(c) => c,
+            None => bail!("{}::{} requires a capability", self.appname, name),
+        };
+        let granted = self
+            .capabilities
+            .as_ref()
+            .map_or(false, |caps| caps.has(caller, &self.appname, name));
+        ensure!(
+            granted,
+            "{} doesn't have the capability {}::{}",
+            caller,
+            self.appname,
+            name
+        );
+        Ok(())
+    }
+
+    /// Synchronously send `msg` to the `handle_tx` of another registered AppModule
+    /// within the same transaction.  The sender is unchanged and the receiving
+    /// AppModule can check `caller()` to see who dispatched it. Events emitted by the
+    /// receiver are added to this context on success.  Nested dispatches are limited
+    /// to `MAX_DISPATCH_DEPTH`.  Example, a governance module executing a transfer:
+    /// ```ignore
+    /// ctx.dispatch("bank", BankMsgs::Transfer(to, amount), view)?;
+    /// ```
+    pub fn dispatch<M: BorshSerialize>(
+        &self,
+        app: &str,
+        msg: M,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        self.dispatch_with_sender(self.sender.clone(), app, msg.try_to_vec()?, view)
+    }
+
+    /// Like `dispatch` but the receiving AppModule sees `sender` as the sender.
+    /// `msg` is the encoded message, as in a `SignedTransaction`.  Used by AppModules
+    /// that control accounts, e.g. a multisig executing an approved transaction.
+    /// Requires the capability `{app}::dispatch_as`. See `AppBuilder::grant`.
+    pub fn dispatch_as(
+        &self,
+        sender: AccountId,
+        app: &str,
+        msg: &[u8],
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let granted = self
+            .capabilities
+            .as_ref()
+            .map_or(false, |caps| caps.has(&self.appname, app, "dispatch_as"));
+        ensure!(
+            granted,
+            "{} doesn't have the capability {}::dispatch_as",
+            self.appname,
+            app
+        );
+        self.dispatch_with_sender(sender, app, msg.to_vec(), view)
+    }
+
+    /// Like `dispatch` with an encoded message, e.g. a message stored to be
+    /// dispatched later
+    pub fn dispatch_encoded(
+        &self,
+        app: &str,
+        msg: &[u8],
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        self.dispatch_with_sender(self.sender.clone(), app, msg.to_vec(), view)
+    }
+
+    /// Like `dispatch_encoded` but the receiving AppModule sees the AppModule
+    /// `module` as the `caller()`, so the message has the capabilities of `module`
+    /// rather than this AppModule's.  Used by AppModules that dispatch messages on
+    /// behalf of others, e.g. a timelock executing a queued message.  Requires the
+    /// capability `{app}::dispatch_as_module`. See `AppBuilder::grant`.
+    pub fn dispatch_as_module(
+        &self,
+        module: &str,
+        app: &str,
+        msg: &[u8],
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let granted = self.capabilities.as_ref().map_or(false, |caps| {
+            caps.has(&self.appname, app, "dispatch_as_module")
+        });
+        ensure!(
+            granted,
+            "{} doesn't have the capability {}::dispatch_as_module",
+            self.appname,
+            app
+        );
+        ensure!(
+            self.router
+                .as_ref()
+                .map_or(false, |router| router.contains_key(module)),
+            "No registered AppModule found for name: {}",
+            module
+        );
+        self.dispatch_from(module, self.sender.clone(), app, msg.to_vec(), view)
+    }
+
+    fn dispatch_with_sender(
+        &self,
+        sender: AccountId,
+        app: &str,
+        msg: Vec<u8>,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        self.dispatch_from(&self.appname, sender, app, msg, view)
+    }
+
+    // Dispatch the message to `app` with `caller` as the caller
+    fn dispatch_from(
+        &self,
+        caller: &str,
+        sender: AccountId,
+        app: &str,
+        msg: Vec<u8>,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        ensure!(
+            self.depth < MAX_DISPATCH_DEPTH,
+            "max dispatch depth of {} exceeded",
+            MAX_DISPATCH_DEPTH
+        );
+        let router = match &self.router {
+            Some(r) => r,
+            None => bail!("dispatch is not available in this context"),
+        };
+        let appmodule = match router.get(app) {
+            Some(a) => a,
+            None => bail!("No registered AppModule found for name: {}", app),
+        };
+
+        let child = Self {
+            sender,
+            msg,
+            events: RefCell::new(Vec::new()),
+            appname: app.into(),
+            block: self.block.clone(),
+            chain: self.chain.clone(),
+            gas: self.gas.clone(),
+            tx_hash: self.tx_hash.clone(),
+            rand_counter: self.rand_counter.clone(),
+            event_count: self.event_count.clone(),
+            router: Some(router.clone()),
+            capabilities: self.capabilities.clone(),
+            caller: Some(caller.into()),
+            depth: self.depth + 1,
+            fee_payer: self.fee_payer.clone(),
+            fee_charged: self.fee_charged.clone(),
+            validator_updates: None,
+        };
+        appmodule.handle_tx(&child, view)?;
+
+        self.events.borrow_mut().extend(child.events.into_inner());
+        Ok(())
+    }
+
+    /// Return a read-only client to query another registered AppModule via its
+    /// public query paths.  Example, reading an account from the account module:
+    /// ```ignore
+    /// let account: Account = ctx.module("rapido.account")?.query_as("/", id, view)?;
+    /// ```
+    pub fn module(&self, app: &str) -> anyhow::Result<ModuleClient, anyhow::Error> {
+        let router = match &self.router {
+            Some(r) => r,
+            None => bail!("module queries are not available in this context"),
+        };
+        ensure!(
+            router.contains_key(app),
+            "No registered AppModule found for name: {}",
+            app
+        );
+        Ok(ModuleClient {
+            app: app.into(),
+            router: router.clone(),
+            height: self.block.height,
+        })
+    }
+
     /// Helper to decode a specific application msg.
     /// For example, if the app has a message such as:
     /// ```ignore
@@ -66,27 +541,41 @@ impl Context {
     /// Example:
     /// ```ignore
     /// let pairs = &[("name", "bob"), ("employer", "Acme")];
-    /// ctx.dispatch_event(pairs);
+    /// ctx.dispatch_event("employee", pairs)?;
     ///```
-    pub fn dispatch_event<T: Into<String>>(&self, event_type: T, pairs: &[(&str, &str)]) {
-        let mut rf = RepeatedField::<Pair>::new();
-        for (k, v) in pairs {
-            let mut p = Pair::new();
-            p.set_key(k.as_bytes().to_vec());
-            p.set_value(v.as_bytes().to_vec());
-            rf.push(p);
-        }
+    /// Returns an error if the event type is not a valid name, an attribute is too
+    /// large, or the tx has already emitted `MAX_EVENTS_PER_TX` events.
+    pub fn dispatch_event<T: Into<String>>(
+        &self,
+        event_type: T,
+        pairs: &[(&str, &str)],
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let event_type = event_type.into();
+        validate_event(&event_type, pairs)?;
+        ensure!(
+            self.event_count.get() < MAX_EVENTS_PER_TX,
+            "max of {} events per tx exceeded",
+            MAX_EVENTS_PER_TX
+        );
+        self.event_count.set(self.event_count.get() + 1);
 
         // Create a type with the appname: 'hello.transfer'
-        let full_event_type = format!("{}.{}", self.appname, event_type.into());
-        let mut e = Event::new();
-        e.set_field_type(full_event_type.into());
-        e.set_attributes(rf);
-        self.events.borrow_mut().push(e);
+        let full_event_type = format!("{}.{}", self.appname, event_type);
+        self.events
+            .borrow_mut()
+            .push(create_event(full_event_type, pairs));
+        Ok(())
+    }
 
-        //self.event_manager
-        //    .borrow_mut()
-        //    .dispatch_event(event_type, pairs)
+    /// Emit a typed event. See `RapidoEvent`. Like `dispatch_event`, the
+    /// event type is prefixed with the appname
+    pub fn emit<E: RapidoEvent>(&self, event: E) -> anyhow::Result<(), anyhow::Error> {
+        let attributes = event.attributes();
+        let pairs: Vec<(&str, &str)> = attributes
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        self.dispatch_event(event.event_type(), &pairs)
     }
 
     /// Return recorded events - called internally
@@ -96,6 +585,106 @@ impl Context {
     }
 }
 
+/// Read-only handle to the queries of another AppModule. See `Context::module`.
+/// Queries are answered by the AppModule's `handle_query`, so a module's store
+/// layout stays private to it.
+pub struct ModuleClient {
+    app: String,
+    router: Router,
+    height: i64,
+}
+
+impl ModuleClient {
+    /// Return the name of the AppModule
+    pub fn name(&self) -> &str {
+        &*self.app
+    }
+
+    /// Query the AppModule with the given path and key. The path is the same path
+    /// used by clients, without the appname: '/' or '/hello'. Returns the value
+    pub fn query<K: Into<Vec<u8>>>(
+        &self,
+        path: &str,
+        key: K,
+        view: &StoreView,
+    ) -> anyhow::Result<Vec<u8>, anyhow::Error> {
+        // Checked when the client was created
+        let appmodule = self.router.get(&self.app).expect("app module");
+        Ok(appmodule
+            .handle_query(
+                path,
+                key.into(),
+                &QueryView::new(view).with_height(self.height),
+            )?
+            .value)
+    }
+
+    /// Query the AppModule and decode the result
+    pub fn query_as<K: Into<Vec<u8>>, T: BorshDeserialize>(
+        &self,
+        path: &str,
+        key: K,
+        view: &StoreView,
+    ) -> anyhow::Result<T, anyhow::Error> {
+        let raw = self.query(path, key, view)?;
+        T::try_from_slice(&raw).map_err(anyhow::Error::msg)
+    }
+}
+
+// Create an ABCI event from the type and key/value pairs
+pub(crate) fn create_event(event_type: String, pairs: &[(&str, &str)]) -> Event {
+    let mut rf = RepeatedField::<Pair>::new();
+    for (k, v) in pairs {
+        let mut p = Pair::new();
+        p.set_key(k.as_bytes().to_vec());
+        p.set_value(v.as_bytes().to_vec());
+        rf.push(p);
+    }
+
+    let mut e = Event::new();
+    e.set_field_type(event_type);
+    e.set_attributes(rf);
+    e
+}
+
+/// Returned by a successful `Authenticator::validate`. The events and log are added
+/// to the check_tx response so auth outcomes (e.g. fee deduction) are observable
+/// by clients and indexers.
+#[derive(Debug, Default, Clone)]
+pub struct AuthResult {
+    pub(crate) events: Vec<Event>,
+    pub(crate) log: String,
+}
+
+impl AuthResult {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add an event. The type is prefixed with 'auth': 'auth.fee'
+    pub fn with_event<T: Into<String>>(mut self, event_type: T, pairs: &[(&str, &str)]) -> Self {
+        let full_event_type = format!("auth.{}", event_type.into());
+        self.events.push(create_event(full_event_type, pairs));
+        self
+    }
+
+    /// Set the log message
+    pub fn with_log<L: Into<String>>(mut self, log: L) -> Self {
+        self.log = log.into();
+        self
+    }
+
+    /// Return the recorded events
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Return the log message
+    pub fn log(&self) -> &str {
+        &self.log
+    }
+}
+
 /// Implement to create an authenticator for the app.
 /// A default authenticator is set by `AppBuilder` if one is not set by your application.
 /// Note: the default authenticator allows all transactions and doesn't increment a nonce - used
@@ -105,11 +694,14 @@ pub trait Authenticator: Sync + Send + 'static {
     /// in the Tendermint tx mempool. Validation checks should be limited to
     /// checking signatures and other read-only operations against the store.
     /// Data read from the store is based on committed (not-cached) data.
+    /// On success, return an `AuthResult` with any events or log to include
+    /// in the check_tx response. `ctx` is the same Context passed to the AppModule.
     fn validate(
         &self,
+        ctx: &Context,
         tx: &SignedTransaction,
         view: &StoreView,
-    ) -> anyhow::Result<(), anyhow::Error>;
+    ) -> anyhow::Result<AuthResult, anyhow::Error>;
 
     /// Provide the logic to increment a nonce. This is usually needed for
     /// account based accounts to ensure the proper order of transactions,
@@ -117,6 +709,7 @@ pub trait Authenticator: Sync + Send + 'static {
     /// This is called automatically in both check_tx, and deliver_tx.
     fn increment_nonce(
         &self,
+        _ctx: &Context,
         _tx: &SignedTransaction,
         _view: &mut StoreView,
     ) -> anyhow::Result<(), anyhow::Error> {
@@ -134,6 +727,234 @@ where
     }
 }
 
+/// Implement to accept unsigned transactions. Unsigned transactions skip the
+/// `Authenticator` entirely (no signature check or nonce increment) and are only
+/// accepted when a policy is set via `AppBuilder::set_unsigned_policy`. Use with care,
+/// e.g. to accept oracle votes from validator infrastructure or a faucet in devnets.
+pub trait UnsignedPolicy: Sync + Send + 'static {
+    /// Decide whether the unsigned transaction is allowed. Called in both
+    /// check_tx and deliver_tx.
+    fn allow(&self, tx: &SignedTransaction, view: &StoreView) -> anyhow::Result<(), anyhow::Error>;
+}
+
+// Convert an UnsignedPolicy in Box<UnsignedPolicy>
+impl<T> From<T> for Box<dyn UnsignedPolicy>
+where
+    T: UnsignedPolicy,
+{
+    fn from(factory: T) -> Self {
+        Box::new(factory) as Self
+    }
+}
+
+/// Implement to run logic around every tx handled by an AppModule, e.g. metering,
+/// audit logging or pausing the chain. Register with `AppBuilder::with_middleware`.
+/// Middleware is called in the order it's registered for `before`, and in reverse
+/// order for `after`. Used in deliver_tx, check_tx with `CheckMode::Execute` and
+/// for scheduled txs.
+pub trait TxMiddleware: Sync + Send + 'static {
+    /// Called before the AppModule's `handle_tx`. Return an error to reject the tx.
+    /// Use `ctx.appname()` for the AppModule handling the tx
+    fn before(&self, _ctx: &Context, _view: &mut StoreView) -> anyhow::Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Called after `handle_tx` with its result. Return an error to fail the tx
+    fn after(
+        &self,
+        _ctx: &Context,
+        _result: &anyhow::Result<(), anyhow::Error>,
+        _view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+// Convert a TxMiddleware in Box<TxMiddleware>
+impl<T> From<T> for Box<dyn TxMiddleware>
+where
+    T: TxMiddleware,
+{
+    fn from(factory: T) -> Self {
+        Box::new(factory) as Self
+    }
+}
+
+/// Returned by `AppModule::handle_query`. Only the value is required. Bytes convert
+/// into a response: `Ok(value.into())`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueryResponse {
+    /// The encoded value
+    pub value: Vec<u8>,
+    /// The height of the state the value was read from. Defaults to the
+    /// last committed height
+    pub height: Option<i64>,
+    /// An encoded proof of the value. See `Store::query_proof`
+    pub proof: Option<Vec<u8>>,
+    /// Additional information for the client
+    pub log: String,
+    /// How the value is encoded. Returned to the client in the response `info`
+    pub encoding: QueryEncoding,
+}
+
+impl QueryResponse {
+    pub fn new(value: Vec<u8>) -> Self {
+        Self {
+            value,
+            ..Default::default()
+        }
+    }
+
+    /// Set the height of the state the value was read from
+    pub fn with_height(mut self, height: i64) -> Self {
+        self.height = Some(height);
+        self
+    }
+
+    /// Set the proof
+    pub fn with_proof(mut self, proof: Vec<u8>) -> Self {
+        self.proof = Some(proof);
+        self
+    }
+
+    /// Set the log
+    pub fn with_log<L: Into<String>>(mut self, log: L) -> Self {
+        self.log = log.into();
+        self
+    }
+
+    /// Encode a value for the response. See `QueryEncode`.
+    /// Example: `QueryResponse::encode(&account)` or `QueryResponse::encode(&Json(account))`
+    pub fn encode<E: QueryEncode + ?Sized>(value: &E) -> anyhow::Result<Self, anyhow::Error> {
+        value.encode_query()
+    }
+}
+
+impl From<Vec<u8>> for QueryResponse {
+    fn from(value: Vec<u8>) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Describes a registered AppModule. Returned (Borsh encoded) as a list
+/// by the `rapido/modules` query
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct ModuleInfo {
+    /// The name of the AppModule
+    pub name: String,
+    /// The version. See `AppModule::version`
+    pub version: u32,
+    /// The query paths. See `AppModule::query_paths`
+    pub query_paths: Vec<String>,
+}
+
+/// An event emitted by a simulated tx. See `SimulationResult`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct SimulatedEvent {
+    /// The event type, e.g. 'rapido.bank.transfer'
+    pub kind: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl SimulatedEvent {
+    pub(crate) fn from_abci(event: &Event) -> Self {
+        Self {
+            kind: event.get_field_type().into(),
+            attributes: event
+                .get_attributes()
+                .iter()
+                .map(|p| {
+                    (
+                        String::from_utf8_lossy(p.get_key()).into_owned(),
+                        String::from_utf8_lossy(p.get_value()).into_owned(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Result of running a tx against the latest committed state without committing
+/// it. Returned (Borsh encoded) by the `rapido/simulate` query
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+pub struct SimulationResult {
+    /// Gas the tx consumed. See `GasMeter`
+    pub gas_used: u64,
+    /// The events the tx would emit, including the 'tx' event
+    pub events: Vec<SimulatedEvent>,
+}
+
+/// A validator's ed25519 public key and voting power. The validator set, from
+/// genesis and `Context::update_validator`, is returned (Borsh encoded) as a
+/// list by the `rapido/validators` query
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+pub struct ValidatorPower {
+    pub pub_key: Vec<u8>,
+    pub power: i64,
+}
+
+impl ValidatorPower {
+    pub fn new<K: Into<Vec<u8>>>(pub_key: K, power: i64) -> Self {
+        Self {
+            pub_key: pub_key.into(),
+            power,
+        }
+    }
+
+    pub(crate) fn from_abci(update: &ValidatorUpdate) -> Self {
+        Self::new(update.get_pub_key().get_data(), update.get_power())
+    }
+
+    /// The validator's address, as in `VoteInfo`: the first 20 bytes of the
+    /// SHA-256 hash of the public key
+    pub fn address(&self) -> Vec<u8> {
+        exonum_crypto::hash(&self.pub_key).as_ref()[..20].to_vec()
+    }
+
+    /// Convert to the ABCI type, as in Tendermint's genesis
+    pub fn to_abci(&self) -> ValidatorUpdate {
+        let mut pub_key = PubKey::new();
+        pub_key.set_field_type("ed25519".into());
+        pub_key.set_data(self.pub_key.clone());
+        let mut update = ValidatorUpdate::new();
+        update.set_pub_key(pub_key);
+        update.set_power(self.power);
+        update
+    }
+}
+
+/// Error initializing the chain from the genesis app_state. See `AppModule::initialize`
+#[derive(Debug)]
+pub enum InitError {
+    /// The genesis chain_id is empty
+    EmptyChainId,
+    /// The app_state is not a JSON object
+    AppState(anyhow::Error),
+    /// The app_state has a section for an AppModule that isn't registered
+    UnknownModule(String),
+    /// An AppModule's `initialize` returned an error
+    Module { name: String, error: anyhow::Error },
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::EmptyChainId => write!(f, "genesis chain_id is empty"),
+            InitError::AppState(error) => write!(f, "problem parsing genesis app_state: {}", error),
+            InitError::UnknownModule(name) => write!(
+                f,
+                "genesis app_state has a section for unknown AppModule {}",
+                name
+            ),
+            InitError::Module { name, error } => {
+                write!(f, "AppModule {} failed to initialize: {}", name, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InitError {}
+
 /// Main trait to implement the core logic of your application.
 pub trait AppModule: Sync + Send + 'static {
     /// This should return a application wide unique name for your application.
@@ -142,16 +963,68 @@ pub trait AppModule: Sync + Send + 'static {
     fn name(&self) -> String;
 
     /// Called on the initial start-up of the application. Can be used to establish
-    /// initial state of your application. The data processed here should be passed
-    /// through your AppModule implementation during AppBuilder setup.  For example,
-    /// if you wanted to insert a list of tuples into the store during initialization
-    /// of the application, you can do so like this:
+    /// initial state of your application. `genesis` is this AppModule's section of
+    /// the `app_state` in Tendermint's genesis.json, encoded as JSON. The `app_state`
+    /// is expected to be an object keyed by AppModule name:
     /// ```ignore
-    /// let data = vec![(name, value), ...];
-    /// AppBuilder.with_app(MyModule::new(data));
+    /// "app_state": {
+    ///     "mymodule": [["bob", 1], ["alice", 2]],
+    ///     "rapido.account": ["<hex encoded public key>"]
+    /// }
     /// ```
-    /// How the data is processed below is up to the implementor.  
-    fn initialize(&self, _view: &mut StoreView) -> Result<(), anyhow::Error> {
+    /// `genesis` is empty if there's no section for the AppModule.
+    /// Data can also be passed through your AppModule implementation during
+    /// AppBuilder setup.  How the data is processed below is up to the implementor.
+    fn initialize(&self, _genesis: &[u8], _view: &mut StoreView) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Export the current state of the AppModule as its genesis section, in the same
+    /// JSON format accepted by `initialize`. Used to restart a chain (e.g. a hard fork)
+    /// from live state. Return an empty Vec (the default) to omit the section.
+    fn export_genesis(&self, _view: &StoreView) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Names of other AppModules this AppModule depends on. They're initialized and
+    /// called on each block before this one.  The node will fail to start if
+    /// a dependency is not registered.
+    fn dependencies(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// The version of the AppModule's state layout. Increment it when a new
+    /// release changes how state is stored, and implement `on_upgrade` to migrate
+    /// the existing state. Defaults to 1.
+    fn version(&self) -> u32 {
+        1
+    }
+
+    /// Return true if `on_upgrade` can migrate state from the given stored version.
+    /// The default accepts any older version. The node refuses to start if this
+    /// returns false, e.g. when a binary's version regresses relative to the
+    /// stored state.
+    fn can_migrate_from(&self, from_version: u32) -> bool {
+        from_version <= self.version()
+    }
+
+    /// Called by the framework in the first block after a restart when the version
+    /// stored for the AppModule is lower than `version()`. Use it to transform state
+    /// written by `from_version` to the current layout.  An error will halt the node.
+    fn on_upgrade(&self, _from_version: u32, _view: &mut StoreView) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Called at the start of each block, before any transactions. The Context
+    /// has the block information but no sender or msg. Events are added to the
+    /// begin_block response.  Errors are logged and ignored.
+    fn begin_block(&self, _ctx: &Context, _view: &mut StoreView) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    /// Called at the end of each block, after all transactions. See `begin_block`.
+    /// Validator set changes are made here with `Context::update_validator`.
+    fn end_block(&self, _ctx: &Context, _view: &mut StoreView) -> Result<(), anyhow::Error> {
         Ok(())
     }
 
@@ -167,12 +1040,91 @@ pub trait AppModule: Sync + Send + 'static {
     ///    `/hello`
     ///    `/hello/world`
     /// However the *client* must call: `appmodule/hello`
+    /// See `QueryRouter` to route on path patterns. The `view` is read-only.
     fn handle_query(
         &self,
         path: &str,
         key: Vec<u8>,
-        view: &StoreView,
-    ) -> Result<Vec<u8>, anyhow::Error>;
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error>;
+
+    /// Return the query paths handled by `handle_query`, for discovery by clients
+    /// via the `rapido/modules` and `rapido/openapi` queries. If you use a
+    /// `QueryRouter`, return its `paths()`.
+    fn query_paths(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+// Registers an AppModule under another name. See `AppBuilder::with_app_as`.
+// Everything but the name is forwarded to the AppModule
+pub(crate) struct AppAlias {
+    name: String,
+    inner: Box<dyn AppModule>,
+}
+
+impl AppAlias {
+    pub(crate) fn new(name: &str, inner: Box<dyn AppModule>) -> Self {
+        Self {
+            name: name.into(),
+            inner,
+        }
+    }
+}
+
+impl AppModule for AppAlias {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn initialize(&self, genesis: &[u8], view: &mut StoreView) -> Result<(), anyhow::Error> {
+        self.inner.initialize(genesis, view)
+    }
+
+    fn export_genesis(&self, view: &StoreView) -> Result<Vec<u8>, anyhow::Error> {
+        self.inner.export_genesis(view)
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        self.inner.dependencies()
+    }
+
+    fn version(&self) -> u32 {
+        self.inner.version()
+    }
+
+    fn can_migrate_from(&self, from_version: u32) -> bool {
+        self.inner.can_migrate_from(from_version)
+    }
+
+    fn on_upgrade(&self, from_version: u32, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        self.inner.on_upgrade(from_version, view)
+    }
+
+    fn begin_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        self.inner.begin_block(ctx, view)
+    }
+
+    fn end_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        self.inner.end_block(ctx, view)
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        self.inner.handle_tx(ctx, view)
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        self.inner.handle_query(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        self.inner.query_paths()
+    }
 }
 
 // Convert an AppModule in Box<App>
@@ -187,7 +1139,7 @@ where
 
 /// SignedTransaction is used to transport transactions from the client to the your
 /// application. It provides a wrapper around application specific information.
-#[derive(BorshSerialize, BorshDeserialize)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub struct SignedTransaction {
     // The sender/signer of the transaction
     sender: AccountId,
@@ -197,6 +1149,14 @@ pub struct SignedTransaction {
     msg: Vec<u8>,
     // nonce
     nonce: u64,
+    // the nonce sequence (lane) the nonce belongs to. See `NonceStore`
+    lane: u8,
+    // explicitly marked as unsigned. See `UnsignedPolicy`
+    unsigned: bool,
+    // optional account paying the fees on behalf of the sender. See `fees`
+    fee_payer: Option<AccountId>,
+    // the chain the tx is intended for. Required to be accepted by a node
+    chain_id: String,
     // the signature over the transaction
     signature: Vec<u8>,
 }
@@ -213,10 +1173,30 @@ impl SignedTransaction {
             app: String::from(app),
             msg: payload,
             nonce,
+            lane: 0,
+            unsigned: false,
+            fee_payer: None,
+            chain_id: String::new(),
             signature: Default::default(),
         }
     }
 
+    /// Create a new transaction explicitly marked as unsigned. It will only be
+    /// accepted by the application if an `UnsignedPolicy` is set and allows it.
+    pub fn create_unsigned<S: Into<AccountId>, M>(
+        sender: S,
+        app: &'static str,
+        msg: M,
+        nonce: u64,
+    ) -> Self
+    where
+        M: BorshSerialize + BorshDeserialize,
+    {
+        let mut tx = Self::create(sender, app, msg, nonce);
+        tx.unsigned = true;
+        tx
+    }
+
     /// Return the value of app
     pub fn appname(&self) -> &str {
         &*self.app
@@ -232,6 +1212,53 @@ impl SignedTransaction {
         self.nonce
     }
 
+    /// Return the nonce lane
+    pub fn lane(&self) -> u8 {
+        self.lane
+    }
+
+    /// Set the nonce lane. This clears any existing signature.
+    pub fn set_lane(&mut self, lane: u8) {
+        self.lane = lane;
+        self.signature = Default::default();
+    }
+
+    /// Set the nonce. This clears any existing signature, as the
+    /// nonce is part of the signed content.
+    pub fn set_nonce(&mut self, nonce: u64) {
+        self.nonce = nonce;
+        self.signature = Default::default();
+    }
+
+    /// Is the transaction marked as unsigned?
+    pub fn is_unsigned(&self) -> bool {
+        self.unsigned
+    }
+
+    /// Return the fee payer, if set
+    pub fn fee_payer(&self) -> Option<AccountId> {
+        self.fee_payer.clone()
+    }
+
+    /// Set an account to pay the fees for this transaction. The fee payer must
+    /// have granted an allowance to the sender. This clears any existing signature.
+    pub fn set_fee_payer<P: Into<AccountId>>(&mut self, payer: P) {
+        self.fee_payer = Some(payer.into());
+        self.signature = Default::default();
+    }
+
+    /// Return the chain id
+    pub fn chain_id(&self) -> &str {
+        &*self.chain_id
+    }
+
+    /// Set the chain the transaction is intended for. A node only accepts a
+    /// transaction with its chain id. This clears any existing signature.
+    pub fn set_chain_id<C: Into<String>>(&mut self, chain_id: C) {
+        self.chain_id = chain_id.into();
+        self.signature = Default::default();
+    }
+
     /// Get the signature
     pub fn signature(&self) -> Vec<u8> {
         self.signature.clone()
@@ -255,25 +1282,71 @@ impl SignedTransaction {
             .map_err(|_| anyhow!("problem decoding the signed tx"))
     }
 
-    /// Sign the transaction
-    pub fn sign(&mut self, private_key: &SecretKey) {
+    /// Sign the transaction. Returns an error if the chain id isn't set, as the
+    /// signed transaction would be valid on any chain. See `set_chain_id`
+    pub fn sign(&mut self, private_key: &SecretKey) -> anyhow::Result<(), anyhow::Error> {
+        ensure!(
+            !self.chain_id.is_empty(),
+            "a chain id is required to sign a transaction"
+        );
         self.signature = exonum_crypto::sign(&self.hash()[..], private_key)
             .as_ref()
             .into();
+        Ok(())
     }
 
-    fn hash(&self) -> Hash {
-        // Hash order: sender, appname, msg, nonce
-        let contents: Vec<u8> = vec![
+    /// Return the canonical bytes of the transaction hashed by `sign()`.
+    /// Used for detached signing (hardware wallets, remote signers): the signer
+    /// produces an Ed25519 signature over the SHA-256 hash of these bytes, which is
+    /// then added to the transaction with `attach_signature()`.
+    pub fn sign_bytes(&self) -> Vec<u8> {
+        // Order: sender, appname, msg, nonce, lane, unsigned, fee payer, chain id
+        vec![
             self.sender.clone(),
             self.app.as_bytes().to_vec(),
             self.msg.clone(),
             self.nonce().to_le_bytes().to_vec(),
+            vec![self.lane],
+            vec![self.unsigned as u8],
+            encode_fee_payer(&self.fee_payer),
+            self.chain_id.as_bytes().to_vec(),
         ]
         .into_iter()
         .flatten()
-        .collect();
-        exonum_crypto::hash(&contents[..])
+        .collect()
+    }
+
+    /// Attach a signature produced outside of the process over `sign_bytes()`.
+    /// Returns an error if the signature is not a valid length.
+    pub fn attach_signature(&mut self, signature: &[u8]) -> anyhow::Result<(), anyhow::Error> {
+        ensure!(
+            signature.len() == SIGNATURE_LENGTH,
+            "expected a signature of {} bytes",
+            SIGNATURE_LENGTH
+        );
+        self.signature = signature.to_vec();
+        Ok(())
+    }
+
+    fn hash(&self) -> Hash {
+        exonum_crypto::hash(&self.sign_bytes()[..])
+    }
+
+    /// Return the canonical id of the transaction: the hash of `sign_bytes()`.
+    /// This is the hash used in the `rapido_tx` event and for indexing.
+    pub fn id(&self) -> Hash {
+        self.hash()
+    }
+
+    /// Return the id as a hex string
+    pub fn id_hex(&self) -> String {
+        hex::encode(self.id().as_ref())
+    }
+
+    /// Return the hash Tendermint uses for the transaction: the SHA256 of the
+    /// encoded tx. Use this to cross-reference RPC results (e.g. `/tx?hash=`).
+    pub fn tendermint_hash(&self) -> Hash {
+        exonum_crypto::hash(&self.encode()[..])
     }
 
     /// Convert the tx to a context
@@ -281,18 +1354,216 @@ impl SignedTransaction {
         Context::new(self)
     }
 
-    // Encode the Tx as a hex value (prefixed with 0x).
-    // Can be used to send Txs via http GET api.
+    /// Encode the Tx as a hex value (prefixed with 0x).
+    /// Can be used to send Txs via http GET api. See `from_hex`
     pub fn to_hex(&self) -> String {
         format!("0x{:}", hex::encode(self.encode()))
     }
+
+    /// Decode a Tx encoded with `to_hex`. The '0x' prefix is optional
+    pub fn from_hex(encoded: &str) -> anyhow::Result<Self, anyhow::Error> {
+        match hex::decode(encoded.trim().trim_start_matches("0x")) {
+            Ok(raw) => Self::decode(&raw),
+            Err(_) => bail!("bad hex encoded tx"),
+        }
+    }
+
+    /// Encode the Tx as portable JSON, e.g. to sign it on another (offline)
+    /// machine.  Bytes are hex encoded and the signature is empty until signed.
+    /// `sign_bytes` is included to review what's signed. See `from_json`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "app": self.app,
+            "sender": hex::encode(&self.sender),
+            "msg": hex::encode(&self.msg),
+            "nonce": self.nonce,
+            "lane": self.lane,
+            "unsigned": self.unsigned,
+            "fee_payer": self.fee_payer.as_ref().map(hex::encode),
+            "chain_id": self.chain_id,
+            "signature": hex::encode(&self.signature),
+            "sign_bytes": hex::encode(self.sign_bytes()),
+        })
+    }
+
+    /// Decode a Tx encoded with `to_json`
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let text = |field: &str| -> Result<String> {
+            match value[field].as_str() {
+                Some(text) => Ok(text.to_string()),
+                None => Err(anyhow!("missing '{}' in the tx", field)),
+            }
+        };
+        let bytes = |field: &str| -> Result<Vec<u8>> {
+            match hex::decode(text(field)?) {
+                Ok(raw) => Ok(raw),
+                Err(_) => Err(anyhow!("bad hex in '{}'", field)),
+            }
+        };
+        let nonce = match value["nonce"].as_u64() {
+            Some(nonce) => nonce,
+            None => bail!("missing 'nonce' in the tx"),
+        };
+        let lane = match value["lane"].as_u64() {
+            Some(lane) if lane <= u8::MAX as u64 => lane as u8,
+            _ => bail!("missing or bad 'lane' in the tx"),
+        };
+        let fee_payer = match value["fee_payer"] {
+            Value::Null => None,
+            _ => Some(bytes("fee_payer")?),
+        };
+        Ok(Self {
+            sender: bytes("sender")?,
+            app: text("app")?,
+            msg: bytes("msg")?,
+            nonce,
+            lane,
+            unsigned: value["unsigned"].as_bool().unwrap_or_default(),
+            fee_payer,
+            chain_id: text("chain_id")?,
+            signature: bytes("signature")?,
+        })
+    }
 }
 
-/// Sign a transaction
-pub fn sign_transaction(tx: &mut SignedTransaction, private_key: &SecretKey) {
-    tx.signature = exonum_crypto::sign(&tx.hash()[..], private_key)
-        .as_ref()
-        .into();
+/// Fluent builder to create a `SignedTransaction`. The app name, sender and msg
+/// are required.  Example:
+/// ```ignore
+/// let tx = TxBuilder::new("myapp")
+///     .sender(account_id)
+///     .msg(Msgs::Inc)
+///     .nonce(1)
+///     .chain_id("test-chain")
+///     .sign(&secret_key)?;
+/// ```
+#[derive(Default, Clone)]
+pub struct TxBuilder {
+    app: String,
+    sender: Option<AccountId>,
+    msg: Option<Vec<u8>>,
+    nonce: u64,
+    lane: u8,
+    unsigned: bool,
+    fee_payer: Option<AccountId>,
+    chain_id: String,
+}
+
+impl TxBuilder {
+    /// Start a transaction for the given app. Same as `AppModule.name()`
+    pub fn new<A: Into<String>>(app: A) -> Self {
+        Self {
+            app: app.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Set the sender (required)
+    pub fn sender<S: Into<AccountId>>(mut self, sender: S) -> Self {
+        self.sender = Some(sender.into());
+        self
+    }
+
+    /// Set the message (required). Will `panic` on a serialization error.
+    pub fn msg<M: BorshSerialize>(mut self, msg: M) -> Self {
+        self.msg = Some(msg.try_to_vec().expect("encoding msg"));
+        self
+    }
+
+    /// Set the message (required), already Borsh encoded. E.g. by bindings
+    /// in another language
+    pub fn encoded_msg(mut self, msg: Vec<u8>) -> Self {
+        self.msg = Some(msg);
+        self
+    }
+
+    /// Set the nonce. Defaults to 0
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    /// Set the nonce lane. Defaults to 0. See `NonceStore`
+    pub fn lane(mut self, lane: u8) -> Self {
+        self.lane = lane;
+        self
+    }
+
+    /// Set the chain id the transaction is intended for
+    pub fn chain_id<C: Into<String>>(mut self, chain_id: C) -> Self {
+        self.chain_id = chain_id.into();
+        self
+    }
+
+    /// Set an account to pay the fees for the sender. See `fees`
+    pub fn fee_payer<P: Into<AccountId>>(mut self, payer: P) -> Self {
+        self.fee_payer = Some(payer.into());
+        self
+    }
+
+    /// Mark the transaction as unsigned. See `UnsignedPolicy`
+    pub fn unsigned(mut self) -> Self {
+        self.unsigned = true;
+        self
+    }
+
+    /// Build the transaction without signing it. Returns an error if
+    /// a required field is missing.
+    pub fn build(self) -> anyhow::Result<SignedTransaction, anyhow::Error> {
+        ensure!(!self.app.is_empty(), "missing the app name");
+        let sender = match self.sender {
+            Some(s) => s,
+            None => return Err(anyhow!("missing the sender")),
+        };
+        let msg = match self.msg {
+            Some(m) => m,
+            None => return Err(anyhow!("missing the msg")),
+        };
+
+        Ok(SignedTransaction {
+            sender,
+            app: self.app,
+            msg,
+            nonce: self.nonce,
+            lane: self.lane,
+            unsigned: self.unsigned,
+            fee_payer: self.fee_payer,
+            chain_id: self.chain_id,
+            signature: Default::default(),
+        })
+    }
+
+    /// Build and sign the transaction
+    pub fn sign(self, private_key: &SecretKey) -> anyhow::Result<SignedTransaction, anyhow::Error> {
+        ensure!(
+            !self.unsigned,
+            "cannot sign a transaction marked as unsigned"
+        );
+        let mut tx = self.build()?;
+        tx.sign(private_key)?;
+        Ok(tx)
+    }
+}
+
+// The fee payer in the sign bytes: 0 if not set, or 1 followed by the length
+// and the account, so a tx without one can't be confused with an empty payer
+fn encode_fee_payer(fee_payer: &Option<AccountId>) -> Vec<u8> {
+    match fee_payer {
+        Some(payer) => {
+            let mut encoded = vec![1];
+            encoded.extend_from_slice(&(payer.len() as u32).to_le_bytes());
+            encoded.extend_from_slice(payer);
+            encoded
+        }
+        None => vec![0],
+    }
+}
+
+/// Sign a transaction. See `SignedTransaction::sign`
+pub fn sign_transaction(
+    tx: &mut SignedTransaction,
+    private_key: &SecretKey,
+) -> anyhow::Result<(), anyhow::Error> {
+    tx.sign(private_key)
 }
 
 /// Verify a transaction
@@ -319,7 +1590,10 @@ mod tests {
         let (pk, sk) = exonum_crypto::gen_keypair();
         let mut tx =
             SignedTransaction::create(accountid.clone(), "example", Message::Add(10u16), 1u64);
-        tx.sign(&sk);
+        // A chain id is required
+        assert!(tx.sign(&sk).is_err());
+        tx.set_chain_id("test-chain");
+        tx.sign(&sk).unwrap();
         let encoded = tx.encode();
 
         let back = SignedTransaction::decode(&encoded).unwrap();
@@ -330,4 +1604,206 @@ mod tests {
         assert_eq!(accountid, ctx.sender);
         assert_eq!("example", back.appname());
     }
+
+    #[test]
+    fn test_rand_seed() {
+        let tx = SignedTransaction::create(vec![1], "example", Message::Add(10u16), 1u64);
+        let block = BlockInfo {
+            height: 1,
+            time: 0,
+            hash: vec![1, 2, 3],
+            proposer: vec![],
+            ..Default::default()
+        };
+        let ctx = tx.into_context().with_block(block.clone());
+        let first = ctx.rand_seed();
+        let second = ctx.rand_seed();
+        assert_ne!(first, second);
+
+        // Same inputs, same seeds
+        let again = tx.into_context().with_block(block.clone());
+        assert_eq!(first, again.rand_seed());
+        assert_eq!(second, again.rand_seed());
+
+        // Without a tx, each account and AppModule has its own sequence
+        let seed = |sender: Vec<u8>, app: &str| {
+            Context::from_parts(sender, app, Vec::new())
+                .with_block(block.clone())
+                .rand_seed()
+        };
+        assert_ne!(seed(vec![1], "example"), seed(vec![2], "example"));
+        assert_ne!(seed(vec![], "one"), seed(vec![], "two"));
+        assert_ne!(seed(vec![1], "example"), seed(vec![], "\u{1}example"));
+        assert_eq!(seed(vec![1], "example"), seed(vec![1], "example"));
+    }
+
+    #[test]
+    fn test_tx_ids() {
+        let mut tx = SignedTransaction::create(vec![1], "example", Message::Add(10u16), 1u64);
+        tx.set_chain_id("test-chain");
+        let id = tx.id();
+        let tmhash = tx.tendermint_hash();
+
+        // Signing doesn't change the id, but does change the encoded tx
+        let (_, sk) = exonum_crypto::gen_keypair();
+        tx.sign(&sk).unwrap();
+        assert_eq!(id, tx.id());
+        assert_ne!(tmhash, tx.tendermint_hash());
+        assert_eq!(64, tx.id_hex().len());
+    }
+
+    #[test]
+    fn test_tx_builder() {
+        let (pk, sk) = exonum_crypto::gen_keypair();
+
+        // Missing sender and msg
+        assert!(TxBuilder::new("example").build().is_err());
+        assert!(TxBuilder::new("example").sender(vec![1]).build().is_err());
+
+        // Missing chain id
+        assert!(TxBuilder::new("example")
+            .sender(vec![1])
+            .msg(Message::Add(1))
+            .sign(&sk)
+            .is_err());
+
+        let tx = TxBuilder::new("example")
+            .sender(vec![1])
+            .msg(Message::Send("hello".into()))
+            .nonce(2)
+            .chain_id("test-chain")
+            .sign(&sk)
+            .unwrap();
+
+        let back = SignedTransaction::decode(&tx.encode()).unwrap();
+        assert!(verify_tx_signature(&back, &pk));
+        assert_eq!(2u64, back.nonce());
+        assert_eq!("test-chain", back.chain_id());
+        assert_eq!(
+            Message::Send("hello".into()),
+            back.into_context().decode_msg().unwrap()
+        );
+
+        // Same tx from the encoded msg
+        let encoded = TxBuilder::new("example")
+            .sender(vec![1])
+            .encoded_msg(Message::Send("hello".into()).try_to_vec().unwrap())
+            .nonce(2)
+            .chain_id("test-chain")
+            .sign(&sk)
+            .unwrap();
+        assert_eq!(tx.encode(), encoded.encode());
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let (pk, sk) = exonum_crypto::gen_keypair();
+        let tx = TxBuilder::new("example")
+            .sender(vec![1])
+            .msg(Message::Add(10u16))
+            .nonce(4)
+            .chain_id("test-chain")
+            .sign(&sk)
+            .unwrap();
+
+        let encoded = tx.to_hex();
+        assert!(encoded.starts_with("0x"));
+        let back = SignedTransaction::from_hex(&encoded).unwrap();
+        assert_eq!(tx.encode(), back.encode());
+        assert!(verify_tx_signature(&back, &pk));
+
+        // Without the prefix
+        let back = SignedTransaction::from_hex(&encoded[2..]).unwrap();
+        assert_eq!(tx.encode(), back.encode());
+
+        assert!(SignedTransaction::from_hex("0xzz").is_err());
+        assert!(SignedTransaction::from_hex("0x0102").is_err());
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let (pk, sk) = exonum_crypto::gen_keypair();
+        let mut tx = TxBuilder::new("example")
+            .sender(vec![1])
+            .msg(Message::Add(10u16))
+            .nonce(4)
+            .lane(2)
+            .fee_payer(vec![3])
+            .chain_id("test-chain")
+            .build()
+            .unwrap();
+
+        // Export unsigned, sign the import, export again and import signed
+        let exported = tx.to_json();
+        assert_eq!("", exported["signature"]);
+        assert_eq!(hex::encode(tx.sign_bytes()), exported["sign_bytes"]);
+        let mut imported = SignedTransaction::from_json(&exported).unwrap();
+        assert_eq!(tx.encode(), imported.encode());
+        imported.sign(&sk).unwrap();
+        let signed = SignedTransaction::from_json(&imported.to_json()).unwrap();
+        assert!(verify_tx_signature(&signed, &pk));
+        tx.sign(&sk).unwrap();
+        assert_eq!(tx.encode(), signed.encode());
+
+        let mut missing = tx.to_json();
+        missing["app"] = Value::Null;
+        assert!(SignedTransaction::from_json(&missing).is_err());
+    }
+
+    #[test]
+    fn test_fee_payer_sign_bytes() {
+        let tx = |payer: Option<Vec<u8>>| {
+            let builder = TxBuilder::new("example").sender(vec![1]).msg(1u8);
+            match payer {
+                Some(payer) => builder.fee_payer(payer),
+                None => builder,
+            }
+            .build()
+            .unwrap()
+        };
+        // Not set, set to an empty account, and a payer that could be
+        // confused with the start of the chain id
+        let none = tx(None).sign_bytes();
+        let empty = tx(Some(vec![])).sign_bytes();
+        let zero = tx(Some(vec![0])).sign_bytes();
+        assert_ne!(none, empty);
+        assert_ne!(none, zero);
+        assert_ne!(empty, zero);
+    }
+    #[test]
+    fn test_detached_signature() {
+        let (pk, sk) = exonum_crypto::gen_keypair();
+        let mut tx = SignedTransaction::create(vec![1], "example", Message::Add(10u16), 1u64);
+
+        // Sign 'remotely'
+        let hashed = exonum_crypto::hash(&tx.sign_bytes());
+        let signature = exonum_crypto::sign(&hashed[..], &sk);
+
+        assert!(tx.attach_signature(&[1u8; 10]).is_err());
+        assert!(tx.attach_signature(signature.as_ref()).is_ok());
+        assert!(verify_tx_signature(&tx, &pk));
+    }
+
+    #[test]
+    fn test_validator_updates() {
+        let (pk, _) = exonum_crypto::gen_keypair();
+        let validator = ValidatorPower::new(pk.as_ref(), 10);
+        assert_eq!(20, validator.address().len());
+        assert_eq!(validator, ValidatorPower::from_abci(&validator.to_abci()));
+
+        // Only in end_block
+        let ctx = Context::from_parts(vec![], "staking", vec![]);
+        assert!(ctx.update_validator(validator.clone()).is_err());
+
+        let ctx = ctx.with_validator_updates();
+        assert!(ctx
+            .update_validator(ValidatorPower::new(vec![1u8; 4], 1))
+            .is_err());
+        assert!(ctx
+            .update_validator(ValidatorPower::new(pk.as_ref(), -1))
+            .is_err());
+        ctx.update_validator(validator.clone()).unwrap();
+        assert_eq!(vec![validator], ctx.take_validator_updates());
+        assert!(ctx.take_validator_updates().is_empty());
+    }
 }
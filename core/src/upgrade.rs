@@ -0,0 +1,258 @@
+//! Coordinate software upgrades.  An authority (an admin account or governance)
+//! schedules an upgrade plan with a name and height via the `UpgradeModule`.  At the
+//! upgrade height the node halts unless the binary has an upgrade handler for the
+//! plan, registered with `AppBuilder::with_upgrade_handler`.  Operators then restart
+//! with the new binary, which runs the handler at the upgrade height and continues.
+//! A binary with the handler refuses to run before the upgrade height.
+//! Example, the new binary:
+//! ```ignore
+//! AppBuilder::new()
+//!     .with_app(UpgradeModule::governed_by("rapido.gov"))
+//!     .with_upgrade_handler("v2", |view| {
+//!         // migrate state
+//!         Ok(())
+//!     })
+//! ```
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::params::Authority;
+use crate::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, Store, StoreValue,
+    StoreView,
+};
+
+/// Name of the upgrade AppModule
+pub const UPGRADE_APP_NAME: &str = "rapido.upgrade";
+const PLAN_STORE_NAME: &str = "rapido.upgrade.plan";
+const APPLIED_STORE_NAME: &str = "rapido.upgrade.applied";
+const PLAN_KEY: &str = "plan";
+
+/// A handler run by the new binary at the upgrade height, e.g. to migrate state
+pub type UpgradeHandler =
+    Box<dyn Fn(&mut StoreView) -> anyhow::Result<(), anyhow::Error> + Send + Sync>;
+
+/// A scheduled upgrade
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct UpgradePlan {
+    /// Name of the upgrade handler
+    pub name: String,
+    /// Height the upgrade is applied, in begin_block
+    pub height: i64,
+    /// Information for operators, e.g. where to find the binary
+    pub info: String,
+}
+
+// The scheduled plan, if any
+struct PlanStore;
+impl Store for PlanStore {
+    type Key = String;
+    type Value = UpgradePlan;
+
+    fn name(&self) -> String {
+        PLAN_STORE_NAME.into()
+    }
+}
+
+// Height each upgrade was applied, keyed by name
+struct AppliedStore;
+impl Store for AppliedStore {
+    type Key = String;
+    type Value = i64;
+
+    fn name(&self) -> String {
+        APPLIED_STORE_NAME.into()
+    }
+}
+
+/// Return the scheduled plan, if any
+pub fn current_plan(view: &StoreView) -> Option<UpgradePlan> {
+    PlanStore {}.get(PLAN_KEY.into(), view)
+}
+
+/// Return the height the named upgrade was applied, if it was
+pub fn applied_height(name: &str, view: &StoreView) -> Option<i64> {
+    AppliedStore {}.get(name.into(), view)
+}
+
+// Record the plan as applied and remove it
+pub(crate) fn mark_applied(plan: &UpgradePlan, height: i64, view: &mut StoreView) {
+    AppliedStore {}.put(plan.name.clone(), height, view);
+    PlanStore {}.remove(PLAN_KEY.into(), view);
+}
+
+// What the node does with the plan at a height
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UpgradeAction {
+    Continue,
+    // Run the upgrade handler
+    Apply,
+    // The binary doesn't have the upgrade handler
+    Halt,
+    // The binary has the upgrade handler before the upgrade height
+    Early,
+}
+
+pub(crate) fn upgrade_action(
+    plan: Option<&UpgradePlan>,
+    height: i64,
+    has_handler: bool,
+) -> UpgradeAction {
+    match plan {
+        None => UpgradeAction::Continue,
+        Some(p) if height >= p.height && has_handler => UpgradeAction::Apply,
+        Some(p) if height >= p.height => UpgradeAction::Halt,
+        Some(_) if has_handler => UpgradeAction::Early,
+        Some(_) => UpgradeAction::Continue,
+    }
+}
+
+/// Messages to the `UpgradeModule`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum UpgradeMsgs {
+    /// Schedule an upgrade, replacing any scheduled plan
+    Schedule {
+        name: String,
+        height: i64,
+        info: String,
+    },
+    /// Cancel the scheduled plan
+    Cancel,
+}
+
+/// AppModule to schedule upgrades. Add it via `AppBuilder::with_app`.
+pub struct UpgradeModule {
+    authority: Authority,
+}
+
+impl UpgradeModule {
+    /// Only accept plans sent by the account
+    pub fn with_authority(account: impl Into<AccountId>) -> Self {
+        Self {
+            authority: Authority::Account(account.into()),
+        }
+    }
+
+    /// Only accept plans dispatched by the AppModule, e.g. governance.
+    /// See `Context::dispatch`
+    pub fn governed_by(app: &str) -> Self {
+        Self {
+            authority: Authority::Module(app.into()),
+        }
+    }
+}
+
+impl AppModule for UpgradeModule {
+    fn name(&self) -> String {
+        UPGRADE_APP_NAME.into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        ensure!(self.authority.allows(ctx), "not authorized to upgrade");
+        match ctx.decode_msg()? {
+            UpgradeMsgs::Schedule { name, height, info } => {
+                ensure!(!name.is_empty(), "upgrade name is empty");
+                ensure!(
+                    height > ctx.height(),
+                    "upgrade height must be a future height"
+                );
+                if applied_height(&name, view).is_some() {
+                    bail!("upgrade {} was applied", name);
+                }
+                PlanStore {}.put(PLAN_KEY.into(), UpgradePlan { name, height, info }, view);
+                Ok(())
+            }
+            UpgradeMsgs::Cancel => {
+                ensure!(current_plan(view).is_some(), "no upgrade is scheduled");
+                PlanStore {}.remove(PLAN_KEY.into(), view);
+                Ok(())
+            }
+        }
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/plan", |_, view| {
+                Ok(PlanStore {}.query(PLAN_KEY.into(), view))
+            })
+            .add("/applied/:name", |params, view| {
+                Ok(AppliedStore {}.query(params.require("name")?.into(), view))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/plan".into(), "/applied/:name".into()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SignedTransaction;
+
+    fn plan(height: i64) -> UpgradePlan {
+        UpgradePlan {
+            name: "v2".into(),
+            height,
+            info: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_upgrade_action() {
+        assert_eq!(UpgradeAction::Continue, upgrade_action(None, 10, false));
+        assert_eq!(
+            UpgradeAction::Continue,
+            upgrade_action(Some(&plan(10)), 9, false)
+        );
+        assert_eq!(
+            UpgradeAction::Early,
+            upgrade_action(Some(&plan(10)), 9, true)
+        );
+        assert_eq!(
+            UpgradeAction::Halt,
+            upgrade_action(Some(&plan(10)), 10, false)
+        );
+        assert_eq!(
+            UpgradeAction::Apply,
+            upgrade_action(Some(&plan(10)), 10, true)
+        );
+    }
+
+    #[test]
+    fn test_upgrade_module() {
+        let db: Box<dyn exonum_merkledb::Database> = Box::new(exonum_merkledb::TemporaryDB::new());
+        let snap = db.snapshot();
+        let mut view = StoreView::wrap(&snap, Default::default());
+
+        let module = UpgradeModule::with_authority("admin");
+        let schedule = UpgradeMsgs::Schedule {
+            name: "v2".into(),
+            height: 10,
+            info: "https://example.com/v2".into(),
+        };
+        let ctx =
+            SignedTransaction::create("bob", UPGRADE_APP_NAME, schedule.clone(), 0).into_context();
+        assert!(module.handle_tx(&ctx, &mut view).is_err());
+        let ctx = SignedTransaction::create("admin", UPGRADE_APP_NAME, schedule.clone(), 0)
+            .into_context();
+        assert!(module.handle_tx(&ctx, &mut view).is_ok());
+        assert_eq!(10, current_plan(&view).unwrap().height);
+
+        // Can't schedule an applied upgrade
+        mark_applied(&current_plan(&view).unwrap(), 10, &mut view);
+        assert!(current_plan(&view).is_none());
+        assert_eq!(Some(10), applied_height("v2", &view));
+        assert!(module.handle_tx(&ctx, &mut view).is_err());
+
+        let ctx = SignedTransaction::create("admin", UPGRADE_APP_NAME, UpgradeMsgs::Cancel, 0)
+            .into_context();
+        assert!(module.handle_tx(&ctx, &mut view).is_err());
+    }
+}
@@ -1,14 +1,19 @@
-use anyhow::bail;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, ensure};
 use borsh::{BorshDeserialize, BorshSerialize};
 use exonum_crypto::PublicKey;
 
 use rapido_core::{
-    verify_tx_signature, AccountId, AppModule, Authenticator, Context, SignedTransaction, Store,
-    StoreView,
+    verify_tx_signature, AccountId, AppModule, AuthResult, Authenticator, Context, Cron,
+    QueryResponse, QueryView, Scheduler, SignedTransaction, Store, StoreValue, StoreView,
+    TxMiddleware, UnsignedPolicy, ValidatorPower,
 };
 
 // Model
-#[derive(Debug, BorshDeserialize, BorshSerialize)]
+#[derive(Debug, BorshDeserialize, BorshSerialize, StoreValue)]
 pub struct Model {
     pub value: u8,
 }
@@ -19,9 +24,6 @@ impl Model {
     }
 }
 
-// Make the model tree compliant
-impl_store_values!(Model);
-
 // Store
 pub struct ModelStore {
     name: String,
@@ -67,6 +69,19 @@ impl AppModule for ModelApp {
         self.name.clone()
     }
 
+    // Genesis is a JSON object of account to value: {"bob": 1}
+    fn initialize(&self, genesis: &[u8], view: &mut StoreView) -> Result<(), anyhow::Error> {
+        if genesis.is_empty() {
+            return Ok(());
+        }
+        let models: HashMap<String, u8> = serde_json::from_slice(genesis)?;
+        let store = ModelStore::load(&self.name);
+        for (account, value) in models {
+            store.put(account.into_bytes(), Model { value }, view);
+        }
+        Ok(())
+    }
+
     fn handle_tx(&self, ctx: &Context, cache: &mut StoreView) -> Result<(), anyhow::Error> {
         let msg: Msgs = ctx.decode_msg()?;
         match msg {
@@ -74,7 +89,7 @@ impl AppModule for ModelApp {
                 let store = ModelStore::load(&self.name);
                 store.put(ctx.sender.clone(), Model { value: val }, cache);
                 let n = String::from_utf8(ctx.sender.clone()).unwrap();
-                ctx.dispatch_event("model", &[("created", &n)]);
+                ctx.dispatch_event("model", &[("created", &n)])?;
                 return Ok(());
             }
             Msgs::Inc => {
@@ -84,7 +99,7 @@ impl AppModule for ModelApp {
                         m.value += 1;
                         store.put(ctx.sender.clone(), m, cache);
                         let n = String::from_utf8(ctx.sender.clone()).unwrap();
-                        ctx.dispatch_event("model", &[("inc", &n)]);
+                        ctx.dispatch_event("model", &[("inc", &n)])?;
                         return Ok(());
                     }
                     _ => bail!("model for user not found"),
@@ -97,13 +112,13 @@ impl AppModule for ModelApp {
         &self,
         path: &str,
         key: Vec<u8>,
-        view: &StoreView,
-    ) -> Result<Vec<u8>, anyhow::Error> {
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
         match path {
             "/" => {
                 let store = ModelStore::load(&self.name);
                 match store.query(key, view) {
-                    Some(p) => Ok(p.try_to_vec().unwrap()),
+                    Some(p) => Ok(p.try_to_vec().unwrap().into()),
                     None => bail!("Model not found for user"),
                 }
             }
@@ -124,12 +139,541 @@ impl TestAuthenticator {
 impl Authenticator for TestAuthenticator {
     fn validate(
         &self,
+        _ctx: &Context,
         tx: &SignedTransaction,
         _view: &StoreView,
-    ) -> anyhow::Result<(), anyhow::Error> {
+    ) -> anyhow::Result<AuthResult, anyhow::Error> {
         // Check the signature
         ensure!(verify_tx_signature(tx, &self.pubkey), "bad signature");
 
+        Ok(AuthResult::new()
+            .with_event("signature", &[("verified", "true")])
+            .with_log("signature verified"))
+    }
+}
+
+// Test policy that only accepts unsigned txs for a single app. Set via AppBuilder
+pub struct TestUnsignedPolicy {
+    appname: String,
+}
+impl TestUnsignedPolicy {
+    pub fn new(appname: &str) -> Self {
+        Self {
+            appname: appname.into(),
+        }
+    }
+}
+impl UnsignedPolicy for TestUnsignedPolicy {
+    fn allow(
+        &self,
+        tx: &SignedTransaction,
+        _view: &StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        ensure!(tx.appname() == self.appname, "unsigned tx not allowed");
+        Ok(())
+    }
+}
+
+// Forwards its message to another AppModule via `Context::dispatch`
+pub struct ProxyApp {
+    name: String,
+    target: String,
+}
+
+impl ProxyApp {
+    pub fn new(name: &str, target: &str) -> Self {
+        Self {
+            name: name.into(),
+            target: target.into(),
+        }
+    }
+}
+
+impl AppModule for ProxyApp {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let msg: Msgs = ctx.decode_msg()?;
+        ctx.dispatch(&self.target, msg, view)
+    }
+
+    fn handle_query(
+        &self,
+        _path: &str,
+        _key: Vec<u8>,
+        _view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        bail!("invalid query")
+    }
+}
+
+// Only accepts txs from senders with a model in the target AppModule.
+// Uses `Context::module` to read the other module
+pub struct MemberApp {
+    target: String,
+}
+
+impl MemberApp {
+    pub fn new(target: &str) -> Self {
+        Self {
+            target: target.into(),
+        }
+    }
+}
+
+impl AppModule for MemberApp {
+    fn name(&self) -> String {
+        "member".into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let model: Model = ctx
+            .module(&self.target)?
+            .query_as("/", ctx.sender(), view)?;
+        ensure!(model.value > 0, "not a member");
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        _path: &str,
+        _key: Vec<u8>,
+        _view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        bail!("invalid query")
+    }
+}
+
+// Records the order AppModules are initialized
+#[derive(Debug, Default, BorshDeserialize, BorshSerialize, StoreValue)]
+pub struct InitOrder(pub Vec<String>);
+
+struct InitOrderStore;
+impl Store for InitOrderStore {
+    type Key = String;
+    type Value = InitOrder;
+
+    fn name(&self) -> String {
+        "init.order".into()
+    }
+}
+
+pub struct InitApp {
+    name: String,
+    deps: Vec<String>,
+}
+
+impl InitApp {
+    pub fn new(name: &str) -> Self {
+        Self::with_deps(name, &[])
+    }
+
+    pub fn with_deps(name: &str, deps: &[&str]) -> Self {
+        Self {
+            name: name.into(),
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+        }
+    }
+}
+
+impl AppModule for InitApp {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        self.deps.clone()
+    }
+
+    fn export_genesis(&self, view: &StoreView) -> Result<Vec<u8>, anyhow::Error> {
+        let order = InitOrderStore {}
+            .query("order".into(), view)
+            .unwrap_or_default();
+        Ok(serde_json::to_vec(&order.0)?)
+    }
+
+    fn initialize(&self, _genesis: &[u8], view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let store = InitOrderStore {};
+        let mut order = store.get("order".into(), view).unwrap_or_default();
+        order.0.push(self.name.clone());
+        store.put("order".into(), order, view);
+        Ok(())
+    }
+
+    fn handle_tx(&self, _ctx: &Context, _view: &mut StoreView) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        _path: &str,
+        _key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        let order = InitOrderStore {}
+            .query("order".into(), view)
+            .unwrap_or_default();
+        Ok(order.try_to_vec()?.into())
+    }
+}
+
+// Stores the sender's model and then panics on Msgs::Inc, or fails on
+// Msgs::Create(0). Stores a model under 'begin' and 'end' and then fails in
+// begin_block and panics in end_block. Panics on queries
+pub struct PanicApp;
+
+impl AppModule for PanicApp {
+    fn name(&self) -> String {
+        "panic".into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let store = ModelStore::load("panic");
+        match ctx.decode_msg()? {
+            Msgs::Inc => {
+                store.put(ctx.sender(), Model { value: 1 }, view);
+                panic!("inc is not supported")
+            }
+            Msgs::Create(value) => {
+                store.put(ctx.sender(), Model { value }, view);
+                ensure!(value > 0, "zero is not supported");
+                Ok(())
+            }
+        }
+    }
+
+    fn begin_block(&self, _ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        ModelStore::load("panic").put(b"begin".to_vec(), Model { value: 1 }, view);
+        bail!("begin_block is not supported")
+    }
+
+    fn end_block(&self, _ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        ModelStore::load("panic").put(b"end".to_vec(), Model { value: 1 }, view);
+        panic!("end_block is not supported")
+    }
+
+    fn handle_query(
+        &self,
+        _path: &str,
+        _key: Vec<u8>,
+        _view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        panic!("query is not supported")
+    }
+}
+
+// Rejects txs from a blocked sender and counts the successful txs
+pub struct BlockSender {
+    blocked: String,
+    pub handled: Arc<AtomicUsize>,
+}
+
+impl BlockSender {
+    pub fn new(blocked: &str) -> Self {
+        Self {
+            blocked: blocked.into(),
+            handled: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl TxMiddleware for BlockSender {
+    fn before(&self, ctx: &Context, _view: &mut StoreView) -> Result<(), anyhow::Error> {
+        ensure!(ctx.sender() != self.blocked.as_bytes(), "sender is blocked");
+        Ok(())
+    }
+
+    fn after(
+        &self,
+        _ctx: &Context,
+        result: &Result<(), anyhow::Error>,
+        _view: &mut StoreView,
+    ) -> Result<(), anyhow::Error> {
+        if result.is_ok() {
+            self.handled.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+}
+
+// Fails the txs of a sender after they're handled, so their changes are discarded
+pub struct RejectAfter {
+    rejected: String,
+}
+
+impl RejectAfter {
+    pub fn new(rejected: &str) -> Self {
+        Self {
+            rejected: rejected.into(),
+        }
+    }
+}
+
+impl TxMiddleware for RejectAfter {
+    fn after(
+        &self,
+        ctx: &Context,
+        _result: &Result<(), anyhow::Error>,
+        _view: &mut StoreView,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(
+            ctx.sender() != self.rejected.as_bytes(),
+            "sender is rejected"
+        );
+        Ok(())
+    }
+}
+
+pub struct TxCountStore;
+
+impl Store for TxCountStore {
+    type Key = String;
+    type Value = u64;
+
+    fn name(&self) -> String {
+        "txcount.store".into()
+    }
+}
+
+// Counts the txs handled in the store, before they're handled
+pub struct CountTxs;
+
+impl CountTxs {
+    fn count(view: &StoreView) -> u64 {
+        TxCountStore.get("count".into(), view).unwrap_or_default()
+    }
+}
+
+impl TxMiddleware for CountTxs {
+    fn before(&self, _ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        TxCountStore.put("count".into(), Self::count(view) + 1, view);
+        Ok(())
+    }
+}
+
+// Schedules the message to the 'panic' AppModule at the next height, or on
+// Msgs::Inc registers a job sending it Msgs::Create(0) every block
+pub struct DeferApp;
+
+impl AppModule for DeferApp {
+    fn name(&self) -> String {
+        "defer".into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        match ctx.decode_msg()? {
+            Msgs::Inc => Cron::new().register("panic", "zero", 1, Msgs::Create(0), view),
+            msg => {
+                Scheduler::new().schedule(ctx, ctx.height() + 1, "panic", msg, view)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn handle_query(
+        &self,
+        _path: &str,
+        _key: Vec<u8>,
+        _view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        bail!("invalid query")
+    }
+}
+
+// Only accepts messages dispatched by an AppModule with the 'mint::mint' capability
+pub struct MintApp;
+
+impl AppModule for MintApp {
+    fn name(&self) -> String {
+        "mint".into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, _view: &mut StoreView) -> Result<(), anyhow::Error> {
+        ctx.require_capability("mint")
+    }
+
+    fn handle_query(
+        &self,
+        _path: &str,
+        _key: Vec<u8>,
+        _view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        bail!("invalid query")
+    }
+}
+
+// The height and time of a block, as seen by ClockApp
+#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, StoreValue)]
+pub struct Clock {
+    pub height: i64,
+    pub time: i64,
+}
+
+pub struct ClockStore;
+
+impl Store for ClockStore {
+    type Key = Vec<u8>;
+    type Value = Clock;
+
+    fn name(&self) -> String {
+        "clock.store".into()
+    }
+}
+
+// Records the block of each tx under the sender, and of the last end_block under 'end'
+pub struct ClockApp;
+
+impl ClockApp {
+    fn record(ctx: &Context, key: Vec<u8>, view: &mut StoreView) {
+        let clock = Clock {
+            height: ctx.height(),
+            time: ctx.block_time(),
+        };
+        ClockStore.put(key, clock, view);
+    }
+}
+
+impl AppModule for ClockApp {
+    fn name(&self) -> String {
+        "clock".into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        Self::record(ctx, ctx.sender.clone(), view);
         Ok(())
     }
+
+    fn end_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        Self::record(ctx, b"end".to_vec(), view);
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        _path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        match ClockStore.query(key, view) {
+            Some(clock) => Ok(clock.try_to_vec()?.into()),
+            None => bail!("no clock"),
+        }
+    }
+}
+
+// Blocks a validator can miss in a row before StakeApp removes it
+pub const MAX_MISSED_BLOCKS: u32 = 2;
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum StakeMsgs {
+    SetPower(Vec<u8>, i64),
+}
+
+#[derive(Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, StoreValue)]
+pub struct Staking {
+    // Public keys of the validators
+    pub known: Vec<Vec<u8>>,
+    // Power changes for the next end_block
+    pub pending: Vec<(Vec<u8>, i64)>,
+    // Blocks missed in a row by public key
+    pub missed: Vec<(Vec<u8>, u32)>,
+}
+
+pub struct StakingStore;
+
+impl Store for StakingStore {
+    type Key = Vec<u8>;
+    type Value = Staking;
+
+    fn name(&self) -> String {
+        "staking.store".into()
+    }
+}
+
+// Sets the power of validators with txs, and removes the ones that miss
+// MAX_MISSED_BLOCKS blocks in a row. The genesis is a list of hex public keys
+pub struct StakeApp;
+
+impl AppModule for StakeApp {
+    fn name(&self) -> String {
+        "staking".into()
+    }
+
+    fn initialize(&self, genesis: &[u8], view: &mut StoreView) -> Result<(), anyhow::Error> {
+        if genesis.is_empty() {
+            return Ok(());
+        }
+        let keys: Vec<String> = serde_json::from_slice(genesis)?;
+        let mut state = Staking::default();
+        for key in keys {
+            state.known.push(hex::decode(key)?);
+        }
+        StakingStore.put(b"state".to_vec(), state, view);
+        Ok(())
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let StakeMsgs::SetPower(pub_key, power) = ctx.decode_msg()?;
+        let mut state = StakingStore
+            .get(b"state".to_vec(), view)
+            .unwrap_or_default();
+        if !state.known.contains(&pub_key) {
+            state.known.push(pub_key.clone());
+        }
+        state.pending.push((pub_key, power));
+        StakingStore.put(b"state".to_vec(), state, view);
+        Ok(())
+    }
+
+    fn end_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let mut state = StakingStore
+            .get(b"state".to_vec(), view)
+            .unwrap_or_default();
+        for (pub_key, power) in state.pending.drain(..) {
+            ctx.update_validator(ValidatorPower::new(pub_key, power))?;
+        }
+
+        for vote in ctx.votes() {
+            let pub_key = match state
+                .known
+                .iter()
+                .find(|k| ValidatorPower::new(k.as_slice(), 0).address() == vote.address)
+            {
+                Some(pub_key) => pub_key.clone(),
+                None => continue,
+            };
+            let i = match state.missed.iter().position(|(k, _)| *k == pub_key) {
+                Some(i) => i,
+                None => {
+                    state.missed.push((pub_key.clone(), 0));
+                    state.missed.len() - 1
+                }
+            };
+            let missed = if vote.signed {
+                0
+            } else {
+                state.missed[i].1 + 1
+            };
+            state.missed[i].1 = missed;
+            if missed == MAX_MISSED_BLOCKS {
+                ctx.update_validator(ValidatorPower::new(pub_key, 0))?;
+            }
+        }
+        StakingStore.put(b"state".to_vec(), state, view);
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        _path: &str,
+        _key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        let state = StakingStore
+            .query(b"state".to_vec(), view)
+            .unwrap_or_default();
+        Ok(state.try_to_vec()?.into())
+    }
 }
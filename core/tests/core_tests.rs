@@ -1,12 +1,21 @@
+use borsh::{BorshDeserialize, BorshSerialize};
 use exonum_crypto::gen_keypair;
 
 #[macro_use]
 extern crate rapido_core;
 
-use rapido_core::{AppBuilder, SignedTransaction, TestKit};
+use rapido_core::{
+    AppBuilder, ChainState, DefaultAuthenticator, GenesisConfig, InitError, ModuleInfo,
+    RateLimiter, SignedTransaction, SimulationResult, Store, TestKit, TxBuilder, ValidatorPower,
+    TESTKIT_BLOCK_INTERVAL, TESTKIT_CHAIN_ID, TESTKIT_GENESIS_TIME,
+};
 
 pub mod app;
-use app::{Model, ModelApp, Msgs, TestAuthenticator};
+use app::{
+    BlockSender, Clock, ClockApp, CountTxs, DeferApp, InitApp, InitOrder, MemberApp, MintApp,
+    Model, ModelApp, ModelStore, Msgs, PanicApp, ProxyApp, RejectAfter, StakeApp, StakeMsgs,
+    Staking, TestAuthenticator, TestUnsignedPolicy, TxCountStore, MAX_MISSED_BLOCKS,
+};
 
 #[test]
 fn test_core_basics() {
@@ -104,15 +113,1012 @@ fn test_check_with_simple_authenticator() {
 
     // Check alice passes
     let mut alicetx = SignedTransaction::create(alice, app1, Msgs::Create(1), 0u64);
-    alicetx.sign(&alicesk);
+    alicetx.set_chain_id(TESTKIT_CHAIN_ID);
+    alicetx.sign(&alicesk).unwrap();
 
     let txs = &[&alicetx];
     assert!(tester.check_tx(txs).is_ok());
 
     // Check 'bad' fails
     let mut badtx = SignedTransaction::create(badguy, app1, Msgs::Create(1), 0u64);
-    badtx.sign(&badsk);
+    badtx.set_chain_id(TESTKIT_CHAIN_ID);
+    badtx.sign(&badsk).unwrap();
 
     let txs1 = &[&badtx];
     assert!(tester.check_tx(txs1).is_err());
 }
+
+#[test]
+fn test_unsigned_policy() {
+    let app1 = "app1";
+    let app2 = "app2";
+    let alice = "alice";
+    let (alicepk, _) = gen_keypair();
+
+    // No policy: unsigned txs are rejected
+    let mut tester = TestKit::create(
+        AppBuilder::new()
+            .set_authenticator(TestAuthenticator::new(alicepk))
+            .with_app(ModelApp::new(app1)),
+    );
+    tester.start();
+
+    let tx = SignedTransaction::create_unsigned(alice, app1, Msgs::Create(1), 0u64);
+    assert!(tester.check_tx(&[&tx]).is_err());
+    assert!(tester.commit_tx(&[&tx]).is_err());
+
+    // With a policy: skips the authenticator
+    let mut tester = TestKit::create(
+        AppBuilder::new()
+            .set_authenticator(TestAuthenticator::new(alicepk))
+            .set_unsigned_policy(TestUnsignedPolicy::new(app1))
+            .with_app(ModelApp::new(app1))
+            .with_app(ModelApp::new(app2)),
+    );
+    tester.start();
+
+    assert!(tester.check_tx(&[&tx]).is_ok());
+    assert!(tester.commit_tx(&[&tx]).is_ok());
+    assert!(tester.query(app1, alice).is_ok());
+
+    // Policy rejects unsigned txs to app2
+    let tx2 = SignedTransaction::create_unsigned(alice, app2, Msgs::Create(1), 0u64);
+    assert!(tester.check_tx(&[&tx2]).is_err());
+}
+
+#[test]
+fn test_fee_payer_grant() {
+    use rapido_core::fees::{FeeGrantModule, FeeGrantMsgs, FEE_GRANT_APP_NAME};
+
+    let app1 = "app1";
+    let sponsor = "sponsor";
+    let alice = "alice";
+
+    let mut tester = TestKit::create(
+        AppBuilder::new()
+            .with_app(FeeGrantModule)
+            .with_app(ModelApp::new(app1)),
+    );
+    tester.start();
+
+    // No grant yet
+    let mut tx = SignedTransaction::create(alice, app1, Msgs::Create(1), 0u64);
+    tx.set_fee_payer(sponsor);
+    assert!(tester.check_tx(&[&tx]).is_err());
+
+    let grant = SignedTransaction::create(
+        sponsor,
+        FEE_GRANT_APP_NAME,
+        FeeGrantMsgs::Grant {
+            grantee: alice.into(),
+            allowance: 100,
+        },
+        0u64,
+    );
+    assert!(tester.commit_tx(&[&grant]).is_ok());
+    assert!(tester.commit_tx(&[&tx]).is_ok());
+
+    let revoke = SignedTransaction::create(
+        sponsor,
+        FEE_GRANT_APP_NAME,
+        FeeGrantMsgs::Revoke {
+            grantee: alice.into(),
+        },
+        0u64,
+    );
+    assert!(tester.commit_tx(&[&revoke]).is_ok());
+    assert!(tester.check_tx(&[&tx]).is_err());
+}
+
+#[test]
+fn test_check_mode_execute() {
+    use rapido_core::CheckMode;
+
+    let app1 = "app1";
+    let bob = "bob";
+
+    // Inc fails in the handler as bob doesn't have a model yet
+    let inc = SignedTransaction::create(bob, app1, Msgs::Inc, 0u64);
+
+    // Stateless check only runs the authenticator
+    let mut tester = TestKit::create(AppBuilder::new().with_app(ModelApp::new(app1)));
+    tester.start();
+    assert!(tester.check_tx(&[&inc]).is_ok());
+
+    let mut tester = TestKit::create(
+        AppBuilder::new()
+            .check_mode(CheckMode::Execute)
+            .with_app(ModelApp::new(app1)),
+    );
+    tester.start();
+    assert!(tester.check_tx(&[&inc]).is_err());
+
+    // Changes are tracked in the check cache
+    let create = SignedTransaction::create(bob, app1, Msgs::Create(1), 0u64);
+    assert!(tester.check_tx(&[&create, &inc]).is_ok());
+}
+
+#[test]
+fn test_check_failure_discarded() {
+    use rapido_core::CheckMode;
+
+    let app1 = "app1";
+    let mut tester = TestKit::create(
+        AppBuilder::new()
+            .check_mode(CheckMode::Execute)
+            .with_middleware(RejectAfter::new("carol"))
+            .with_app(ModelApp::new(app1)),
+    );
+    tester.start();
+
+    // The model is created by the handler before the tx fails
+    let create = SignedTransaction::create("carol", app1, Msgs::Create(1), 0u64);
+    let inc = SignedTransaction::create("carol", app1, Msgs::Inc, 0u64);
+    assert!(tester.check_tx(&[&create]).is_err());
+    let err = tester.check_tx(&[&inc]).unwrap_err();
+    assert_eq!("model for user not found", err.log);
+
+    // The check cache is cleared after commit
+    let create = SignedTransaction::create("bob", app1, Msgs::Create(1), 0u64);
+    let inc = SignedTransaction::create("bob", app1, Msgs::Inc, 0u64);
+    assert!(tester.check_tx(&[&create]).is_ok());
+    assert!(tester.commit_tx(&[]).is_ok());
+    assert!(tester.check_tx(&[&inc]).is_err());
+}
+
+register_modules! {
+    enum Routes {
+        One => "app1",
+        Two => "app2",
+    }
+}
+
+#[test]
+fn test_module_registry() {
+    assert_eq!("app1", Routes::One.as_str());
+    assert_eq!(Some(Routes::Two), Routes::from_route("app2"));
+    assert_eq!(None, Routes::from_route("app3"));
+    assert_eq!(2, Routes::ALL.len());
+
+    let app = AppBuilder::new()
+        .with_registry(Routes::NAMES)
+        .with_app(ModelApp::new(Routes::One.as_str()))
+        .with_app(ModelApp::new(Routes::Two.as_str()));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let tx = TxBuilder::new(Routes::Two)
+        .sender("bob")
+        .msg(Msgs::Create(1))
+        .build()
+        .unwrap();
+    assert!(tester.commit_tx(&[&tx]).is_ok());
+    assert!(tester.query(Routes::Two.as_str(), "bob").is_ok());
+}
+
+#[test]
+#[should_panic]
+fn test_module_registry_mismatch() {
+    let app = AppBuilder::new()
+        .with_registry(Routes::NAMES)
+        .with_app(ModelApp::new("app1"));
+    TestKit::create(app);
+}
+
+#[test]
+fn test_cross_module_dispatch() {
+    let app = AppBuilder::new()
+        .with_app(ModelApp::new("model"))
+        .with_app(ProxyApp::new("proxy", "model"))
+        .with_app(ProxyApp::new("loop", "loop"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    // The proxy creates the model on behalf of the sender
+    let tx = TxBuilder::new("proxy")
+        .sender("bob")
+        .msg(Msgs::Create(1))
+        .build()
+        .unwrap();
+    assert!(tester.commit_tx(&[&tx]).is_ok());
+    let model = Model::decode(tester.query("model", "bob").unwrap());
+    assert_eq!(1, model.value);
+
+    // Recursive dispatch hits the depth limit
+    let tx = TxBuilder::new("loop")
+        .sender("bob")
+        .msg(Msgs::Inc)
+        .build()
+        .unwrap();
+    assert!(tester.commit_tx(&[&tx]).is_err());
+}
+
+#[test]
+fn test_module_client() {
+    let app = AppBuilder::new()
+        .with_app(ModelApp::new("model"))
+        .with_app(MemberApp::new("model"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let member_tx = TxBuilder::new("member")
+        .sender("bob")
+        .msg(Msgs::Inc)
+        .build()
+        .unwrap();
+
+    // bob doesn't have a model yet
+    assert!(tester.commit_tx(&[&member_tx]).is_err());
+
+    let create = TxBuilder::new("model")
+        .sender("bob")
+        .msg(Msgs::Create(1))
+        .build()
+        .unwrap();
+    assert!(tester.commit_tx(&[&create]).is_ok());
+    assert!(tester.commit_tx(&[&member_tx]).is_ok());
+}
+
+#[test]
+fn test_module_order() {
+    let init_order = |app: AppBuilder| {
+        let mut tester = TestKit::create(app);
+        tester.start();
+        let raw = tester.query("a", vec![]).unwrap();
+        InitOrder::try_from_slice(&raw).unwrap().0
+    };
+
+    // Registration order
+    let app = AppBuilder::new()
+        .with_app(InitApp::new("b"))
+        .with_app(InitApp::new("a"))
+        .with_app(InitApp::new("c"));
+    assert_eq!(vec!["b", "a", "c"], init_order(app));
+
+    // Explicit order
+    let app = AppBuilder::new()
+        .with_app(InitApp::new("b"))
+        .with_app(InitApp::new("a"))
+        .with_app(InitApp::new("c"))
+        .with_order(&["c", "a", "b"]);
+    assert_eq!(vec!["c", "a", "b"], init_order(app));
+
+    // Dependencies are initialized first
+    let app = AppBuilder::new()
+        .with_app(InitApp::with_deps("a", &["c"]))
+        .with_app(InitApp::new("b"))
+        .with_app(InitApp::new("c"));
+    assert_eq!(vec!["b", "c", "a"], init_order(app));
+}
+
+#[test]
+#[should_panic]
+fn test_module_missing_dependency() {
+    let app = AppBuilder::new().with_app(InitApp::with_deps("a", &["bank"]));
+    TestKit::create(app);
+}
+
+#[test]
+#[should_panic]
+fn test_module_order_missing() {
+    let app = AppBuilder::new()
+        .with_app(InitApp::new("a"))
+        .with_app(InitApp::new("b"))
+        .with_order(&["a"]);
+    TestKit::create(app);
+}
+
+#[test]
+fn test_genesis_app_state() {
+    let app = AppBuilder::new()
+        .with_app(ModelApp::new("one"))
+        .with_app(ModelApp::new("two"));
+    let mut tester = TestKit::create(app);
+    tester.start_with_genesis(r#"{"one": {"bob": 3, "alice": 4}}"#);
+
+    let model = Model::decode(tester.query("one", "alice").unwrap());
+    assert_eq!(4, model.value);
+    assert!(tester.query("two", "alice").is_err());
+}
+
+#[test]
+fn test_export_genesis() {
+    let app = AppBuilder::new()
+        .with_app(InitApp::new("a"))
+        .with_app(InitApp::new("b"))
+        .with_app(ModelApp::new("model"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let exported = tester.export_app_state().unwrap();
+    let value: serde_json::Value = serde_json::from_str(&exported).unwrap();
+    assert_eq!(serde_json::json!(["a", "b"]), value["a"]);
+    // ModelApp doesn't export
+    assert!(value.get("model").is_none());
+}
+
+#[test]
+fn test_chain_id() {
+    let mut tester = TestKit::create(AppBuilder::new().with_app(ModelApp::new("model")));
+    tester.start();
+
+    let wrong = TxBuilder::new("model")
+        .sender("bob")
+        .msg(Msgs::Create(1))
+        .chain_id("other-chain")
+        .build()
+        .unwrap();
+    assert!(tester.check_tx(&[&wrong]).is_err());
+    assert!(tester.commit_tx(&[&wrong]).is_err());
+
+    let right = TxBuilder::new("model")
+        .sender("bob")
+        .msg(Msgs::Create(1))
+        .chain_id(TESTKIT_CHAIN_ID)
+        .build()
+        .unwrap();
+    assert!(tester.commit_tx(&[&right]).is_ok());
+}
+
+#[test]
+fn test_apphash_query() {
+    let mut tester = TestKit::create(AppBuilder::new().with_app(ModelApp::new("model")));
+    tester.start();
+
+    let tx = TxBuilder::new("model")
+        .sender("bob")
+        .msg(Msgs::Create(1))
+        .build()
+        .unwrap();
+    let result = tester.commit_tx(&[&tx]).unwrap();
+    let apphash = result.app_hash;
+    assert_eq!(
+        Some("bob".to_string()),
+        result.txs[0].attribute("model.model", "created")
+    );
+    assert_eq!(
+        Some(tx.id_hex()),
+        result.txs[0].attribute("rapido_tx", "hash")
+    );
+
+    let raw = tester.query("rapido/apphash", vec![]).unwrap();
+    let state = ChainState::try_from_slice(&raw).unwrap();
+    assert_eq!(1, state.height);
+    assert_eq!(apphash, state.apphash);
+
+    assert!(tester.query("rapido/other", vec![]).is_err());
+}
+
+#[test]
+fn test_modules_query() {
+    let app = AppBuilder::new()
+        .with_app(ModelApp::new("one"))
+        .with_app(ModelApp::new("two"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let raw = tester.query("rapido/modules", vec![]).unwrap();
+    let modules = Vec::<ModuleInfo>::try_from_slice(&raw).unwrap();
+    assert_eq!(2, modules.len());
+    assert_eq!("one", modules[0].name);
+    assert_eq!(1, modules[0].version);
+    assert_eq!("two", modules[1].name);
+
+    let raw = tester.query("rapido/openapi", vec![]).unwrap();
+    let doc: serde_json::Value = serde_json::from_slice(&raw).unwrap();
+    assert_eq!("3.0.3", doc["openapi"]);
+    assert!(doc["paths"]["/txs"]["post"].is_object());
+}
+
+#[test]
+fn test_simulate_query() {
+    let (alicepk, _) = gen_keypair();
+    let app = AppBuilder::new()
+        .set_authenticator(TestAuthenticator::new(alicepk))
+        .with_app(ModelApp::new("model"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    // Not signed: the authenticator isn't called
+    let mut create = SignedTransaction::create("alice", "model", Msgs::Create(1), 0u64);
+    // The simulation is checked against the chain id
+    assert!(tester.query("rapido/simulate", create.encode()).is_err());
+    create.set_chain_id(TESTKIT_CHAIN_ID);
+    let result: SimulationResult = tester.query_as("rapido/simulate", create.encode()).unwrap();
+    assert!(result.events.iter().any(|e| e.kind.contains("model")));
+    assert_eq!("rapido_tx", result.events.last().unwrap().kind);
+
+    // Nothing was committed
+    assert!(tester.query("model", "alice").is_err());
+    let mut inc = SignedTransaction::create("alice", "model", Msgs::Inc, 0u64);
+    inc.set_chain_id(TESTKIT_CHAIN_ID);
+    assert!(tester.query("rapido/simulate", inc.encode()).is_err());
+    assert!(tester.query("rapido/simulate", vec![1, 2]).is_err());
+}
+
+#[test]
+fn test_handler_panic() {
+    let app = AppBuilder::new().with_app(PanicApp);
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let tx = |msg| {
+        TxBuilder::new("panic")
+            .sender("bob")
+            .msg(msg)
+            .build()
+            .unwrap()
+    };
+
+    // The panic fails the tx instead of killing the node
+    let err = tester.commit_tx(&[&tx(Msgs::Inc)]).unwrap_err();
+    assert!(err.to_string().contains("inc is not supported"));
+    assert!(tester.query("panic", "bob").is_err());
+
+    // and the node keeps going
+    assert!(tester.commit_tx(&[&tx(Msgs::Create(1))]).is_ok());
+}
+
+#[test]
+fn test_failed_tx_discarded() {
+    let app = AppBuilder::new().with_app(PanicApp);
+    let mut tester = TestKit::create(app);
+    tester.start();
+    let store = ModelStore::load("panic");
+
+    let tx = |sender: &str, msg| {
+        TxBuilder::new("panic")
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    };
+
+    // The changes of a handler that fails or panics aren't committed with the
+    // rest of the block
+    let results = tester
+        .produce_block(&[
+            &tx("bob", Msgs::Create(0)),
+            &tx("alice", Msgs::Inc),
+            &tx("carol", Msgs::Create(2)),
+        ])
+        .unwrap();
+    assert!(!results.txs[0].is_ok());
+    assert!(!results.txs[1].is_ok());
+    assert!(results.txs[2].is_ok());
+    assert!(tester.read_store(&store, b"bob".to_vec()).is_none());
+    assert!(tester.read_store(&store, b"alice".to_vec()).is_none());
+    assert_eq!(
+        2,
+        tester.read_store(&store, b"carol".to_vec()).unwrap().value
+    );
+}
+
+#[test]
+fn test_block_hook_failure() {
+    let app = AppBuilder::new()
+        .with_app(PanicApp)
+        .with_app(ModelApp::new("model"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+    let store = ModelStore::load("panic");
+
+    // The changes of a failed or panicking block hook aren't committed, and the
+    // other AppModules and the node keep going
+    let tx = TxBuilder::new("model")
+        .sender("bob")
+        .msg(Msgs::Create(1))
+        .build()
+        .unwrap();
+    tester.produce_block(&[&tx]).unwrap();
+    tester.produce_block(&[]).unwrap();
+    assert_eq!(2, tester.height());
+    assert!(tester.read_store(&store, b"begin".to_vec()).is_none());
+    assert!(tester.read_store(&store, b"end".to_vec()).is_none());
+    let model = tester
+        .read_store(&ModelStore::load("model"), b"bob".to_vec())
+        .unwrap();
+    assert_eq!(1, model.value);
+}
+
+#[test]
+fn test_failed_scheduled_tx_discarded() {
+    let app = AppBuilder::new()
+        .with_middleware(CountTxs)
+        .with_app(DeferApp)
+        .with_app(PanicApp);
+    let mut tester = TestKit::create(app);
+    tester.start();
+    let store = ModelStore::load("panic");
+    let count = |tester: &TestKit| tester.read_store(&TxCountStore, "count".into());
+
+    let tx = |sender: &str, msg| {
+        TxBuilder::new("defer")
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    };
+
+    // None of the changes of a failed scheduled tx are committed, including the
+    // middleware's
+    tester
+        .produce_block(&[&tx("bob", Msgs::Create(0))])
+        .unwrap();
+    tester.produce_block(&[]).unwrap();
+    assert_eq!(Some(1), count(&tester));
+    assert!(tester.read_store(&store, b"bob".to_vec()).is_none());
+
+    tester
+        .produce_block(&[&tx("alice", Msgs::Create(2))])
+        .unwrap();
+    tester.produce_block(&[]).unwrap();
+    assert_eq!(Some(3), count(&tester));
+    let model = tester.read_store(&store, b"alice".to_vec()).unwrap();
+    assert_eq!(2, model.value);
+}
+
+#[test]
+fn test_failed_cron_job_discarded() {
+    let app = AppBuilder::new()
+        .with_middleware(CountTxs)
+        .with_app(DeferApp)
+        .with_app(PanicApp);
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let tx = TxBuilder::new("defer")
+        .sender("bob")
+        .msg(Msgs::Inc)
+        .build()
+        .unwrap();
+    tester.produce_block(&[&tx]).unwrap();
+    tester.produce_block(&[]).unwrap();
+
+    // The job runs in each block and fails. None of its changes are committed
+    assert_eq!(Some(1), tester.read_store(&TxCountStore, "count".into()));
+    let store = ModelStore::load("panic");
+    assert!(tester.read_store(&store, Vec::new()).is_none());
+}
+
+#[test]
+fn test_tx_middleware() {
+    let middleware = BlockSender::new("mallory");
+    let handled = middleware.handled.clone();
+    let app = AppBuilder::new()
+        .with_middleware(middleware)
+        .with_app(ModelApp::new("model"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let create = |sender: &str| {
+        TxBuilder::new("model")
+            .sender(sender)
+            .msg(Msgs::Create(1))
+            .build()
+            .unwrap()
+    };
+    assert!(tester.commit_tx(&[&create("bob")]).is_ok());
+    assert!(tester.commit_tx(&[&create("mallory")]).is_err());
+    assert!(tester.query("model", "mallory").is_err());
+    assert_eq!(1, handled.load(std::sync::atomic::Ordering::SeqCst));
+}
+
+#[test]
+fn test_rate_limiter() {
+    let app = AppBuilder::new()
+        .set_authenticator(RateLimiter::new(DefaultAuthenticator, 2))
+        .with_app(ModelApp::new("model"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let tx = |sender: &str| {
+        TxBuilder::new("model")
+            .sender(sender)
+            .msg(Msgs::Create(1))
+            .build()
+            .unwrap()
+    };
+
+    // Only 2 txs from bob in the block
+    assert!(tester.check_tx(&[&tx("bob"), &tx("bob")]).is_ok());
+    assert!(tester.check_tx(&[&tx("bob")]).is_err());
+    // Other senders aren't affected
+    assert!(tester.check_tx(&[&tx("alice")]).is_ok());
+
+    // Committed txs count in the window too
+    assert!(tester.commit_tx(&[&tx("carol"), &tx("carol")]).is_ok());
+    assert!(tester.check_tx(&[&tx("carol")]).is_err());
+}
+
+#[test]
+fn test_app_alias() {
+    let app = AppBuilder::new()
+        .with_app(ModelApp::new("model"))
+        .with_app_as("bank", ModelApp::new("bank.model"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let tx = TxBuilder::new("bank")
+        .sender("bob")
+        .msg(Msgs::Create(2))
+        .build()
+        .unwrap();
+    assert!(tester.commit_tx(&[&tx]).is_ok());
+    let model = Model::decode(tester.query("bank", "bob").unwrap());
+    assert_eq!(2, model.value);
+    assert!(tester.query("model", "bob").is_err());
+    assert!(tester.query("bank.model", "bob").is_err());
+}
+
+#[test]
+#[should_panic(expected = "registered more than once")]
+fn test_duplicate_app_name() {
+    let app = AppBuilder::new()
+        .with_app(ModelApp::new("model"))
+        .with_app_as("model", ModelApp::new("other"));
+    TestKit::create(app);
+}
+
+#[test]
+fn test_capabilities() {
+    let app = AppBuilder::new()
+        .with_app(MintApp)
+        .with_app(ProxyApp::new("gov", "mint"))
+        .with_app(ProxyApp::new("other", "mint"))
+        .grant("gov", "mint::mint");
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let tx = |app: &'static str| {
+        TxBuilder::new(app)
+            .sender("bob")
+            .msg(Msgs::Inc)
+            .build()
+            .unwrap()
+    };
+    assert!(tester.commit_tx(&[&tx("gov")]).is_ok());
+    assert!(tester.commit_tx(&[&tx("other")]).is_err());
+    // Not via dispatch
+    assert!(tester.commit_tx(&[&tx("mint")]).is_err());
+}
+
+#[test]
+#[should_panic(expected = "unknown AppModule")]
+fn test_capability_unknown_app() {
+    let app = AppBuilder::new()
+        .with_app(MintApp)
+        .grant("gov", "mint::mint");
+    TestKit::create(app);
+}
+
+#[test]
+fn test_init_error() {
+    let app = AppBuilder::new()
+        .with_app(ModelApp::new("one"))
+        .with_app(ModelApp::new("two"));
+    let mut tester = TestKit::create(app);
+
+    match tester.try_start_with_genesis(r#"{"two": "not a map"}"#) {
+        Err(InitError::Module { name, .. }) => assert_eq!("two", name),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match tester.try_start_with_genesis(r#"{"three": {}}"#) {
+        Err(InitError::UnknownModule(name)) => assert_eq!("three", name),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(tester
+        .try_start_with_genesis(r#"{"one": {"bob": 1}}"#)
+        .is_ok());
+
+    let mut tester = TestKit::create_with_genesis(
+        AppBuilder::new().with_app(ModelApp::new("one")),
+        GenesisConfig::new(""),
+    );
+    match tester.try_start_with_genesis("") {
+        Err(InitError::EmptyChainId) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_produce_block() {
+    let app = AppBuilder::new()
+        .with_app(ClockApp)
+        .with_app(ModelApp::new("model"));
+    let mut tester = TestKit::create(app);
+    tester.start();
+
+    let clock =
+        |tester: &mut TestKit, key: &str| -> Clock { tester.query_as("clock", key).unwrap() };
+    let tx = |app: &str, sender: &str, msg: Msgs| {
+        TxBuilder::new(app).sender(sender).msg(msg).build().unwrap()
+    };
+
+    let first = tester
+        .produce_block(&[&tx("clock", "bob", Msgs::Inc)])
+        .unwrap();
+    assert_eq!((1, TESTKIT_GENESIS_TIME), (first.height, first.time));
+    assert!(first.txs[0].is_ok());
+    let expected = Clock {
+        height: 1,
+        time: TESTKIT_GENESIS_TIME,
+    };
+    assert_eq!(expected, clock(&mut tester, "bob"));
+    assert_eq!(expected, clock(&mut tester, "end"));
+
+    // A failed tx doesn't stop the block
+    tester.advance_time(60);
+    let second = tester
+        .produce_block(&[
+            &tx("model", "bob", Msgs::Inc),
+            &tx("clock", "alice", Msgs::Inc),
+        ])
+        .unwrap();
+    assert_eq!(2, second.height);
+    assert!(!second.txs[0].is_ok());
+    assert!(second.txs[0].log.contains("model for user not found"));
+    assert!(second.txs[1].is_ok());
+    assert_eq!(
+        Clock {
+            height: 2,
+            time: TESTKIT_GENESIS_TIME + TESTKIT_BLOCK_INTERVAL + 60,
+        },
+        clock(&mut tester, "alice")
+    );
+
+    tester.set_block_time(2_000_000_000);
+    let third = tester.produce_block(&[]).unwrap();
+    assert_eq!(2_000_000_000, clock(&mut tester, "end").time);
+    assert_eq!(3, tester.height());
+    assert_eq!(2_000_000_000 + TESTKIT_BLOCK_INTERVAL, tester.block_time());
+    assert_ne!(second.app_hash, third.app_hash);
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let mut tester = TestKit::create(AppBuilder::new().with_app(ModelApp::new("model")));
+    tester.start();
+    let tx = |msg: Msgs| {
+        TxBuilder::new("model")
+            .sender("bob")
+            .msg(msg)
+            .build()
+            .unwrap()
+    };
+    let value = |tester: &mut TestKit| Model::decode(tester.query("model", "bob").unwrap()).value;
+
+    tester.commit_tx(&[&tx(Msgs::Create(1))]).unwrap();
+    let snapshot = tester.snapshot();
+    let apphash = tester.app_hash();
+    let block_time = tester.block_time();
+
+    // Each scenario starts from the snapshot
+    for _ in 0..2 {
+        tester.commit_tx(&[&tx(Msgs::Inc)]).unwrap();
+        tester.produce_block(&[&tx(Msgs::Inc)]).unwrap();
+        assert_eq!(3, value(&mut tester));
+        assert_eq!(3, tester.height());
+
+        tester.restore(&snapshot);
+        assert_eq!(1, value(&mut tester));
+        assert_eq!(1, tester.height());
+        assert_eq!(apphash, tester.app_hash());
+        assert_eq!(block_time, tester.block_time());
+    }
+
+    // State created after the snapshot is gone
+    tester
+        .commit_tx(&[&TxBuilder::new("model")
+            .sender("alice")
+            .msg(Msgs::Create(1))
+            .build()
+            .unwrap()])
+        .unwrap();
+    tester.restore(&snapshot);
+    assert!(tester.query("model", "alice").is_err());
+}
+
+#[test]
+fn test_genesis_config() {
+    let (one, two) = (gen_keypair().0, gen_keypair().0);
+    let (one, two): (&[u8], &[u8]) = (one.as_ref(), two.as_ref());
+    let genesis = GenesisConfig::new("my-chain")
+        .validator(ValidatorPower::new(one, 10))
+        .validator(ValidatorPower::new(two, 5))
+        .app_state_json(r#"{"model": {"bob": 1}}"#);
+    let app = AppBuilder::new().with_app(ModelApp::new("model"));
+    let mut tester = TestKit::create_with_genesis(app, genesis);
+    tester.start();
+
+    assert_eq!(
+        1,
+        Model::decode(tester.query("model", "bob").unwrap()).value
+    );
+    let raw = tester.query("rapido/validators", vec![]).unwrap();
+    let mut validators = Vec::<ValidatorPower>::try_from_slice(&raw).unwrap();
+    validators.sort_by_key(|v| v.power);
+    assert_eq!(
+        vec![ValidatorPower::new(two, 5), ValidatorPower::new(one, 10)],
+        validators
+    );
+
+    // Txs are checked against the genesis chain id
+    let tx = |chain_id: &str| {
+        TxBuilder::new("model")
+            .sender("bob")
+            .msg(Msgs::Inc)
+            .chain_id(chain_id)
+            .build()
+            .unwrap()
+    };
+    assert!(tester.commit_tx(&[&tx(TESTKIT_CHAIN_ID)]).is_err());
+    // a tx without a chain id is rejected, even when signed
+    let mut unset = tx("");
+    unset.attach_signature(&[0u8; 64]).unwrap();
+    let err = tester.commit_tx(&[&unset]).unwrap_err();
+    assert!(err.to_string().contains("missing chain id"));
+    let block = tester.produce_block(&[&tx("my-chain")]).unwrap();
+    assert!(block.txs[0].is_ok());
+    assert_eq!(
+        2,
+        Model::decode(tester.query("model", "bob").unwrap()).value
+    );
+}
+
+#[test]
+fn test_validator_set() {
+    let (one, two) = (gen_keypair().0, gen_keypair().0);
+    let (one, two): (&[u8], &[u8]) = (one.as_ref(), two.as_ref());
+    let genesis = GenesisConfig::default()
+        .validator(ValidatorPower::new(one, 10))
+        .validator(ValidatorPower::new(two, 10))
+        .app_state_json(format!(
+            r#"{{"staking": ["{}", "{}"]}}"#,
+            hex::encode(one),
+            hex::encode(two)
+        ));
+    let mut tester = TestKit::create_with_genesis(AppBuilder::new().with_app(StakeApp), genesis);
+    tester.start();
+
+    // Validator updates from end_block
+    let tx = TxBuilder::new("staking")
+        .sender("bob")
+        .msg(StakeMsgs::SetPower(two.to_vec(), 20))
+        .build()
+        .unwrap();
+    let block = tester.produce_block(&[&tx]).unwrap();
+    assert_eq!(vec![ValidatorPower::new(two, 20)], block.validator_updates);
+    tester.assert_validator_power(two, 20);
+    tester.assert_validator_power(one, 10);
+
+    // A validator that doesn't sign is removed
+    tester.set_absent(one, true);
+    for _ in 1..MAX_MISSED_BLOCKS {
+        assert!(tester
+            .produce_block(&[])
+            .unwrap()
+            .validator_updates
+            .is_empty());
+    }
+    let block = tester.produce_block(&[]).unwrap();
+    assert_eq!(vec![ValidatorPower::new(one, 0)], block.validator_updates);
+    tester.assert_validator_removed(one);
+    assert_eq!(1, tester.validators().len());
+
+    // Votes are only from the validator set
+    tester.produce_block(&[]).unwrap();
+    let state: Staking = tester.query_as("staking", vec![]).unwrap();
+    let missed = |key: &[u8]| state.missed.iter().find(|(k, _)| k == key).unwrap().1;
+    assert_eq!((MAX_MISSED_BLOCKS, 0), (missed(one), missed(two)));
+}
+
+#[test]
+fn test_advance_blocks() {
+    let mut tester = TestKit::create(AppBuilder::new().with_app(ClockApp));
+    tester.start();
+
+    let blocks = tester.advance_blocks(3).unwrap();
+    assert_eq!(
+        vec![1, 2, 3],
+        blocks.iter().map(|b| b.height).collect::<Vec<_>>()
+    );
+    assert!(blocks.iter().all(|b| b.txs.is_empty()));
+    let clock: Clock = tester.query_as("clock", "end").unwrap();
+    assert_eq!(
+        Clock {
+            height: 3,
+            time: TESTKIT_GENESIS_TIME + 2 * TESTKIT_BLOCK_INTERVAL,
+        },
+        clock
+    );
+
+    assert!(tester.advance_blocks(0).unwrap().is_empty());
+    assert_eq!(3, tester.height());
+}
+
+#[test]
+fn test_read_store() {
+    let mut tester = TestKit::create(AppBuilder::new().with_app(ModelApp::new("model")));
+    tester.start();
+    let store = ModelStore::load("model");
+    assert!(tester.read_store(&store, b"bob".to_vec()).is_none());
+
+    let tx = TxBuilder::new("model")
+        .sender("bob")
+        .msg(Msgs::Create(7))
+        .build()
+        .unwrap();
+    tester.commit_tx(&[&tx]).unwrap();
+
+    let model = tester.read_store(&store, b"bob".to_vec()).unwrap();
+    assert_eq!(7, model.value);
+    let encoded = Model { value: 7 }.try_to_vec().unwrap();
+    assert!(tester.dump_state().iter().any(|(_, v)| *v == encoded));
+}
+
+#[test]
+fn test_tx_failure() {
+    let mut tester = TestKit::create(AppBuilder::new().with_app(ModelApp::new("model")));
+    tester.start();
+    let tx = |app: &str, msg: Msgs| TxBuilder::new(app).sender("bob").msg(msg).build().unwrap();
+
+    let outcomes = tester.check_tx(&[&tx("model", Msgs::Create(1))]).unwrap();
+    assert!(outcomes[0].is_ok());
+
+    // Failures from the AppModule have its codespace
+    let failure = tester.commit_tx(&[&tx("model", Msgs::Inc)]).unwrap_err();
+    assert_eq!((1, "model"), (failure.code, &*failure.codespace));
+    assert!(failure.log.contains("model for user not found"));
+    assert!(failure.to_string().contains("model for user not found"));
+
+    // A tx that can't be routed fails in the framework
+    let failure = tester.check_tx(&[&tx("other", Msgs::Inc)]).unwrap_err();
+    assert_eq!("rapido", failure.codespace);
+    assert_ne!(0, failure.code);
+}
+
+// Fails once bob's model reaches 3
+fn small_models(tester: &mut TestKit) {
+    tester.add_invariant("small models", |view| {
+        let model = ModelStore::load("model").query(b"bob".to_vec(), view);
+        anyhow::ensure!(model.map_or(0, |m| m.value) < 3, "model too large");
+        Ok(())
+    });
+}
+
+#[test]
+fn test_invariants() {
+    let mut tester = TestKit::create(AppBuilder::new().with_app(ModelApp::new("model")));
+    tester.start();
+    small_models(&mut tester);
+    let tx = |msg: Msgs| {
+        TxBuilder::new("model")
+            .sender("bob")
+            .msg(msg)
+            .build()
+            .unwrap()
+    };
+
+    tester.commit_tx(&[&tx(Msgs::Create(1))]).unwrap();
+    tester.produce_block(&[&tx(Msgs::Inc)]).unwrap();
+    tester.assert_invariants();
+}
+
+#[test]
+#[should_panic(expected = "invariant 'small models' failed: model too large")]
+fn test_invariant_failure() {
+    let mut tester = TestKit::create(AppBuilder::new().with_app(ModelApp::new("model")));
+    tester.start();
+    small_models(&mut tester);
+    let tx = |msg: Msgs| {
+        TxBuilder::new("model")
+            .sender("bob")
+            .msg(msg)
+            .build()
+            .unwrap()
+    };
+
+    tester.commit_tx(&[&tx(Msgs::Create(2))]).unwrap();
+    tester.produce_block(&[&tx(Msgs::Inc)]).unwrap();
+}
@@ -0,0 +1,457 @@
+//! Derive macros for Rapido applications. These are re-exported by `rapido-core`.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Error, Field, Fields,
+    GenericParam, Ident, Lit, Meta, NestedMeta,
+};
+
+/// Derive on an AppModule's message enum to generate the decode/dispatch glue.
+/// For an enum `Msgs`, this generates a trait `MsgsHandler` with one method per
+/// variant (the snake_case name of the variant) and `Msgs::dispatch` to decode the
+/// message from the Context and call the matching method. Variant fields are passed
+/// as arguments. Use `#[handler(name = "...")]` on a variant to rename its method.
+/// Example:
+/// ```ignore
+/// #[derive(BorshSerialize, BorshDeserialize, RapidoMessages)]
+/// pub enum Msgs {
+///     Create,
+///     Add(u16),
+///     #[handler(name = "sub")]
+///     Subtract { value: u16 },
+/// }
+///
+/// impl MsgsHandler for MyModule {
+///     fn create(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {..}
+///     fn add(&self, ctx: &Context, view: &mut StoreView, arg0: u16) -> Result<(), anyhow::Error> {..}
+///     fn sub(&self, ctx: &Context, view: &mut StoreView, value: u16) -> Result<(), anyhow::Error> {..}
+/// }
+///
+/// // In AppModule::handle_tx
+/// fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+///     Msgs::dispatch(self, ctx, view)
+/// }
+/// ```
+#[proc_macro_derive(RapidoMessages, attributes(handler))]
+pub fn derive_rapido_messages(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_messages(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_messages(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(Error::new_spanned(
+                input,
+                "RapidoMessages can only be derived for enums",
+            ))
+        }
+    };
+
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "RapidoMessages does not support generic enums",
+        ));
+    }
+
+    let name = &input.ident;
+    let vis = &input.vis;
+    let handler_trait = format_ident!("{}Handler", name);
+
+    let mut methods = Vec::new();
+    let mut arms = Vec::new();
+    for variant in &data.variants {
+        let variant_name = &variant.ident;
+        let method = match handler_name(&variant.attrs)? {
+            Some(renamed) => renamed,
+            None => Ident::new(&to_snake_case(&variant_name.to_string()), Span::call_site()),
+        };
+
+        match &variant.fields {
+            Fields::Unit => {
+                methods.push(quote! {
+                    fn #method(
+                        &self,
+                        ctx: &::rapido_core::Context,
+                        view: &mut ::rapido_core::StoreView,
+                    ) -> ::std::result::Result<(), ::rapido_core::__private::anyhow::Error>;
+                });
+                arms.push(quote! {
+                    #name::#variant_name => handler.#method(ctx, view)
+                });
+            }
+            Fields::Unnamed(fields) => {
+                let args: Vec<Ident> = (0..fields.unnamed.len())
+                    .map(|i| format_ident!("arg{}", i))
+                    .collect();
+                let types: Vec<_> = fields.unnamed.iter().map(|f| &f.ty).collect();
+                methods.push(quote! {
+                    fn #method(
+                        &self,
+                        ctx: &::rapido_core::Context,
+                        view: &mut ::rapido_core::StoreView,
+                        #(#args: #types),*
+                    ) -> ::std::result::Result<(), ::rapido_core::__private::anyhow::Error>;
+                });
+                arms.push(quote! {
+                    #name::#variant_name(#(#args),*) => handler.#method(ctx, view, #(#args),*)
+                });
+            }
+            Fields::Named(fields) => {
+                let args = fields
+                    .named
+                    .iter()
+                    .map(field_ident)
+                    .collect::<syn::Result<Vec<&Ident>>>()?;
+                let types: Vec<_> = fields.named.iter().map(|f| &f.ty).collect();
+                methods.push(quote! {
+                    fn #method(
+                        &self,
+                        ctx: &::rapido_core::Context,
+                        view: &mut ::rapido_core::StoreView,
+                        #(#args: #types),*
+                    ) -> ::std::result::Result<(), ::rapido_core::__private::anyhow::Error>;
+                });
+                arms.push(quote! {
+                    #name::#variant_name { #(#args),* } => handler.#method(ctx, view, #(#args),*)
+                });
+            }
+        }
+    }
+
+    let trait_doc = format!(
+        "Handler for `{}` messages. Generated by RapidoMessages",
+        name
+    );
+    Ok(quote! {
+        #[doc = #trait_doc]
+        #vis trait #handler_trait {
+            #(#methods)*
+        }
+
+        impl #name {
+            /// Decode the message from the Context and dispatch it to the handler
+            #vis fn dispatch<H: #handler_trait + ?Sized>(
+                handler: &H,
+                ctx: &::rapido_core::Context,
+                view: &mut ::rapido_core::StoreView,
+            ) -> ::std::result::Result<(), ::rapido_core::__private::anyhow::Error> {
+                let msg: #name = ctx.decode_msg()?;
+                msg.dispatch_to(handler, ctx, view)
+            }
+
+            /// Dispatch an already decoded message to the handler
+            #vis fn dispatch_to<H: #handler_trait + ?Sized>(
+                self,
+                handler: &H,
+                ctx: &::rapido_core::Context,
+                view: &mut ::rapido_core::StoreView,
+            ) -> ::std::result::Result<(), ::rapido_core::__private::anyhow::Error> {
+                match self {
+                    #(#arms),*
+                }
+            }
+        }
+    })
+}
+
+/// Derive on a struct to emit it as an event via `Context::emit`. The event type is
+/// the snake_case name of the struct without an 'Event' suffix: `TransferEvent` is
+/// 'transfer'. Each named field is an attribute, converted with `EventValue`.
+/// Use `#[event(name = "...")]` on the struct to set the event type and `#[event(skip)]`
+/// on a field to leave it out.
+#[proc_macro_derive(RapidoEvent, attributes(event))]
+pub fn derive_rapido_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_event(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_event(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields.named.iter().collect(),
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(_) => {
+                return Err(Error::new_spanned(
+                    input,
+                    "RapidoEvent requires named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                input,
+                "RapidoEvent can only be derived for structs",
+            ))
+        }
+    };
+
+    let name = &input.ident;
+    let event_type = match event_attr(&input.attrs)? {
+        EventAttr::Name(n) => n,
+        EventAttr::Skip => return Err(Error::new_spanned(input, "skip is only valid on fields")),
+        EventAttr::None => {
+            let snake = to_snake_case(&name.to_string());
+            match snake.strip_suffix("_event") {
+                Some(s) if !s.is_empty() => s.to_string(),
+                _ => snake,
+            }
+        }
+    };
+
+    let mut attributes = Vec::new();
+    for field in fields {
+        match event_attr(&field.attrs)? {
+            EventAttr::Skip => continue,
+            EventAttr::Name(_) => {
+                return Err(Error::new_spanned(
+                    field,
+                    "name is only valid on the struct",
+                ))
+            }
+            EventAttr::None => {}
+        }
+        let ident = field_ident(field)?;
+        let key = ident.to_string();
+        attributes.push(quote! {
+            (
+                ::std::string::String::from(#key),
+                ::rapido_core::EventValue::to_event_value(&self.#ident),
+            )
+        });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rapido_core::RapidoEvent for #name #ty_generics #where_clause {
+            fn event_type(&self) -> ::std::string::String {
+                ::std::string::String::from(#event_type)
+            }
+
+            fn attributes(&self) -> ::std::vec::Vec<(::std::string::String, ::std::string::String)> {
+                vec![#(#attributes),*]
+            }
+        }
+    })
+}
+
+enum EventAttr {
+    None,
+    Name(String),
+    Skip,
+}
+
+// Parse `#[event(name = "...")]` or `#[event(skip)]`
+fn event_attr(attrs: &[Attribute]) -> syn::Result<EventAttr> {
+    for attr in attrs {
+        if !attr.path.is_ident("event") {
+            continue;
+        }
+        if let Meta::List(list) = attr.parse_meta()? {
+            for nested in list.nested.iter() {
+                match nested {
+                    NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => {
+                        if let Lit::Str(s) = &nv.lit {
+                            return Ok(EventAttr::Name(s.value()));
+                        }
+                    }
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("skip") => {
+                        return Ok(EventAttr::Skip);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        return Err(Error::new_spanned(
+            attr,
+            "expected #[event(name = \"...\")] or #[event(skip)]",
+        ));
+    }
+    Ok(EventAttr::None)
+}
+
+/// Derive on a type stored in the merkle db (a `Store::Value`) to implement
+/// `BinaryValue` and `ObjectHash` using Borsh.  The type must also derive
+/// `BorshSerialize` and `BorshDeserialize`. Works with structs, enums and generic
+/// types (type parameters must be Borsh serializable). A type that can't be
+/// serialized is a compile error.  Encoding into memory can't fail, so `to_bytes`
+/// (which returns bytes, not a `Result`) doesn't return an error. Example:
+/// ```ignore
+/// #[derive(BorshSerialize, BorshDeserialize, StoreValue)]
+/// pub struct Person {
+///     name: String,
+///     age: u8,
+/// }
+/// ```
+#[proc_macro_derive(StoreValue)]
+pub fn derive_store_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_store_value(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_store_value(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    if let Data::Union(_) = &input.data {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "StoreValue can't be derived for unions",
+        ));
+    }
+    let name = &input.ident;
+
+    // Each type parameter must be serializable
+    for param in input.generics.params.iter_mut() {
+        if let GenericParam::Type(ty) = param {
+            ty.bounds.push(parse_quote!(
+                ::rapido_core::__private::borsh::BorshSerialize
+            ));
+            ty.bounds.push(parse_quote!(
+                ::rapido_core::__private::borsh::BorshDeserialize
+            ));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::rapido_core::__private::exonum_merkledb::BinaryValue
+            for #name #ty_generics #where_clause
+        {
+            fn to_bytes(&self) -> ::std::vec::Vec<u8> {
+                ::rapido_core::__private::borsh::BorshSerialize::try_to_vec(self)
+                    .expect("borsh encoding into a vec can't fail")
+            }
+
+            fn from_bytes(
+                bytes: ::std::borrow::Cow<[u8]>,
+            ) -> ::std::result::Result<Self, ::rapido_core::__private::anyhow::Error> {
+                <Self as ::rapido_core::__private::borsh::BorshDeserialize>::try_from_slice(
+                    bytes.as_ref(),
+                )
+                .map_err(::std::convert::From::from)
+            }
+        }
+
+        impl #impl_generics ::rapido_core::__private::exonum_merkledb::ObjectHash
+            for #name #ty_generics #where_clause
+        {
+            fn object_hash(&self) -> ::rapido_core::__private::exonum_crypto::Hash {
+                ::rapido_core::__private::exonum_crypto::hash(
+                    &::rapido_core::__private::exonum_merkledb::BinaryValue::to_bytes(self),
+                )
+            }
+        }
+    })
+}
+
+// The name of a field of a struct or variant with named fields
+fn field_ident(field: &Field) -> syn::Result<&Ident> {
+    match &field.ident {
+        Some(ident) => Ok(ident),
+        None => Err(Error::new_spanned(field, "expected a named field")),
+    }
+}
+
+// Parse `#[handler(name = "...")]`. The name must be a valid method name
+fn handler_name(attrs: &[Attribute]) -> syn::Result<Option<Ident>> {
+    for attr in attrs {
+        if !attr.path.is_ident("handler") {
+            continue;
+        }
+        if let Meta::List(list) = attr.parse_meta()? {
+            for nested in list.nested.iter() {
+                if let NestedMeta::Meta(Meta::NameValue(nv)) = nested {
+                    if nv.path.is_ident("name") {
+                        if let Lit::Str(s) = &nv.lit {
+                            return match syn::parse_str::<Ident>(&s.value()) {
+                                Ok(mut ident) => {
+                                    ident.set_span(s.span());
+                                    Ok(Some(ident))
+                                }
+                                Err(_) => Err(Error::new_spanned(s, "expected a method name")),
+                            };
+                        }
+                    }
+                }
+            }
+        }
+        return Err(Error::new_spanned(
+            attr,
+            "expected #[handler(name = \"...\")]",
+        ));
+    }
+    Ok(None)
+}
+
+// 'SetValue' => 'set_value'
+fn to_snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!("create", to_snake_case("Create"));
+        assert_eq!("change_pub_key", to_snake_case("ChangePubKey"));
+    }
+
+    #[test]
+    fn test_handler_name() {
+        let input: DeriveInput = parse_quote! {
+            enum Msgs {
+                #[handler(name = "sub")]
+                Subtract,
+                #[handler(name = "not a name")]
+                Bad,
+            }
+        };
+        let variants = match &input.data {
+            Data::Enum(data) => &data.variants,
+            _ => unreachable!(),
+        };
+        let name = handler_name(&variants[0].attrs).unwrap().unwrap();
+        assert_eq!("sub", name.to_string());
+        let err = handler_name(&variants[1].attrs).unwrap_err();
+        assert_eq!("expected a method name", err.to_string());
+
+        // An error, not a panic
+        assert!(expand_messages(&input).is_err());
+    }
+
+    #[test]
+    fn test_store_value_union() {
+        let input: DeriveInput = parse_quote! {
+            union Value {
+                a: u8,
+            }
+        };
+        let err = expand_store_value(input).unwrap_err();
+        assert_eq!("StoreValue can't be derived for unions", err.to_string());
+    }
+}
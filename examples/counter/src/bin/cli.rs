@@ -1,72 +1,75 @@
 //!
 //! Command line application for the Counter Application
 //!
-//!  Quick use: `cargo run --bin cli create dave`
+//!  Quick use:
+//!  `cargo run --bin cli keys add dave`
+//!  `cargo run --bin cli tx send --from dave create`
+//!  `cargo run --bin cli query count dave`
 //!
 use borsh::BorshDeserialize;
-use rapido_client::{query, send_transaction_commit};
-use rapido_core::SignedTransaction;
+use exonum_crypto::PublicKey;
+use rapido_cli::AppCli;
+use rapido_core::AccountId;
 
 use counter::{Counter, Msgs, APP_NAME};
 use structopt::StructOpt;
-use tendermint_rpc::HttpClient;
 
-#[macro_use]
-extern crate log;
+// Tx commands
+#[derive(StructOpt, Debug)]
+enum CounterTxCommands {
+    Create,
+    Add { value: u16 },
+    Subtract { value: u16 },
+}
 
-// CLI Commands
+// Query commands
 #[derive(StructOpt, Debug)]
-#[structopt(about = "Counter App")]
-enum CounterAppCommands {
-    Create { name: String },
-    Add { name: String, value: u16 },
-    Subtract { name: String, value: u16 },
-    Query { name: String },
+enum CounterQueryCommands {
+    Count { name: String },
 }
 
-// Helper: get the tendermint HTTP client
-fn get_client() -> HttpClient {
-    HttpClient::new("tcp://127.0.0.1:26657".parse().unwrap()).unwrap()
+struct CounterCli;
+impl AppCli for CounterCli {
+    type TxCommand = CounterTxCommands;
+    type QueryCommand = CounterQueryCommands;
+    type Msg = Msgs;
+
+    fn app_name() -> &'static str {
+        APP_NAME
+    }
+
+    fn msg(cmd: CounterTxCommands) -> anyhow::Result<Msgs, anyhow::Error> {
+        Ok(match cmd {
+            CounterTxCommands::Create => Msgs::Create,
+            CounterTxCommands::Add { value } => Msgs::Add(value),
+            CounterTxCommands::Subtract { value } => Msgs::Subtract(value),
+        })
+    }
+
+    fn query(cmd: &CounterQueryCommands) -> anyhow::Result<(String, Vec<u8>), anyhow::Error> {
+        match cmd {
+            CounterQueryCommands::Count { name } => Ok(("/".into(), name.as_bytes().to_vec())),
+        }
+    }
+
+    fn format_query(
+        cmd: &CounterQueryCommands,
+        value: &[u8],
+    ) -> anyhow::Result<String, anyhow::Error> {
+        let CounterQueryCommands::Count { name } = cmd;
+        Ok(format!("{} => {:?}", name, Counter::try_from_slice(value)?))
+    }
+
+    // The counter uses the key name as the account
+    fn sender(name: &str, _public_key: &PublicKey) -> AccountId {
+        name.as_bytes().to_vec()
+    }
 }
 
 // Process the command line
 #[tokio::main]
 async fn main() {
-    let opts = CounterAppCommands::from_args();
-    match opts {
-        CounterAppCommands::Create { name } => {
-            let client = get_client();
-            let tx = SignedTransaction::create(name, APP_NAME, Msgs::Create, 0u64);
-            match send_transaction_commit(&tx, &client).await {
-                Ok(r) => info!("{:?}", r),
-                Err(err) => error!("{:?}", err),
-            }
-        }
-        CounterAppCommands::Add { name, value } => {
-            let client = get_client();
-            let tx = SignedTransaction::create(name, APP_NAME, Msgs::Add(value), 0u64);
-            match send_transaction_commit(&tx, &client).await {
-                Ok(r) => info!("{:?}", r),
-                Err(err) => error!("{:?}", err),
-            }
-        }
-        CounterAppCommands::Subtract { name, value } => {
-            let client = get_client();
-            let tx = SignedTransaction::create(name, APP_NAME, Msgs::Subtract(value), 0u64);
-            match send_transaction_commit(&tx, &client).await {
-                Ok(r) => info!("{:?}", r),
-                Err(err) => error!("{:?}", err),
-            }
-        }
-        CounterAppCommands::Query { name } => {
-            let client = get_client();
-            match query(APP_NAME, name.as_bytes().to_vec(), &client).await {
-                Ok(count_bits) => {
-                    let o = Counter::try_from_slice(&count_bits).unwrap();
-                    info!(" {:} => {:?}", name, o)
-                }
-                Err(err) => error!("{:?}", err),
-            }
-        }
+    if let Err(err) = rapido_cli::run::<CounterCli>().await {
+        eprintln!("{:?}", err);
     }
 }
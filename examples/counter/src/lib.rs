@@ -5,11 +5,12 @@
 //! (testing) authenticator that allows all transactions (don't need to signed).  User's can increase
 //! and decrease their Counters and check the current count.
 //!
+use anyhow::{bail, ensure};
 use borsh::{BorshDeserialize, BorshSerialize};
-use rapido_core::{AccountId, AppModule, Context, Store, StoreView};
-
-#[macro_use]
-extern crate rapido_core;
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoMessages, Store,
+    StoreValue, StoreView,
+};
 
 #[macro_use]
 extern crate log;
@@ -20,7 +21,9 @@ pub const APP_NAME: &'static str = "example.counter.app";
 
 /// Implement what you want to store (model).  Each user has a Count in the Merkle Tree.  
 /// We simple store the count as a u16.
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default)]
+/// Deriving `StoreValue` makes `Counter` something that can be stored. It implements
+/// the traits required by the underlying storage model
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, Default)]
 pub struct Counter(pub u16);
 impl Counter {
     // Add a value to the current count
@@ -37,10 +40,6 @@ impl Counter {
     }
 }
 
-// Make `Counter` something that can be stored.  This macro implements the traits required
-// by the underlying storage model
-impl_store_values!(Counter);
-
 /// Implement the Store for this application.  The `Store trait` already implements the
 /// common store operations such as: `put`, `get`, etc....   
 pub(crate) struct CounterStore;
@@ -58,8 +57,9 @@ impl Store for CounterStore {
 }
 
 /// Implement the messages the drive state changes in your AppModule.  `Msgs` are included in
-/// the transaction.
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+/// the transaction.  `RapidoMessages` generates the `MsgsHandler` trait with a method
+/// for each message, and `Msgs::dispatch` to decode and route a message to it.
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone)]
 pub enum Msgs {
     Create,
     Add(u16),
@@ -76,54 +76,17 @@ impl AppModule for CounterHandler {
 
     /// Implement this to load any unique data to this application.  This is genesis
     /// data and only loaded once, on the first start.
-    fn initialize(&self, _view: &mut StoreView) -> Result<(), anyhow::Error> {
+    fn initialize(&self, _genesis: &[u8], _view: &mut StoreView) -> Result<(), anyhow::Error> {
         debug!("counter run init");
         Ok(())
     }
 
-    /// Called to handle incoming transaction to this application
+    /// Called to handle incoming transaction to this application.
+    /// Decode the message that was delivered in the transaction and
+    /// route it to the matching `MsgsHandler` method below.
     fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
-        // Decode the message the was delivered in the transaction
-        let msg: Msgs = ctx.decode_msg()?;
-        // Get our store
-        let store = CounterStore {};
-        debug!("counter: handle tx!");
-
-        // Operate on the message based on type
-        match msg {
-            // Create a new Counter for the sender, as long as they don't have one already
-            Msgs::Create => {
-                // Ctx contains the sender
-                let user = ctx.sender.clone();
-
-                // Check the store. Errs if user exists
-                ensure!(
-                    store.get(user.clone(), view).is_none(),
-                    "User already exists"
-                );
-
-                // Store the new user/counter
-                store.put(user, Counter::default(), view);
-                Ok(())
-            }
-            Msgs::Add(val) => {
-                // Call Add on the user's Counter
-                if let Some(cnt) = store.get(ctx.sender.clone(), view) {
-                    store.put(ctx.sender.clone(), cnt.add(val), view);
-                    return Ok(());
-                }
-                bail!("user not found")
-            }
-            Msgs::Subtract(val) => {
-                // Call Subtract on the user's counter
-                if let Some(cnt) = store.get(ctx.sender.clone(), view) {
-                    let new_count = cnt.subtract(val)?;
-                    store.put(ctx.sender.clone(), new_count, view);
-                    return Ok(());
-                }
-                bail!("user not found")
-            }
-        }
+        ctx.logger().debug("handle tx");
+        Msgs::dispatch(self, ctx, view)
     }
 
     /// Handle RPC queries to the application. You define the relative paths to the App.
@@ -131,21 +94,66 @@ impl AppModule for CounterHandler {
         &self,
         path: &str,
         key: Vec<u8>,
-        view: &StoreView,
-    ) -> Result<Vec<u8>, anyhow::Error> {
-        let account = key;
-
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
         // This is the only path for this example.  This would respond to the RPC call:
         // http://127.0.0.1:26657/example.counter.app/
-        //
-        if path == "/" {
-            let store = CounterStore {};
-            return match store.get(account.clone(), view) {
-                Some(c) => Ok(c.try_to_vec().unwrap()), // Convert the counter to a Vec for transport
-                None => bail!("not count found for the given user"),
-            };
+        // where the key is the account.  The router encodes the counter for transport
+        QueryRouter::new()
+            .add("/", |params, view| {
+                Ok(CounterStore {}.get(params.key().to_vec(), view))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/".into()]
+    }
+}
+
+/// Handle each message
+impl MsgsHandler for CounterHandler {
+    /// Create a new Counter for the sender, as long as they don't have one already
+    fn create(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let store = CounterStore {};
+        // Ctx contains the sender
+        let user = ctx.sender.clone();
+
+        // Check the store. Errs if user exists
+        ensure!(
+            store.get(user.clone(), view).is_none(),
+            "User already exists"
+        );
+
+        // Store the new user/counter
+        store.put(user, Counter::default(), view);
+        Ok(())
+    }
+
+    /// Call Add on the user's Counter
+    fn add(&self, ctx: &Context, view: &mut StoreView, value: u16) -> Result<(), anyhow::Error> {
+        let store = CounterStore {};
+        if let Some(cnt) = store.get(ctx.sender.clone(), view) {
+            store.put(ctx.sender.clone(), cnt.add(value), view);
+            return Ok(());
         }
-        bail!("nothing else to see here...")
+        bail!("user not found")
+    }
+
+    /// Call Subtract on the user's counter
+    fn subtract(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        value: u16,
+    ) -> Result<(), anyhow::Error> {
+        let store = CounterStore {};
+        if let Some(cnt) = store.get(ctx.sender.clone(), view) {
+            let new_count = cnt.subtract(value)?;
+            store.put(ctx.sender.clone(), new_count, view);
+            return Ok(());
+        }
+        bail!("user not found")
     }
 }
 
@@ -154,8 +162,16 @@ impl AppModule for CounterHandler {
 #[cfg(test)]
 mod tests {
     use crate::{Counter, CounterHandler, Msgs, APP_NAME};
-    use borsh::BorshDeserialize;
-    use rapido_core::{AppBuilder, SignedTransaction, TestKit};
+    use rapido_core::{AppBuilder, TestKit, TxBuilder};
+
+    // Helper to build a tx for the counter app
+    fn counter_tx(sender: &[u8], msg: Msgs) -> rapido_core::SignedTransaction {
+        TxBuilder::new(APP_NAME)
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    }
 
     #[test]
     fn test_counter_app() {
@@ -173,8 +189,8 @@ mod tests {
         {
             // Create txs
             let txs = &[
-                &SignedTransaction::create(bob.clone(), APP_NAME, Msgs::Create, 0u64),
-                &SignedTransaction::create(alice.clone(), APP_NAME, Msgs::Create, 0u64),
+                &counter_tx(&bob, Msgs::Create),
+                &counter_tx(&alice, Msgs::Create),
             ];
 
             // We call commit to save to state (deliver_tx)
@@ -183,31 +199,20 @@ mod tests {
 
         {
             // Add 5 to bob's count
-            let txs = &[&SignedTransaction::create(
-                bob.clone(),
-                APP_NAME,
-                Msgs::Add(5u16),
-                0u64,
-            )];
+            let txs = &[&counter_tx(&bob, Msgs::Add(5u16))];
             assert!(tester.commit_tx(txs).is_ok());
         }
 
         {
             // Try to subtract a value from Alice.  Should fail as we don't allow
             // subtracting from 0.
-            let txs = &[&SignedTransaction::create(
-                alice.clone(),
-                APP_NAME,
-                Msgs::Subtract(5u16),
-                0u64,
-            )];
+            let txs = &[&counter_tx(&alice, Msgs::Subtract(5u16))];
             assert!(tester.commit_tx(txs).is_err());
         }
 
         {
             // Query Bob's count - should be 5
-            let v = tester.query(APP_NAME, bob.clone()).unwrap();
-            let count = Counter::try_from_slice(&v).unwrap();
+            let count: Counter = tester.query_as(APP_NAME, bob.clone()).unwrap();
             assert_eq!(count.0, 5u16);
         }
     }
@@ -0,0 +1,163 @@
+//!
+//! Delegated trust anchors.  Genesis accounts are the root trust anchors.  A trust
+//! anchor can grant the right to create accounts to another account, along with a
+//! depth: how many more levels the grantee can delegate.  An account's delegation is
+//! only valid while its delegator is still a trust anchor, so revoking an account
+//! also revokes everyone it delegated to.
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{AccountId, Context, RapidoEvent, Store, StoreValue, StoreView};
+
+use crate::{Account, AccountStore};
+
+const DELEGATION_STORE_NAME: &str = "rapido.account.delegations";
+
+/// Maximum depth of a delegation chain below a root trust anchor
+pub const MAX_DELEGATION_DEPTH: u8 = 5;
+
+/// The right to create accounts granted to an account
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, PartialEq, Clone)]
+pub struct Delegation {
+    pub delegator: AccountId,
+    /// Number of levels the account can delegate below it
+    pub depth: u8,
+}
+
+/// Delegations keyed by the delegated account
+pub(crate) struct DelegationStore;
+impl Store for DelegationStore {
+    type Key = AccountId;
+    type Value = Delegation;
+
+    fn name(&self) -> String {
+        DELEGATION_STORE_NAME.into()
+    }
+}
+
+impl DelegationStore {
+    pub fn new() -> Self {
+        DelegationStore {}
+    }
+}
+
+/// Emitted as 'rapido.account.grant_trust_anchor'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct GrantTrustAnchorEvent {
+    pub delegator: AccountId,
+    pub account: AccountId,
+    pub depth: u8,
+}
+
+/// Emitted as 'rapido.account.revoke_trust_anchor'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct RevokeTrustAnchorEvent {
+    pub account: AccountId,
+}
+
+/// Return the delegation chain of the account: the account, its delegator and so on
+/// up to a root trust anchor.  Errors if the account isn't a trust anchor
+pub fn delegation_chain(
+    account: &AccountId,
+    view: &StoreView,
+) -> anyhow::Result<Vec<AccountId>, anyhow::Error> {
+    let accounts = AccountStore::new();
+    let delegations = DelegationStore::new();
+    let mut chain = vec![account.clone()];
+    let mut current = account.clone();
+    loop {
+        match accounts.get(current.clone(), view) {
+            Some(acct) if acct.is_trust_anchor() => return Ok(chain),
+            Some(_) => {}
+            None => bail!("user not found"),
+        }
+        let delegation = match delegations.get(current.clone(), view) {
+            Some(d) => d,
+            None => bail!("not a trust anchor"),
+        };
+        // Bounded by the depth, but don't trust the store to be
+        ensure!(
+            chain.len() <= MAX_DELEGATION_DEPTH as usize,
+            "delegation chain is too long"
+        );
+        chain.push(delegation.delegator.clone());
+        current = delegation.delegator;
+    }
+}
+
+/// Can the account create accounts?
+pub fn can_create_accounts(account: &Account, view: &StoreView) -> bool {
+    delegation_chain(&account.id, view).is_ok()
+}
+
+// The number of levels the trust anchor can delegate
+fn remaining_depth(account: &AccountId, view: &StoreView) -> anyhow::Result<u8, anyhow::Error> {
+    let chain = delegation_chain(account, view)?;
+    if chain.len() == 1 {
+        return Ok(MAX_DELEGATION_DEPTH);
+    }
+    match DelegationStore::new().get(account.clone(), view) {
+        Some(d) => Ok(d.depth),
+        None => bail!("not a trust anchor"),
+    }
+}
+
+/// The sender grants the account the right to create accounts
+pub(crate) fn grant(
+    ctx: &Context,
+    account: AccountId,
+    depth: u8,
+    view: &mut StoreView,
+) -> anyhow::Result<(), anyhow::Error> {
+    let delegator = ctx.sender();
+    let remaining = remaining_depth(&delegator, view)?;
+    ensure!(remaining > 0, "the trust anchor can't delegate");
+    ensure!(depth < remaining, "depth must be less than {}", remaining);
+    ensure!(
+        AccountStore::new().get(account.clone(), view).is_some(),
+        "user not found"
+    );
+    ensure!(
+        delegation_chain(&account, view).is_err(),
+        "account is a trust anchor"
+    );
+
+    DelegationStore::new().put(
+        account.clone(),
+        Delegation {
+            delegator: delegator.clone(),
+            depth,
+        },
+        view,
+    );
+    ctx.emit(GrantTrustAnchorEvent {
+        delegator,
+        account,
+        depth,
+    })
+}
+
+/// Revoke the account's delegation. The sender must be above it in the chain
+pub(crate) fn revoke(
+    ctx: &Context,
+    account: AccountId,
+    view: &mut StoreView,
+) -> anyhow::Result<(), anyhow::Error> {
+    let store = DelegationStore::new();
+    ensure!(
+        store.get(account.clone(), view).is_some(),
+        "account has no delegation"
+    );
+    // A broken chain can be cleaned up by the direct delegator
+    let above = match delegation_chain(&account, view) {
+        Ok(chain) => chain[1..].to_vec(),
+        Err(_) => vec![store.get(account.clone(), view).unwrap().delegator],
+    };
+    ensure!(
+        above.contains(&ctx.sender()),
+        "only a trust anchor above the account can revoke"
+    );
+
+    store.remove(account.clone(), view);
+    ctx.emit(RevokeTrustAnchorEvent { account })
+}
@@ -0,0 +1,141 @@
+//!
+//! Genesis accounts.  Configure the genesis accounts of a network from a JSON or
+//! TOML file instead of compiling the keys into the binary:
+//! ```toml
+//! [[accounts]]
+//! pubkey = "<hex encoded public key>"
+//!
+//! [[accounts]]
+//! pubkey = "<hex encoded public key>"
+//! trust_anchor = false
+//! nonce = 5
+//! ```
+//! Accounts are trust anchors unless `trust_anchor = false`.  `nonce` sets the
+//! next nonce on lane 0.  The accounts are validated at init_chain.
+//!
+use std::path::Path;
+
+use anyhow::{bail, ensure};
+use exonum_crypto::{PublicKey, PUBLIC_KEY_LENGTH};
+use serde::Deserialize;
+
+use crate::Account;
+
+fn default_trust_anchor() -> bool {
+    true
+}
+
+/// A genesis account
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct GenesisAccount {
+    /// Hex encoded public key
+    pub pubkey: String,
+    #[serde(default = "default_trust_anchor")]
+    pub trust_anchor: bool,
+    #[serde(default)]
+    pub nonce: u64,
+}
+
+impl GenesisAccount {
+    /// A trust anchor with the public key
+    pub fn trust_anchor(pubkey: &[u8; PUBLIC_KEY_LENGTH]) -> Self {
+        Self {
+            pubkey: hex::encode(pubkey),
+            trust_anchor: true,
+            nonce: 0,
+        }
+    }
+
+    /// Validate the public key and create the account
+    pub fn to_account(&self) -> anyhow::Result<Account, anyhow::Error> {
+        let raw = match hex::decode(&self.pubkey) {
+            Ok(raw) => raw,
+            Err(_) => bail!("genesis: bad public key hex: {}", self.pubkey),
+        };
+        ensure!(
+            raw.len() == PUBLIC_KEY_LENGTH,
+            "genesis: bad public key length"
+        );
+        match PublicKey::from_slice(&raw) {
+            Some(pk) => Ok(Account::create(&pk, self.trust_anchor)),
+            None => bail!("genesis: problem decoding the public key"),
+        }
+    }
+}
+
+/// The format of a genesis file
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountGenesis {
+    pub accounts: Vec<GenesisAccount>,
+}
+
+impl AccountGenesis {
+    /// Read a genesis file. Files ending in '.toml' are TOML, others JSON
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(toml::from_str(&contents)?),
+            _ => Ok(serde_json::from_str(&contents)?),
+        }
+    }
+}
+
+/// An entry of the account genesis section: a hex encoded public key of a
+/// trust anchor or a `GenesisAccount`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum GenesisEntry {
+    Key(String),
+    Account(GenesisAccount),
+}
+
+impl From<GenesisEntry> for GenesisAccount {
+    fn from(entry: GenesisEntry) -> Self {
+        match entry {
+            GenesisEntry::Key(pubkey) => GenesisAccount {
+                pubkey,
+                trust_anchor: true,
+                nonce: 0,
+            },
+            GenesisEntry::Account(account) => account,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_genesis() {
+        let toml_genesis: AccountGenesis = toml::from_str(
+            r#"
+            [[accounts]]
+            pubkey = "aa"
+
+            [[accounts]]
+            pubkey = "bb"
+            trust_anchor = false
+            nonce = 5
+            "#,
+        )
+        .unwrap();
+        let json_genesis: AccountGenesis = serde_json::from_str(
+            r#"{"accounts": [{"pubkey": "aa"}, {"pubkey": "bb", "trust_anchor": false, "nonce": 5}]}"#,
+        )
+        .unwrap();
+        assert_eq!(toml_genesis, json_genesis);
+        assert!(toml_genesis.accounts[0].trust_anchor);
+        assert_eq!(5, toml_genesis.accounts[1].nonce);
+
+        // Validated when creating the account
+        assert!(toml_genesis.accounts[0].to_account().is_err());
+
+        let entries: Vec<GenesisEntry> =
+            serde_json::from_str(r#"["aa", {"pubkey": "bb", "nonce": 1}]"#).unwrap();
+        let accounts: Vec<GenesisAccount> = entries.into_iter().map(Into::into).collect();
+        assert_eq!("aa", accounts[0].pubkey);
+        assert_eq!(1, accounts[1].nonce);
+    }
+}
@@ -0,0 +1,129 @@
+//!
+//! Key rotation history.  When an account's public key is rotated the old key is
+//! retired and recorded with the rotation height.  Signatures made with a retired
+//! key are accepted by the `AccountAuthenticator` for a grace period, so txs signed
+//! before the rotation can still be committed, and rejected after.  The grace period
+//! is the `key_grace_period` param of the account AppModule.  Keys retired by a
+//! guardian recovery are presumed lost and have no grace period.
+//!
+use anyhow::ensure;
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{params::Params, AccountId, Store, StoreValue, StoreView};
+
+use crate::{Account, PublicKeyBytes, ACCOUNT_APP_NAME};
+
+const KEY_HISTORY_STORE_NAME: &str = "rapido.account.key_history";
+
+/// Maximum number of retired keys kept per account. The oldest are dropped
+pub const MAX_KEY_HISTORY: usize = 10;
+
+/// Default number of blocks a retired key is still accepted
+pub const DEFAULT_KEY_GRACE_PERIOD: i64 = 10;
+
+/// A key that was rotated out
+#[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+pub struct RetiredKey {
+    pub pubkey: PublicKeyBytes,
+    /// Height the key was rotated
+    pub retired: i64,
+    /// Rotated by a guardian recovery
+    pub recovered: bool,
+}
+
+impl RetiredKey {
+    /// Is the key still accepted at the height?
+    pub fn in_grace(&self, height: i64, grace_period: i64) -> bool {
+        !self.recovered && height - self.retired <= grace_period
+    }
+}
+
+/// The retired keys of an account, oldest first
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, PartialEq, Clone, Default)]
+pub struct KeyHistory(pub Vec<RetiredKey>);
+
+impl KeyHistory {
+    /// Find a retired key
+    pub fn find(&self, pubkey: &PublicKeyBytes) -> Option<&RetiredKey> {
+        self.0.iter().rev().find(|k| &k.pubkey == pubkey)
+    }
+
+    fn push(&mut self, key: RetiredKey) {
+        self.0.push(key);
+        if self.0.len() > MAX_KEY_HISTORY {
+            self.0.remove(0);
+        }
+    }
+}
+
+/// Key history keyed by account
+pub(crate) struct KeyHistoryStore;
+impl Store for KeyHistoryStore {
+    type Key = AccountId;
+    type Value = KeyHistory;
+
+    fn name(&self) -> String {
+        KEY_HISTORY_STORE_NAME.into()
+    }
+}
+
+impl KeyHistoryStore {
+    pub fn new() -> Self {
+        KeyHistoryStore {}
+    }
+}
+
+/// The grace period of retired keys
+pub(crate) fn grace_period(view: &StoreView) -> anyhow::Result<i64, anyhow::Error> {
+    Params::new(ACCOUNT_APP_NAME).get_or("key_grace_period", DEFAULT_KEY_GRACE_PERIOD, view)
+}
+
+/// Rotate the account's key, retiring the current key.  A key can't be reused
+pub(crate) fn rotate_key(
+    account: &Account,
+    pubkey: PublicKeyBytes,
+    height: i64,
+    recovered: bool,
+    view: &mut StoreView,
+) -> anyhow::Result<Account, anyhow::Error> {
+    ensure!(account.pubkey != pubkey, "the key is the current key");
+    let store = KeyHistoryStore::new();
+    let mut history = store.get(account.id(), view).unwrap_or_default();
+    ensure!(history.find(&pubkey).is_none(), "the key was retired");
+
+    history.push(RetiredKey {
+        pubkey: account.pubkey,
+        retired: height,
+        recovered,
+    });
+    store.put(account.id(), history, view);
+    Ok(account.update_pubkey(pubkey))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retired(n: u8, retired: i64, recovered: bool) -> RetiredKey {
+        RetiredKey {
+            pubkey: [n; 32],
+            retired,
+            recovered,
+        }
+    }
+
+    #[test]
+    fn test_key_history() {
+        let mut history = KeyHistory::default();
+        for i in 0..12 {
+            history.push(retired(i, i as i64, false));
+        }
+        assert_eq!(MAX_KEY_HISTORY, history.0.len());
+        assert!(history.find(&[0u8; 32]).is_none());
+        assert!(history.find(&[11u8; 32]).is_some());
+
+        assert!(retired(1, 10, false).in_grace(12, 2));
+        assert!(!retired(1, 9, false).in_grace(12, 2));
+        // No grace for keys retired by a recovery
+        assert!(!retired(1, 12, true).in_grace(12, 2));
+    }
+}
@@ -6,15 +6,24 @@ use anyhow::{bail, ensure};
 use borsh::{BorshDeserialize, BorshSerialize};
 use exonum_crypto::{hash, PublicKey, PUBLIC_KEY_LENGTH};
 use rapido_core::{
-    verify_tx_signature, AccountId, AppModule, Authenticator, Context, SignedTransaction, Store,
-    StoreView,
+    verify_tx_signature, AccountId, AppModule, AuthResult, Authenticator, Context, NonceStore,
+    QueryResponse, QueryView, SignedTransaction, Store, StoreValue, StoreView,
 };
 
-#[macro_use]
-extern crate rapido_core;
+pub mod delegation;
+pub mod genesis;
+pub mod keys;
+pub mod meta;
+pub mod multisig;
+pub mod recovery;
+
+use genesis::{AccountGenesis, GenesisAccount, GenesisEntry};
+use keys::{KeyHistoryStore, RetiredKey};
+use recovery::{GuardianStore, RecoveryStore};
 
 const ACCOUNT_APP_NAME: &str = "rapido.account";
 const ACCOUNT_STORE_NAME: &str = "rapido.account.store";
+const VESTING_STORE_NAME: &str = "rapido.account.vesting";
 
 pub type PublicKeyBytes = [u8; PUBLIC_KEY_LENGTH];
 
@@ -24,11 +33,10 @@ fn generate_account_id(pk: &PublicKey) -> Vec<u8> {
     bs58::encode(&hash.as_bytes()).into_vec()
 }
 
-/// Account Model
-#[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+/// Account Model. Nonces are tracked per lane in the core `NonceStore`
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, PartialEq, Clone)]
 pub struct Account {
     pub id: AccountId,
-    pub nonce: u64,
     pub pubkey: PublicKeyBytes,
     // flag: can this entity create accounts
     trustanchor: bool,
@@ -39,7 +47,6 @@ impl Account {
     pub fn create(pk: &PublicKey, is_ta: bool) -> Self {
         Self {
             id: generate_account_id(pk),
-            nonce: 0u64,
             pubkey: pk.as_bytes(),
             trustanchor: is_ta,
         }
@@ -64,25 +71,12 @@ impl Account {
     pub fn update_pubkey(&self, pk: PublicKeyBytes) -> Self {
         Self {
             id: self.id.clone(),
-            nonce: self.nonce,
             pubkey: pk,
             trustanchor: self.trustanchor,
         }
     }
-
-    /// Increment the nonce for the account
-    pub fn increment_nonce(&self) -> Self {
-        Self {
-            id: self.id.clone(),
-            nonce: self.nonce + 1,
-            pubkey: self.pubkey,
-            trustanchor: self.trustanchor,
-        }
-    }
 }
 
-impl_store_values!(Account);
-
 /// Account Store
 pub(crate) struct AccountStore;
 impl Store for AccountStore {
@@ -100,21 +94,146 @@ impl AccountStore {
     }
 }
 
+/// A vesting schedule attached to an account. `total` tokens vest linearly from the
+/// `start` to the `end` height. Nothing is vested before the `cliff` height.
+/// Unvested tokens can't be spent when the account module is a lock provider of
+/// the bank: `BankModule::new().with_lock_provider("rapido.account")`
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, PartialEq, Clone)]
+pub struct VestingSchedule {
+    pub total: u64,
+    pub start: i64,
+    pub cliff: i64,
+    pub end: i64,
+}
+
+impl VestingSchedule {
+    pub fn new(
+        total: u64,
+        start: i64,
+        cliff: i64,
+        end: i64,
+    ) -> anyhow::Result<Self, anyhow::Error> {
+        ensure!(
+            start <= cliff && cliff <= end && start < end,
+            "vesting: expected start <= cliff <= end"
+        );
+        Ok(Self {
+            total,
+            start,
+            cliff,
+            end,
+        })
+    }
+
+    /// Amount vested at the height
+    pub fn vested_at(&self, height: i64) -> u64 {
+        if height < self.cliff || height <= self.start {
+            return 0;
+        }
+        if height >= self.end {
+            return self.total;
+        }
+        let elapsed = (height - self.start) as u128;
+        let duration = (self.end - self.start) as u128;
+        (self.total as u128 * elapsed / duration) as u64
+    }
+
+    /// Amount still vesting (locked) at the height
+    pub fn locked_at(&self, height: i64) -> u64 {
+        self.total - self.vested_at(height)
+    }
+}
+
+/// Vested and vesting amounts of an account at a height
+#[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+pub struct VestingStatus {
+    pub height: i64,
+    pub total: u64,
+    pub vested: u64,
+    pub vesting: u64,
+}
+
+/// Vesting schedules keyed by account
+pub(crate) struct VestingStore;
+impl Store for VestingStore {
+    type Key = AccountId;
+    type Value = VestingSchedule;
+
+    fn name(&self) -> String {
+        VESTING_STORE_NAME.into()
+    }
+}
+
+impl VestingStore {
+    pub fn new() -> Self {
+        VestingStore {}
+    }
+}
+
 /// Message used in Transactions
 #[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
 pub enum Msgs {
     Create(PublicKeyBytes),
     ChangePubKey(PublicKeyBytes),
+    /// Create an account with a vesting schedule. The tokens are transferred
+    /// to the account separately
+    CreateVesting(PublicKeyBytes, VestingSchedule),
+    /// Set the guardians and threshold of the sender's account. See `recovery`
+    SetGuardians(Vec<AccountId>, u8),
+    /// A guardian proposes a new public key for the account
+    ProposeRecovery(AccountId, PublicKeyBytes),
+    /// A guardian approves the pending recovery of the account
+    ApproveRecovery(AccountId),
+    /// A guardian executes the approved recovery of the account
+    ExecuteRecovery(AccountId),
+    /// The owner cancels a pending recovery of their account
+    CancelRecovery,
+    /// A trust anchor grants the account the right to create accounts, with the
+    /// number of levels it can delegate below it. See `delegation`
+    GrantTrustAnchor(AccountId, u8),
+    /// Revoke the account's right to create accounts
+    RevokeTrustAnchor(AccountId),
+    /// Set or, with `None`, remove metadata entries of the sender's account
+    SetMeta(Vec<(String, Option<String>)>),
 }
 
+/// Default number of blocks between proposing and executing a recovery
+pub const DEFAULT_RECOVERY_DELAY: i64 = 100;
+
 pub struct AccountModule {
-    // PublicKeys of genesis accounts
-    genesis: Vec<[u8; 32]>,
+    genesis: Vec<GenesisAccount>,
+    recovery_delay: i64,
 }
 
 impl AccountModule {
+    /// Create the module with the PublicKeys of the genesis trust anchors
     pub fn new(genesis: Vec<[u8; 32]>) -> Self {
-        Self { genesis }
+        Self::with_genesis(genesis.iter().map(GenesisAccount::trust_anchor).collect())
+    }
+
+    /// Create the module with the genesis accounts
+    pub fn with_genesis(genesis: Vec<GenesisAccount>) -> Self {
+        Self {
+            genesis,
+            recovery_delay: DEFAULT_RECOVERY_DELAY,
+        }
+    }
+
+    /// Create the module with the genesis accounts in a JSON or TOML file.
+    /// See `genesis`
+    pub fn from_genesis_file<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> anyhow::Result<Self, anyhow::Error> {
+        Ok(Self::with_genesis(
+            AccountGenesis::from_file(path)?.accounts,
+        ))
+    }
+
+    /// Number of blocks the owner has to cancel a recovery before it can be
+    /// executed. Default: `DEFAULT_RECOVERY_DELAY`
+    pub fn recovery_delay(mut self, blocks: i64) -> Self {
+        self.recovery_delay = blocks;
+        self
     }
 }
 
@@ -123,12 +242,26 @@ impl AppModule for AccountModule {
         ACCOUNT_APP_NAME.into()
     }
 
-    // Load genesis accounts.  These entries become the trust anchors
-    fn initialize(&self, view: &mut StoreView) -> Result<(), anyhow::Error> {
+    // Load genesis accounts.  Accounts come from the constructor and/or the genesis
+    // section: a list of hex encoded public keys of trust anchors or `GenesisAccount`s
+    fn initialize(&self, genesis: &[u8], view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let mut accounts = self.genesis.clone();
+        if !genesis.is_empty() {
+            let entries: Vec<GenesisEntry> = serde_json::from_slice(genesis)?;
+            accounts.extend(entries.into_iter().map(GenesisAccount::from));
+        }
+
         let store = AccountStore::new();
-        for pk in &self.genesis {
-            let pubkey = PublicKey::from_slice(&pk[..]).expect("genesis: decode public key");
-            let account = Account::create(&pubkey, true); // <= make them a trust anchor
+        for genesis_account in &accounts {
+            let account = genesis_account.to_account()?;
+            ensure!(
+                store.get(account.id(), view).is_none(),
+                "genesis: duplicate account {}",
+                genesis_account.pubkey
+            );
+            if genesis_account.nonce > 0 {
+                NonceStore::new().put((account.id(), 0), genesis_account.nonce, view);
+            }
             store.put(account.id(), account, view)
         }
         Ok(())
@@ -139,23 +272,7 @@ impl AppModule for AccountModule {
         match msg {
             // Create an account.  The origin of this call, must be a trust anchor
             Msgs::Create(pubkey) => {
-                let store = AccountStore::new();
-                // Ensure the caller's account exists and they are a trust anchor
-                let caller_acct = store.get(ctx.sender(), &view);
-                ensure!(caller_acct.is_some(), "user not found");
-                let acct = caller_acct.unwrap();
-
-                ensure!(
-                    acct.is_trust_anchor(),
-                    "only a trust anchor can create an account"
-                );
-
-                let pk = PublicKey::from_slice(&pubkey[..]);
-                ensure!(pk.is_some(), "problem decoding the public key");
-
-                // Create the new account
-                let new_account = Account::create(&pk.unwrap(), false);
-                store.put(new_account.id(), new_account, view);
+                create_new_account(ctx, pubkey, view)?;
                 Ok(())
             }
 
@@ -167,10 +284,39 @@ impl AppModule for AccountModule {
                 ensure!(caller_acct.is_some(), "user not found");
                 let acct = caller_acct.unwrap();
 
-                let updated = acct.update_pubkey(pubkey);
+                let updated = keys::rotate_key(&acct, pubkey, ctx.height(), false, view)?;
                 store.put(updated.id(), updated, view);
                 Ok(())
             }
+
+            // Create an account with a vesting schedule. The origin of this call
+            // must be a trust anchor
+            Msgs::CreateVesting(pubkey, schedule) => {
+                let schedule = VestingSchedule::new(
+                    schedule.total,
+                    schedule.start,
+                    schedule.cliff,
+                    schedule.end,
+                )?;
+                let id = create_new_account(ctx, pubkey, view)?;
+                VestingStore::new().put(id, schedule, view);
+                Ok(())
+            }
+
+            Msgs::SetGuardians(guardians, threshold) => {
+                recovery::set_guardians(ctx, guardians, threshold, view)
+            }
+            Msgs::ProposeRecovery(account, pubkey) => recovery::propose(ctx, account, pubkey, view),
+            Msgs::ApproveRecovery(account) => recovery::approve(ctx, account, view),
+            Msgs::ExecuteRecovery(account) => {
+                recovery::execute(ctx, account, self.recovery_delay, view)
+            }
+            Msgs::CancelRecovery => recovery::cancel(ctx, view),
+
+            Msgs::GrantTrustAnchor(account, depth) => delegation::grant(ctx, account, depth, view),
+            Msgs::RevokeTrustAnchor(account) => delegation::revoke(ctx, account, view),
+
+            Msgs::SetMeta(entries) => meta::set_meta(ctx, entries, view),
         }
     }
 
@@ -178,8 +324,8 @@ impl AppModule for AccountModule {
         &self,
         path: &str,
         key: Vec<u8>,
-        view: &StoreView,
-    ) -> Result<Vec<u8>, anyhow::Error> {
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
         ensure!(key.len() > 0, "bad account key");
 
         // return a serialized account for the given id.
@@ -191,12 +337,98 @@ impl AppModule for AccountModule {
                 ensure!(req_acct.is_some(), "account not found");
 
                 let acct: Account = req_acct.unwrap();
-                let bits = acct.try_to_vec()?;
-                Ok(bits)
+                QueryResponse::encode(&acct)
+            }
+            // The vesting status of the account at the current height
+            "/vesting" => {
+                let schedule = VestingStore::new().query(key, &view);
+                ensure!(schedule.is_some(), "vesting schedule not found");
+                let schedule = schedule.unwrap();
+                let vested = schedule.vested_at(view.height());
+                QueryResponse::encode(&VestingStatus {
+                    height: view.height(),
+                    total: schedule.total,
+                    vested,
+                    vesting: schedule.total - vested,
+                })
+            }
+            // The amount locked by vesting. See `BankModule::with_lock_provider`
+            "/locked" => {
+                let locked = VestingStore::new()
+                    .query(key, &view)
+                    .map_or(0, |s| s.locked_at(view.height()));
+                QueryResponse::encode(&locked)
+            }
+            "/guardians" => {
+                let guardians = GuardianStore::new().query(key, &view);
+                ensure!(guardians.is_some(), "guardians not found");
+                QueryResponse::encode(&guardians.unwrap())
+            }
+            // The pending recovery of the account
+            "/recovery" => {
+                let recovery = RecoveryStore::new().query(key, &view);
+                ensure!(recovery.is_some(), "no pending recovery");
+                QueryResponse::encode(&recovery.unwrap())
+            }
+            // The metadata of the account. Empty if none is set
+            "/meta" => {
+                let meta = meta::MetaStore::new().query(key, &view).unwrap_or_default();
+                QueryResponse::encode(&meta)
+            }
+            // The account and its delegators up to a root trust anchor
+            "/delegation_chain" => {
+                let chain = delegation::delegation_chain(&key, &view)?;
+                QueryResponse::encode(&chain)
+            }
+            // The retired keys of the account
+            "/key_history" => {
+                let history = KeyHistoryStore::new().query(key, &view).unwrap_or_default();
+                QueryResponse::encode(&history)
             }
             _ => bail!("{:} not found", path),
         }
     }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec![
+            "/".into(),
+            "/vesting".into(),
+            "/locked".into(),
+            "/guardians".into(),
+            "/recovery".into(),
+            "/key_history".into(),
+            "/delegation_chain".into(),
+            "/meta".into(),
+        ]
+    }
+}
+
+// Create an account for the public key. The sender must be a trust anchor.
+// Returns the id of the new account
+fn create_new_account(
+    ctx: &Context,
+    pubkey: PublicKeyBytes,
+    view: &mut StoreView,
+) -> anyhow::Result<AccountId, anyhow::Error> {
+    let store = AccountStore::new();
+    // Ensure the caller's account exists and they are a trust anchor
+    let caller_acct = store.get(ctx.sender(), &view);
+    ensure!(caller_acct.is_some(), "user not found");
+    let acct = caller_acct.unwrap();
+
+    ensure!(
+        delegation::can_create_accounts(&acct, &view),
+        "only a trust anchor can create an account"
+    );
+
+    let pk = PublicKey::from_slice(&pubkey[..]);
+    ensure!(pk.is_some(), "problem decoding the public key");
+
+    // Create the new account
+    let new_account = Account::create(&pk.unwrap(), false);
+    let id = new_account.id();
+    store.put(id.clone(), new_account, view);
+    Ok(id)
 }
 
 // Authenticator
@@ -204,11 +436,11 @@ pub struct AccountAuthenticator;
 impl Authenticator for AccountAuthenticator {
     fn validate(
         &self,
+        ctx: &Context,
         tx: &SignedTransaction,
         view: &StoreView,
-    ) -> anyhow::Result<(), anyhow::Error> {
+    ) -> anyhow::Result<AuthResult, anyhow::Error> {
         let caller = tx.sender();
-        let txnonce = tx.nonce();
         let store = AccountStore::new();
 
         let caller_acct = store.get(caller, &view);
@@ -221,32 +453,40 @@ impl Authenticator for AccountAuthenticator {
             "problem decoding the user's public key"
         );
 
-        // Validate signature
-        ensure!(
-            verify_tx_signature(&tx, &caller_pubkey.unwrap()),
-            "bad signature"
-        );
+        // Validate signature.  Retired keys are accepted during the grace period
+        if !verify_tx_signature(&tx, &caller_pubkey.unwrap()) {
+            let history = KeyHistoryStore::new()
+                .get(caller.clone(), &view)
+                .unwrap_or_default();
+            let signer: Option<&RetiredKey> = history.0.iter().rev().find(|k| {
+                PublicKey::from_slice(&k.pubkey[..])
+                    .map_or(false, |pk| verify_tx_signature(&tx, &pk))
+            });
+            match signer {
+                None => bail!("bad signature"),
+                Some(k) if !k.in_grace(ctx.height(), keys::grace_period(view)?) => {
+                    bail!("signature made with a retired key")
+                }
+                Some(_) => {}
+            }
+        }
 
-        // Check nonce
-        ensure!(acct.nonce == txnonce, "nonce don't match");
+        // Check nonce for the tx lane
+        NonceStore::new().check(tx, view)?;
 
-        Ok(())
+        Ok(AuthResult::new())
     }
 
     fn increment_nonce(
         &self,
+        _ctx: &Context,
         tx: &SignedTransaction,
         view: &mut StoreView,
     ) -> anyhow::Result<(), anyhow::Error> {
-        let caller = tx.sender();
         let store = AccountStore::new();
-        let caller_acct = store.get(caller.clone(), &view);
-        ensure!(caller_acct.is_some(), "user not found");
-
-        let acct = caller_acct.unwrap();
-        let unonce = acct.increment_nonce();
-        store.put(caller.clone(), unonce, view);
+        ensure!(store.get(tx.sender(), &view).is_some(), "user not found");
 
+        NonceStore::new().increment(tx, view);
         Ok(())
     }
 }
@@ -255,7 +495,8 @@ impl Authenticator for AccountAuthenticator {
 mod tests {
     use super::*;
     use exonum_crypto::{gen_keypair, SecretKey};
-    use rapido_core::{testing_keypair, AppBuilder, TestKit};
+    use rapido_bank::{BankModule, BankMsgs, BANK_APP_NAME};
+    use rapido_core::{testing_keypair, AppBuilder, TestKit, TESTKIT_CHAIN_ID};
 
     fn create_account(name: &str) -> (Vec<u8>, PublicKeyBytes, SecretKey) {
         let (pk, sk) = testing_keypair(name);
@@ -278,7 +519,8 @@ mod tests {
             Msgs::Create([1u8; 32]), // fake data
             nonce,
         );
-        tx.sign(&secret_key);
+        tx.set_chain_id(TESTKIT_CHAIN_ID);
+        tx.sign(&secret_key).unwrap();
         tx
     }
 
@@ -309,6 +551,14 @@ mod tests {
             .check_tx(&[&gen_tx(bob.clone(), &bsk, 5u64)])
             .is_err());
 
+        // Nonces on another lane are tracked separately
+        let mut lane_tx =
+            SignedTransaction::create(bob.clone(), ACCOUNT_APP_NAME, Msgs::Create([1u8; 32]), 0u64);
+        lane_tx.set_lane(1);
+        lane_tx.set_chain_id(TESTKIT_CHAIN_ID);
+        lane_tx.sign(&bsk).unwrap();
+        assert!(tester.check_tx(&[&lane_tx]).is_ok());
+
         // Bad signature: bob's ID but signed with wrong key
         let (_rpk, rsk) = gen_keypair();
         assert!(tester
@@ -333,16 +583,24 @@ mod tests {
 
         let mut tx =
             SignedTransaction::create(bob.clone(), ACCOUNT_APP_NAME, Msgs::Create(cpk), 0u64);
-        tx.sign(&bsk);
+        tx.set_chain_id(TESTKIT_CHAIN_ID);
+        tx.sign(&bsk).unwrap();
 
         assert!(tester.check_tx(&[&tx]).is_ok());
         assert!(tester.commit_tx(&[&tx]).is_ok());
 
         assert!(tester.query("rapido.account", carol.clone()).is_ok());
 
+        // Next nonce of bob, on lane 0 and lane 1
+        let nonce: u64 = tester.query_as("rapido/nonce", bob.clone()).unwrap();
+        assert_eq!(1, nonce);
+        let nonce: u64 = tester.query_as("rapido/nonce/1", bob.clone()).unwrap();
+        assert_eq!(0, nonce);
+
         let mut tx1 =
             SignedTransaction::create(carol.clone(), ACCOUNT_APP_NAME, Msgs::Create(apk), 0u64);
-        tx1.sign(&csk);
+        tx1.set_chain_id(TESTKIT_CHAIN_ID);
+        tx1.sign(&csk).unwrap();
 
         // Check passes...but
         assert!(tester.check_tx(&[&tx1]).is_ok());
@@ -350,10 +608,278 @@ mod tests {
         assert!(tester.commit_tx(&[&tx1]).is_err());
     }
 
+    #[test]
+    fn test_vesting_schedule() {
+        assert!(VestingSchedule::new(100, 10, 5, 20).is_err());
+        assert!(VestingSchedule::new(100, 10, 10, 10).is_err());
+
+        let schedule = VestingSchedule::new(100, 0, 10, 20).unwrap();
+        assert_eq!(0, schedule.vested_at(5));
+        assert_eq!(50, schedule.vested_at(10));
+        assert_eq!(75, schedule.vested_at(15));
+        assert_eq!(100, schedule.vested_at(25));
+        assert_eq!(25, schedule.locked_at(15));
+    }
+
+    #[test]
+    fn test_vesting_locks_bank_transfers() {
+        let (bob, _bpk, _bsk) = create_account("bob");
+        let (carol, cpk, _csk) = create_account("carol");
+        let carol_id = String::from_utf8(carol.clone()).unwrap();
+
+        let app = AppBuilder::new()
+            .with_app(AccountModule::new(get_genesis_accounts()))
+            .with_app(BankModule::new().with_lock_provider(ACCOUNT_APP_NAME));
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(&format!(r#"{{"rapido.bank": {{"{}": 150}}}}"#, carol_id));
+
+        // Bob (a trust anchor) creates carol with 100 vesting
+        let schedule = VestingSchedule::new(100, 0, 10, 20).unwrap();
+        let tx =
+            SignedTransaction::create(bob, ACCOUNT_APP_NAME, Msgs::CreateVesting(cpk, schedule), 0);
+        assert!(tester.commit_tx(&[&tx]).is_ok());
+
+        let status: VestingStatus = tester
+            .query_as("rapido.account/vesting", carol.clone())
+            .unwrap();
+        assert_eq!(100, status.vesting);
+
+        // Carol can only spend the unlocked 50
+        let transfer = |amount| {
+            SignedTransaction::create(
+                carol.clone(),
+                BANK_APP_NAME,
+                BankMsgs::Transfer {
+                    to: "dave".into(),
+                    amount,
+                },
+                0,
+            )
+        };
+        assert!(tester.commit_tx(&[&transfer(51)]).is_err());
+        assert!(tester.commit_tx(&[&transfer(50)]).is_ok());
+    }
+
+    #[test]
+    fn test_guardian_recovery() {
+        let (bob, bpk, _bsk) = create_account("bob");
+        let (alice, _apk, _ask) = create_account("alice");
+        let (tom, _tpk, _tsk) = create_account("tom");
+        let (_, newpk, _) = create_account("newbob");
+
+        let app = AppBuilder::new()
+            .with_app(AccountModule::new(get_genesis_accounts()).recovery_delay(0));
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let tx = |sender: &Vec<u8>, msg: Msgs| {
+            SignedTransaction::create(sender.clone(), ACCOUNT_APP_NAME, msg, 0)
+        };
+
+        // A guardian can't be the owner
+        assert!(tester
+            .commit_tx(&[&tx(&bob, Msgs::SetGuardians(vec![bob.clone()], 1))])
+            .is_err());
+        assert!(tester
+            .commit_tx(&[&tx(
+                &bob,
+                Msgs::SetGuardians(vec![alice.clone(), tom.clone()], 2)
+            )])
+            .is_ok());
+
+        // Only a guardian can propose
+        assert!(tester
+            .commit_tx(&[&tx(&bob, Msgs::ProposeRecovery(bob.clone(), newpk))])
+            .is_err());
+        assert!(tester
+            .commit_tx(&[&tx(&alice, Msgs::ProposeRecovery(bob.clone(), newpk))])
+            .is_ok());
+        let pending: recovery::PendingRecovery = tester
+            .query_as("rapido.account/recovery", bob.clone())
+            .unwrap();
+        assert_eq!(vec![alice.clone()], pending.approvals);
+
+        // Needs 2 approvals
+        assert!(tester
+            .commit_tx(&[&tx(&alice, Msgs::ExecuteRecovery(bob.clone()))])
+            .is_err());
+        assert!(tester
+            .commit_tx(&[&tx(&tom, Msgs::ApproveRecovery(bob.clone()))])
+            .is_ok());
+        assert!(tester
+            .commit_tx(&[&tx(&alice, Msgs::ExecuteRecovery(bob.clone()))])
+            .is_ok());
+
+        let acct: Account = tester.query_as("rapido.account", bob.clone()).unwrap();
+        assert_eq!(newpk, acct.pubkey);
+        assert_ne!(bpk, acct.pubkey);
+        assert!(tester
+            .query("rapido.account/recovery", bob.clone())
+            .is_err());
+
+        // The owner cancels
+        assert!(tester
+            .commit_tx(&[&tx(&alice, Msgs::ProposeRecovery(bob.clone(), bpk))])
+            .is_ok());
+        assert!(tester.commit_tx(&[&tx(&bob, Msgs::CancelRecovery)]).is_ok());
+        assert!(tester
+            .commit_tx(&[&tx(&tom, Msgs::ApproveRecovery(bob.clone()))])
+            .is_err());
+    }
+
+    #[test]
+    fn test_delegated_trust_anchors() {
+        let app = AppBuilder::new().with_app(AccountModule::new(get_genesis_accounts()));
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let (bob, _, _) = create_account("bob");
+        let (carol, cpk, _) = create_account("carol");
+        let (andy, apk, _) = create_account("andy");
+        let (_, xpk, _) = create_account("xavier");
+        let (_, ypk, _) = create_account("yolanda");
+        let tx = |sender: &Vec<u8>, msg: Msgs| {
+            SignedTransaction::create(sender.clone(), ACCOUNT_APP_NAME, msg, 0)
+        };
+
+        assert!(tester.commit_tx(&[&tx(&bob, Msgs::Create(cpk))]).is_ok());
+        assert!(tester.commit_tx(&[&tx(&carol, Msgs::Create(apk))]).is_err());
+
+        // Bob delegates to carol, carol to andy
+        let result = tester
+            .commit_tx(&[&tx(&bob, Msgs::GrantTrustAnchor(carol.clone(), 1))])
+            .unwrap();
+        assert_eq!(
+            Some("1".to_string()),
+            result.txs[0].attribute("rapido.account.grant_trust_anchor", "depth")
+        );
+        assert!(tester.commit_tx(&[&tx(&carol, Msgs::Create(apk))]).is_ok());
+        assert!(tester
+            .commit_tx(&[&tx(&carol, Msgs::GrantTrustAnchor(andy.clone(), 1))])
+            .is_err());
+        assert!(tester
+            .commit_tx(&[&tx(&carol, Msgs::GrantTrustAnchor(andy.clone(), 0))])
+            .is_ok());
+        assert!(tester.commit_tx(&[&tx(&andy, Msgs::Create(xpk))]).is_ok());
+
+        let chain: Vec<AccountId> = tester
+            .query_as("rapido.account/delegation_chain", andy.clone())
+            .unwrap();
+        assert_eq!(vec![andy.clone(), carol.clone(), bob.clone()], chain);
+
+        // Revoking carol revokes andy
+        assert!(tester
+            .commit_tx(&[&tx(&andy, Msgs::RevokeTrustAnchor(carol.clone()))])
+            .is_err());
+        assert!(tester
+            .commit_tx(&[&tx(&bob, Msgs::RevokeTrustAnchor(carol.clone()))])
+            .is_ok());
+        assert!(tester.commit_tx(&[&tx(&andy, Msgs::Create(ypk))]).is_err());
+        assert!(tester
+            .query("rapido.account/delegation_chain", andy.clone())
+            .is_err());
+    }
+
+    #[test]
+    fn test_account_meta() {
+        let app = AppBuilder::new().with_app(AccountModule::new(get_genesis_accounts()));
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let (bob, _, _) = create_account("bob");
+        let set_name = |sender: &Vec<u8>| {
+            SignedTransaction::create(
+                sender.clone(),
+                ACCOUNT_APP_NAME,
+                Msgs::SetMeta(vec![(meta::META_DISPLAY_NAME.into(), Some("Bob".into()))]),
+                0,
+            )
+        };
+        // Needs an account
+        assert!(tester.commit_tx(&[&set_name(&b"nobody".to_vec())]).is_err());
+        assert!(tester.commit_tx(&[&set_name(&bob)]).is_ok());
+
+        let bob_meta: meta::AccountMeta =
+            tester.query_as("rapido.account/meta", bob.clone()).unwrap();
+        assert_eq!(Some("Bob"), bob_meta.get(meta::META_DISPLAY_NAME));
+        let (alice, _, _) = create_account("alice");
+        let alice_meta: meta::AccountMeta = tester.query_as("rapido.account/meta", alice).unwrap();
+        assert!(alice_meta.0.is_empty());
+    }
+
+    #[test]
+    fn test_genesis_file() {
+        let (bob, bpk, bsk) = create_account("bob");
+        let (carol, cpk, _) = create_account("carol");
+        let path = std::env::temp_dir().join("rapido_account_genesis.toml");
+        std::fs::write(
+            &path,
+            format!(
+                "[[accounts]]\npubkey = \"{}\"\nnonce = 3\n\n[[accounts]]\npubkey = \"{}\"\ntrust_anchor = false\n",
+                hex::encode(bpk),
+                hex::encode(cpk)
+            ),
+        )
+        .unwrap();
+
+        let app = AppBuilder::new()
+            .set_authenticator(AccountAuthenticator {})
+            .with_app(AccountModule::from_genesis_file(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let acct: Account = tester.query_as("rapido.account", bob.clone()).unwrap();
+        assert!(acct.is_trust_anchor());
+        let acct: Account = tester.query_as("rapido.account", carol).unwrap();
+        assert!(!acct.is_trust_anchor());
+        assert!(tester.check_tx(&[&gen_tx(bob.clone(), &bsk, 3)]).is_ok());
+
+        // Invalid keys fail init_chain
+        let app = AppBuilder::new().with_app(AccountModule::with_genesis(vec![
+            genesis::GenesisAccount {
+                pubkey: "abcd".into(),
+                trust_anchor: true,
+                nonce: 0,
+            },
+        ]));
+        assert!(TestKit::create(app).try_start_with_genesis("").is_err());
+    }
+
     #[test]
     fn test_account_chng_pubkey() {
         // Bob will change is pubkey.  Make sure he can authenticate with it
+        let app = AppBuilder::new()
+            .set_authenticator(AccountAuthenticator {})
+            .with_app(AccountModule::new(get_genesis_accounts()));
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let (bob, bpk, bsk) = create_account("bob");
+        let (_, npk, nsk) = create_account("newbob");
+
+        let mut tx =
+            SignedTransaction::create(bob.clone(), ACCOUNT_APP_NAME, Msgs::ChangePubKey(npk), 0);
+        tx.set_chain_id(TESTKIT_CHAIN_ID);
+        tx.sign(&bsk).unwrap();
+        assert!(tester.commit_tx(&[&tx]).is_ok());
+
+        let acct: Account = tester.query_as("rapido.account", bob.clone()).unwrap();
+        assert_eq!(npk, acct.pubkey);
+        let history: keys::KeyHistory = tester
+            .query_as("rapido.account/key_history", bob.clone())
+            .unwrap();
+        assert_eq!(bpk, history.0[0].pubkey);
 
-        assert!(true)
+        // The new key works and the old key is in its grace period
+        assert!(tester.check_tx(&[&gen_tx(bob.clone(), &nsk, 1)]).is_ok());
+        assert!(tester.check_tx(&[&gen_tx(bob.clone(), &bsk, 1)]).is_ok());
+
+        // A retired key can't be reused
+        let mut tx =
+            SignedTransaction::create(bob.clone(), ACCOUNT_APP_NAME, Msgs::ChangePubKey(bpk), 1);
+        tx.set_chain_id(TESTKIT_CHAIN_ID);
+        tx.sign(&nsk).unwrap();
+        assert!(tester.commit_tx(&[&tx]).is_err());
     }
 }
@@ -0,0 +1,150 @@
+//!
+//! Account metadata.  A small map of strings the owner of an account can update,
+//! e.g. a display name or service URL, so apps don't each need their own profile
+//! store.  Common keys are defined here.  The size of the map is capped.
+//!
+use std::collections::BTreeMap;
+
+use anyhow::ensure;
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{AccountId, Context, RapidoEvent, Store, StoreValue, StoreView};
+
+use crate::AccountStore;
+
+const META_STORE_NAME: &str = "rapido.account.meta";
+
+/// Display name of the account
+pub const META_DISPLAY_NAME: &str = "display_name";
+/// URL of a service run by the account
+pub const META_SERVICE_URL: &str = "service_url";
+/// What the account's key is used for, e.g. 'sign,encrypt'
+pub const META_KEY_USAGE: &str = "key_usage";
+
+/// Maximum number of entries
+pub const MAX_META_ENTRIES: usize = 16;
+/// Maximum length of a key
+pub const MAX_META_KEY_LENGTH: usize = 32;
+/// Maximum length of a value
+pub const MAX_META_VALUE_LENGTH: usize = 256;
+
+/// The metadata of an account
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, PartialEq, Clone, Default)]
+pub struct AccountMeta(pub BTreeMap<String, String>);
+
+impl AccountMeta {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|v| v.as_str())
+    }
+
+    /// Set or, with `None`, remove the entries
+    pub fn update(
+        &mut self,
+        entries: Vec<(String, Option<String>)>,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        for (key, value) in entries {
+            ensure!(
+                !key.is_empty() && key.len() <= MAX_META_KEY_LENGTH,
+                "metadata key must be 1 to {} characters",
+                MAX_META_KEY_LENGTH
+            );
+            match value {
+                Some(v) => {
+                    ensure!(
+                        v.len() <= MAX_META_VALUE_LENGTH,
+                        "metadata value of '{}' is longer than {}",
+                        key,
+                        MAX_META_VALUE_LENGTH
+                    );
+                    self.0.insert(key, v);
+                }
+                None => {
+                    self.0.remove(&key);
+                }
+            }
+        }
+        ensure!(
+            self.0.len() <= MAX_META_ENTRIES,
+            "an account can have at most {} metadata entries",
+            MAX_META_ENTRIES
+        );
+        Ok(())
+    }
+}
+
+/// Metadata keyed by account
+pub(crate) struct MetaStore;
+impl Store for MetaStore {
+    type Key = AccountId;
+    type Value = AccountMeta;
+
+    fn name(&self) -> String {
+        META_STORE_NAME.into()
+    }
+}
+
+impl MetaStore {
+    pub fn new() -> Self {
+        MetaStore {}
+    }
+}
+
+/// Emitted as 'rapido.account.meta_updated'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct MetaUpdatedEvent {
+    pub account: AccountId,
+}
+
+/// Update the metadata of the sender's account
+pub(crate) fn set_meta(
+    ctx: &Context,
+    entries: Vec<(String, Option<String>)>,
+    view: &mut StoreView,
+) -> anyhow::Result<(), anyhow::Error> {
+    let account = ctx.sender();
+    ensure!(
+        AccountStore::new().get(account.clone(), view).is_some(),
+        "user not found"
+    );
+    let store = MetaStore::new();
+    let mut meta = store.get(account.clone(), view).unwrap_or_default();
+    meta.update(entries)?;
+    if meta.0.is_empty() {
+        store.remove(account.clone(), view);
+    } else {
+        store.put(account.clone(), meta, view);
+    }
+    ctx.emit(MetaUpdatedEvent { account })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_update() {
+        let mut meta = AccountMeta::default();
+        assert!(meta
+            .update(vec![
+                (META_DISPLAY_NAME.into(), Some("Bob".into())),
+                (
+                    META_SERVICE_URL.into(),
+                    Some("https://bob.example.com".into())
+                ),
+            ])
+            .is_ok());
+        assert_eq!(Some("Bob"), meta.get(META_DISPLAY_NAME));
+
+        assert!(meta.update(vec![(META_DISPLAY_NAME.into(), None)]).is_ok());
+        assert_eq!(None, meta.get(META_DISPLAY_NAME));
+
+        assert!(meta.update(vec![("".into(), Some("x".into()))]).is_err());
+        assert!(meta
+            .update(vec![(META_KEY_USAGE.into(), Some("x".repeat(300)))])
+            .is_err());
+
+        let many = (0..MAX_META_ENTRIES)
+            .map(|i| (format!("k{}", i), Some("v".into())))
+            .collect();
+        assert!(meta.update(many).is_err());
+    }
+}
@@ -0,0 +1,485 @@
+//!
+//! k-of-n multisig accounts. A multisig account is controlled by a set of signers.
+//! A signer proposes a transaction for the multisig and the other signers approve it
+//! with their own (signed) transactions.  Once `threshold` signers have approved, the
+//! inner transaction is executed with the multisig account as the sender.
+//!
+//! Executing requires the capability `{app}::dispatch_as` for each AppModule the
+//! multisig can send to:
+//! ```ignore
+//! AppBuilder::new()
+//!     .with_app(MultisigModule {})
+//!     .with_app(BankModule::new())
+//!     .grant(MULTISIG_APP_NAME, "rapido.bank::dispatch_as")
+//! ```
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use exonum_crypto::hash;
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoMessages, Store,
+    StoreValue, StoreView,
+};
+
+pub const MULTISIG_APP_NAME: &str = "rapido.multisig";
+const MULTISIG_STORE_NAME: &str = "rapido.multisig.accounts";
+const SESSION_STORE_NAME: &str = "rapido.multisig.sessions";
+const PENDING_STORE_NAME: &str = "rapido.multisig.pending";
+const SESSION_ID_STORE_NAME: &str = "rapido.multisig.session_id";
+
+/// Maximum number of signers of a multisig account
+pub const MAX_MULTISIG_SIGNERS: usize = 20;
+
+/// Multisig account Model
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, PartialEq, Clone)]
+pub struct MultisigAccount {
+    pub id: AccountId,
+    pub signers: Vec<AccountId>,
+    pub threshold: u8,
+}
+
+impl MultisigAccount {
+    /// Create a multisig account. The id is generated from the signers and
+    /// threshold: base58(hash(signers, threshold))
+    pub fn create(signers: Vec<AccountId>, threshold: u8) -> anyhow::Result<Self, anyhow::Error> {
+        ensure!(!signers.is_empty(), "a multisig needs at least 1 signer");
+        ensure!(
+            signers.len() <= MAX_MULTISIG_SIGNERS,
+            "a multisig can have at most {} signers",
+            MAX_MULTISIG_SIGNERS
+        );
+        ensure!(
+            threshold > 0 && threshold as usize <= signers.len(),
+            "threshold must be between 1 and the number of signers"
+        );
+        let mut sorted = signers.clone();
+        sorted.sort();
+        sorted.dedup();
+        ensure!(sorted.len() == signers.len(), "duplicate signer");
+
+        let hashed = hash(&(sorted.clone(), threshold).try_to_vec()?);
+        Ok(Self {
+            id: bs58::encode(&hashed.as_bytes()).into_vec(),
+            signers: sorted,
+            threshold,
+        })
+    }
+
+    pub fn is_signer(&self, account: &[u8]) -> bool {
+        self.signers.iter().any(|s| s.as_slice() == account)
+    }
+}
+
+/// A transaction proposed for a multisig account, waiting for approvals
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, PartialEq, Clone)]
+pub struct MultisigSession {
+    pub id: u64,
+    pub multisig: AccountId,
+    pub proposer: AccountId,
+    /// AppModule of the inner transaction
+    pub app: String,
+    /// Encoded message of the inner transaction
+    pub msg: Vec<u8>,
+    pub approvals: Vec<AccountId>,
+}
+
+// Ids of the pending sessions of a multisig
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, Clone, Default)]
+pub(crate) struct SessionIds(Vec<u64>);
+
+pub(crate) struct MultisigStore;
+impl Store for MultisigStore {
+    type Key = AccountId;
+    type Value = MultisigAccount;
+
+    fn name(&self) -> String {
+        MULTISIG_STORE_NAME.into()
+    }
+}
+
+impl MultisigStore {
+    pub fn new() -> Self {
+        MultisigStore {}
+    }
+}
+
+pub(crate) struct SessionStore;
+impl Store for SessionStore {
+    type Key = u64;
+    type Value = MultisigSession;
+
+    fn name(&self) -> String {
+        SESSION_STORE_NAME.into()
+    }
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        SessionStore {}
+    }
+}
+
+pub(crate) struct PendingStore;
+impl Store for PendingStore {
+    type Key = AccountId;
+    type Value = SessionIds;
+
+    fn name(&self) -> String {
+        PENDING_STORE_NAME.into()
+    }
+}
+
+impl PendingStore {
+    pub fn new() -> Self {
+        PendingStore {}
+    }
+
+    fn add(&self, multisig: AccountId, id: u64, view: &mut StoreView) {
+        let mut ids = self.get(multisig.clone(), view).unwrap_or_default();
+        ids.0.push(id);
+        self.put(multisig, ids, view)
+    }
+
+    fn remove_session(&self, multisig: AccountId, id: u64, view: &mut StoreView) {
+        let mut ids = self.get(multisig.clone(), view).unwrap_or_default();
+        ids.0.retain(|i| *i != id);
+        if ids.0.is_empty() {
+            self.remove(multisig, view)
+        } else {
+            self.put(multisig, ids, view)
+        }
+    }
+}
+
+pub(crate) struct SessionIdStore;
+impl Store for SessionIdStore {
+    type Key = String;
+    type Value = u64;
+
+    fn name(&self) -> String {
+        SESSION_ID_STORE_NAME.into()
+    }
+}
+
+impl SessionIdStore {
+    pub fn new() -> Self {
+        SessionIdStore {}
+    }
+
+    fn next(&self, view: &mut StoreView) -> u64 {
+        let id = self.get("next".into(), view).unwrap_or_default();
+        self.put("next".into(), id + 1, view);
+        id
+    }
+}
+
+/// Messages to the multisig AppModule
+#[derive(BorshDeserialize, BorshSerialize, RapidoMessages, Debug, PartialEq, Clone)]
+pub enum MultisigMsgs {
+    /// Create a multisig account
+    Create {
+        signers: Vec<AccountId>,
+        threshold: u8,
+    },
+    /// Propose a transaction for the multisig. The proposer must be a signer
+    /// and counts as the first approval
+    Propose {
+        multisig: AccountId,
+        app: String,
+        msg: Vec<u8>,
+    },
+    /// Approve a pending transaction. Executed once the threshold is met
+    Approve { session: u64 },
+    /// Cancel a pending transaction. Only the proposer can cancel
+    Cancel { session: u64 },
+}
+
+pub struct MultisigModule;
+
+impl MultisigModule {
+    // Execute the session if it has enough approvals, otherwise save it
+    fn execute_or_save(
+        &self,
+        ctx: &Context,
+        account: &MultisigAccount,
+        session: MultisigSession,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        if session.approvals.len() < account.threshold as usize {
+            SessionStore::new().put(session.id, session, view);
+            return Ok(());
+        }
+
+        ctx.dispatch_as(account.id.clone(), &session.app, &session.msg, view)?;
+        SessionStore::new().remove(session.id, view);
+        PendingStore::new().remove_session(account.id.clone(), session.id, view);
+        let id = session.id.to_string();
+        ctx.dispatch_event("executed", &[("session", id.as_str())])
+    }
+
+    fn load_session(
+        &self,
+        id: u64,
+        view: &StoreView,
+    ) -> anyhow::Result<(MultisigSession, MultisigAccount), anyhow::Error> {
+        let session = match SessionStore::new().get(id, view) {
+            Some(s) => s,
+            None => bail!("multisig session {} not found", id),
+        };
+        let account = match MultisigStore::new().get(session.multisig.clone(), view) {
+            Some(a) => a,
+            None => bail!("multisig account not found"),
+        };
+        Ok((session, account))
+    }
+}
+
+impl AppModule for MultisigModule {
+    fn name(&self) -> String {
+        MULTISIG_APP_NAME.into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        MultisigMsgs::dispatch(self, ctx, view)
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            // The multisig account for the id in the key
+            .add("/", |params, view| {
+                Ok(MultisigStore::new().query(params.key().to_vec(), view))
+            })
+            .add("/session/:id", |params, view| {
+                let id: u64 = params.require("id")?.parse()?;
+                Ok(SessionStore::new().query(id, view))
+            })
+            // The pending sessions of the multisig id in the key
+            .add("/pending", |params, view| {
+                let ids = PendingStore {}
+                    .query(params.key().to_vec(), view)
+                    .unwrap_or_default();
+                let sessions: Vec<MultisigSession> = ids
+                    .0
+                    .into_iter()
+                    .filter_map(|id| SessionStore::new().query(id, view))
+                    .collect();
+                Ok(Some(sessions))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/".into(), "/session/:id".into(), "/pending".into()]
+    }
+}
+
+impl MultisigMsgsHandler for MultisigModule {
+    fn create(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        signers: Vec<AccountId>,
+        threshold: u8,
+    ) -> Result<(), anyhow::Error> {
+        let account = MultisigAccount::create(signers, threshold)?;
+        let store = MultisigStore {};
+        ensure!(
+            store.get(account.id.clone(), view).is_none(),
+            "multisig account already exists"
+        );
+
+        let id = String::from_utf8(account.id.clone())?;
+        store.put(account.id.clone(), account, view);
+        ctx.dispatch_event("create", &[("account", id.as_str())])
+    }
+
+    fn propose(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        multisig: AccountId,
+        app: String,
+        msg: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        let account = match MultisigStore::new().get(multisig.clone(), view) {
+            Some(a) => a,
+            None => bail!("multisig account not found"),
+        };
+        ensure!(account.is_signer(&ctx.sender()), "not a signer");
+
+        let id = SessionIdStore::new().next(view);
+        PendingStore::new().add(multisig.clone(), id, view);
+        let session = MultisigSession {
+            id,
+            multisig,
+            proposer: ctx.sender(),
+            app,
+            msg,
+            approvals: vec![ctx.sender()],
+        };
+        ctx.dispatch_event("propose", &[("session", id.to_string().as_str())])?;
+        self.execute_or_save(ctx, &account, session, view)
+    }
+
+    fn approve(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        session: u64,
+    ) -> Result<(), anyhow::Error> {
+        let (mut session, account) = self.load_session(session, view)?;
+        let signer = ctx.sender();
+        ensure!(account.is_signer(&signer), "not a signer");
+        ensure!(!session.approvals.contains(&signer), "already approved");
+
+        session.approvals.push(signer);
+        self.execute_or_save(ctx, &account, session, view)
+    }
+
+    fn cancel(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        session: u64,
+    ) -> Result<(), anyhow::Error> {
+        let (session, account) = self.load_session(session, view)?;
+        ensure!(
+            session.proposer == ctx.sender(),
+            "only the proposer can cancel"
+        );
+
+        SessionStore::new().remove(session.id, view);
+        PendingStore::new().remove_session(account.id, session.id, view);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    // Records the sender of the last tx
+    struct EchoApp;
+    pub(crate) struct LastSenderStore;
+    impl Store for LastSenderStore {
+        type Key = String;
+        type Value = Vec<u8>;
+
+        fn name(&self) -> String {
+            "echo.last".into()
+        }
+    }
+
+    impl AppModule for EchoApp {
+        fn name(&self) -> String {
+            "echo".into()
+        }
+
+        fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+            LastSenderStore.put("last".into(), ctx.sender(), view);
+            Ok(())
+        }
+
+        fn handle_query(
+            &self,
+            _path: &str,
+            _key: Vec<u8>,
+            view: &QueryView,
+        ) -> Result<QueryResponse, anyhow::Error> {
+            match LastSenderStore.query("last".into(), view) {
+                Some(sender) => Ok(sender.into()),
+                None => bail!("no tx"),
+            }
+        }
+    }
+
+    fn msig_tx(sender: &str, msg: MultisigMsgs) -> SignedTransaction {
+        TxBuilder::new(MULTISIG_APP_NAME)
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_multisig() {
+        let app = AppBuilder::new()
+            .with_app(MultisigModule {})
+            .with_app(EchoApp {})
+            .grant(MULTISIG_APP_NAME, "echo::dispatch_as");
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let signers: Vec<AccountId> = vec!["bob".into(), "alice".into(), "carol".into()];
+        assert!(MultisigAccount::create(signers.clone(), 4).is_err());
+        let account = MultisigAccount::create(signers.clone(), 2).unwrap();
+
+        let create = MultisigMsgs::Create {
+            signers,
+            threshold: 2,
+        };
+        assert!(tester.commit_tx(&[&msig_tx("bob", create)]).is_ok());
+
+        // Only signers can propose
+        let propose = MultisigMsgs::Propose {
+            multisig: account.id.clone(),
+            app: "echo".into(),
+            msg: vec![1],
+        };
+        assert!(tester
+            .commit_tx(&[&msig_tx("dave", propose.clone())])
+            .is_err());
+        assert!(tester.commit_tx(&[&msig_tx("bob", propose)]).is_ok());
+
+        let pending: Vec<MultisigSession> = tester
+            .query_as("rapido.multisig/pending", account.id.clone())
+            .unwrap();
+        assert_eq!(1, pending.len());
+        assert_eq!(vec![b"bob".to_vec()], pending[0].approvals);
+        assert!(tester.query("echo", vec![]).is_err());
+
+        // Can't approve twice
+        let approve = MultisigMsgs::Approve { session: 0 };
+        assert!(tester
+            .commit_tx(&[&msig_tx("bob", approve.clone())])
+            .is_err());
+
+        // Threshold met: executed as the multisig
+        assert!(tester.commit_tx(&[&msig_tx("carol", approve)]).is_ok());
+        assert_eq!(account.id, tester.query("echo", vec![]).unwrap());
+        let pending: Vec<MultisigSession> = tester
+            .query_as("rapido.multisig/pending", account.id.clone())
+            .unwrap();
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn test_multisig_requires_capability() {
+        let app = AppBuilder::new()
+            .with_app(MultisigModule {})
+            .with_app(EchoApp {});
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let signers: Vec<AccountId> = vec!["bob".into()];
+        let account = MultisigAccount::create(signers.clone(), 1).unwrap();
+        let create = MultisigMsgs::Create {
+            signers,
+            threshold: 1,
+        };
+        assert!(tester.commit_tx(&[&msig_tx("bob", create)]).is_ok());
+
+        let propose = MultisigMsgs::Propose {
+            multisig: account.id,
+            app: "echo".into(),
+            msg: vec![1],
+        };
+        assert!(tester.commit_tx(&[&msig_tx("bob", propose)]).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+//!
+//! Guardian based account recovery.  The owner of an account designates guardian
+//! accounts and a threshold.  If the owner loses their key, a guardian proposes a
+//! new public key and the other guardians approve it.  Once `threshold` guardians
+//! have approved and the delay window has passed, a guardian executes the recovery
+//! and the account's public key is rotated.  The owner can cancel a recovery during
+//! the delay window.
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{AccountId, Context, RapidoEvent, Store, StoreValue, StoreView};
+
+use crate::{keys::rotate_key, AccountStore, PublicKeyBytes};
+
+const GUARDIAN_STORE_NAME: &str = "rapido.account.guardians";
+const RECOVERY_STORE_NAME: &str = "rapido.account.recovery";
+
+/// Maximum number of guardians of an account
+pub const MAX_GUARDIANS: usize = 10;
+
+/// The guardians of an account
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, PartialEq, Clone)]
+pub struct Guardians {
+    pub guardians: Vec<AccountId>,
+    pub threshold: u8,
+}
+
+impl Guardians {
+    pub fn new(
+        account: &[u8],
+        guardians: Vec<AccountId>,
+        threshold: u8,
+    ) -> anyhow::Result<Self, anyhow::Error> {
+        ensure!(
+            !guardians.is_empty() && guardians.len() <= MAX_GUARDIANS,
+            "an account can have 1 to {} guardians",
+            MAX_GUARDIANS
+        );
+        ensure!(
+            threshold > 0 && threshold as usize <= guardians.len(),
+            "threshold must be between 1 and the number of guardians"
+        );
+        ensure!(
+            !guardians.iter().any(|g| g.as_slice() == account),
+            "an account can't be its own guardian"
+        );
+        let mut sorted = guardians.clone();
+        sorted.sort();
+        sorted.dedup();
+        ensure!(sorted.len() == guardians.len(), "duplicate guardian");
+        Ok(Self {
+            guardians,
+            threshold,
+        })
+    }
+
+    pub fn is_guardian(&self, account: &[u8]) -> bool {
+        self.guardians.iter().any(|g| g.as_slice() == account)
+    }
+}
+
+/// A pending recovery of an account
+#[derive(BorshDeserialize, BorshSerialize, StoreValue, Debug, PartialEq, Clone)]
+pub struct PendingRecovery {
+    pub account: AccountId,
+    pub new_pubkey: PublicKeyBytes,
+    pub approvals: Vec<AccountId>,
+    /// Height the recovery was proposed
+    pub proposed: i64,
+}
+
+impl PendingRecovery {
+    // Approvals of accounts that are still guardians
+    fn approved(&self, guardians: &Guardians) -> usize {
+        self.approvals
+            .iter()
+            .filter(|a| guardians.is_guardian(a))
+            .count()
+    }
+}
+
+/// Guardians keyed by account
+pub(crate) struct GuardianStore;
+impl Store for GuardianStore {
+    type Key = AccountId;
+    type Value = Guardians;
+
+    fn name(&self) -> String {
+        GUARDIAN_STORE_NAME.into()
+    }
+}
+
+impl GuardianStore {
+    pub fn new() -> Self {
+        GuardianStore {}
+    }
+}
+
+/// Pending recoveries keyed by account
+pub(crate) struct RecoveryStore;
+impl Store for RecoveryStore {
+    type Key = AccountId;
+    type Value = PendingRecovery;
+
+    fn name(&self) -> String {
+        RECOVERY_STORE_NAME.into()
+    }
+}
+
+impl RecoveryStore {
+    pub fn new() -> Self {
+        RecoveryStore {}
+    }
+}
+
+/// Emitted as 'rapido.account.recovery_proposed'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct RecoveryProposedEvent {
+    pub account: AccountId,
+    pub guardian: AccountId,
+}
+
+/// Emitted as 'rapido.account.recovery_approved'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct RecoveryApprovedEvent {
+    pub account: AccountId,
+    pub guardian: AccountId,
+}
+
+/// Emitted as 'rapido.account.recovered'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct RecoveredEvent {
+    pub account: AccountId,
+}
+
+/// Emitted as 'rapido.account.recovery_cancelled'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct RecoveryCancelledEvent {
+    pub account: AccountId,
+}
+
+// Load the guardians of the account and check the sender is one
+fn sender_guardians(
+    ctx: &Context,
+    account: &AccountId,
+    view: &StoreView,
+) -> anyhow::Result<Guardians, anyhow::Error> {
+    let guardians = match GuardianStore::new().get(account.clone(), view) {
+        Some(g) => g,
+        None => bail!("account has no guardians"),
+    };
+    ensure!(guardians.is_guardian(&ctx.sender()), "not a guardian");
+    Ok(guardians)
+}
+
+fn load_recovery(
+    account: &AccountId,
+    view: &StoreView,
+) -> anyhow::Result<PendingRecovery, anyhow::Error> {
+    match RecoveryStore::new().get(account.clone(), view) {
+        Some(r) => Ok(r),
+        None => bail!("no pending recovery"),
+    }
+}
+
+/// Set the guardians of the sender's account. No guardians removes them
+pub(crate) fn set_guardians(
+    ctx: &Context,
+    guardians: Vec<AccountId>,
+    threshold: u8,
+    view: &mut StoreView,
+) -> anyhow::Result<(), anyhow::Error> {
+    let account = ctx.sender();
+    ensure!(
+        AccountStore::new().get(account.clone(), view).is_some(),
+        "user not found"
+    );
+    let store = GuardianStore::new();
+    if guardians.is_empty() {
+        store.remove(account, view);
+        return Ok(());
+    }
+    let guardians = Guardians::new(&account, guardians, threshold)?;
+    store.put(account, guardians, view);
+    Ok(())
+}
+
+/// A guardian proposes a new public key for the account
+pub(crate) fn propose(
+    ctx: &Context,
+    account: AccountId,
+    new_pubkey: PublicKeyBytes,
+    view: &mut StoreView,
+) -> anyhow::Result<(), anyhow::Error> {
+    sender_guardians(ctx, &account, view)?;
+    ensure!(
+        AccountStore::new().get(account.clone(), view).is_some(),
+        "user not found"
+    );
+    let store = RecoveryStore::new();
+    ensure!(
+        store.get(account.clone(), view).is_none(),
+        "a recovery is pending"
+    );
+
+    let recovery = PendingRecovery {
+        account: account.clone(),
+        new_pubkey,
+        approvals: vec![ctx.sender()],
+        proposed: ctx.height(),
+    };
+    store.put(account.clone(), recovery, view);
+    ctx.emit(RecoveryProposedEvent {
+        account,
+        guardian: ctx.sender(),
+    })
+}
+
+/// A guardian approves the pending recovery of the account
+pub(crate) fn approve(
+    ctx: &Context,
+    account: AccountId,
+    view: &mut StoreView,
+) -> anyhow::Result<(), anyhow::Error> {
+    sender_guardians(ctx, &account, view)?;
+    let mut recovery = load_recovery(&account, view)?;
+    ensure!(
+        !recovery.approvals.contains(&ctx.sender()),
+        "already approved"
+    );
+
+    recovery.approvals.push(ctx.sender());
+    RecoveryStore::new().put(account.clone(), recovery, view);
+    ctx.emit(RecoveryApprovedEvent {
+        account,
+        guardian: ctx.sender(),
+    })
+}
+
+/// A guardian executes the approved recovery after the delay window
+pub(crate) fn execute(
+    ctx: &Context,
+    account: AccountId,
+    delay: i64,
+    view: &mut StoreView,
+) -> anyhow::Result<(), anyhow::Error> {
+    let guardians = sender_guardians(ctx, &account, view)?;
+    let recovery = load_recovery(&account, view)?;
+    ensure!(
+        recovery.approved(&guardians) >= guardians.threshold as usize,
+        "recovery needs {} approvals",
+        guardians.threshold
+    );
+    ensure!(
+        ctx.height() >= recovery.proposed + delay,
+        "recovery can be executed at height {}",
+        recovery.proposed + delay
+    );
+
+    let store = AccountStore::new();
+    let acct = match store.get(account.clone(), view) {
+        Some(a) => a,
+        None => bail!("user not found"),
+    };
+    let updated = rotate_key(&acct, recovery.new_pubkey, ctx.height(), true, view)?;
+    store.put(updated.id(), updated, view);
+    RecoveryStore::new().remove(account.clone(), view);
+    ctx.emit(RecoveredEvent { account })
+}
+
+/// The owner cancels a pending recovery of their account
+pub(crate) fn cancel(ctx: &Context, view: &mut StoreView) -> anyhow::Result<(), anyhow::Error> {
+    let account = ctx.sender();
+    load_recovery(&account, view)?;
+    RecoveryStore::new().remove(account.clone(), view);
+    ctx.emit(RecoveryCancelledEvent { account })
+}
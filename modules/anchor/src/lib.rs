@@ -0,0 +1,253 @@
+//!
+//! Content anchoring (proof of existence).  Anchor the hash of a document with
+//! optional metadata.  The anchor records the owner and the block it was anchored
+//! in; a hash can only be anchored once.  The `/proof` query returns the anchor
+//! with a Merkle proof of it (or of its absence) in the committed state:
+//! ```ignore
+//! let hash = Sha256::digest(&document);
+//! let tx = TxBuilder::new(ANCHOR_APP_NAME)
+//!     .sender("bob")
+//!     .msg(AnchorMsgs::Anchor { hash: hash.to_vec(), metadata: "contract.pdf".into() })
+//!     .build()?;
+//! ```
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreValue, StoreView,
+};
+
+/// Name of the anchor AppModule
+pub const ANCHOR_APP_NAME: &str = "rapido.anchor";
+/// Name of the store of anchors, to verify query proofs
+pub const ANCHOR_STORE_NAME: &str = "rapido.anchor.anchors";
+
+/// Minimum length of an anchored hash
+pub const MIN_HASH_LENGTH: usize = 16;
+/// Maximum length of an anchored hash
+pub const MAX_HASH_LENGTH: usize = 64;
+/// Maximum length of the metadata
+pub const MAX_METADATA_LENGTH: usize = 256;
+
+/// An anchored hash
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct Anchor {
+    pub owner: AccountId,
+    /// Height of the block the hash was anchored in
+    pub height: i64,
+    /// Time of the block the hash was anchored in
+    pub time: i64,
+    pub metadata: String,
+}
+
+/// Anchors keyed by hash
+pub(crate) struct AnchorStore;
+impl Store for AnchorStore {
+    type Key = Vec<u8>;
+    type Value = Anchor;
+
+    fn name(&self) -> String {
+        ANCHOR_STORE_NAME.into()
+    }
+}
+
+impl AnchorStore {
+    pub fn new() -> Self {
+        AnchorStore {}
+    }
+}
+
+/// Messages to the anchor AppModule
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum AnchorMsgs {
+    /// Anchor the hash, owned by the sender
+    Anchor { hash: Vec<u8>, metadata: String },
+}
+
+/// Emitted as 'rapido.anchor.anchored'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct AnchoredEvent {
+    pub hash: String,
+    pub owner: AccountId,
+}
+
+/// The anchor AppModule. Add it via `AppBuilder::with_app`.
+#[derive(Default)]
+pub struct AnchorModule;
+
+impl AnchorModule {
+    pub fn new() -> Self {
+        AnchorModule {}
+    }
+}
+
+// Decode a hex hash from a query path
+fn decode_hash(hash: &str) -> anyhow::Result<Vec<u8>, anyhow::Error> {
+    match hex::decode(hash) {
+        Ok(raw) => Ok(raw),
+        Err(_) => bail!("bad hash hex: {}", hash),
+    }
+}
+
+impl AppModule for AnchorModule {
+    fn name(&self) -> String {
+        ANCHOR_APP_NAME.into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        AnchorMsgs::dispatch(self, ctx, view)
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/", |params, view| {
+                Ok(AnchorStore::new().query(params.key().to_vec(), view))
+            })
+            .add("/exists/:hash", |params, view| {
+                let hash = decode_hash(params.require("hash")?)?;
+                Ok(Some(AnchorStore::new().query(hash, view).is_some()))
+            })
+            .add("/proof", |params, view| {
+                let store = AnchorStore::new();
+                let hash = params.key().to_vec();
+                let anchor = store.query(hash.clone(), view);
+                let proof = store.query_proof(hash, view)?;
+                Ok(Some(QueryResponse::encode(&anchor)?.with_proof(proof)))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/".into(), "/exists/:hash".into(), "/proof".into()]
+    }
+}
+
+impl AnchorMsgsHandler for AnchorModule {
+    fn anchor(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        hash: Vec<u8>,
+        metadata: String,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(
+            hash.len() >= MIN_HASH_LENGTH && hash.len() <= MAX_HASH_LENGTH,
+            "hash must be {} to {} bytes",
+            MIN_HASH_LENGTH,
+            MAX_HASH_LENGTH
+        );
+        ensure!(
+            metadata.len() <= MAX_METADATA_LENGTH,
+            "metadata is longer than {}",
+            MAX_METADATA_LENGTH
+        );
+        let store = AnchorStore::new();
+        ensure!(
+            !store.contains_key(hash.clone(), view),
+            "hash is already anchored"
+        );
+
+        let owner = ctx.sender();
+        store.put(
+            hash.clone(),
+            Anchor {
+                owner: owner.clone(),
+                height: ctx.height(),
+                time: ctx.block_time(),
+                metadata,
+            },
+            view,
+        );
+        ctx.emit(AnchoredEvent {
+            hash: hex::encode(hash),
+            owner,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{assert_proof_valid, AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    fn anchor_tx(sender: &str, hash: &[u8]) -> SignedTransaction {
+        TxBuilder::new(ANCHOR_APP_NAME)
+            .sender(sender)
+            .msg(AnchorMsgs::Anchor {
+                hash: hash.to_vec(),
+                metadata: "contract.pdf".into(),
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_anchor() {
+        let app = AppBuilder::new().with_app(AnchorModule::new());
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let hash = [7u8; 32];
+        assert!(tester.commit_tx(&[&anchor_tx("bob", &hash)]).is_ok());
+        // Only once
+        assert!(tester.commit_tx(&[&anchor_tx("alice", &hash)]).is_err());
+        // Too short
+        assert!(tester.commit_tx(&[&anchor_tx("alice", &[1u8; 8])]).is_err());
+
+        let anchor: Anchor = tester.query_as(ANCHOR_APP_NAME, hash.to_vec()).unwrap();
+        assert_eq!(b"bob".to_vec(), anchor.owner);
+        assert_eq!("contract.pdf", anchor.metadata);
+
+        let exists: bool = tester
+            .query_as(
+                &format!("{}/exists/{}", ANCHOR_APP_NAME, hex::encode(hash)),
+                vec![],
+            )
+            .unwrap();
+        assert!(exists);
+
+        let proved: Option<Anchor> = tester
+            .query_as(&format!("{}/proof", ANCHOR_APP_NAME), hash.to_vec())
+            .unwrap();
+        assert_eq!(Some(anchor.clone()), proved);
+        let absent: Option<Anchor> = tester
+            .query_as(&format!("{}/proof", ANCHOR_APP_NAME), vec![1u8; 32])
+            .unwrap();
+        assert_eq!(None, absent);
+
+        // The proofs check out against the apphash
+        let apphash = tester.app_hash();
+        let proof = tester
+            .query_with_proof(&format!("{}/proof", ANCHOR_APP_NAME), hash.to_vec())
+            .unwrap();
+        assert_eq!(
+            Some(anchor.try_to_vec().unwrap()),
+            assert_proof_valid(ANCHOR_STORE_NAME, hash.to_vec(), &proof, &apphash)
+        );
+        let absent = tester
+            .query_with_proof(&format!("{}/proof", ANCHOR_APP_NAME), vec![1u8; 32])
+            .unwrap();
+        assert_eq!(
+            None,
+            assert_proof_valid(ANCHOR_STORE_NAME, vec![1u8; 32], &absent, &apphash)
+        );
+        // A proof of another key doesn't prove the value, or the absence, of the key
+        let result = std::panic::catch_unwind(|| {
+            assert_proof_valid(ANCHOR_STORE_NAME, vec![1u8; 32], &proof, &apphash)
+        });
+        assert!(result.is_err());
+        let result = std::panic::catch_unwind(|| {
+            assert_proof_valid(ANCHOR_STORE_NAME, hash.to_vec(), &absent, &apphash)
+        });
+        assert!(result.is_err());
+        assert!(tester
+            .query_with_proof(ANCHOR_APP_NAME, hash.to_vec())
+            .is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//!
+//! A faucet for development networks.  Mints tokens to any account that asks, so
+//! example apps and test networks don't need genesis balances.  What an account can
+//! request in total and what is minted in a block are capped.  Only compiled with
+//! the `faucet` feature; don't enable it on a production network.
+//! ```ignore
+//! AppBuilder::new()
+//!     .with_app(BankModule::new())
+//!     .with_app(FaucetModule::new().per_account(1_000).per_block(10_000))
+//!     .grant(FAUCET_APP_NAME, "rapido.bank::mint")
+//! ```
+//!
+use anyhow::ensure;
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreValue, StoreView,
+};
+
+use crate::{BankMsgs, BANK_APP_NAME};
+
+/// Name of the faucet AppModule
+pub const FAUCET_APP_NAME: &str = "rapido.faucet";
+const DRIPPED_STORE_NAME: &str = "rapido.faucet.dripped";
+const BLOCK_STORE_NAME: &str = "rapido.faucet.block";
+
+// Key of the BlockTotal in its store
+const BLOCK_KEY: &str = "block";
+
+/// Default total an account can request
+pub const DEFAULT_PER_ACCOUNT: u64 = 1_000;
+/// Default total minted in a block
+pub const DEFAULT_PER_BLOCK: u64 = 10_000;
+
+/// Total requested keyed by account
+pub(crate) struct DrippedStore;
+impl Store for DrippedStore {
+    type Key = AccountId;
+    type Value = u64;
+
+    fn name(&self) -> String {
+        DRIPPED_STORE_NAME.into()
+    }
+}
+
+impl DrippedStore {
+    pub fn new() -> Self {
+        DrippedStore {}
+    }
+}
+
+// Minted in the last block the faucet was used
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, Default)]
+pub(crate) struct BlockTotal {
+    height: i64,
+    minted: u64,
+}
+
+pub(crate) struct BlockTotalStore;
+impl Store for BlockTotalStore {
+    type Key = String;
+    type Value = BlockTotal;
+
+    fn name(&self) -> String {
+        BLOCK_STORE_NAME.into()
+    }
+}
+
+impl BlockTotalStore {
+    pub fn new() -> Self {
+        BlockTotalStore {}
+    }
+
+    // Minted so far at the height
+    fn minted(&self, height: i64, view: &StoreView) -> u64 {
+        match self.get(BLOCK_KEY.into(), view) {
+            Some(total) if total.height == height => total.minted,
+            _ => 0,
+        }
+    }
+}
+
+/// Messages to the faucet
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum FaucetMsgs {
+    /// Mint tokens to the sender
+    Request { amount: u64 },
+}
+
+/// Emitted as 'rapido.faucet.drip'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct DripEvent {
+    pub to: AccountId,
+    pub amount: u64,
+}
+
+/// The faucet AppModule. Add it via `AppBuilder::with_app` and grant it the
+/// capability `rapido.bank::mint`.
+pub struct FaucetModule {
+    per_account: u64,
+    per_block: u64,
+}
+
+impl Default for FaucetModule {
+    fn default() -> Self {
+        Self {
+            per_account: DEFAULT_PER_ACCOUNT,
+            per_block: DEFAULT_PER_BLOCK,
+        }
+    }
+}
+
+impl FaucetModule {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Total an account can request. Default: 1000
+    pub fn per_account(mut self, amount: u64) -> Self {
+        self.per_account = amount;
+        self
+    }
+
+    /// Total minted in a block. Default: 10000
+    pub fn per_block(mut self, amount: u64) -> Self {
+        self.per_block = amount;
+        self
+    }
+}
+
+impl AppModule for FaucetModule {
+    fn name(&self) -> String {
+        FAUCET_APP_NAME.into()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        vec![BANK_APP_NAME.into()]
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        FaucetMsgs::dispatch(self, ctx, view)
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        let per_account = self.per_account;
+        QueryRouter::new()
+            .add("/remaining", move |params, view| {
+                let dripped = DrippedStore::new()
+                    .query(params.key().to_vec(), view)
+                    .unwrap_or_default();
+                Ok(Some(per_account.saturating_sub(dripped)))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/remaining".into()]
+    }
+}
+
+impl FaucetMsgsHandler for FaucetModule {
+    fn request(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        amount: u64,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(amount > 0, "amount must be greater than 0");
+        let to = ctx.sender();
+
+        let dripped_store = DrippedStore::new();
+        let dripped = dripped_store.get(to.clone(), view).unwrap_or_default();
+        ensure!(
+            amount <= self.per_account.saturating_sub(dripped),
+            "the account can request {} more",
+            self.per_account.saturating_sub(dripped)
+        );
+
+        let block_store = BlockTotalStore::new();
+        let minted = block_store.minted(ctx.height(), view);
+        ensure!(
+            amount <= self.per_block.saturating_sub(minted),
+            "the faucet can mint {} more in this block",
+            self.per_block.saturating_sub(minted)
+        );
+
+        dripped_store.put(to.clone(), dripped + amount, view);
+        block_store.put(
+            BLOCK_KEY.into(),
+            BlockTotal {
+                height: ctx.height(),
+                minted: minted + amount,
+            },
+            view,
+        );
+        ctx.dispatch(
+            BANK_APP_NAME,
+            BankMsgs::Mint {
+                to: to.clone(),
+                amount,
+            },
+            view,
+        )?;
+        ctx.emit(DripEvent { to, amount })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BankModule;
+    use rapido_core::{AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    fn request(sender: &str, amount: u64) -> SignedTransaction {
+        TxBuilder::new(FAUCET_APP_NAME)
+            .sender(sender)
+            .msg(FaucetMsgs::Request { amount })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_faucet() {
+        let app = AppBuilder::new()
+            .with_app(BankModule::new())
+            .with_app(FaucetModule::new().per_account(100).per_block(150))
+            .grant(FAUCET_APP_NAME, "rapido.bank::mint");
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        assert!(tester.commit_tx(&[&request("bob", 60)]).is_ok());
+        let balance: u64 = tester.query_as(BANK_APP_NAME, b"bob".to_vec()).unwrap();
+        assert_eq!(60, balance);
+        let remaining: u64 = tester
+            .query_as(&format!("{}/remaining", FAUCET_APP_NAME), b"bob".to_vec())
+            .unwrap();
+        assert_eq!(40, remaining);
+
+        // Over the account cap
+        assert!(tester.commit_tx(&[&request("bob", 41)]).is_err());
+        assert!(tester.commit_tx(&[&request("alice", 0)]).is_err());
+
+        // Over the block cap
+        assert!(tester.commit_tx(&[&request("alice", 100)]).is_err());
+        assert!(tester.commit_tx(&[&request("alice", 90)]).is_ok());
+    }
+}
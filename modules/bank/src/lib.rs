@@ -0,0 +1,612 @@
+//!
+//! A fungible token. Balances are keyed by AccountId and the total supply is
+//! tracked as tokens are minted and burned.  Other AppModules (fees, staking, ...)
+//! move tokens by dispatching `BankMsgs` and read balances via the query paths:
+//! ```ignore
+//! ctx.dispatch(BANK_APP_NAME, BankMsgs::Transfer { to, amount }, view)?;
+//! let balance: u64 = ctx.module(BANK_APP_NAME)?.query_as("/", account, view)?;
+//! ```
+//!
+use std::collections::BTreeMap;
+
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreView,
+};
+
+#[cfg(feature = "faucet")]
+pub mod faucet;
+
+/// Name of the bank AppModule
+pub const BANK_APP_NAME: &str = "rapido.bank";
+const BALANCE_STORE_NAME: &str = "rapido.bank.balances";
+const SUPPLY_STORE_NAME: &str = "rapido.bank.supply";
+const ALLOWANCE_STORE_NAME: &str = "rapido.bank.allowances";
+
+// Key of the total supply in the supply store
+const SUPPLY_KEY: &str = "supply";
+
+/// Balances keyed by account
+pub(crate) struct BalanceStore;
+impl Store for BalanceStore {
+    type Key = AccountId;
+    type Value = u64;
+
+    fn name(&self) -> String {
+        BALANCE_STORE_NAME.into()
+    }
+}
+
+impl BalanceStore {
+    pub fn new() -> Self {
+        BalanceStore {}
+    }
+
+    /// Return the balance of the account. Zero if it has none
+    pub fn balance(&self, account: AccountId, view: &StoreView) -> u64 {
+        self.get(account, view).unwrap_or_default()
+    }
+
+    // Committed balance, for queries
+    fn query_balance(&self, account: AccountId, view: &StoreView) -> u64 {
+        self.query(account, view).unwrap_or_default()
+    }
+
+    fn set_balance(&self, account: AccountId, amount: u64, view: &mut StoreView) {
+        if amount == 0 {
+            self.remove(account, view)
+        } else {
+            self.put(account, amount, view)
+        }
+    }
+
+    fn credit(
+        &self,
+        account: AccountId,
+        amount: u64,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let balance = self.balance(account.clone(), view);
+        let updated = match balance.checked_add(amount) {
+            Some(b) => b,
+            None => bail!("balance overflow"),
+        };
+        self.set_balance(account, updated, view);
+        Ok(())
+    }
+
+    fn debit(
+        &self,
+        account: AccountId,
+        amount: u64,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let balance = self.balance(account.clone(), view);
+        ensure!(balance >= amount, "insufficient balance");
+        self.set_balance(account, balance - amount, view);
+        Ok(())
+    }
+}
+
+/// The total supply
+pub(crate) struct SupplyStore;
+impl Store for SupplyStore {
+    type Key = String;
+    type Value = u64;
+
+    fn name(&self) -> String {
+        SUPPLY_STORE_NAME.into()
+    }
+}
+
+impl SupplyStore {
+    pub fn new() -> Self {
+        SupplyStore {}
+    }
+
+    pub fn supply(&self, view: &StoreView) -> u64 {
+        self.get(SUPPLY_KEY.into(), view).unwrap_or_default()
+    }
+
+    // Committed supply, for queries
+    fn query_supply(&self, view: &StoreView) -> u64 {
+        self.query(SUPPLY_KEY.into(), view).unwrap_or_default()
+    }
+
+    fn increase(&self, amount: u64, view: &mut StoreView) -> anyhow::Result<(), anyhow::Error> {
+        let updated = match self.supply(view).checked_add(amount) {
+            Some(s) => s,
+            None => bail!("supply overflow"),
+        };
+        self.put(SUPPLY_KEY.into(), updated, view);
+        Ok(())
+    }
+
+    fn decrease(&self, amount: u64, view: &mut StoreView) -> anyhow::Result<(), anyhow::Error> {
+        let supply = self.supply(view);
+        ensure!(supply >= amount, "supply underflow");
+        self.put(SUPPLY_KEY.into(), supply - amount, view);
+        Ok(())
+    }
+}
+
+/// Allowances keyed by (owner, spender)
+pub(crate) struct AllowanceStore;
+impl Store for AllowanceStore {
+    type Key = (AccountId, AccountId);
+    type Value = u64;
+
+    fn name(&self) -> String {
+        ALLOWANCE_STORE_NAME.into()
+    }
+}
+
+impl AllowanceStore {
+    pub fn new() -> Self {
+        AllowanceStore {}
+    }
+
+    /// Return what the spender can transfer from the owner. Zero if nothing
+    pub fn allowance(&self, owner: AccountId, spender: AccountId, view: &StoreView) -> u64 {
+        self.get((owner, spender), view).unwrap_or_default()
+    }
+
+    fn set_allowance(
+        &self,
+        owner: AccountId,
+        spender: AccountId,
+        amount: u64,
+        view: &mut StoreView,
+    ) {
+        if amount == 0 {
+            self.remove((owner, spender), view)
+        } else {
+            self.put((owner, spender), amount, view)
+        }
+    }
+}
+
+/// Messages to the bank. Tokens are always moved from the sender
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum BankMsgs {
+    /// Move tokens from the sender to another account
+    Transfer { to: AccountId, amount: u64 },
+    /// Create tokens. See `BankModule::with_minter`
+    Mint { to: AccountId, amount: u64 },
+    /// Destroy tokens of the sender
+    Burn { amount: u64 },
+    /// Move tokens from the account of the AppModule dispatching the message.
+    /// The account of an AppModule is its name. Used to escrow tokens, e.g.
+    /// governance deposits. Only accepted via `Context::dispatch`
+    SendFromModule { to: AccountId, amount: u64 },
+    /// Allow the spender to transfer up to the amount from the sender's account.
+    /// Replaces any allowance; an amount of 0 removes it
+    Approve { spender: AccountId, amount: u64 },
+    /// Move tokens from the owner to another account, spending the allowance the
+    /// owner approved for the sender
+    TransferFrom {
+        owner: AccountId,
+        to: AccountId,
+        amount: u64,
+    },
+}
+
+/// Emitted as 'rapido.bank.transfer'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct TransferEvent {
+    pub from: AccountId,
+    pub to: AccountId,
+    pub amount: u64,
+}
+
+/// Emitted as 'rapido.bank.mint'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct MintEvent {
+    pub to: AccountId,
+    pub amount: u64,
+}
+
+/// Emitted as 'rapido.bank.approval'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct ApprovalEvent {
+    pub owner: AccountId,
+    pub spender: AccountId,
+    pub amount: u64,
+}
+
+/// Emitted as 'rapido.bank.burn'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct BurnEvent {
+    pub from: AccountId,
+    pub amount: u64,
+}
+
+/// The bank AppModule. Add it via `AppBuilder::with_app`.
+/// Tokens can be minted by the minter account (if set) or by AppModules granted
+/// the capability `rapido.bank::mint`.
+#[derive(Default)]
+pub struct BankModule {
+    minter: Option<AccountId>,
+    lock_providers: Vec<String>,
+}
+
+impl BankModule {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allow the account to mint tokens via a transaction
+    pub fn with_minter(mut self, account: impl Into<AccountId>) -> Self {
+        self.minter = Some(account.into());
+        self
+    }
+
+    /// Ask the AppModule how much of an account's balance is locked, e.g. unvested
+    /// tokens. Transfers and burns can't spend locked tokens.  The AppModule must
+    /// answer the query '/locked', with the account as the key, with the locked
+    /// amount (`u64`) at the height of the `QueryView`.
+    pub fn with_lock_provider(mut self, app: &str) -> Self {
+        self.lock_providers.push(app.into());
+        self
+    }
+
+    // Check the account can spend the amount without dipping into locked tokens
+    fn check_spendable(
+        &self,
+        ctx: &Context,
+        account: AccountId,
+        amount: u64,
+        view: &StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let balance = BalanceStore::new().balance(account.clone(), view);
+        ensure!(balance >= amount, "insufficient balance");
+
+        let mut locked = 0u64;
+        for app in &self.lock_providers {
+            let amount: u64 = ctx
+                .module(app)?
+                .query_as("/locked", account.clone(), view)?;
+            locked = locked.saturating_add(amount);
+        }
+        ensure!(
+            balance - amount >= locked,
+            "insufficient balance: {} is locked",
+            locked
+        );
+        Ok(())
+    }
+
+    fn check_minter(&self, ctx: &Context) -> anyhow::Result<(), anyhow::Error> {
+        match (ctx.caller(), &self.minter) {
+            (None, Some(minter)) if ctx.sender() == *minter => Ok(()),
+            (None, _) => bail!("not authorized to mint"),
+            (Some(_), _) => ctx.require_capability("mint"),
+        }
+    }
+}
+
+impl AppModule for BankModule {
+    fn name(&self) -> String {
+        BANK_APP_NAME.into()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        self.lock_providers.clone()
+    }
+
+    // Genesis is a map of account to balance: {"bob": 100, "alice": 50}
+    fn initialize(&self, genesis: &[u8], view: &mut StoreView) -> Result<(), anyhow::Error> {
+        if genesis.is_empty() {
+            return Ok(());
+        }
+        let balances: BTreeMap<String, u64> = serde_json::from_slice(genesis)?;
+        let store = BalanceStore::new();
+        let supply = SupplyStore::new();
+        for (account, amount) in balances {
+            store.credit(account.into_bytes(), amount, view)?;
+            supply.increase(amount, view)?;
+        }
+        Ok(())
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        BankMsgs::dispatch(self, ctx, view)
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/", |params, view| {
+                Ok(Some(
+                    BalanceStore::new().query_balance(params.key().to_vec(), view),
+                ))
+            })
+            .add("/balance/:account", |params, view| {
+                let account = params.require("account")?;
+                Ok(Some(
+                    BalanceStore::new().query_balance(account.into(), view),
+                ))
+            })
+            .add("/supply", |_, view| {
+                Ok(Some(SupplyStore::new().query_supply(view)))
+            })
+            .add("/allowance/:owner/:spender", |params, view| {
+                let owner = params.require("owner")?;
+                let spender = params.require("spender")?;
+                let allowance = AllowanceStore::new()
+                    .query((owner.into(), spender.into()), view)
+                    .unwrap_or_default();
+                Ok(Some(allowance))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec![
+            "/".into(),
+            "/balance/:account".into(),
+            "/supply".into(),
+            "/allowance/:owner/:spender".into(),
+        ]
+    }
+}
+
+impl BankMsgsHandler for BankModule {
+    fn transfer(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        to: AccountId,
+        amount: u64,
+    ) -> Result<(), anyhow::Error> {
+        let from = ctx.sender();
+        ensure!(amount > 0, "amount must be greater than 0");
+        ensure!(from != to, "cannot transfer to yourself");
+
+        self.check_spendable(ctx, from.clone(), amount, view)?;
+        let store = BalanceStore::new();
+        store.debit(from.clone(), amount, view)?;
+        store.credit(to.clone(), amount, view)?;
+        ctx.emit(TransferEvent { from, to, amount })
+    }
+
+    fn mint(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        to: AccountId,
+        amount: u64,
+    ) -> Result<(), anyhow::Error> {
+        self.check_minter(ctx)?;
+        ensure!(amount > 0, "amount must be greater than 0");
+
+        SupplyStore::new().increase(amount, view)?;
+        BalanceStore::new().credit(to.clone(), amount, view)?;
+        ctx.emit(MintEvent { to, amount })
+    }
+
+    fn burn(&self, ctx: &Context, view: &mut StoreView, amount: u64) -> Result<(), anyhow::Error> {
+        ensure!(amount > 0, "amount must be greater than 0");
+        let from = ctx.sender();
+
+        self.check_spendable(ctx, from.clone(), amount, view)?;
+        BalanceStore::new().debit(from.clone(), amount, view)?;
+        SupplyStore::new().decrease(amount, view)?;
+        ctx.emit(BurnEvent { from, amount })
+    }
+
+    fn send_from_module(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        to: AccountId,
+        amount: u64,
+    ) -> Result<(), anyhow::Error> {
+        let from: AccountId = match ctx.caller() {
+            Some(app) => app.into(),
+            None => bail!("only an AppModule can send from its account"),
+        };
+        ensure!(amount > 0, "amount must be greater than 0");
+
+        let store = BalanceStore::new();
+        store.debit(from.clone(), amount, view)?;
+        store.credit(to.clone(), amount, view)?;
+        ctx.emit(TransferEvent { from, to, amount })
+    }
+
+    fn approve(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        spender: AccountId,
+        amount: u64,
+    ) -> Result<(), anyhow::Error> {
+        let owner = ctx.sender();
+        ensure!(owner != spender, "cannot approve yourself");
+
+        AllowanceStore::new().set_allowance(owner.clone(), spender.clone(), amount, view);
+        ctx.emit(ApprovalEvent {
+            owner,
+            spender,
+            amount,
+        })
+    }
+
+    fn transfer_from(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        owner: AccountId,
+        to: AccountId,
+        amount: u64,
+    ) -> Result<(), anyhow::Error> {
+        let spender = ctx.sender();
+        ensure!(amount > 0, "amount must be greater than 0");
+        ensure!(owner != to, "cannot transfer to the owner");
+
+        let allowances = AllowanceStore::new();
+        let allowance = allowances.allowance(owner.clone(), spender.clone(), view);
+        ensure!(allowance >= amount, "insufficient allowance");
+
+        self.check_spendable(ctx, owner.clone(), amount, view)?;
+        let store = BalanceStore::new();
+        store.debit(owner.clone(), amount, view)?;
+        store.credit(to.clone(), amount, view)?;
+        allowances.set_allowance(owner.clone(), spender, allowance - amount, view);
+        ctx.emit(TransferEvent {
+            from: owner,
+            to,
+            amount,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    fn bank_tx(sender: &str, msg: BankMsgs) -> SignedTransaction {
+        TxBuilder::new(BANK_APP_NAME)
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    }
+
+    fn balance(tester: &mut TestKit, account: &str) -> u64 {
+        tester
+            .query_as(BANK_APP_NAME, account.as_bytes().to_vec())
+            .unwrap()
+    }
+
+    fn supply(tester: &mut TestKit) -> u64 {
+        tester
+            .query_as(&format!("{}/supply", BANK_APP_NAME), vec![])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bank() {
+        let app = AppBuilder::new().with_app(BankModule::new().with_minter("treasury"));
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(r#"{"rapido.bank": {"bob": 100, "alice": 50}}"#);
+
+        assert_eq!(100, balance(&mut tester, "bob"));
+        assert_eq!(150, supply(&mut tester));
+
+        // Transfer
+        let tx = bank_tx(
+            "bob",
+            BankMsgs::Transfer {
+                to: "carol".into(),
+                amount: 40,
+            },
+        );
+        assert!(tester.commit_tx(&[&tx]).is_ok());
+        assert_eq!(60, balance(&mut tester, "bob"));
+        assert_eq!(40, balance(&mut tester, "carol"));
+        let by_path: u64 = tester
+            .query_as(&format!("{}/balance/carol", BANK_APP_NAME), vec![])
+            .unwrap();
+        assert_eq!(40, by_path);
+
+        // Insufficient balance
+        let tx = bank_tx(
+            "alice",
+            BankMsgs::Transfer {
+                to: "carol".into(),
+                amount: 51,
+            },
+        );
+        assert!(tester.commit_tx(&[&tx]).is_err());
+
+        // Only the minter can mint
+        let mint = BankMsgs::Mint {
+            to: "alice".into(),
+            amount: 25,
+        };
+        assert!(tester.commit_tx(&[&bank_tx("bob", mint.clone())]).is_err());
+        assert!(tester.commit_tx(&[&bank_tx("treasury", mint)]).is_ok());
+        assert_eq!(75, balance(&mut tester, "alice"));
+        assert_eq!(175, supply(&mut tester));
+
+        // Only an AppModule can send from its account
+        let tx = bank_tx(
+            "bob",
+            BankMsgs::SendFromModule {
+                to: "bob".into(),
+                amount: 1,
+            },
+        );
+        assert!(tester.commit_tx(&[&tx]).is_err());
+
+        // Burn
+        let tx = bank_tx("alice", BankMsgs::Burn { amount: 75 });
+        assert!(tester.commit_tx(&[&tx]).is_ok());
+        assert_eq!(0, balance(&mut tester, "alice"));
+        assert_eq!(100, supply(&mut tester));
+    }
+
+    #[test]
+    fn test_allowance() {
+        let app = AppBuilder::new().with_app(BankModule::new());
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(r#"{"rapido.bank": {"bob": 100}}"#);
+
+        let allowance = |tester: &mut TestKit| -> u64 {
+            tester
+                .query_as(&format!("{}/allowance/bob/escrow", BANK_APP_NAME), vec![])
+                .unwrap()
+        };
+        let transfer_from = |amount| {
+            bank_tx(
+                "escrow",
+                BankMsgs::TransferFrom {
+                    owner: "bob".into(),
+                    to: "carol".into(),
+                    amount,
+                },
+            )
+        };
+
+        // No allowance
+        assert!(tester.commit_tx(&[&transfer_from(10)]).is_err());
+
+        let approve = bank_tx(
+            "bob",
+            BankMsgs::Approve {
+                spender: "escrow".into(),
+                amount: 30,
+            },
+        );
+        assert!(tester.commit_tx(&[&approve]).is_ok());
+        assert_eq!(30, allowance(&mut tester));
+
+        assert!(tester.commit_tx(&[&transfer_from(20)]).is_ok());
+        assert_eq!(80, balance(&mut tester, "bob"));
+        assert_eq!(20, balance(&mut tester, "carol"));
+        assert_eq!(10, allowance(&mut tester));
+
+        // Over the allowance
+        assert!(tester.commit_tx(&[&transfer_from(11)]).is_err());
+
+        // Revoke
+        let revoke = bank_tx(
+            "bob",
+            BankMsgs::Approve {
+                spender: "escrow".into(),
+                amount: 0,
+            },
+        );
+        assert!(tester.commit_tx(&[&revoke]).is_ok());
+        assert_eq!(0, allowance(&mut tester));
+        assert!(tester.commit_tx(&[&transfer_from(1)]).is_err());
+    }
+}
@@ -0,0 +1,469 @@
+//!
+//! A bridge between two Rapido chains running this module, with trusted relayers.
+//! Sending burns the sender's bank tokens and commits a `Packet` to the bridge's
+//! store.  A relayer submits the root of the sending chain's state (a header) to the
+//! receiving chain, and anyone can then submit the packet with a Merkle proof of its
+//! commitment against the header.  The receiving chain mints the tokens to the
+//! receiver, once per packet.  Relayers are trusted to submit correct roots.
+//!
+//! Relaying a packet, with the proof from the `/proof/:sequence` query on the
+//! sending chain:
+//! ```ignore
+//! let PacketProof { packet, proof, height } = ...;
+//! let root = rapido_core::proof_root(&proof)?;
+//! // To the receiving chain
+//! BridgeMsgs::SubmitHeader { height, root: root.as_ref().to_vec() };
+//! BridgeMsgs::Receive { packet, height, proof };
+//! ```
+//!
+use std::collections::HashSet;
+
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use exonum_crypto::Hash;
+use rapido_bank::{BankMsgs, BANK_APP_NAME};
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreValue, StoreView,
+};
+
+/// Name of the bridge AppModule
+pub const BRIDGE_APP_NAME: &str = "rapido.bridge";
+const HEADER_STORE_NAME: &str = "rapido.bridge.headers";
+const COMMITMENT_STORE_NAME: &str = "rapido.bridge.commitments";
+const RECEIPT_STORE_NAME: &str = "rapido.bridge.receipts";
+const SEQUENCE_STORE_NAME: &str = "rapido.bridge.sequence";
+
+// Key of the next sequence in its store
+const SEQUENCE_KEY: &str = "sequence";
+
+/// A transfer of tokens to the counterparty chain
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub sequence: u64,
+    /// Chain id of the sending chain
+    pub source: String,
+    /// Chain id of the receiving chain
+    pub destination: String,
+    pub sender: AccountId,
+    pub receiver: AccountId,
+    pub amount: u64,
+}
+
+/// The root of the counterparty's state at a height, submitted by a relayer
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct Header {
+    pub root: Vec<u8>,
+    pub relayer: AccountId,
+}
+
+/// A committed packet with the Merkle proof of its commitment
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct PacketProof {
+    pub packet: Packet,
+    pub proof: Vec<u8>,
+    /// Height of the state the proof was made against
+    pub height: i64,
+}
+
+/// Counterparty headers keyed by height
+pub(crate) struct HeaderStore;
+impl Store for HeaderStore {
+    type Key = i64;
+    type Value = Header;
+
+    fn name(&self) -> String {
+        HEADER_STORE_NAME.into()
+    }
+}
+
+impl HeaderStore {
+    pub fn new() -> Self {
+        HeaderStore {}
+    }
+}
+
+/// Packets sent keyed by sequence
+pub(crate) struct CommitmentStore;
+impl Store for CommitmentStore {
+    type Key = u64;
+    type Value = Packet;
+
+    fn name(&self) -> String {
+        COMMITMENT_STORE_NAME.into()
+    }
+}
+
+impl CommitmentStore {
+    pub fn new() -> Self {
+        CommitmentStore {}
+    }
+}
+
+/// Height each packet was received at, keyed by sequence
+pub(crate) struct ReceiptStore;
+impl Store for ReceiptStore {
+    type Key = u64;
+    type Value = i64;
+
+    fn name(&self) -> String {
+        RECEIPT_STORE_NAME.into()
+    }
+}
+
+impl ReceiptStore {
+    pub fn new() -> Self {
+        ReceiptStore {}
+    }
+}
+
+// The sequence of the next packet sent
+pub(crate) struct SequenceStore;
+impl Store for SequenceStore {
+    type Key = String;
+    type Value = u64;
+
+    fn name(&self) -> String {
+        SEQUENCE_STORE_NAME.into()
+    }
+}
+
+impl SequenceStore {
+    pub fn new() -> Self {
+        SequenceStore {}
+    }
+
+    fn next(&self, view: &mut StoreView) -> u64 {
+        let sequence = self.get(SEQUENCE_KEY.into(), view).unwrap_or_default();
+        self.put(SEQUENCE_KEY.into(), sequence + 1, view);
+        sequence
+    }
+}
+
+/// Messages to the bridge
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum BridgeMsgs {
+    /// Burn tokens of the sender and commit a packet to mint them to the receiver
+    /// on the counterparty
+    Send { receiver: AccountId, amount: u64 },
+    /// The root of the counterparty's state at the height. Only accepted from a
+    /// relayer
+    SubmitHeader { height: i64, root: Vec<u8> },
+    /// Mint the tokens of a packet committed on the counterparty.  The proof is
+    /// checked against the header at the height
+    Receive {
+        packet: Packet,
+        height: i64,
+        proof: Vec<u8>,
+    },
+}
+
+/// Emitted as 'rapido.bridge.send'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct SendEvent {
+    pub sequence: u64,
+    pub sender: AccountId,
+    pub receiver: AccountId,
+    pub amount: u64,
+}
+
+/// Emitted as 'rapido.bridge.header'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct HeaderEvent {
+    pub height: i64,
+    pub relayer: AccountId,
+}
+
+/// Emitted as 'rapido.bridge.receive'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct ReceiveEvent {
+    pub sequence: u64,
+    pub receiver: AccountId,
+    pub amount: u64,
+}
+
+/// The bridge AppModule. Add it via `AppBuilder::with_app` and grant it the
+/// capability `rapido.bank::mint`.
+pub struct BridgeModule {
+    counterparty: String,
+    relayers: HashSet<AccountId>,
+}
+
+impl BridgeModule {
+    /// Bridge to the chain with the chain id
+    pub fn new(counterparty: &str) -> Self {
+        Self {
+            counterparty: counterparty.into(),
+            relayers: HashSet::new(),
+        }
+    }
+
+    /// Accept headers from the account
+    pub fn with_relayer(mut self, account: impl Into<AccountId>) -> Self {
+        self.relayers.insert(account.into());
+        self
+    }
+}
+
+impl AppModule for BridgeModule {
+    fn name(&self) -> String {
+        BRIDGE_APP_NAME.into()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        vec![BANK_APP_NAME.into()]
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        BridgeMsgs::dispatch(self, ctx, view)
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        let height = view.height();
+        QueryRouter::new()
+            .add("/header/:height", |params, view| {
+                let height: i64 = params.require("height")?.parse()?;
+                Ok(HeaderStore::new().query(height, view))
+            })
+            .add("/commitment/:sequence", |params, view| {
+                let sequence: u64 = params.require("sequence")?.parse()?;
+                Ok(CommitmentStore::new().query(sequence, view))
+            })
+            .add("/proof/:sequence", move |params, view| {
+                let sequence: u64 = params.require("sequence")?.parse()?;
+                let store = CommitmentStore::new();
+                match store.query(sequence, view) {
+                    Some(packet) => Ok(Some(PacketProof {
+                        packet,
+                        proof: store.query_proof(sequence, view)?,
+                        height,
+                    })),
+                    None => Ok(None),
+                }
+            })
+            .add("/received/:sequence", |params, view| {
+                let sequence: u64 = params.require("sequence")?.parse()?;
+                Ok(Some(ReceiptStore::new().query(sequence, view).is_some()))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec![
+            "/header/:height".into(),
+            "/commitment/:sequence".into(),
+            "/proof/:sequence".into(),
+            "/received/:sequence".into(),
+        ]
+    }
+}
+
+impl BridgeMsgsHandler for BridgeModule {
+    fn send(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        receiver: AccountId,
+        amount: u64,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(amount > 0, "amount must be greater than 0");
+        let sender = ctx.sender();
+        ctx.dispatch(BANK_APP_NAME, BankMsgs::Burn { amount }, view)?;
+
+        let sequence = SequenceStore::new().next(view);
+        let packet = Packet {
+            sequence,
+            source: ctx.chain_id().into(),
+            destination: self.counterparty.clone(),
+            sender: sender.clone(),
+            receiver: receiver.clone(),
+            amount,
+        };
+        CommitmentStore::new().put(sequence, packet, view);
+        ctx.emit(SendEvent {
+            sequence,
+            sender,
+            receiver,
+            amount,
+        })
+    }
+
+    fn submit_header(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        height: i64,
+        root: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        let relayer = ctx.sender();
+        ensure!(
+            ctx.caller().is_none() && self.relayers.contains(&relayer),
+            "not a relayer"
+        );
+        ensure!(
+            Hash::from_slice(&root).is_some(),
+            "root must be a 32 byte hash"
+        );
+        let store = HeaderStore::new();
+        ensure!(
+            !store.contains_key(height, view),
+            "header at {} was submitted",
+            height
+        );
+
+        store.put(
+            height,
+            Header {
+                root,
+                relayer: relayer.clone(),
+            },
+            view,
+        );
+        ctx.emit(HeaderEvent { height, relayer })
+    }
+
+    fn receive(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        packet: Packet,
+        height: i64,
+        proof: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(
+            packet.source == self.counterparty,
+            "packet is not from the counterparty"
+        );
+        ensure!(
+            packet.destination == ctx.chain_id(),
+            "packet is not for this chain"
+        );
+        let receipts = ReceiptStore::new();
+        ensure!(
+            !receipts.contains_key(packet.sequence, view),
+            "packet {} was received",
+            packet.sequence
+        );
+
+        let root = match HeaderStore::new().get(height, view) {
+            Some(header) => Hash::from_slice(&header.root).expect("checked hash"),
+            None => bail!("no header at {}", height),
+        };
+        // The counterparty's commitment store has the same name
+        match CommitmentStore::new().verify_proof(packet.sequence, &proof, &root)? {
+            Some(committed) if committed == packet => {}
+            Some(_) => bail!("packet doesn't match the commitment"),
+            None => bail!("packet {} was not committed", packet.sequence),
+        }
+
+        receipts.put(packet.sequence, ctx.height(), view);
+        ctx.dispatch(
+            BANK_APP_NAME,
+            BankMsgs::Mint {
+                to: packet.receiver.clone(),
+                amount: packet.amount,
+            },
+            view,
+        )?;
+        ctx.emit(ReceiveEvent {
+            sequence: packet.sequence,
+            receiver: packet.receiver,
+            amount: packet.amount,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_bank::BankModule;
+    use rapido_core::{
+        proof_root, AppBuilder, SignedTransaction, TestKit, TxBuilder, TESTKIT_CHAIN_ID,
+    };
+
+    fn bridge_tx(sender: &str, msg: BridgeMsgs) -> SignedTransaction {
+        TxBuilder::new(BRIDGE_APP_NAME)
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    }
+
+    fn chain() -> TestKit {
+        // Both kits have the same chain id
+        let app = AppBuilder::new()
+            .with_app(BankModule::new())
+            .with_app(BridgeModule::new(TESTKIT_CHAIN_ID).with_relayer("relayer"))
+            .grant(BRIDGE_APP_NAME, "rapido.bank::mint");
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(r#"{"rapido.bank": {"bob": 100}}"#);
+        tester
+    }
+
+    fn balance(tester: &mut TestKit, account: &str) -> u64 {
+        tester
+            .query_as(BANK_APP_NAME, account.as_bytes().to_vec())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bridge() {
+        let mut source = chain();
+        let mut destination = chain();
+
+        let send = BridgeMsgs::Send {
+            receiver: "alice".into(),
+            amount: 40,
+        };
+        assert!(source.commit_tx(&[&bridge_tx("bob", send)]).is_ok());
+        assert_eq!(60, balance(&mut source, "bob"));
+
+        let PacketProof {
+            packet,
+            proof,
+            height,
+        } = source
+            .query_as(&format!("{}/proof/0", BRIDGE_APP_NAME), vec![])
+            .unwrap();
+        let root = proof_root(&proof).unwrap().as_ref().to_vec();
+
+        // Only a relayer can submit a header
+        let header = BridgeMsgs::SubmitHeader { height, root };
+        assert!(destination
+            .commit_tx(&[&bridge_tx("bob", header.clone())])
+            .is_err());
+        assert!(destination
+            .commit_tx(&[&bridge_tx("relayer", header)])
+            .is_ok());
+
+        // A forged packet
+        let mut forged = packet.clone();
+        forged.amount = 1000;
+        let receive = |packet: Packet| {
+            bridge_tx(
+                "anyone",
+                BridgeMsgs::Receive {
+                    packet,
+                    height,
+                    proof: proof.clone(),
+                },
+            )
+        };
+        assert!(destination.commit_tx(&[&receive(forged)]).is_err());
+
+        assert!(destination.commit_tx(&[&receive(packet.clone())]).is_ok());
+        assert_eq!(40, balance(&mut destination, "alice"));
+        let received: bool = destination
+            .query_as(&format!("{}/received/0", BRIDGE_APP_NAME), vec![])
+            .unwrap();
+        assert!(received);
+
+        // Only once
+        assert!(destination.commit_tx(&[&receive(packet)]).is_err());
+    }
+}
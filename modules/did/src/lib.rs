@@ -0,0 +1,407 @@
+//!
+//! Decentralized identifiers.  An account controls one DID: `did:rapido:{account}`.
+//! The controller creates, updates and deactivates the DID document with its
+//! verification keys and service endpoints.  The `/resolve/:did` query returns a
+//! W3C shaped document as JSON:
+//! ```ignore
+//! let resolved: serde_json::Value = client.query_json("rapido.did/resolve/did:rapido:bob", vec![])?;
+//! ```
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{
+    AccountId, AppModule, Context, Json, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreValue, StoreView,
+};
+use serde_json::json;
+
+pub mod revocation;
+
+/// Name of the DID AppModule
+pub const DID_APP_NAME: &str = "rapido.did";
+const DID_STORE_NAME: &str = "rapido.did.documents";
+
+/// Prefix of the DIDs of this method
+pub const DID_METHOD_PREFIX: &str = "did:rapido:";
+const DID_CONTEXT: &str = "https://www.w3.org/ns/did/v1";
+const ED25519_KEY_TYPE: &str = "Ed25519VerificationKey2018";
+
+/// Maximum number of keys and of services in a document
+pub const MAX_DID_ENTRIES: usize = 16;
+
+/// Return the DID controlled by the account
+pub fn did_for_account(account: &[u8]) -> anyhow::Result<String, anyhow::Error> {
+    match std::str::from_utf8(account) {
+        Ok(a) if !a.is_empty() => Ok(format!("{}{}", DID_METHOD_PREFIX, a)),
+        _ => bail!("account can't be used in a DID"),
+    }
+}
+
+/// An ed25519 verification key. `id` is the fragment: 'key-1' is '{did}#key-1'
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct VerificationKey {
+    pub id: String,
+    pub public_key: [u8; 32],
+}
+
+/// A service endpoint. `id` is the fragment as with keys
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct ServiceEndpoint {
+    pub id: String,
+    pub service_type: String,
+    pub endpoint: String,
+}
+
+/// DID Document Model
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct DidDocument {
+    pub id: String,
+    pub controller: AccountId,
+    pub keys: Vec<VerificationKey>,
+    pub services: Vec<ServiceEndpoint>,
+    /// Height the document was created
+    pub created: i64,
+    /// Height of the last change
+    pub updated: i64,
+    pub deactivated: bool,
+}
+
+impl DidDocument {
+    /// The W3C shaped resolution result: the document and its metadata
+    pub fn to_w3c(&self) -> serde_json::Value {
+        let methods: Vec<serde_json::Value> = self
+            .keys
+            .iter()
+            .map(|k| {
+                json!({
+                    "id": format!("{}#{}", self.id, k.id),
+                    "type": ED25519_KEY_TYPE,
+                    "controller": self.id,
+                    "publicKeyBase58": bs58::encode(&k.public_key).into_string(),
+                })
+            })
+            .collect();
+        let authentication: Vec<String> = self
+            .keys
+            .iter()
+            .map(|k| format!("{}#{}", self.id, k.id))
+            .collect();
+        let services: Vec<serde_json::Value> = self
+            .services
+            .iter()
+            .map(|s| {
+                json!({
+                    "id": format!("{}#{}", self.id, s.id),
+                    "type": s.service_type,
+                    "serviceEndpoint": s.endpoint,
+                })
+            })
+            .collect();
+
+        json!({
+            "didDocument": {
+                "@context": DID_CONTEXT,
+                "id": self.id,
+                "controller": self.id,
+                "verificationMethod": methods,
+                "authentication": authentication,
+                "service": services,
+            },
+            "didDocumentMetadata": {
+                "created": self.created,
+                "updated": self.updated,
+                "deactivated": self.deactivated,
+            },
+        })
+    }
+}
+
+// Fragments are part of a DID URL
+fn check_fragment(id: &str) -> anyhow::Result<(), anyhow::Error> {
+    if id.is_empty()
+        || id
+            .chars()
+            .any(|c| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+    {
+        bail!("invalid id fragment: '{}'", id);
+    }
+    Ok(())
+}
+
+fn check_entries(
+    keys: &[VerificationKey],
+    services: &[ServiceEndpoint],
+) -> anyhow::Result<(), anyhow::Error> {
+    ensure!(!keys.is_empty(), "a DID document needs at least one key");
+    ensure!(
+        keys.len() <= MAX_DID_ENTRIES && services.len() <= MAX_DID_ENTRIES,
+        "a DID document has at most {} keys and {} services",
+        MAX_DID_ENTRIES,
+        MAX_DID_ENTRIES
+    );
+
+    let mut ids: Vec<&str> = Vec::new();
+    for id in keys
+        .iter()
+        .map(|k| k.id.as_str())
+        .chain(services.iter().map(|s| s.id.as_str()))
+    {
+        check_fragment(id)?;
+        ensure!(!ids.contains(&id), "duplicate id fragment: '{}'", id);
+        ids.push(id);
+    }
+    for s in services {
+        ensure!(
+            !s.service_type.is_empty() && !s.endpoint.is_empty(),
+            "service '{}' needs a type and an endpoint",
+            s.id
+        );
+    }
+    Ok(())
+}
+
+/// DID documents keyed by DID
+pub(crate) struct DidStore;
+impl Store for DidStore {
+    type Key = String;
+    type Value = DidDocument;
+
+    fn name(&self) -> String {
+        DID_STORE_NAME.into()
+    }
+}
+
+impl DidStore {
+    pub fn new() -> Self {
+        DidStore {}
+    }
+}
+
+/// Messages to the DID AppModule. The DID is the sender's
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum DidMsgs {
+    Create {
+        keys: Vec<VerificationKey>,
+        services: Vec<ServiceEndpoint>,
+    },
+    /// Replace the keys and services
+    Update {
+        keys: Vec<VerificationKey>,
+        services: Vec<ServiceEndpoint>,
+    },
+    /// Permanently deactivate the DID. The keys and services are removed
+    Deactivate,
+}
+
+/// Emitted as 'rapido.did.create'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct CreateEvent {
+    pub did: String,
+}
+
+/// Emitted as 'rapido.did.update'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct UpdateEvent {
+    pub did: String,
+}
+
+/// Emitted as 'rapido.did.deactivate'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct DeactivateEvent {
+    pub did: String,
+}
+
+/// The DID AppModule. Add it via `AppBuilder::with_app`.
+pub struct DidModule;
+
+impl DidModule {
+    // Load the active document of the sender
+    fn load_active(
+        &self,
+        ctx: &Context,
+        view: &StoreView,
+    ) -> anyhow::Result<DidDocument, anyhow::Error> {
+        let did = did_for_account(&ctx.sender())?;
+        match DidStore::new().get(did.clone(), view) {
+            Some(doc) if doc.deactivated => bail!("{} is deactivated", did),
+            Some(doc) => Ok(doc),
+            None => bail!("{} not found", did),
+        }
+    }
+}
+
+impl AppModule for DidModule {
+    fn name(&self) -> String {
+        DID_APP_NAME.into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        DidMsgs::dispatch(self, ctx, view)
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            // The DidDocument. The key is the DID
+            .add("/", |params, view| {
+                let did = String::from_utf8(params.key().to_vec())?;
+                Ok(DidStore::new().query(did, view))
+            })
+            .add("/resolve/:did", |params, view| {
+                Ok(DidStore::new()
+                    .query(params.require("did")?.into(), view)
+                    .map(|doc| Json(doc.to_w3c())))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/".into(), "/resolve/:did".into()]
+    }
+}
+
+impl DidMsgsHandler for DidModule {
+    fn create(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        keys: Vec<VerificationKey>,
+        services: Vec<ServiceEndpoint>,
+    ) -> Result<(), anyhow::Error> {
+        let did = did_for_account(&ctx.sender())?;
+        let store = DidStore::new();
+        ensure!(store.get(did.clone(), view).is_none(), "{} exists", did);
+        check_entries(&keys, &services)?;
+
+        let doc = DidDocument {
+            id: did.clone(),
+            controller: ctx.sender(),
+            keys,
+            services,
+            created: ctx.height(),
+            updated: ctx.height(),
+            deactivated: false,
+        };
+        store.put(did.clone(), doc, view);
+        ctx.emit(CreateEvent { did })
+    }
+
+    fn update(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        keys: Vec<VerificationKey>,
+        services: Vec<ServiceEndpoint>,
+    ) -> Result<(), anyhow::Error> {
+        let mut doc = self.load_active(ctx, view)?;
+        check_entries(&keys, &services)?;
+
+        doc.keys = keys;
+        doc.services = services;
+        doc.updated = ctx.height();
+        let did = doc.id.clone();
+        DidStore::new().put(did.clone(), doc, view);
+        ctx.emit(UpdateEvent { did })
+    }
+
+    fn deactivate(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let mut doc = self.load_active(ctx, view)?;
+        doc.keys.clear();
+        doc.services.clear();
+        doc.updated = ctx.height();
+        doc.deactivated = true;
+        let did = doc.id.clone();
+        DidStore::new().put(did.clone(), doc, view);
+        ctx.emit(DeactivateEvent { did })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    fn did_tx(sender: &str, msg: DidMsgs) -> SignedTransaction {
+        TxBuilder::new(DID_APP_NAME)
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    }
+
+    fn key(id: &str) -> VerificationKey {
+        VerificationKey {
+            id: id.into(),
+            public_key: [1u8; 32],
+        }
+    }
+
+    fn service(id: &str) -> ServiceEndpoint {
+        ServiceEndpoint {
+            id: id.into(),
+            service_type: "LinkedDomains".into(),
+            endpoint: "https://bob.example.com".into(),
+        }
+    }
+
+    #[test]
+    fn test_check_entries() {
+        assert!(check_entries(&[key("key-1")], &[service("web")]).is_ok());
+        assert!(check_entries(&[], &[]).is_err());
+        assert!(check_entries(&[key("key-1")], &[service("key-1")]).is_err());
+        assert!(check_entries(&[key("key#1")], &[]).is_err());
+    }
+
+    #[test]
+    fn test_did_lifecycle() {
+        let app = AppBuilder::new().with_app(DidModule {});
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let create = DidMsgs::Create {
+            keys: vec![key("key-1")],
+            services: vec![service("web")],
+        };
+        assert!(tester.commit_tx(&[&did_tx("bob", create.clone())]).is_ok());
+        // Only one per account
+        assert!(tester.commit_tx(&[&did_tx("bob", create)]).is_err());
+
+        let resolved: serde_json::Value = tester
+            .query_json("rapido.did/resolve/did:rapido:bob", vec![])
+            .unwrap();
+        let doc = &resolved["didDocument"];
+        assert_eq!("did:rapido:bob", doc["id"]);
+        assert_eq!("did:rapido:bob#key-1", doc["verificationMethod"][0]["id"]);
+        assert_eq!("did:rapido:bob#key-1", doc["authentication"][0]);
+        assert_eq!(
+            "https://bob.example.com",
+            doc["service"][0]["serviceEndpoint"]
+        );
+
+        // Alice has no DID to update
+        let update = DidMsgs::Update {
+            keys: vec![key("key-2")],
+            services: vec![],
+        };
+        assert!(tester
+            .commit_tx(&[&did_tx("alice", update.clone())])
+            .is_err());
+        assert!(tester.commit_tx(&[&did_tx("bob", update.clone())]).is_ok());
+        let doc: DidDocument = tester.query_as(DID_APP_NAME, "did:rapido:bob").unwrap();
+        assert_eq!(vec![key("key-2")], doc.keys);
+        assert!(doc.services.is_empty());
+
+        assert!(tester
+            .commit_tx(&[&did_tx("bob", DidMsgs::Deactivate)])
+            .is_ok());
+        assert!(tester.commit_tx(&[&did_tx("bob", update)]).is_err());
+        let resolved: serde_json::Value = tester
+            .query_json("rapido.did/resolve/did:rapido:bob", vec![])
+            .unwrap();
+        assert_eq!(true, resolved["didDocumentMetadata"]["deactivated"]);
+    }
+}
@@ -0,0 +1,345 @@
+//!
+//! Credential revocation registries.  An issuer with an active DID publishes
+//! registries of credential status as bitmaps: each credential it issues is
+//! assigned an index in a registry, and the bit at the index is set when the
+//! credential is revoked.  Verifiers check the status on-chain:
+//! ```ignore
+//! let revoked: bool = client.query_as("rapido.revocation/status/did:rapido:bob/degrees/42", vec![])?;
+//! ```
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{
+    AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent, RapidoMessages, Store,
+    StoreValue, StoreView,
+};
+
+use crate::{check_fragment, did_for_account, DidStore};
+
+pub const REVOCATION_APP_NAME: &str = "rapido.revocation";
+const REGISTRY_STORE_NAME: &str = "rapido.revocation.registries";
+
+/// Maximum number of credentials in a registry
+pub const MAX_REGISTRY_SIZE: u32 = 131_072;
+
+/// Revocation registry Model. Bit `i` of `bits` is set if credential `i` is revoked
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct RevocationRegistry {
+    pub issuer: String,
+    pub id: String,
+    pub size: u32,
+    pub bits: Vec<u8>,
+    /// Incremented on each update
+    pub version: u64,
+    /// Height of the last update
+    pub updated: i64,
+}
+
+impl RevocationRegistry {
+    /// Create a registry with no revoked credentials
+    pub fn new(issuer: &str, id: &str, size: u32) -> anyhow::Result<Self, anyhow::Error> {
+        ensure!(
+            size > 0 && size <= MAX_REGISTRY_SIZE,
+            "registry size must be 1 to {}",
+            MAX_REGISTRY_SIZE
+        );
+        Ok(Self {
+            issuer: issuer.into(),
+            id: id.into(),
+            size,
+            bits: vec![0u8; ((size + 7) / 8) as usize],
+            version: 0,
+            updated: 0,
+        })
+    }
+
+    /// Is the credential at the index revoked?
+    pub fn is_revoked(&self, index: u32) -> anyhow::Result<bool, anyhow::Error> {
+        self.check_index(index)?;
+        Ok(self.bits[(index / 8) as usize] & (1 << (index % 8)) != 0)
+    }
+
+    /// Set the status of the credential at the index
+    pub fn set_revoked(&mut self, index: u32, revoked: bool) -> anyhow::Result<(), anyhow::Error> {
+        self.check_index(index)?;
+        let byte = &mut self.bits[(index / 8) as usize];
+        if revoked {
+            *byte |= 1 << (index % 8);
+        } else {
+            *byte &= !(1 << (index % 8));
+        }
+        Ok(())
+    }
+
+    fn check_index(&self, index: u32) -> anyhow::Result<(), anyhow::Error> {
+        ensure!(
+            index < self.size,
+            "index {} out of range for registry {}",
+            index,
+            self.id
+        );
+        Ok(())
+    }
+}
+
+/// Registries keyed by (issuer DID, registry id)
+pub(crate) struct RegistryStore;
+impl Store for RegistryStore {
+    type Key = (String, String);
+    type Value = RevocationRegistry;
+
+    fn name(&self) -> String {
+        REGISTRY_STORE_NAME.into()
+    }
+}
+
+impl RegistryStore {
+    pub fn new() -> Self {
+        RegistryStore {}
+    }
+}
+
+/// Messages to the revocation AppModule. The issuer is the sender's DID
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum RevocationMsgs {
+    /// Publish a registry for `size` credentials
+    Publish { registry: String, size: u32 },
+    /// Revoke and restore credentials by index
+    Update {
+        registry: String,
+        revoke: Vec<u32>,
+        restore: Vec<u32>,
+    },
+}
+
+/// Emitted as 'rapido.revocation.publish'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct PublishEvent {
+    pub issuer: String,
+    pub registry: String,
+    pub size: u32,
+}
+
+/// Emitted as 'rapido.revocation.update'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct UpdateEvent {
+    pub issuer: String,
+    pub registry: String,
+    pub version: u64,
+}
+
+/// The revocation AppModule. Add it via `AppBuilder::with_app`. Depends on the DID
+/// AppModule.
+pub struct RevocationModule;
+
+impl RevocationModule {
+    // The DID of the sender. It must be active
+    fn issuer(&self, ctx: &Context, view: &StoreView) -> anyhow::Result<String, anyhow::Error> {
+        let did = did_for_account(&ctx.sender())?;
+        match DidStore::new().get(did.clone(), view) {
+            Some(doc) if !doc.deactivated => Ok(did),
+            _ => bail!("issuer needs an active DID"),
+        }
+    }
+}
+
+impl AppModule for RevocationModule {
+    fn name(&self) -> String {
+        REVOCATION_APP_NAME.into()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        vec![crate::DID_APP_NAME.into()]
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        RevocationMsgs::dispatch(self, ctx, view)
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/registry/:issuer/:id", |params, view| {
+                let key = (
+                    params.require("issuer")?.into(),
+                    params.require("id")?.into(),
+                );
+                Ok(RegistryStore::new().query(key, view))
+            })
+            .add("/status/:issuer/:id/:index", |params, view| {
+                let key = (
+                    params.require("issuer")?.into(),
+                    params.require("id")?.into(),
+                );
+                let index: u32 = params.require("index")?.parse()?;
+                match RegistryStore::new().query(key, view) {
+                    Some(registry) => Ok(Some(registry.is_revoked(index)?)),
+                    None => Ok(None),
+                }
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec![
+            "/registry/:issuer/:id".into(),
+            "/status/:issuer/:id/:index".into(),
+        ]
+    }
+}
+
+impl RevocationMsgsHandler for RevocationModule {
+    fn publish(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        registry: String,
+        size: u32,
+    ) -> Result<(), anyhow::Error> {
+        let issuer = self.issuer(ctx, view)?;
+        check_fragment(&registry)?;
+        let store = RegistryStore::new();
+        let key = (issuer.clone(), registry.clone());
+        ensure!(
+            store.get(key.clone(), view).is_none(),
+            "registry {} exists",
+            registry
+        );
+
+        let mut reg = RevocationRegistry::new(&issuer, &registry, size)?;
+        reg.updated = ctx.height();
+        store.put(key, reg, view);
+        ctx.emit(PublishEvent {
+            issuer,
+            registry,
+            size,
+        })
+    }
+
+    fn update(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        registry: String,
+        revoke: Vec<u32>,
+        restore: Vec<u32>,
+    ) -> Result<(), anyhow::Error> {
+        let issuer = self.issuer(ctx, view)?;
+        let store = RegistryStore::new();
+        let key = (issuer.clone(), registry.clone());
+        let mut reg = match store.get(key.clone(), view) {
+            Some(r) => r,
+            None => bail!("registry {} not found", registry),
+        };
+
+        for index in revoke {
+            reg.set_revoked(index, true)?;
+        }
+        for index in restore {
+            reg.set_revoked(index, false)?;
+        }
+        reg.version += 1;
+        reg.updated = ctx.height();
+        let version = reg.version;
+        store.put(key, reg, view);
+        ctx.emit(UpdateEvent {
+            issuer,
+            registry,
+            version,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DidModule, DidMsgs, VerificationKey, DID_APP_NAME};
+    use rapido_core::{AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    fn revocation_tx(sender: &str, msg: RevocationMsgs) -> SignedTransaction {
+        TxBuilder::new(REVOCATION_APP_NAME)
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_registry_bits() {
+        let mut reg = RevocationRegistry::new("did:rapido:bob", "r", 10).unwrap();
+        assert_eq!(2, reg.bits.len());
+        assert!(reg.set_revoked(9, true).is_ok());
+        assert!(reg.is_revoked(9).unwrap());
+        assert!(!reg.is_revoked(8).unwrap());
+        assert!(reg.set_revoked(9, false).is_ok());
+        assert!(!reg.is_revoked(9).unwrap());
+        assert!(reg.is_revoked(10).is_err());
+        assert!(RevocationRegistry::new("did:rapido:bob", "r", 0).is_err());
+    }
+
+    #[test]
+    fn test_revocation() {
+        let app = AppBuilder::new()
+            .with_app(DidModule {})
+            .with_app(RevocationModule {});
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let publish = RevocationMsgs::Publish {
+            registry: "degrees".into(),
+            size: 100,
+        };
+        // Needs a DID
+        assert!(tester
+            .commit_tx(&[&revocation_tx("bob", publish.clone())])
+            .is_err());
+
+        let create = TxBuilder::new(DID_APP_NAME)
+            .sender("bob")
+            .msg(DidMsgs::Create {
+                keys: vec![VerificationKey {
+                    id: "key-1".into(),
+                    public_key: [1u8; 32],
+                }],
+                services: vec![],
+            })
+            .build()
+            .unwrap();
+        assert!(tester.commit_tx(&[&create]).is_ok());
+        assert!(tester
+            .commit_tx(&[&revocation_tx("bob", publish.clone())])
+            .is_ok());
+        assert!(tester.commit_tx(&[&revocation_tx("bob", publish)]).is_err());
+
+        let update = RevocationMsgs::Update {
+            registry: "degrees".into(),
+            revoke: vec![42],
+            restore: vec![],
+        };
+        assert!(tester
+            .commit_tx(&[&revocation_tx("bob", update.clone())])
+            .is_ok());
+        // Alice can't update bob's registry
+        assert!(tester
+            .commit_tx(&[&revocation_tx("alice", update)])
+            .is_err());
+
+        let revoked: bool = tester
+            .query_as("rapido.revocation/status/did:rapido:bob/degrees/42", vec![])
+            .unwrap();
+        assert!(revoked);
+        let revoked: bool = tester
+            .query_as("rapido.revocation/status/did:rapido:bob/degrees/41", vec![])
+            .unwrap();
+        assert!(!revoked);
+
+        let reg: RevocationRegistry = tester
+            .query_as("rapido.revocation/registry/did:rapido:bob/degrees", vec![])
+            .unwrap();
+        assert_eq!(1, reg.version);
+    }
+}
@@ -0,0 +1,398 @@
+//!
+//! Fee distribution. `FeeMiddleware` charges a fee of bank tokens for each tx and
+//! collects it in the distribution AppModule's bank account.  In `end_block` the fees
+//! collected in the block are split between the community pool (the community tax)
+//! and the operator of the validator that proposed the block.  Operators withdraw
+//! their rewards with a `DistributionMsgs::Withdraw`.
+//!
+use std::collections::HashMap;
+
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_bank::{BankMsgs, BANK_APP_NAME};
+use rapido_core::{
+    params::Params, AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView,
+    RapidoEvent, RapidoMessages, Store, StoreValue, StoreView, TxMiddleware,
+};
+
+/// Name of the distribution AppModule. Also the bank account holding the fees
+pub const DISTRIBUTION_APP_NAME: &str = "rapido.distribution";
+const REWARDS_STORE_NAME: &str = "rapido.distribution.rewards";
+const POOL_STORE_NAME: &str = "rapido.distribution.pool";
+
+// Key of the FeePool in its store
+const POOL_KEY: &str = "pool";
+
+// Fees collected in the current block and the community pool
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, Default)]
+pub(crate) struct FeePool {
+    collected: u64,
+    community_pool: u64,
+}
+
+pub(crate) struct FeePoolStore;
+impl Store for FeePoolStore {
+    type Key = String;
+    type Value = FeePool;
+
+    fn name(&self) -> String {
+        POOL_STORE_NAME.into()
+    }
+}
+
+impl FeePoolStore {
+    pub fn new() -> Self {
+        FeePoolStore {}
+    }
+
+    fn load(&self, view: &StoreView) -> FeePool {
+        self.get(POOL_KEY.into(), view).unwrap_or_default()
+    }
+
+    fn save(&self, pool: FeePool, view: &mut StoreView) {
+        self.put(POOL_KEY.into(), pool, view)
+    }
+}
+
+/// Pending rewards keyed by operator account
+pub(crate) struct RewardsStore;
+impl Store for RewardsStore {
+    type Key = AccountId;
+    type Value = u64;
+
+    fn name(&self) -> String {
+        REWARDS_STORE_NAME.into()
+    }
+}
+
+impl RewardsStore {
+    pub fn new() -> Self {
+        RewardsStore {}
+    }
+
+    fn add(&self, account: AccountId, amount: u64, view: &mut StoreView) {
+        let pending = self.get(account.clone(), view).unwrap_or_default();
+        self.put(account, pending + amount, view)
+    }
+}
+
+/// Split the fees into (community pool, proposer) shares given the community tax
+/// percent
+pub fn split_fees(fees: u64, community_tax: u64) -> (u64, u64) {
+    let tax = (fees as u128 * community_tax.min(100) as u128 / 100) as u64;
+    (tax, fees - tax)
+}
+
+/// Middleware that charges the sender, or the tx's fee payer, a fee for each tx. The
+/// fee is the `tx_fee` param of the distribution AppModule, or the default given
+/// here.  Txs without a sender (scheduled txs and cron jobs) are not charged.
+pub struct FeeMiddleware {
+    fee: u64,
+}
+
+impl FeeMiddleware {
+    pub fn new(fee: u64) -> Self {
+        Self { fee }
+    }
+}
+
+impl TxMiddleware for FeeMiddleware {
+    fn before(&self, ctx: &Context, view: &mut StoreView) -> anyhow::Result<(), anyhow::Error> {
+        if ctx.sender().is_empty() {
+            return Ok(());
+        }
+        let fee: u64 = Params::new(DISTRIBUTION_APP_NAME).get_or("tx_fee", self.fee, view)?;
+        if fee == 0 {
+            return Ok(());
+        }
+
+        // Paid by the fee payer of the tx, if it has granted the sender an allowance
+        ctx.charge_fee(
+            fee,
+            BANK_APP_NAME,
+            BankMsgs::Transfer {
+                to: DISTRIBUTION_APP_NAME.into(),
+                amount: fee,
+            },
+            view,
+        )
+        .map_err(|err| anyhow::anyhow!("unable to pay the fee of {}: {}", fee, err))?;
+
+        let store = FeePoolStore::new();
+        let mut pool = store.load(view);
+        pool.collected += fee;
+        store.save(pool, view);
+        Ok(())
+    }
+}
+
+/// Messages to the distribution AppModule
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum DistributionMsgs {
+    /// Send the pending rewards of the sender to it
+    Withdraw,
+    /// Spend from the community pool. Requires the capability
+    /// `rapido.distribution::community_spend`
+    SpendCommunityPool { to: AccountId, amount: u64 },
+}
+
+/// Emitted as 'rapido.distribution.rewards' in end_block
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct RewardsEvent {
+    pub proposer: String,
+    pub fees: u64,
+    pub community_pool: u64,
+}
+
+/// Emitted as 'rapido.distribution.withdraw'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct WithdrawEvent {
+    pub account: AccountId,
+    pub amount: u64,
+}
+
+/// The distribution AppModule. Add it via `AppBuilder::with_app` and add the
+/// `FeeMiddleware` via `AppBuilder::with_middleware`. Depends on the bank.
+pub struct DistributionModule {
+    community_tax: u64,
+    // Validator address to operator account
+    operators: HashMap<Vec<u8>, AccountId>,
+}
+
+impl Default for DistributionModule {
+    fn default() -> Self {
+        Self {
+            community_tax: 2,
+            operators: HashMap::new(),
+        }
+    }
+}
+
+impl DistributionModule {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Percent of the fees that go to the community pool. Default: 2
+    pub fn community_tax(mut self, percent: u64) -> Self {
+        self.community_tax = percent;
+        self
+    }
+
+    /// Pay the rewards of the validator with the address to the account
+    pub fn with_operator(mut self, address: &[u8], account: impl Into<AccountId>) -> Self {
+        self.operators.insert(address.to_vec(), account.into());
+        self
+    }
+}
+
+impl AppModule for DistributionModule {
+    fn name(&self) -> String {
+        DISTRIBUTION_APP_NAME.into()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        vec![BANK_APP_NAME.into()]
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        DistributionMsgs::dispatch(self, ctx, view)
+    }
+
+    // Distribute the fees collected in the block
+    fn end_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let store = FeePoolStore::new();
+        let mut pool = store.load(view);
+        if pool.collected == 0 {
+            return Ok(());
+        }
+
+        let fees = pool.collected;
+        let (tax, reward) = split_fees(fees, self.community_tax);
+        pool.collected = 0;
+        pool.community_pool += tax;
+        match self.operators.get(ctx.proposer()) {
+            Some(operator) => RewardsStore::new().add(operator.clone(), reward, view),
+            // No operator, it all goes to the community
+            None => pool.community_pool += reward,
+        }
+        store.save(pool, view);
+
+        ctx.emit(RewardsEvent {
+            proposer: hex::encode(ctx.proposer()),
+            fees,
+            community_pool: tax,
+        })
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/rewards", |params, view| {
+                let pending = RewardsStore::new()
+                    .query(params.key().to_vec(), view)
+                    .unwrap_or_default();
+                Ok(Some(pending))
+            })
+            .add("/community_pool", |_, view| {
+                let pool = FeePoolStore::new()
+                    .query(POOL_KEY.into(), view)
+                    .unwrap_or_default();
+                Ok(Some(pool.community_pool))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/rewards".into(), "/community_pool".into()]
+    }
+}
+
+impl DistributionMsgsHandler for DistributionModule {
+    fn withdraw(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let store = RewardsStore::new();
+        let account = ctx.sender();
+        let amount = store.get(account.clone(), view).unwrap_or_default();
+        ensure!(amount > 0, "no rewards to withdraw");
+
+        store.remove(account.clone(), view);
+        ctx.dispatch(
+            BANK_APP_NAME,
+            BankMsgs::SendFromModule {
+                to: account.clone(),
+                amount,
+            },
+            view,
+        )?;
+        ctx.emit(WithdrawEvent { account, amount })
+    }
+
+    fn spend_community_pool(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        to: AccountId,
+        amount: u64,
+    ) -> Result<(), anyhow::Error> {
+        ctx.require_capability("community_spend")?;
+
+        let store = FeePoolStore::new();
+        let mut pool = store.load(view);
+        if pool.community_pool < amount {
+            bail!("community pool has {}", pool.community_pool);
+        }
+        pool.community_pool -= amount;
+        store.save(pool, view);
+
+        ctx.dispatch(BANK_APP_NAME, BankMsgs::SendFromModule { to, amount }, view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_bank::BankModule;
+    use rapido_core::fees::{FeeGrantModule, FeeGrantMsgs, FEE_GRANT_APP_NAME};
+    use rapido_core::{AppBuilder, TestKit, TxBuilder};
+
+    fn balance(tester: &mut TestKit, account: &str) -> u64 {
+        tester
+            .query_as(BANK_APP_NAME, account.as_bytes().to_vec())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_split_fees() {
+        assert_eq!((2, 98), split_fees(100, 2));
+        assert_eq!((0, 10), split_fees(10, 0));
+        assert_eq!((10, 0), split_fees(10, 150));
+    }
+
+    #[test]
+    fn test_fee_middleware() {
+        let app = AppBuilder::new()
+            .with_app(BankModule::new())
+            .with_app(DistributionModule::new())
+            .with_middleware(FeeMiddleware::new(10));
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(r#"{"rapido.bank": {"bob": 100}}"#);
+
+        let transfer = |amount| {
+            TxBuilder::new(BANK_APP_NAME)
+                .sender("bob")
+                .msg(BankMsgs::Transfer {
+                    to: "alice".into(),
+                    amount,
+                })
+                .build()
+                .unwrap()
+        };
+        assert!(tester.commit_tx(&[&transfer(40)]).is_ok());
+        assert_eq!(50, balance(&mut tester, "bob"));
+        assert_eq!(10, balance(&mut tester, DISTRIBUTION_APP_NAME));
+
+        // Can't pay the fee
+        let tx = TxBuilder::new(BANK_APP_NAME)
+            .sender("carol")
+            .msg(BankMsgs::Burn { amount: 1 })
+            .build()
+            .unwrap();
+        assert!(tester.commit_tx(&[&tx]).is_err());
+
+        // Nothing to withdraw
+        let tx = TxBuilder::new(DISTRIBUTION_APP_NAME)
+            .sender("alice")
+            .msg(DistributionMsgs::Withdraw)
+            .build()
+            .unwrap();
+        assert!(tester.commit_tx(&[&tx]).is_err());
+    }
+
+    #[test]
+    fn test_fee_payer() {
+        let app = AppBuilder::new()
+            .with_app(BankModule::new())
+            .with_app(FeeGrantModule)
+            .with_app(DistributionModule::new())
+            .with_middleware(FeeMiddleware::new(10));
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(r#"{"rapido.bank": {"bob": 100, "alice": 100}}"#);
+
+        let grant = TxBuilder::new(FEE_GRANT_APP_NAME)
+            .sender("alice")
+            .msg(FeeGrantMsgs::Grant {
+                grantee: "bob".into(),
+                allowance: 15,
+            })
+            .build()
+            .unwrap();
+        assert!(tester.commit_tx(&[&grant]).is_ok());
+        assert_eq!(90, balance(&mut tester, "alice"));
+
+        let burn = |sender: &str| {
+            TxBuilder::new(BANK_APP_NAME)
+                .sender(sender)
+                .msg(BankMsgs::Burn { amount: 1 })
+                .fee_payer("alice")
+                .build()
+                .unwrap()
+        };
+        assert!(tester.commit_tx(&[&burn("bob")]).is_ok());
+        assert_eq!(80, balance(&mut tester, "alice"));
+        assert_eq!(99, balance(&mut tester, "bob"));
+        assert_eq!(20, balance(&mut tester, DISTRIBUTION_APP_NAME));
+
+        // The allowance left doesn't cover the fee
+        assert!(tester.commit_tx(&[&burn("bob")]).is_err());
+        assert_eq!(80, balance(&mut tester, "alice"));
+        assert_eq!(99, balance(&mut tester, "bob"));
+
+        // No grant
+        assert!(tester.commit_tx(&[&burn("carol")]).is_err());
+        assert_eq!(80, balance(&mut tester, "alice"));
+    }
+}
@@ -0,0 +1,457 @@
+//!
+//! Escrow of bank tokens.  A sender locks tokens for a recipient.  The tokens are
+//! held by the escrow AppModule's bank account until the arbiter releases them to the
+//! recipient.  If the escrow is still open at the timeout height it's refunded to the
+//! sender in `end_block`.
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_bank::{BankMsgs, BANK_APP_NAME};
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreValue, StoreView,
+};
+
+/// Name of the escrow AppModule
+pub const ESCROW_APP_NAME: &str = "rapido.escrow";
+const ESCROW_STORE_NAME: &str = "rapido.escrow.escrows";
+const ESCROW_STATE_STORE_NAME: &str = "rapido.escrow.state";
+
+// Key of the EscrowState in its store
+const ESCROW_STATE_KEY: &str = "state";
+
+/// Escrow Model
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct Escrow {
+    pub id: u64,
+    pub sender: AccountId,
+    pub recipient: AccountId,
+    pub arbiter: AccountId,
+    pub amount: u64,
+    /// Height the escrow is refunded if it's still open
+    pub timeout: i64,
+}
+
+impl Escrow {
+    /// Is the account the sender, recipient or arbiter?
+    pub fn is_party(&self, account: &[u8]) -> bool {
+        self.sender.as_slice() == account
+            || self.recipient.as_slice() == account
+            || self.arbiter.as_slice() == account
+    }
+}
+
+// The next escrow id and the ids of the open escrows
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, Default)]
+pub(crate) struct EscrowState {
+    next_id: u64,
+    open: Vec<u64>,
+}
+
+/// Open escrows keyed by id. Closed escrows are removed
+pub(crate) struct EscrowStore;
+impl Store for EscrowStore {
+    type Key = u64;
+    type Value = Escrow;
+
+    fn name(&self) -> String {
+        ESCROW_STORE_NAME.into()
+    }
+}
+
+impl EscrowStore {
+    pub fn new() -> Self {
+        EscrowStore {}
+    }
+}
+
+pub(crate) struct EscrowStateStore;
+impl Store for EscrowStateStore {
+    type Key = String;
+    type Value = EscrowState;
+
+    fn name(&self) -> String {
+        ESCROW_STATE_STORE_NAME.into()
+    }
+}
+
+impl EscrowStateStore {
+    pub fn new() -> Self {
+        EscrowStateStore {}
+    }
+
+    fn load(&self, view: &StoreView) -> EscrowState {
+        self.get(ESCROW_STATE_KEY.into(), view).unwrap_or_default()
+    }
+
+    fn save(&self, state: EscrowState, view: &mut StoreView) {
+        self.put(ESCROW_STATE_KEY.into(), state, view)
+    }
+}
+
+/// Messages to the escrow AppModule
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum EscrowMsgs {
+    /// Lock tokens of the sender for the recipient
+    Lock {
+        recipient: AccountId,
+        arbiter: AccountId,
+        amount: u64,
+        timeout: i64,
+    },
+    /// Send the tokens to the recipient. Only the arbiter can release
+    Release { id: u64 },
+    /// Return the tokens to the sender. The arbiter or the recipient can refund
+    Refund { id: u64 },
+}
+
+/// Emitted as 'rapido.escrow.lock'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct LockEvent {
+    pub id: u64,
+    pub sender: AccountId,
+    pub recipient: AccountId,
+    pub amount: u64,
+}
+
+/// Emitted as 'rapido.escrow.release'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct ReleaseEvent {
+    pub id: u64,
+    pub recipient: AccountId,
+    pub amount: u64,
+}
+
+/// Emitted as 'rapido.escrow.refund'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct RefundEvent {
+    pub id: u64,
+    pub sender: AccountId,
+    pub amount: u64,
+}
+
+/// The escrow AppModule. Add it via `AppBuilder::with_app`. Depends on the bank.
+pub struct EscrowModule;
+
+impl EscrowModule {
+    fn load(&self, id: u64, view: &StoreView) -> anyhow::Result<Escrow, anyhow::Error> {
+        match EscrowStore::new().get(id, view) {
+            Some(e) => Ok(e),
+            None => bail!("escrow {} not found", id),
+        }
+    }
+
+    // Pay out the escrow and remove it
+    fn close(
+        &self,
+        ctx: &Context,
+        escrow: &Escrow,
+        to: AccountId,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        ctx.dispatch(
+            BANK_APP_NAME,
+            BankMsgs::SendFromModule {
+                to,
+                amount: escrow.amount,
+            },
+            view,
+        )?;
+        EscrowStore::new().remove(escrow.id, view);
+
+        let state_store = EscrowStateStore::new();
+        let mut state = state_store.load(view);
+        state.open.retain(|id| *id != escrow.id);
+        state_store.save(state, view);
+        Ok(())
+    }
+
+    fn refund_escrow(
+        &self,
+        ctx: &Context,
+        escrow: &Escrow,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        self.close(ctx, escrow, escrow.sender.clone(), view)?;
+        ctx.emit(RefundEvent {
+            id: escrow.id,
+            sender: escrow.sender.clone(),
+            amount: escrow.amount,
+        })
+    }
+}
+
+impl AppModule for EscrowModule {
+    fn name(&self) -> String {
+        ESCROW_APP_NAME.into()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        vec![BANK_APP_NAME.into()]
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        EscrowMsgs::dispatch(self, ctx, view)
+    }
+
+    // Refund the escrows that have timed out
+    fn end_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let store = EscrowStore::new();
+        let expired: Vec<Escrow> = EscrowStateStore::new()
+            .load(view)
+            .open
+            .into_iter()
+            .filter_map(|id| store.get(id, view))
+            .filter(|e| ctx.height() >= e.timeout)
+            .collect();
+        for escrow in expired {
+            // A failed refund is undone and retried next block, without
+            // holding up the others
+            let checkpoint = view.checkpoint();
+            if let Err(err) = self.refund_escrow(ctx, &escrow, view) {
+                view.rollback_to(checkpoint);
+                ctx.logger().warn(&format!(
+                    "escrow {} couldn't be refunded: {}",
+                    escrow.id, err
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/escrow/:id", |params, view| {
+                let id: u64 = params.require("id")?.parse()?;
+                Ok(EscrowStore::new().query(id, view))
+            })
+            // All open escrows or those of the account in the key
+            .add("/open", |params, view| {
+                let state = EscrowStateStore::new()
+                    .query(ESCROW_STATE_KEY.into(), view)
+                    .unwrap_or_default();
+                let escrows: Vec<Escrow> = state
+                    .open
+                    .into_iter()
+                    .filter_map(|id| EscrowStore::new().query(id, view))
+                    .filter(|e| params.key().is_empty() || e.is_party(params.key()))
+                    .collect();
+                Ok(Some(escrows))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/escrow/:id".into(), "/open".into()]
+    }
+}
+
+impl EscrowMsgsHandler for EscrowModule {
+    fn lock(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        recipient: AccountId,
+        arbiter: AccountId,
+        amount: u64,
+        timeout: i64,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(amount > 0, "amount must be greater than 0");
+        ensure!(timeout > ctx.height(), "timeout must be a future height");
+
+        // Move the tokens to the escrow account
+        ctx.dispatch(
+            BANK_APP_NAME,
+            BankMsgs::Transfer {
+                to: ESCROW_APP_NAME.into(),
+                amount,
+            },
+            view,
+        )?;
+
+        let state_store = EscrowStateStore::new();
+        let mut state = state_store.load(view);
+        let id = state.next_id;
+        state.next_id += 1;
+        state.open.push(id);
+        state_store.save(state, view);
+
+        let escrow = Escrow {
+            id,
+            sender: ctx.sender(),
+            recipient: recipient.clone(),
+            arbiter,
+            amount,
+            timeout,
+        };
+        EscrowStore::new().put(id, escrow, view);
+
+        ctx.emit(LockEvent {
+            id,
+            sender: ctx.sender(),
+            recipient,
+            amount,
+        })
+    }
+
+    fn release(&self, ctx: &Context, view: &mut StoreView, id: u64) -> Result<(), anyhow::Error> {
+        let escrow = self.load(id, view)?;
+        ensure!(
+            ctx.sender() == escrow.arbiter,
+            "only the arbiter can release"
+        );
+
+        self.close(ctx, &escrow, escrow.recipient.clone(), view)?;
+        ctx.emit(ReleaseEvent {
+            id,
+            recipient: escrow.recipient,
+            amount: escrow.amount,
+        })
+    }
+
+    fn refund(&self, ctx: &Context, view: &mut StoreView, id: u64) -> Result<(), anyhow::Error> {
+        let escrow = self.load(id, view)?;
+        ensure!(
+            ctx.sender() == escrow.arbiter || ctx.sender() == escrow.recipient,
+            "only the arbiter or recipient can refund"
+        );
+        self.refund_escrow(ctx, &escrow, view)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_bank::BankModule;
+    use rapido_core::{AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    fn escrow_tx(sender: &str, msg: EscrowMsgs) -> SignedTransaction {
+        TxBuilder::new(ESCROW_APP_NAME)
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    }
+
+    fn balance(tester: &mut TestKit, account: &str) -> u64 {
+        tester
+            .query_as(BANK_APP_NAME, account.as_bytes().to_vec())
+            .unwrap()
+    }
+
+    fn lock(amount: u64) -> EscrowMsgs {
+        EscrowMsgs::Lock {
+            recipient: "alice".into(),
+            arbiter: "carol".into(),
+            amount,
+            timeout: 1000,
+        }
+    }
+
+    #[test]
+    fn test_escrow() {
+        let app = AppBuilder::new()
+            .with_app(BankModule::new())
+            .with_app(EscrowModule {});
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(r#"{"rapido.bank": {"bob": 100}}"#);
+
+        assert!(tester.commit_tx(&[&escrow_tx("bob", lock(200))]).is_err());
+        assert!(tester.commit_tx(&[&escrow_tx("bob", lock(60))]).is_ok());
+        assert!(tester.commit_tx(&[&escrow_tx("bob", lock(30))]).is_ok());
+        assert_eq!(10, balance(&mut tester, "bob"));
+        assert_eq!(90, balance(&mut tester, ESCROW_APP_NAME));
+
+        let open: Vec<Escrow> = tester.query_as("rapido.escrow/open", "alice").unwrap();
+        assert_eq!(2, open.len());
+        let open: Vec<Escrow> = tester.query_as("rapido.escrow/open", "dave").unwrap();
+        assert!(open.is_empty());
+
+        // Only the arbiter can release
+        let release = EscrowMsgs::Release { id: 0 };
+        assert!(tester
+            .commit_tx(&[&escrow_tx("alice", release.clone())])
+            .is_err());
+        assert!(tester.commit_tx(&[&escrow_tx("carol", release)]).is_ok());
+        assert_eq!(60, balance(&mut tester, "alice"));
+
+        // The recipient refunds
+        let refund = EscrowMsgs::Refund { id: 1 };
+        assert!(tester
+            .commit_tx(&[&escrow_tx("bob", refund.clone())])
+            .is_err());
+        assert!(tester.commit_tx(&[&escrow_tx("alice", refund)]).is_ok());
+        assert_eq!(40, balance(&mut tester, "bob"));
+        assert_eq!(0, balance(&mut tester, ESCROW_APP_NAME));
+
+        let open: Vec<Escrow> = tester.query_as("rapido.escrow/open", vec![]).unwrap();
+        assert!(open.is_empty());
+    }
+
+    // Inflates the amount of an escrow, so its refund fails
+    struct Corrupt;
+    impl AppModule for Corrupt {
+        fn name(&self) -> String {
+            "corrupt".into()
+        }
+
+        fn handle_tx(&self, _ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+            let mut escrow = EscrowStore::new().get(0, view).unwrap();
+            escrow.amount = 1000;
+            EscrowStore::new().put(0, escrow, view);
+            Ok(())
+        }
+
+        fn handle_query(
+            &self,
+            _path: &str,
+            _key: Vec<u8>,
+            _view: &QueryView,
+        ) -> Result<QueryResponse, anyhow::Error> {
+            bail!("invalid query")
+        }
+    }
+
+    #[test]
+    fn test_escrow_timeout() {
+        let app = AppBuilder::new()
+            .with_app(BankModule::new())
+            .with_app(EscrowModule {})
+            .with_app(Corrupt);
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(r#"{"rapido.bank": {"bob": 100}}"#);
+
+        let timeout = tester.height() + 3;
+        let lock = |amount| EscrowMsgs::Lock {
+            recipient: "alice".into(),
+            arbiter: "carol".into(),
+            amount,
+            timeout,
+        };
+        let corrupt = TxBuilder::new("corrupt")
+            .sender("bob")
+            .msg(0u8)
+            .build()
+            .unwrap();
+        tester
+            .produce_block(&[
+                &escrow_tx("bob", lock(60)),
+                &escrow_tx("bob", lock(30)),
+                &corrupt,
+            ])
+            .unwrap();
+        assert_eq!(10, balance(&mut tester, "bob"));
+
+        // The refund of escrow 0 fails, escrow 1 is still refunded
+        tester.advance_blocks(3).unwrap();
+        assert_eq!(40, balance(&mut tester, "bob"));
+        assert_eq!(60, balance(&mut tester, ESCROW_APP_NAME));
+        let open: Vec<Escrow> = tester.query_as("rapido.escrow/open", vec![]).unwrap();
+        assert_eq!(vec![0], open.iter().map(|e| e.id).collect::<Vec<_>>());
+    }
+}
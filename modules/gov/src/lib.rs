@@ -0,0 +1,881 @@
+//!
+//! On-chain governance.  Accounts submit proposals with a deposit of bank tokens.
+//! Once the deposit reaches the minimum the proposal enters the voting period where
+//! accounts vote with the weight of their balance.  Proposals are tallied in
+//! `end_block` when the voting period ends, with the balances of the voters at
+//! that height, so tokens moved to another account after voting are only counted
+//! once.  Passed proposals are executed via cross-module dispatch.  Deposits are held by the gov AppModule's bank account and
+//! returned when the proposal ends.
+//!
+//! Parameter-change proposals are executed with a `ParamsMsgs::Set` to the params
+//! AppModule, so it should be governed by this module:
+//! ```ignore
+//! AppBuilder::new()
+//!     .with_app(BankModule::new())
+//!     .with_app(ParamsModule::governed_by(GOV_APP_NAME))
+//!     .with_app(GovModule::new())
+//! ```
+//! The gov settings are params of 'rapido.gov' (`min_deposit`, `deposit_period`,
+//! `voting_period`, `quorum`, `threshold`), so they can be changed by a proposal.
+//! The values given to `GovModule` are the defaults.
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_bank::{BankMsgs, BANK_APP_NAME};
+use rapido_core::{
+    params::{Params, ParamsMsgs, PARAMS_APP_NAME},
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreValue, StoreView,
+};
+
+/// Name of the governance AppModule
+pub const GOV_APP_NAME: &str = "rapido.gov";
+const PROPOSAL_STORE_NAME: &str = "rapido.gov.proposals";
+const VOTE_STORE_NAME: &str = "rapido.gov.votes";
+const GOV_STATE_STORE_NAME: &str = "rapido.gov.state";
+
+// Key of the GovState in its store
+const GOV_STATE_KEY: &str = "state";
+
+/// What a proposal does if it passes
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum ProposalContent {
+    /// A signalling proposal. Nothing is executed
+    Text { title: String, description: String },
+    /// Set the Borsh encoded `value` of a parameter. See `rapido_core::params`
+    ParamChange {
+        module: String,
+        key: String,
+        value: Vec<u8>,
+    },
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ProposalStatus {
+    /// Waiting for the deposit to reach the minimum
+    DepositPeriod,
+    VotingPeriod,
+    Passed,
+    Rejected,
+    /// Passed but the execution returned an error
+    Failed,
+    /// The minimum deposit wasn't reached in the deposit period
+    Expired,
+}
+
+impl ProposalStatus {
+    /// Is the proposal still collecting deposits or votes?
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            ProposalStatus::DepositPeriod | ProposalStatus::VotingPeriod
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProposalStatus::DepositPeriod => "deposit_period",
+            ProposalStatus::VotingPeriod => "voting_period",
+            ProposalStatus::Passed => "passed",
+            ProposalStatus::Rejected => "rejected",
+            ProposalStatus::Failed => "failed",
+            ProposalStatus::Expired => "expired",
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub enum VoteOption {
+    Yes,
+    No,
+    Abstain,
+}
+
+impl VoteOption {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VoteOption::Yes => "yes",
+            VoteOption::No => "no",
+            VoteOption::Abstain => "abstain",
+        }
+    }
+}
+
+/// The weight of the votes for each option
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq)]
+pub struct Tally {
+    pub yes: u64,
+    pub no: u64,
+    pub abstain: u64,
+}
+
+impl Tally {
+    pub fn total(&self) -> u64 {
+        self.yes + self.no + self.abstain
+    }
+
+    /// Does the tally pass? At least `quorum` percent of the `supply` must have
+    /// voted and more than `threshold` percent of the yes and no votes are yes
+    pub fn passes(&self, supply: u64, quorum: u64, threshold: u64) -> bool {
+        let has_quorum = self.total() as u128 * 100 >= supply as u128 * quorum as u128;
+        let has_threshold =
+            self.yes as u128 * 100 > (self.yes + self.no) as u128 * threshold as u128;
+        has_quorum && has_threshold
+    }
+
+    fn add(&mut self, option: VoteOption, weight: u64) {
+        match option {
+            VoteOption::Yes => self.yes += weight,
+            VoteOption::No => self.no += weight,
+            VoteOption::Abstain => self.abstain += weight,
+        }
+    }
+
+    fn subtract(&mut self, option: VoteOption, weight: u64) {
+        match option {
+            VoteOption::Yes => self.yes -= weight,
+            VoteOption::No => self.no -= weight,
+            VoteOption::Abstain => self.abstain -= weight,
+        }
+    }
+}
+
+/// Proposal Model
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: AccountId,
+    pub content: ProposalContent,
+    pub status: ProposalStatus,
+    /// Deposits by account
+    pub deposits: Vec<(AccountId, u64)>,
+    /// Last height to reach the minimum deposit
+    pub deposit_end: i64,
+    /// Height the voting period ends. Set when voting starts
+    pub voting_end: Option<i64>,
+    /// Weighted by the balances of the voters when they voted, until the final
+    /// tally with their balances at the end of the voting period
+    pub tally: Tally,
+    /// Accounts that voted, in order
+    pub voters: Vec<AccountId>,
+}
+
+impl Proposal {
+    /// Total deposited
+    pub fn total_deposit(&self) -> u64 {
+        self.deposits.iter().map(|(_, amount)| amount).sum()
+    }
+
+    fn add_deposit(&mut self, depositor: AccountId, amount: u64) {
+        match self.deposits.iter_mut().find(|(d, _)| *d == depositor) {
+            Some((_, total)) => *total += amount,
+            None => self.deposits.push((depositor, amount)),
+        }
+    }
+}
+
+/// A vote and the weight it was cast with
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct Vote {
+    pub option: VoteOption,
+    pub weight: u64,
+}
+
+// The next proposal id and the proposals tallied in end_block
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, Default)]
+pub(crate) struct GovState {
+    next_id: u64,
+    active: Vec<u64>,
+}
+
+pub(crate) struct ProposalStore;
+impl Store for ProposalStore {
+    type Key = u64;
+    type Value = Proposal;
+
+    fn name(&self) -> String {
+        PROPOSAL_STORE_NAME.into()
+    }
+}
+
+impl ProposalStore {
+    pub fn new() -> Self {
+        ProposalStore {}
+    }
+}
+
+/// Votes keyed by (proposal id, voter)
+pub(crate) struct VoteStore;
+impl Store for VoteStore {
+    type Key = (u64, AccountId);
+    type Value = Vote;
+
+    fn name(&self) -> String {
+        VOTE_STORE_NAME.into()
+    }
+}
+
+impl VoteStore {
+    pub fn new() -> Self {
+        VoteStore {}
+    }
+}
+
+pub(crate) struct GovStateStore;
+impl Store for GovStateStore {
+    type Key = String;
+    type Value = GovState;
+
+    fn name(&self) -> String {
+        GOV_STATE_STORE_NAME.into()
+    }
+}
+
+impl GovStateStore {
+    pub fn new() -> Self {
+        GovStateStore {}
+    }
+
+    fn load(&self, view: &StoreView) -> GovState {
+        self.get(GOV_STATE_KEY.into(), view).unwrap_or_default()
+    }
+
+    fn save(&self, state: GovState, view: &mut StoreView) {
+        self.put(GOV_STATE_KEY.into(), state, view)
+    }
+}
+
+/// Messages to the gov AppModule
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum GovMsgs {
+    /// Submit a proposal with an initial deposit
+    Submit {
+        content: ProposalContent,
+        deposit: u64,
+    },
+    /// Add to the deposit of a proposal in the deposit period
+    Deposit { id: u64, amount: u64 },
+    /// Vote on a proposal in the voting period. Voting again replaces the vote
+    Vote { id: u64, option: VoteOption },
+}
+
+/// Emitted as 'rapido.gov.submit'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct SubmitEvent {
+    pub id: u64,
+    pub proposer: AccountId,
+}
+
+/// Emitted as 'rapido.gov.deposit'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct DepositEvent {
+    pub id: u64,
+    pub depositor: AccountId,
+    pub amount: u64,
+}
+
+/// Emitted as 'rapido.gov.vote'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct VoteEvent {
+    pub id: u64,
+    pub voter: AccountId,
+    pub option: String,
+}
+
+/// Emitted as 'rapido.gov.proposal_result' when a proposal ends
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct ProposalResultEvent {
+    pub id: u64,
+    pub status: String,
+}
+
+// Settings read from the params store with the defaults from GovModule
+struct GovParams {
+    min_deposit: u64,
+    deposit_period: i64,
+    voting_period: i64,
+    quorum: u64,
+    threshold: u64,
+}
+
+/// The governance AppModule. Add it via `AppBuilder::with_app`.
+/// Depends on the bank and params AppModules.
+pub struct GovModule {
+    min_deposit: u64,
+    deposit_period: i64,
+    voting_period: i64,
+    quorum: u64,
+    threshold: u64,
+}
+
+impl Default for GovModule {
+    fn default() -> Self {
+        Self {
+            min_deposit: 100,
+            deposit_period: 100,
+            voting_period: 100,
+            quorum: 33,
+            threshold: 50,
+        }
+    }
+}
+
+impl GovModule {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Deposit needed to start voting. Default: 100
+    pub fn min_deposit(mut self, amount: u64) -> Self {
+        self.min_deposit = amount;
+        self
+    }
+
+    /// Number of blocks to reach the minimum deposit. Default: 100
+    pub fn deposit_period(mut self, blocks: i64) -> Self {
+        self.deposit_period = blocks;
+        self
+    }
+
+    /// Number of blocks to vote. Default: 100
+    pub fn voting_period(mut self, blocks: i64) -> Self {
+        self.voting_period = blocks;
+        self
+    }
+
+    /// Percent of the bank supply that must vote for the result to count. Default: 33
+    pub fn quorum(mut self, percent: u64) -> Self {
+        self.quorum = percent;
+        self
+    }
+
+    /// Percent of the yes and no votes that must be yes to pass. Default: 50
+    pub fn threshold(mut self, percent: u64) -> Self {
+        self.threshold = percent;
+        self
+    }
+
+    fn params(&self, view: &StoreView) -> anyhow::Result<GovParams, anyhow::Error> {
+        let params = Params::new(GOV_APP_NAME);
+        Ok(GovParams {
+            min_deposit: params.get_or("min_deposit", self.min_deposit, view)?,
+            deposit_period: params.get_or("deposit_period", self.deposit_period, view)?,
+            voting_period: params.get_or("voting_period", self.voting_period, view)?,
+            quorum: params.get_or("quorum", self.quorum, view)?,
+            threshold: params.get_or("threshold", self.threshold, view)?,
+        })
+    }
+
+    // Move the deposit from the sender to the gov account
+    fn escrow(
+        &self,
+        ctx: &Context,
+        amount: u64,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        ensure!(amount > 0, "deposit must be greater than 0");
+        ctx.dispatch(
+            BANK_APP_NAME,
+            BankMsgs::Transfer {
+                to: GOV_APP_NAME.into(),
+                amount,
+            },
+            view,
+        )
+    }
+
+    // Start voting if the deposit has reached the minimum
+    fn check_deposit(&self, ctx: &Context, proposal: &mut Proposal, params: &GovParams) {
+        if proposal.status == ProposalStatus::DepositPeriod
+            && proposal.total_deposit() >= params.min_deposit
+        {
+            proposal.status = ProposalStatus::VotingPeriod;
+            proposal.voting_end = Some(ctx.height() + params.voting_period);
+        }
+    }
+
+    // Tally or expire the proposal if its period has ended. Returns true if it ended
+    fn process(
+        &self,
+        ctx: &Context,
+        proposal: &mut Proposal,
+        params: &GovParams,
+        view: &mut StoreView,
+    ) -> anyhow::Result<bool, anyhow::Error> {
+        match proposal.status {
+            ProposalStatus::DepositPeriod if ctx.height() >= proposal.deposit_end => {
+                proposal.status = ProposalStatus::Expired;
+            }
+            ProposalStatus::VotingPeriod
+                if proposal.voting_end.map_or(false, |end| ctx.height() >= end) =>
+            {
+                let supply: u64 = ctx
+                    .module(BANK_APP_NAME)?
+                    .query_as("/supply", vec![], view)?;
+                proposal.tally = self.final_tally(ctx, proposal, view)?;
+                proposal.status = if !proposal
+                    .tally
+                    .passes(supply, params.quorum, params.threshold)
+                {
+                    ProposalStatus::Rejected
+                } else {
+                    let checkpoint = view.checkpoint();
+                    match self.execute(ctx, &proposal.content, view) {
+                        Ok(()) => ProposalStatus::Passed,
+                        Err(err) => {
+                            view.rollback_to(checkpoint);
+                            ctx.logger()
+                                .warn(&format!("proposal {} failed: {}", proposal.id, err));
+                            ProposalStatus::Failed
+                        }
+                    }
+                };
+            }
+            _ => return Ok(false),
+        }
+
+        // Return the deposits
+        for (depositor, amount) in &proposal.deposits {
+            ctx.dispatch(
+                BANK_APP_NAME,
+                BankMsgs::SendFromModule {
+                    to: depositor.clone(),
+                    amount: *amount,
+                },
+                view,
+            )?;
+        }
+        ctx.emit(ProposalResultEvent {
+            id: proposal.id,
+            status: proposal.status.as_str().into(),
+        })?;
+        Ok(true)
+    }
+
+    // Weigh each vote with the voter's balance at the end of the voting period.
+    // A voter's tokens can't be moved to another account that votes and be
+    // counted twice
+    fn final_tally(
+        &self,
+        ctx: &Context,
+        proposal: &Proposal,
+        view: &mut StoreView,
+    ) -> anyhow::Result<Tally, anyhow::Error> {
+        let bank = ctx.module(BANK_APP_NAME)?;
+        let votes = VoteStore::new();
+        let mut tally = Tally::default();
+        for voter in &proposal.voters {
+            let key = (proposal.id, voter.clone());
+            let mut vote = match votes.get(key.clone(), view) {
+                Some(v) => v,
+                None => continue,
+            };
+            vote.weight = bank.query_as("/", voter.clone(), view)?;
+            tally.add(vote.option, vote.weight);
+            votes.put(key, vote, view);
+        }
+        Ok(tally)
+    }
+
+    fn execute(
+        &self,
+        ctx: &Context,
+        content: &ProposalContent,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        match content {
+            ProposalContent::Text { .. } => Ok(()),
+            ProposalContent::ParamChange { module, key, value } => ctx.dispatch(
+                PARAMS_APP_NAME,
+                ParamsMsgs::Set {
+                    module: module.clone(),
+                    key: key.clone(),
+                    value: value.clone(),
+                },
+                view,
+            ),
+        }
+    }
+}
+
+impl AppModule for GovModule {
+    fn name(&self) -> String {
+        GOV_APP_NAME.into()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        vec![BANK_APP_NAME.into(), PARAMS_APP_NAME.into()]
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        GovMsgs::dispatch(self, ctx, view)
+    }
+
+    // Tally the proposals whose voting period has ended. A proposal that can't be
+    // ended is left unchanged and tried again in the next block
+    fn end_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let params = self.params(view)?;
+        let state_store = GovStateStore::new();
+        let mut state = state_store.load(view);
+        if state.active.is_empty() {
+            return Ok(());
+        }
+
+        let store = ProposalStore::new();
+        let mut active = Vec::new();
+        for id in state.active {
+            let mut proposal = match store.get(id, view) {
+                Some(p) => p,
+                None => continue,
+            };
+            let checkpoint = view.checkpoint();
+            match self.process(ctx, &mut proposal, &params, view) {
+                Ok(true) => store.put(id, proposal, view),
+                Ok(false) => active.push(id),
+                Err(err) => {
+                    view.rollback_to(checkpoint);
+                    ctx.logger()
+                        .warn(&format!("proposal {} couldn't end: {}", id, err));
+                    active.push(id);
+                }
+            }
+        }
+        state.active = active;
+        state_store.save(state, view);
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/proposal/:id", |params, view| {
+                let id: u64 = params.require("id")?.parse()?;
+                Ok(ProposalStore::new().query(id, view))
+            })
+            .add("/vote/:id", |params, view| {
+                let id: u64 = params.require("id")?.parse()?;
+                Ok(VoteStore::new().query((id, params.key().to_vec()), view))
+            })
+            .add("/active", |_, view| {
+                let state = GovStateStore::new()
+                    .query(GOV_STATE_KEY.into(), view)
+                    .unwrap_or_default();
+                Ok(Some(state.active))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/proposal/:id".into(), "/vote/:id".into(), "/active".into()]
+    }
+}
+
+impl GovMsgsHandler for GovModule {
+    fn submit(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        content: ProposalContent,
+        deposit: u64,
+    ) -> Result<(), anyhow::Error> {
+        let params = self.params(view)?;
+        self.escrow(ctx, deposit, view)?;
+
+        let state_store = GovStateStore::new();
+        let mut state = state_store.load(view);
+        let id = state.next_id;
+        state.next_id += 1;
+        state.active.push(id);
+        state_store.save(state, view);
+
+        let mut proposal = Proposal {
+            id,
+            proposer: ctx.sender(),
+            content,
+            status: ProposalStatus::DepositPeriod,
+            deposits: vec![(ctx.sender(), deposit)],
+            deposit_end: ctx.height() + params.deposit_period,
+            voting_end: None,
+            tally: Tally::default(),
+            voters: Vec::new(),
+        };
+        self.check_deposit(ctx, &mut proposal, &params);
+        ProposalStore::new().put(id, proposal, view);
+
+        ctx.emit(SubmitEvent {
+            id,
+            proposer: ctx.sender(),
+        })
+    }
+
+    fn deposit(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        id: u64,
+        amount: u64,
+    ) -> Result<(), anyhow::Error> {
+        let store = ProposalStore::new();
+        let mut proposal = match store.get(id, view) {
+            Some(p) => p,
+            None => bail!("proposal {} not found", id),
+        };
+        ensure!(
+            proposal.status == ProposalStatus::DepositPeriod,
+            "proposal {} is not in the deposit period",
+            id
+        );
+
+        let params = self.params(view)?;
+        self.escrow(ctx, amount, view)?;
+        proposal.add_deposit(ctx.sender(), amount);
+        self.check_deposit(ctx, &mut proposal, &params);
+        store.put(id, proposal, view);
+
+        ctx.emit(DepositEvent {
+            id,
+            depositor: ctx.sender(),
+            amount,
+        })
+    }
+
+    fn vote(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        id: u64,
+        option: VoteOption,
+    ) -> Result<(), anyhow::Error> {
+        let store = ProposalStore::new();
+        let mut proposal = match store.get(id, view) {
+            Some(p) => p,
+            None => bail!("proposal {} not found", id),
+        };
+        ensure!(
+            proposal.status == ProposalStatus::VotingPeriod,
+            "proposal {} is not in the voting period",
+            id
+        );
+
+        // Votes are weighted by the voter's balance, until the final tally
+        let voter = ctx.sender();
+        let weight: u64 = ctx
+            .module(BANK_APP_NAME)?
+            .query_as("/", voter.clone(), view)?;
+        ensure!(weight > 0, "only accounts with a balance can vote");
+
+        let votes = VoteStore::new();
+        match votes.get((id, voter.clone()), view) {
+            Some(previous) => proposal.tally.subtract(previous.option, previous.weight),
+            None => proposal.voters.push(voter.clone()),
+        }
+        proposal.tally.add(option, weight);
+        votes.put((id, voter.clone()), Vote { option, weight }, view);
+        store.put(id, proposal, view);
+
+        ctx.emit(VoteEvent {
+            id,
+            voter,
+            option: option.as_str().into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_bank::BankModule;
+    use rapido_core::{
+        params::{ParamsModule, ParamsStore},
+        AppBuilder, SignedTransaction, TestKit, TxBuilder,
+    };
+
+    fn gov_tx(sender: &str, msg: GovMsgs) -> SignedTransaction {
+        TxBuilder::new(GOV_APP_NAME)
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    }
+
+    fn proposal(tester: &mut TestKit, id: u64) -> Proposal {
+        tester
+            .query_as(&format!("{}/proposal/{}", GOV_APP_NAME, id), vec![])
+            .unwrap()
+    }
+
+    fn balance(tester: &mut TestKit, account: &str) -> u64 {
+        tester
+            .query_as(BANK_APP_NAME, account.as_bytes().to_vec())
+            .unwrap()
+    }
+
+    fn param_change(value: u64) -> ProposalContent {
+        ProposalContent::ParamChange {
+            module: "myapp".into(),
+            key: "fee".into(),
+            value: value.try_to_vec().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_tally() {
+        let tally = Tally {
+            yes: 30,
+            no: 20,
+            abstain: 10,
+        };
+        assert!(tally.passes(100, 50, 50));
+        // No quorum
+        assert!(!tally.passes(200, 33, 50));
+        // Below the threshold
+        assert!(!tally.passes(100, 50, 60));
+        assert!(!Tally::default().passes(100, 0, 50));
+    }
+
+    #[test]
+    fn test_gov_proposal() {
+        let app = AppBuilder::new()
+            .with_app(BankModule::new())
+            .with_app(ParamsModule::governed_by(GOV_APP_NAME))
+            .with_app(GovModule::new().min_deposit(50));
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(r#"{"rapido.bank": {"bob": 100, "alice": 60, "carol": 40}}"#);
+
+        // Submit with less than the minimum deposit
+        let submit = GovMsgs::Submit {
+            content: param_change(10),
+            deposit: 20,
+        };
+        assert!(tester.commit_tx(&[&gov_tx("bob", submit)]).is_ok());
+        assert_eq!(80, balance(&mut tester, "bob"));
+        assert_eq!(20, balance(&mut tester, GOV_APP_NAME));
+        assert_eq!(
+            ProposalStatus::DepositPeriod,
+            proposal(&mut tester, 0).status
+        );
+
+        // Can't vote yet
+        let vote = GovMsgs::Vote {
+            id: 0,
+            option: VoteOption::Yes,
+        };
+        assert!(tester.commit_tx(&[&gov_tx("alice", vote.clone())]).is_err());
+
+        // Reach the minimum deposit
+        let deposit = GovMsgs::Deposit { id: 0, amount: 30 };
+        assert!(tester.commit_tx(&[&gov_tx("alice", deposit)]).is_ok());
+        let p = proposal(&mut tester, 0);
+        assert_eq!(ProposalStatus::VotingPeriod, p.status);
+        assert_eq!(50, p.total_deposit());
+        assert!(p.voting_end.is_some());
+
+        // Votes are weighted by balance and can be changed
+        assert!(tester.commit_tx(&[&gov_tx("alice", vote)]).is_ok());
+        let no = GovMsgs::Vote {
+            id: 0,
+            option: VoteOption::No,
+        };
+        assert!(tester.commit_tx(&[&gov_tx("carol", no.clone())]).is_ok());
+        let abstain = GovMsgs::Vote {
+            id: 0,
+            option: VoteOption::Abstain,
+        };
+        assert!(tester.commit_tx(&[&gov_tx("carol", abstain)]).is_ok());
+
+        let p = proposal(&mut tester, 0);
+        assert_eq!(
+            Tally {
+                yes: 30,
+                no: 0,
+                abstain: 40
+            },
+            p.tally
+        );
+
+        // No balance, no vote
+        assert!(tester.commit_tx(&[&gov_tx("dave", no)]).is_err());
+    }
+
+    #[test]
+    fn test_gov_proposal_end() {
+        let app = AppBuilder::new()
+            .with_app(BankModule::new())
+            .with_app(ParamsModule::governed_by(GOV_APP_NAME))
+            .with_app(
+                GovModule::new()
+                    .min_deposit(50)
+                    .deposit_period(2)
+                    .voting_period(2),
+            );
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(
+            r#"{"rapido.bank": {"bob": 100, "alice": 60, "carol": 40, "dave": 10}}"#,
+        );
+
+        // Proposal 0 starts voting, proposal 1 never reaches the minimum deposit
+        let submit = |content, deposit| GovMsgs::Submit { content, deposit };
+        let text = ProposalContent::Text {
+            title: "hello".into(),
+            description: "world".into(),
+        };
+        let block = tester
+            .produce_block(&[
+                &gov_tx("bob", submit(param_change(10), 50)),
+                &gov_tx("dave", submit(text, 10)),
+            ])
+            .unwrap();
+        assert!(block.txs.iter().all(|tx| tx.is_ok()));
+        assert_eq!(Some(3), proposal(&mut tester, 0).voting_end);
+
+        // Alice votes and moves her tokens to carol, who votes with them again
+        let vote = |id, option| GovMsgs::Vote { id, option };
+        let transfer = TxBuilder::new(BANK_APP_NAME)
+            .sender("alice")
+            .msg(BankMsgs::Transfer {
+                to: "carol".into(),
+                amount: 60,
+            })
+            .build()
+            .unwrap();
+        let block = tester
+            .produce_block(&[
+                &gov_tx("alice", vote(0, VoteOption::Yes)),
+                &transfer,
+                &gov_tx("carol", vote(0, VoteOption::Yes)),
+                &gov_tx("bob", vote(0, VoteOption::No)),
+            ])
+            .unwrap();
+        assert!(block.txs.iter().all(|tx| tx.is_ok()));
+        assert_eq!(160, proposal(&mut tester, 0).tally.yes);
+
+        // The voting period ends: alice's tokens are only counted once, for carol
+        tester.produce_block(&[]).unwrap();
+        let p = proposal(&mut tester, 0);
+        assert_eq!(ProposalStatus::Passed, p.status);
+        assert_eq!(
+            Tally {
+                yes: 100,
+                no: 50,
+                abstain: 0
+            },
+            p.tally
+        );
+        assert_eq!(ProposalStatus::Expired, proposal(&mut tester, 1).status);
+        let active: Vec<u64> = tester
+            .query_as(&format!("{}/active", GOV_APP_NAME), vec![])
+            .unwrap();
+        assert!(active.is_empty());
+
+        // The param change is executed and the deposits returned
+        let fee = tester.read_store(&ParamsStore::new(), ("myapp".into(), "fee".into()));
+        assert_eq!(Some(10u64.try_to_vec().unwrap()), fee);
+        assert_eq!(100, balance(&mut tester, "bob"));
+        assert_eq!(10, balance(&mut tester, "dave"));
+        assert_eq!(0, balance(&mut tester, GOV_APP_NAME));
+    }
+}
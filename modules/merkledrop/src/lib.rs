@@ -0,0 +1,285 @@
+//!
+//! Merkle drop.  Distribute bank tokens to many accounts without a tx per account.
+//! The module is configured with the Merkle root of the drop's (index, account,
+//! amount) entries and its bank account is funded with the total.  Each account
+//! claims its amount once with a proof of its entry.  After the end height claims
+//! are rejected and the unclaimed tokens go to a community account.
+//! ```ignore
+//! let leaves: Vec<Hash> = entries.iter().map(|(i, account, amount)| leaf_hash(*i, account, *amount)).collect();
+//! let root = merkle_root(&leaves).unwrap();
+//! let proof = merkle_proof(&leaves, 0).unwrap();
+//! ```
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use exonum_crypto::Hash;
+use rapido_bank::{BankMsgs, BANK_APP_NAME};
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreView,
+};
+
+mod tree;
+
+pub use tree::{leaf_hash, merkle_proof, merkle_root, verify_proof};
+
+/// Name of the merkle drop AppModule. Also the bank account holding the drop
+pub const MERKLEDROP_APP_NAME: &str = "rapido.merkledrop";
+const CLAIMED_STORE_NAME: &str = "rapido.merkledrop.claimed";
+const SWEPT_STORE_NAME: &str = "rapido.merkledrop.swept";
+
+// Key of the swept height in its store
+const SWEPT_KEY: &str = "swept";
+
+/// Bitmap of the claimed indexes, 64 per word, keyed by word
+pub(crate) struct ClaimedStore;
+impl Store for ClaimedStore {
+    type Key = u64;
+    type Value = u64;
+
+    fn name(&self) -> String {
+        CLAIMED_STORE_NAME.into()
+    }
+}
+
+impl ClaimedStore {
+    pub fn new() -> Self {
+        ClaimedStore {}
+    }
+
+    /// Was the index claimed?
+    pub fn is_claimed(&self, index: u64, view: &StoreView) -> bool {
+        let word = self.get(index / 64, view).unwrap_or_default();
+        word & (1 << (index % 64)) != 0
+    }
+
+    fn set_claimed(&self, index: u64, view: &mut StoreView) {
+        let word = self.get(index / 64, view).unwrap_or_default();
+        self.put(index / 64, word | (1 << (index % 64)), view)
+    }
+}
+
+// Height the unclaimed tokens were sent to the community account
+pub(crate) struct SweptStore;
+impl Store for SweptStore {
+    type Key = String;
+    type Value = i64;
+
+    fn name(&self) -> String {
+        SWEPT_STORE_NAME.into()
+    }
+}
+
+impl SweptStore {
+    pub fn new() -> Self {
+        SweptStore {}
+    }
+}
+
+/// Messages to the merkle drop
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum MerkleDropMsgs {
+    /// Claim the amount of the sender's entry at the index
+    Claim {
+        index: u64,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    },
+}
+
+/// Emitted as 'rapido.merkledrop.claim'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct ClaimEvent {
+    pub index: u64,
+    pub account: AccountId,
+    pub amount: u64,
+}
+
+/// Emitted as 'rapido.merkledrop.sweep' in end_block
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct SweepEvent {
+    pub to: AccountId,
+    pub amount: u64,
+}
+
+/// The merkle drop AppModule. Add it via `AppBuilder::with_app` and fund its bank
+/// account, e.g. in the bank's genesis.
+pub struct MerkleDropModule {
+    root: Hash,
+    end_height: i64,
+    community: AccountId,
+}
+
+impl MerkleDropModule {
+    /// A drop with the Merkle root.  Claims are accepted before the end height,
+    /// then the unclaimed tokens go to the community account
+    pub fn new(root: Hash, end_height: i64, community: impl Into<AccountId>) -> Self {
+        Self {
+            root,
+            end_height,
+            community: community.into(),
+        }
+    }
+}
+
+impl AppModule for MerkleDropModule {
+    fn name(&self) -> String {
+        MERKLEDROP_APP_NAME.into()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        vec![BANK_APP_NAME.into()]
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        MerkleDropMsgs::dispatch(self, ctx, view)
+    }
+
+    // Send the unclaimed tokens to the community account once the drop ends
+    fn end_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let swept = SweptStore::new();
+        if ctx.height() < self.end_height || swept.get(SWEPT_KEY.into(), view).is_some() {
+            return Ok(());
+        }
+        swept.put(SWEPT_KEY.into(), ctx.height(), view);
+
+        let amount: u64 = ctx.module(BANK_APP_NAME)?.query_as(
+            "/",
+            MERKLEDROP_APP_NAME.as_bytes().to_vec(),
+            view,
+        )?;
+        if amount == 0 {
+            return Ok(());
+        }
+        ctx.dispatch(
+            BANK_APP_NAME,
+            BankMsgs::SendFromModule {
+                to: self.community.clone(),
+                amount,
+            },
+            view,
+        )?;
+        ctx.emit(SweepEvent {
+            to: self.community.clone(),
+            amount,
+        })
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/claimed/:index", |params, view| {
+                let index: u64 = params.require("index")?.parse()?;
+                let word = ClaimedStore::new()
+                    .query(index / 64, view)
+                    .unwrap_or_default();
+                Ok(Some(word & (1 << (index % 64)) != 0))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/claimed/:index".into()]
+    }
+}
+
+impl MerkleDropMsgsHandler for MerkleDropModule {
+    fn claim(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        index: u64,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(ctx.height() < self.end_height, "the drop has ended");
+        let account = ctx.sender();
+        let claimed = ClaimedStore::new();
+        ensure!(!claimed.is_claimed(index, view), "already claimed");
+        if !verify_proof(&self.root, leaf_hash(index, &account, amount), &proof) {
+            bail!("invalid proof");
+        }
+
+        claimed.set_claimed(index, view);
+        ctx.dispatch(
+            BANK_APP_NAME,
+            BankMsgs::SendFromModule {
+                to: account.clone(),
+                amount,
+            },
+            view,
+        )?;
+        ctx.emit(ClaimEvent {
+            index,
+            account,
+            amount,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_bank::BankModule;
+    use rapido_core::{AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    fn claim_tx(sender: &str, index: u64, amount: u64, proof: Vec<[u8; 32]>) -> SignedTransaction {
+        TxBuilder::new(MERKLEDROP_APP_NAME)
+            .sender(sender)
+            .msg(MerkleDropMsgs::Claim {
+                index,
+                amount,
+                proof,
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_claim() {
+        let entries = vec![("bob", 10u64), ("alice", 20), ("carol", 30)];
+        let leaves: Vec<Hash> = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (account, amount))| {
+                leaf_hash(i as u64, &account.as_bytes().to_vec(), *amount)
+            })
+            .collect();
+        let root = merkle_root(&leaves).unwrap();
+
+        let app = AppBuilder::new()
+            .with_app(BankModule::new())
+            .with_app(MerkleDropModule::new(root, 100, "community"));
+        let mut tester = TestKit::create(app);
+        tester.start_with_genesis(r#"{"rapido.bank": {"rapido.merkledrop": 60}}"#);
+
+        let proof = merkle_proof(&leaves, 1).unwrap();
+        // Not alice's entry
+        assert!(tester
+            .commit_tx(&[&claim_tx("bob", 1, 20, proof.clone())])
+            .is_err());
+        // Wrong amount
+        assert!(tester
+            .commit_tx(&[&claim_tx("alice", 1, 25, proof.clone())])
+            .is_err());
+
+        assert!(tester
+            .commit_tx(&[&claim_tx("alice", 1, 20, proof.clone())])
+            .is_ok());
+        let balance: u64 = tester.query_as(BANK_APP_NAME, b"alice".to_vec()).unwrap();
+        assert_eq!(20, balance);
+        let claimed: bool = tester
+            .query_as(&format!("{}/claimed/1", MERKLEDROP_APP_NAME), vec![])
+            .unwrap();
+        assert!(claimed);
+
+        // Only once
+        assert!(tester
+            .commit_tx(&[&claim_tx("alice", 1, 20, proof)])
+            .is_err());
+    }
+}
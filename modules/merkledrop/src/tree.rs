@@ -0,0 +1,127 @@
+//!
+//! The Merkle tree of an airdrop.  Leaves are the hash of (index, account, amount).
+//! Pairs are hashed in sorted order, so a proof is just the sibling hashes from the
+//! leaf up.  Leaves are prefixed with `LEAF_PREFIX` and pairs with `NODE_PREFIX`
+//! before hashing, so an inner node can't be passed off as a leaf.  `merkle_root` and `merkle_proof` are for the tools building the drop.
+//!
+use borsh::BorshSerialize;
+use exonum_crypto::{hash, Hash};
+use rapido_core::AccountId;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Return the leaf of an entry of the drop
+pub fn leaf_hash(index: u64, account: &AccountId, amount: u64) -> Hash {
+    let mut encoded = vec![LEAF_PREFIX];
+    (index, account.clone(), amount)
+        .serialize(&mut encoded)
+        .expect("encoding a leaf");
+    hash(&encoded)
+}
+
+fn hash_pair(a: &Hash, b: &Hash) -> Hash {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let mut both = vec![NODE_PREFIX];
+    both.extend_from_slice(first.as_ref());
+    both.extend_from_slice(second.as_ref());
+    hash(&both)
+}
+
+// The levels of the tree, from the leaves up to the root.  An odd node is
+// promoted to the next level
+fn levels(leaves: &[Hash]) -> Vec<Vec<Hash>> {
+    let mut levels = vec![leaves.to_vec()];
+    while levels.last().map_or(false, |l| l.len() > 1) {
+        let next = levels
+            .last()
+            .unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => hash_pair(a, b),
+                [a] => *a,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// Return the root of the leaves. `None` if there are none
+pub fn merkle_root(leaves: &[Hash]) -> Option<Hash> {
+    levels(leaves).last().and_then(|l| l.first().copied())
+}
+
+/// Return the proof of the leaf at the position. `None` if out of range
+pub fn merkle_proof(leaves: &[Hash], position: usize) -> Option<Vec<[u8; 32]>> {
+    if position >= leaves.len() {
+        return None;
+    }
+    let mut proof = Vec::new();
+    let mut pos = position;
+    for level in levels(leaves).iter().filter(|l| l.len() > 1) {
+        let sibling = pos ^ 1;
+        if sibling < level.len() {
+            let mut raw = [0u8; 32];
+            raw.copy_from_slice(level[sibling].as_ref());
+            proof.push(raw);
+        }
+        pos /= 2;
+    }
+    Some(proof)
+}
+
+/// Does the proof show the leaf is in the tree with the root?
+pub fn verify_proof(root: &Hash, leaf: Hash, proof: &[[u8; 32]]) -> bool {
+    let computed = proof
+        .iter()
+        .fold(leaf, |node, sibling| match Hash::from_slice(sibling) {
+            Some(sibling) => hash_pair(&node, &sibling),
+            None => node,
+        });
+    computed == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merkle_tree() {
+        let leaves: Vec<Hash> = (0..5u64)
+            .map(|i| leaf_hash(i, &format!("user{}", i).into_bytes(), 10 * i))
+            .collect();
+        let root = merkle_root(&leaves).unwrap();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let proof = merkle_proof(&leaves, i).unwrap();
+            assert!(verify_proof(&root, *leaf, &proof));
+        }
+
+        // Wrong amount
+        let proof = merkle_proof(&leaves, 1).unwrap();
+        assert!(!verify_proof(
+            &root,
+            leaf_hash(1, &b"user1".to_vec(), 11),
+            &proof
+        ));
+
+        assert_eq!(Some(leaves[0]), merkle_root(&leaves[..1]));
+        assert!(merkle_root(&[]).is_none());
+        assert!(merkle_proof(&leaves, 5).is_none());
+    }
+
+    #[test]
+    fn test_leaf_is_not_a_node() {
+        // An entry encoding to 64 bytes, the size of a pair of hashes
+        let account = vec![0xffu8; 44];
+        let encoded = (0u64, account.clone(), 7u64).try_to_vec().unwrap();
+        assert_eq!(64, encoded.len());
+        let first = Hash::from_slice(&encoded[..32]).unwrap();
+        let second = Hash::from_slice(&encoded[32..]).unwrap();
+        assert!(first < second);
+
+        assert_ne!(leaf_hash(0, &account, 7), hash_pair(&first, &second));
+        assert_ne!(hash(&encoded), leaf_hash(0, &account, 7));
+    }
+}
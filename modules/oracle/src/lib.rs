@@ -0,0 +1,360 @@
+//!
+//! Oracle for externally reported data.  An allowlisted set of reporters submit
+//! values for a key (e.g. 'BTC-USD') during a window of blocks.  In `end_block` at
+//! the end of the window, the median of the reports for each key becomes its
+//! current value.  Other AppModules read it with `oracle_value`:
+//! ```ignore
+//! let price = oracle_value(ctx, "BTC-USD", view)?.value;
+//! ```
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreValue, StoreView,
+};
+
+/// Name of the oracle AppModule
+pub const ORACLE_APP_NAME: &str = "rapido.oracle";
+const VALUE_STORE_NAME: &str = "rapido.oracle.values";
+const REPORT_STORE_NAME: &str = "rapido.oracle.reports";
+const PENDING_STORE_NAME: &str = "rapido.oracle.pending";
+
+/// Maximum length of a key. Keys may contain ascii alphanumerics, '-', '_' and '.'
+pub const MAX_ORACLE_KEY_LENGTH: usize = 64;
+
+/// The current value of a key
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct OracleValue {
+    pub value: u64,
+    /// Height the value was aggregated
+    pub height: i64,
+    /// Number of reports aggregated
+    pub reports: u32,
+}
+
+/// The reports for a key in the current window
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, Default, PartialEq)]
+pub struct Reports(pub Vec<(AccountId, u64)>);
+
+// Keys with reports in the current window
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, Default)]
+pub(crate) struct PendingKeys(Vec<String>);
+
+pub(crate) struct ValueStore;
+impl Store for ValueStore {
+    type Key = String;
+    type Value = OracleValue;
+
+    fn name(&self) -> String {
+        VALUE_STORE_NAME.into()
+    }
+}
+
+impl ValueStore {
+    pub fn new() -> Self {
+        ValueStore {}
+    }
+}
+
+pub(crate) struct ReportStore;
+impl Store for ReportStore {
+    type Key = String;
+    type Value = Reports;
+
+    fn name(&self) -> String {
+        REPORT_STORE_NAME.into()
+    }
+}
+
+impl ReportStore {
+    pub fn new() -> Self {
+        ReportStore {}
+    }
+}
+
+pub(crate) struct PendingStore;
+impl Store for PendingStore {
+    type Key = String;
+    type Value = PendingKeys;
+
+    fn name(&self) -> String {
+        PENDING_STORE_NAME.into()
+    }
+}
+
+impl PendingStore {
+    pub fn new() -> Self {
+        PendingStore {}
+    }
+}
+
+/// Return the median of the values. None if there are no values
+pub fn median(values: &mut Vec<u64>) -> Option<u64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        Some(((values[mid - 1] as u128 + values[mid] as u128) / 2) as u64)
+    } else {
+        Some(values[mid])
+    }
+}
+
+/// Return the current value of the key from another AppModule
+pub fn oracle_value(
+    ctx: &Context,
+    key: &str,
+    view: &StoreView,
+) -> anyhow::Result<OracleValue, anyhow::Error> {
+    ctx.module(ORACLE_APP_NAME)?
+        .query_as(&format!("/value/{}", key), vec![], view)
+}
+
+/// Messages to the oracle AppModule
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum OracleMsgs {
+    /// Report a value for the key in the current window
+    Report { key: String, value: u64 },
+}
+
+/// Emitted as 'rapido.oracle.update' when a value is aggregated
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct UpdateEvent {
+    pub key: String,
+    pub value: u64,
+    pub reports: u32,
+}
+
+/// The oracle AppModule. Add it via `AppBuilder::with_app`.
+pub struct OracleModule {
+    reporters: Vec<AccountId>,
+    window: i64,
+    min_reports: usize,
+}
+
+impl Default for OracleModule {
+    fn default() -> Self {
+        Self {
+            reporters: Vec::new(),
+            window: 1,
+            min_reports: 1,
+        }
+    }
+}
+
+impl OracleModule {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allow the account to report values
+    pub fn with_reporter(mut self, account: impl Into<AccountId>) -> Self {
+        self.reporters.push(account.into());
+        self
+    }
+
+    /// Number of blocks in a window. Default: 1, aggregate each block
+    pub fn window(mut self, blocks: i64) -> Self {
+        assert!(blocks > 0, "oracle window must be greater than 0");
+        self.window = blocks;
+        self
+    }
+
+    /// Minimum number of reports to update a value. Default: 1
+    pub fn min_reports(mut self, count: usize) -> Self {
+        self.min_reports = count;
+        self
+    }
+
+    // Is the height the last block of a window?
+    fn is_window_end(&self, height: i64) -> bool {
+        (height + 1) % self.window == 0
+    }
+}
+
+impl AppModule for OracleModule {
+    fn name(&self) -> String {
+        ORACLE_APP_NAME.into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        OracleMsgs::dispatch(self, ctx, view)
+    }
+
+    // Aggregate the reports at the end of the window
+    fn end_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        if !self.is_window_end(ctx.height()) {
+            return Ok(());
+        }
+        let pending = PendingStore::new();
+        let keys = match pending.get(ORACLE_APP_NAME.into(), view) {
+            Some(k) => k.0,
+            None => return Ok(()),
+        };
+        pending.remove(ORACLE_APP_NAME.into(), view);
+
+        let reports = ReportStore::new();
+        for key in keys {
+            let mut values: Vec<u64> = reports
+                .get(key.clone(), view)
+                .unwrap_or_default()
+                .0
+                .into_iter()
+                .map(|(_, v)| v)
+                .collect();
+            reports.remove(key.clone(), view);
+            if values.len() < self.min_reports {
+                ctx.logger().info(format!(
+                    "{}: {} reports, need {}",
+                    key,
+                    values.len(),
+                    self.min_reports
+                ));
+                continue;
+            }
+
+            let count = values.len() as u32;
+            let value = median(&mut values).expect("reports");
+            ValueStore::new().put(
+                key.clone(),
+                OracleValue {
+                    value,
+                    height: ctx.height(),
+                    reports: count,
+                },
+                view,
+            );
+            ctx.emit(UpdateEvent {
+                key,
+                value,
+                reports: count,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/value/:key", |params, view| {
+                Ok(ValueStore::new().query(params.require("key")?.into(), view))
+            })
+            .add("/reports/:key", |params, view| {
+                let reports = ReportStore::new()
+                    .query(params.require("key")?.into(), view)
+                    .unwrap_or_default();
+                Ok(Some(reports))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/value/:key".into(), "/reports/:key".into()]
+    }
+}
+
+impl OracleMsgsHandler for OracleModule {
+    fn report(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        key: String,
+        value: u64,
+    ) -> Result<(), anyhow::Error> {
+        let reporter = ctx.sender();
+        ensure!(self.reporters.contains(&reporter), "not an oracle reporter");
+        ensure!(
+            !key.is_empty() && key.len() <= MAX_ORACLE_KEY_LENGTH,
+            "oracle key must be 1 to {} characters",
+            MAX_ORACLE_KEY_LENGTH
+        );
+        // The key is part of the query path
+        if key
+            .chars()
+            .any(|c| !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'))
+        {
+            bail!("invalid oracle key: {}", key);
+        }
+
+        let store = ReportStore::new();
+        let mut reports = store.get(key.clone(), view).unwrap_or_default();
+        if reports.0.is_empty() {
+            let pending = PendingStore::new();
+            let mut keys = pending
+                .get(ORACLE_APP_NAME.into(), view)
+                .unwrap_or_default();
+            keys.0.push(key.clone());
+            pending.put(ORACLE_APP_NAME.into(), keys, view);
+        }
+        match reports.0.iter_mut().find(|(r, _)| *r == reporter) {
+            Some((_, v)) => *v = value,
+            None => reports.0.push((reporter, value)),
+        }
+        store.put(key, reports, view);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    fn report(sender: &str, key: &str, value: u64) -> SignedTransaction {
+        TxBuilder::new(ORACLE_APP_NAME)
+            .sender(sender)
+            .msg(OracleMsgs::Report {
+                key: key.into(),
+                value,
+            })
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(None, median(&mut vec![]));
+        assert_eq!(Some(5), median(&mut vec![9, 5, 1]));
+        assert_eq!(Some(4), median(&mut vec![9, 5, 3, 1]));
+        assert_eq!(Some(u64::MAX), median(&mut vec![u64::MAX, u64::MAX]));
+    }
+
+    #[test]
+    fn test_oracle_reports() {
+        let app = AppBuilder::new().with_app(
+            OracleModule::new()
+                .with_reporter("feeder1")
+                .with_reporter("feeder2"),
+        );
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        assert!(tester.commit_tx(&[&report("bob", "BTC", 10)]).is_err());
+        assert!(tester.commit_tx(&[&report("feeder1", "", 10)]).is_err());
+        assert!(tester
+            .commit_tx(&[
+                &report("feeder1", "BTC-USD", 10),
+                &report("feeder2", "BTC-USD", 12)
+            ])
+            .is_ok());
+        // Replace a report
+        assert!(tester
+            .commit_tx(&[&report("feeder1", "BTC-USD", 11)])
+            .is_ok());
+
+        let reports: Reports = tester
+            .query_as("rapido.oracle/reports/BTC-USD", vec![])
+            .unwrap();
+        assert_eq!(
+            Reports(vec![(b"feeder1".to_vec(), 11), (b"feeder2".to_vec(), 12)]),
+            reports
+        );
+    }
+}
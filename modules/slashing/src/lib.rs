@@ -0,0 +1,541 @@
+//!
+//! Slashing.  In `begin_block` the votes on the last block are used to track the
+//! liveness of each validator over a window of blocks.  A validator that signs less
+//! than the minimum of the window is jailed for a number of blocks.  Evidence of
+//! double signing jails the validator for good (it's tombstoned).  In both cases a
+//! fraction of the validator's bonded stake is slashed.
+//!
+//! Bonds are kept by a staking AppModule, set with `SlashingModule::with_staking`.
+//! The staking module is told to jail, slash and unjail validators by dispatched
+//! `StakingHooks` messages, with this module as the caller:
+//! ```ignore
+//! if ctx.caller() == Some(SLASHING_APP_NAME) {
+//!     match StakingHooks::try_from_slice(&ctx.msg)? {
+//!         StakingHooks::Slash { validator, fraction, .. } => {
+//!             let burned = slash_amount(bonded, fraction);
+//!             ...
+//!         }
+//!         ...
+//!     }
+//! }
+//! ```
+//!
+use std::collections::HashMap;
+
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{
+    params::Params, AccountId, AppModule, Context, Misbehavior, QueryResponse, QueryRouter,
+    QueryView, RapidoEvent, RapidoMessages, Store, StoreValue, StoreView, VoteInfo,
+};
+
+/// Name of the slashing AppModule
+pub const SLASHING_APP_NAME: &str = "rapido.slashing";
+const SIGNING_INFO_STORE_NAME: &str = "rapido.slashing.signing_info";
+
+/// Default number of blocks liveness is tracked over
+pub const DEFAULT_SIGNED_BLOCKS_WINDOW: u64 = 100;
+/// Default percent of the window a validator must sign
+pub const DEFAULT_MIN_SIGNED_PERCENT: u64 = 50;
+/// Default number of blocks a validator is jailed for downtime
+pub const DEFAULT_DOWNTIME_JAIL_BLOCKS: i64 = 600;
+/// Default fraction of the stake slashed for downtime, in basis points
+pub const DEFAULT_SLASH_FRACTION_DOWNTIME: u64 = 1;
+/// Default fraction of the stake slashed for double signing, in basis points
+pub const DEFAULT_SLASH_FRACTION_DOUBLE_SIGN: u64 = 500;
+/// Default number of blocks evidence is accepted after the misbehavior
+pub const DEFAULT_MAX_EVIDENCE_AGE: i64 = 10_000;
+
+/// Basis points in 100%
+pub const BASIS_POINTS: u64 = 10_000;
+
+/// Return the amount slashed from the bonded stake given a fraction in basis points
+pub fn slash_amount(bonded: u64, fraction: u64) -> u64 {
+    (bonded as u128 * fraction.min(BASIS_POINTS) as u128 / BASIS_POINTS as u128) as u64
+}
+
+/// The liveness of a validator
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq, Default)]
+pub struct SigningInfo {
+    /// Number of blocks tracked since the window was last reset
+    pub index: u64,
+    /// Number of blocks missed in the window
+    pub missed: u64,
+    /// Missed blocks in the window, one bit per block
+    pub bitmap: Vec<u8>,
+    /// Height the validator is jailed until
+    pub jailed_until: i64,
+    /// Jailed for good for double signing
+    pub tombstoned: bool,
+}
+
+impl SigningInfo {
+    /// Record whether the validator signed a block
+    pub fn record(&mut self, signed: bool, window: u64) {
+        let bytes = ((window + 7) / 8) as usize;
+        if self.bitmap.len() != bytes {
+            // New validator or the window changed
+            self.reset(window);
+        }
+        let pos = (self.index % window) as usize;
+        let (byte, bit) = (pos / 8, 1u8 << (pos % 8));
+        let missed_before = self.bitmap[byte] & bit != 0;
+        match (missed_before, signed) {
+            (true, true) => {
+                self.bitmap[byte] &= !bit;
+                self.missed -= 1;
+            }
+            (false, false) => {
+                self.bitmap[byte] |= bit;
+                self.missed += 1;
+            }
+            _ => {}
+        }
+        self.index += 1;
+    }
+
+    /// Has the validator missed too many blocks?  Only after a full window
+    pub fn is_down(&self, window: u64, min_signed_percent: u64) -> bool {
+        let min_signed = window * min_signed_percent.min(100) / 100;
+        self.index >= window && self.missed > window - min_signed
+    }
+
+    /// Is the validator jailed at the height?
+    pub fn is_jailed(&self, height: i64) -> bool {
+        self.tombstoned || height < self.jailed_until
+    }
+
+    /// Start a new window
+    pub fn reset(&mut self, window: u64) {
+        self.index = 0;
+        self.missed = 0;
+        self.bitmap = vec![0; ((window + 7) / 8) as usize];
+    }
+}
+
+/// Signing info keyed by validator address
+pub(crate) struct SigningInfoStore;
+impl Store for SigningInfoStore {
+    type Key = Vec<u8>;
+    type Value = SigningInfo;
+
+    fn name(&self) -> String {
+        SIGNING_INFO_STORE_NAME.into()
+    }
+}
+
+impl SigningInfoStore {
+    pub fn new() -> Self {
+        SigningInfoStore {}
+    }
+}
+
+/// Messages dispatched to the staking AppModule.  Fractions are in basis points
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum StakingHooks {
+    /// Remove the validator from the validator set
+    Jail { validator: Vec<u8> },
+    /// Slash the stake bonded to the validator at the infraction height
+    Slash {
+        validator: Vec<u8>,
+        fraction: u64,
+        infraction_height: i64,
+    },
+    /// Return the validator to the validator set
+    Unjail { validator: Vec<u8> },
+}
+
+/// Messages to the slashing AppModule
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum SlashingMsgs {
+    /// Unjail the validator once its jail time is served.  Only accepted from the
+    /// operator of the validator.  See `SlashingModule::with_operator`
+    Unjail { validator: Vec<u8> },
+}
+
+/// Emitted as 'rapido.slashing.jail'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct JailEvent {
+    pub validator: String,
+    pub until: i64,
+    pub reason: String,
+}
+
+/// Emitted as 'rapido.slashing.slash'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct SlashEvent {
+    pub validator: String,
+    pub fraction: u64,
+    pub infraction_height: i64,
+    pub reason: String,
+}
+
+/// Emitted as 'rapido.slashing.unjail'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct UnjailEvent {
+    pub validator: String,
+}
+
+// A punishment of a validator
+struct Infraction<'a> {
+    validator: &'a [u8],
+    height: i64,
+    reason: &'a str,
+    fraction: u64,
+    jailed_until: i64,
+}
+
+// The params of the slashing module
+struct SlashingParams {
+    window: u64,
+    min_signed_percent: u64,
+    downtime_jail_blocks: i64,
+    slash_fraction_downtime: u64,
+    slash_fraction_double_sign: u64,
+    max_evidence_age: i64,
+}
+
+impl SlashingParams {
+    fn load(view: &StoreView) -> anyhow::Result<Self, anyhow::Error> {
+        let params = Params::new(SLASHING_APP_NAME);
+        let window = params.get_or("signed_blocks_window", DEFAULT_SIGNED_BLOCKS_WINDOW, view)?;
+        ensure!(window > 0, "signed_blocks_window must be positive");
+        Ok(Self {
+            window,
+            min_signed_percent: params.get_or(
+                "min_signed_percent",
+                DEFAULT_MIN_SIGNED_PERCENT,
+                view,
+            )?,
+            downtime_jail_blocks: params.get_or(
+                "downtime_jail_blocks",
+                DEFAULT_DOWNTIME_JAIL_BLOCKS,
+                view,
+            )?,
+            slash_fraction_downtime: params.get_or(
+                "slash_fraction_downtime",
+                DEFAULT_SLASH_FRACTION_DOWNTIME,
+                view,
+            )?,
+            slash_fraction_double_sign: params.get_or(
+                "slash_fraction_double_sign",
+                DEFAULT_SLASH_FRACTION_DOUBLE_SIGN,
+                view,
+            )?,
+            max_evidence_age: params.get_or("max_evidence_age", DEFAULT_MAX_EVIDENCE_AGE, view)?,
+        })
+    }
+}
+
+/// The slashing AppModule. Add it via `AppBuilder::with_app`.  The params are
+/// those of `rapido.slashing`: `signed_blocks_window`, `min_signed_percent`,
+/// `downtime_jail_blocks`, `slash_fraction_downtime`, `slash_fraction_double_sign`
+/// and `max_evidence_age`.
+#[derive(Default)]
+pub struct SlashingModule {
+    staking: Option<String>,
+    // Validator address to operator account
+    operators: HashMap<Vec<u8>, AccountId>,
+}
+
+impl SlashingModule {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Dispatch `StakingHooks` to the AppModule holding the bonds
+    pub fn with_staking(mut self, app: &str) -> Self {
+        self.staking = Some(app.into());
+        self
+    }
+
+    /// The account that can unjail the validator with the address
+    pub fn with_operator(mut self, address: &[u8], account: impl Into<AccountId>) -> Self {
+        self.operators.insert(address.to_vec(), account.into());
+        self
+    }
+
+    fn notify_staking(
+        &self,
+        ctx: &Context,
+        hook: StakingHooks,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        match &self.staking {
+            Some(app) => ctx.dispatch(app, hook, view),
+            None => Ok(()),
+        }
+    }
+
+    // Jail and slash the validator
+    fn punish(
+        &self,
+        ctx: &Context,
+        infraction: Infraction,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let validator = infraction.validator.to_vec();
+        self.notify_staking(
+            ctx,
+            StakingHooks::Jail {
+                validator: validator.clone(),
+            },
+            view,
+        )?;
+        self.notify_staking(
+            ctx,
+            StakingHooks::Slash {
+                validator: validator.clone(),
+                fraction: infraction.fraction,
+                infraction_height: infraction.height,
+            },
+            view,
+        )?;
+        ctx.emit(JailEvent {
+            validator: hex::encode(&validator),
+            until: infraction.jailed_until,
+            reason: infraction.reason.into(),
+        })?;
+        ctx.emit(SlashEvent {
+            validator: hex::encode(&validator),
+            fraction: infraction.fraction,
+            infraction_height: infraction.height,
+            reason: infraction.reason.into(),
+        })
+    }
+
+    // Track the vote and jail the validator if it's down
+    fn handle_vote(
+        &self,
+        ctx: &Context,
+        vote: &VoteInfo,
+        params: &SlashingParams,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        let store = SigningInfoStore::new();
+        let mut info = store.get(vote.address.clone(), view).unwrap_or_default();
+        if info.is_jailed(ctx.height()) {
+            return Ok(());
+        }
+        info.record(vote.signed, params.window);
+        if info.is_down(params.window, params.min_signed_percent) {
+            info.jailed_until = ctx.height() + params.downtime_jail_blocks;
+            // Start over once unjailed
+            info.reset(params.window);
+            let jailed_until = info.jailed_until;
+            store.put(vote.address.clone(), info, view);
+            let infraction = Infraction {
+                validator: &vote.address,
+                // The votes are on the last block
+                height: ctx.height() - 1,
+                reason: "downtime",
+                fraction: params.slash_fraction_downtime,
+                jailed_until,
+            };
+            return self.punish(ctx, infraction, view);
+        }
+        store.put(vote.address.clone(), info, view);
+        Ok(())
+    }
+
+    // Tombstone and slash a validator for double signing
+    fn handle_evidence(
+        &self,
+        ctx: &Context,
+        evidence: &Misbehavior,
+        params: &SlashingParams,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        if ctx.height() - evidence.height > params.max_evidence_age {
+            ctx.logger().info(format!(
+                "ignoring expired evidence for {} at {}",
+                hex::encode(&evidence.address),
+                evidence.height
+            ));
+            return Ok(());
+        }
+        let store = SigningInfoStore::new();
+        let mut info = store
+            .get(evidence.address.clone(), view)
+            .unwrap_or_default();
+        // Already punished
+        if info.tombstoned {
+            return Ok(());
+        }
+        info.tombstoned = true;
+        info.jailed_until = i64::MAX;
+        store.put(evidence.address.clone(), info, view);
+        let infraction = Infraction {
+            validator: &evidence.address,
+            height: evidence.height,
+            reason: "double_sign",
+            fraction: params.slash_fraction_double_sign,
+            jailed_until: i64::MAX,
+        };
+        self.punish(ctx, infraction, view)
+    }
+}
+
+impl AppModule for SlashingModule {
+    fn name(&self) -> String {
+        SLASHING_APP_NAME.into()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        self.staking.iter().cloned().collect()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        SlashingMsgs::dispatch(self, ctx, view)
+    }
+
+    fn begin_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let params = SlashingParams::load(view)?;
+        for evidence in ctx.evidence() {
+            self.handle_evidence(ctx, evidence, &params, view)?;
+        }
+        for vote in ctx.votes() {
+            self.handle_vote(ctx, vote, &params, view)?;
+        }
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/signing_info", |params, view| {
+                Ok(SigningInfoStore::new().query(params.key().to_vec(), view))
+            })
+            .add("/jailed", |params, view| {
+                let jailed = SigningInfoStore::new()
+                    .query(params.key().to_vec(), view)
+                    .map_or(false, |info| info.is_jailed(view.height()));
+                Ok(Some(jailed))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/signing_info".into(), "/jailed".into()]
+    }
+}
+
+impl SlashingMsgsHandler for SlashingModule {
+    fn unjail(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        validator: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(
+            self.operators.get(&validator) == Some(&ctx.sender()),
+            "only the operator can unjail the validator"
+        );
+        let store = SigningInfoStore::new();
+        let mut info = match store.get(validator.clone(), view) {
+            Some(info) => info,
+            None => bail!("validator has no signing info"),
+        };
+        ensure!(!info.tombstoned, "validator is tombstoned");
+        ensure!(info.jailed_until > 0, "validator is not jailed");
+        ensure!(
+            ctx.height() >= info.jailed_until,
+            "validator is jailed until {}",
+            info.jailed_until
+        );
+        info.jailed_until = 0;
+        store.put(validator.clone(), info, view);
+
+        self.notify_staking(
+            ctx,
+            StakingHooks::Unjail {
+                validator: validator.clone(),
+            },
+            view,
+        )?;
+        ctx.emit(UnjailEvent {
+            validator: hex::encode(validator),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{AppBuilder, TestKit, TxBuilder};
+
+    #[test]
+    fn test_slash_amount() {
+        assert_eq!(50, slash_amount(1000, 500));
+        assert_eq!(0, slash_amount(1000, 1));
+        assert_eq!(1000, slash_amount(1000, 20_000));
+    }
+
+    #[test]
+    fn test_liveness() {
+        let window = 10;
+        let mut info = SigningInfo::default();
+        for _ in 0..5 {
+            info.record(false, window);
+        }
+        assert_eq!(5, info.missed);
+        // Not a full window yet
+        assert!(!info.is_down(window, 50));
+
+        for _ in 0..5 {
+            info.record(true, window);
+        }
+        assert!(!info.is_down(window, 50));
+
+        // The window slides: a miss replaces a sign
+        for _ in 0..5 {
+            info.record(true, window);
+        }
+        assert_eq!(0, info.missed);
+        for _ in 0..6 {
+            info.record(false, window);
+        }
+        assert_eq!(6, info.missed);
+        assert!(info.is_down(window, 50));
+
+        info.reset(window);
+        assert_eq!(0, info.missed);
+        assert!(!info.is_down(window, 50));
+
+        info.jailed_until = 10;
+        assert!(info.is_jailed(9));
+        assert!(!info.is_jailed(10));
+        info.tombstoned = true;
+        assert!(info.is_jailed(100));
+    }
+
+    #[test]
+    fn test_unjail() {
+        let app = AppBuilder::new().with_app(SlashingModule::new().with_operator(b"val1", "bob"));
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let unjail = |sender: &str| {
+            TxBuilder::new(SLASHING_APP_NAME)
+                .sender(sender)
+                .msg(SlashingMsgs::Unjail {
+                    validator: b"val1".to_vec(),
+                })
+                .build()
+                .unwrap()
+        };
+        // Not the operator
+        assert!(tester.commit_tx(&[&unjail("alice")]).is_err());
+        // Not jailed
+        assert!(tester.commit_tx(&[&unjail("bob")]).is_err());
+
+        let jailed: bool = tester
+            .query_as(&format!("{}/jailed", SLASHING_APP_NAME), b"val1".to_vec())
+            .unwrap();
+        assert!(!jailed);
+    }
+}
@@ -0,0 +1,487 @@
+//!
+//! Timelock.  Queue an encoded message for an AppModule with an unlock height.
+//! The message is dispatched in `end_block` once the height is reached, unless the
+//! owner cancels it first.  Example, governance delaying a parameter change:
+//! ```ignore
+//! let set = ParamsMsgs::Set { module, key, value }.try_to_vec()?;
+//! ctx.dispatch(
+//!     TIMELOCK_APP_NAME,
+//!     TimelockMsgs::Queue { app: PARAMS_APP_NAME.into(), msg: set, unlock_height },
+//!     view,
+//! )?;
+//! ```
+//! The message is dispatched with the governance module as the caller, which
+//! requires granting the timelock `rapido.params::dispatch_as_module`.
+//!
+use anyhow::{bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{
+    AccountId, AppModule, Context, QueryResponse, QueryRouter, QueryView, RapidoEvent,
+    RapidoMessages, Store, StoreValue, StoreView,
+};
+
+/// Name of the timelock AppModule
+pub const TIMELOCK_APP_NAME: &str = "rapido.timelock";
+const ENTRY_STORE_NAME: &str = "rapido.timelock.entries";
+const STATE_STORE_NAME: &str = "rapido.timelock.state";
+
+// Key of the TimelockState in its store
+const STATE_KEY: &str = "state";
+
+/// Who queued a message
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum Owner {
+    /// The sender of a tx
+    Account(AccountId),
+    /// An AppModule that dispatched the `Queue` message
+    Module(String),
+}
+
+impl Owner {
+    // The owner of a message in the context
+    fn from_context(ctx: &Context) -> Self {
+        match ctx.caller() {
+            Some(app) => Owner::Module(app.into()),
+            None => Owner::Account(ctx.sender()),
+        }
+    }
+}
+
+/// A queued message
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, PartialEq)]
+pub struct TimelockEntry {
+    pub id: u64,
+    pub owner: Owner,
+    pub app: String,
+    /// The encoded message
+    pub msg: Vec<u8>,
+    pub unlock_height: i64,
+}
+
+// The next entry id and the ids of the queued entries
+#[derive(BorshSerialize, BorshDeserialize, StoreValue, Debug, Clone, Default)]
+pub(crate) struct TimelockState {
+    next_id: u64,
+    queued: Vec<u64>,
+}
+
+pub(crate) struct EntryStore;
+impl Store for EntryStore {
+    type Key = u64;
+    type Value = TimelockEntry;
+
+    fn name(&self) -> String {
+        ENTRY_STORE_NAME.into()
+    }
+}
+
+impl EntryStore {
+    pub fn new() -> Self {
+        EntryStore {}
+    }
+}
+
+pub(crate) struct StateStore;
+impl Store for StateStore {
+    type Key = String;
+    type Value = TimelockState;
+
+    fn name(&self) -> String {
+        STATE_STORE_NAME.into()
+    }
+}
+
+impl StateStore {
+    pub fn new() -> Self {
+        StateStore {}
+    }
+
+    fn load(&self, view: &StoreView) -> TimelockState {
+        self.get(STATE_KEY.into(), view).unwrap_or_default()
+    }
+
+    fn save(&self, state: TimelockState, view: &mut StoreView) {
+        self.put(STATE_KEY.into(), state, view)
+    }
+}
+
+/// Messages to the timelock AppModule
+#[derive(BorshSerialize, BorshDeserialize, RapidoMessages, Debug, Clone, PartialEq)]
+pub enum TimelockMsgs {
+    /// Queue the encoded `msg` for `app` until the unlock height
+    Queue {
+        app: String,
+        msg: Vec<u8>,
+        unlock_height: i64,
+    },
+    /// Cancel a queued message. Only the owner can cancel
+    Cancel { id: u64 },
+}
+
+/// Emitted as 'rapido.timelock.queue'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct QueueEvent {
+    pub id: u64,
+    pub app: String,
+    pub unlock_height: i64,
+}
+
+/// Emitted as 'rapido.timelock.execute' in end_block
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct ExecuteEvent {
+    pub id: u64,
+    pub success: bool,
+}
+
+/// Emitted as 'rapido.timelock.cancel'
+#[derive(RapidoEvent, Debug, Clone, PartialEq)]
+pub struct CancelEvent {
+    pub id: u64,
+}
+
+/// The timelock AppModule. Add it via `AppBuilder::with_app`.
+pub struct TimelockModule;
+
+impl TimelockModule {
+    fn execute(
+        &self,
+        ctx: &Context,
+        entry: &TimelockEntry,
+        view: &mut StoreView,
+    ) -> anyhow::Result<(), anyhow::Error> {
+        match &entry.owner {
+            Owner::Module(module) => ctx.dispatch_as_module(module, &entry.app, &entry.msg, view),
+            Owner::Account(account) => {
+                ctx.dispatch_as(account.clone(), &entry.app, &entry.msg, view)
+            }
+        }
+    }
+}
+
+impl AppModule for TimelockModule {
+    fn name(&self) -> String {
+        TIMELOCK_APP_NAME.into()
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        TimelockMsgs::dispatch(self, ctx, view)
+    }
+
+    // Dispatch the messages that have unlocked
+    fn end_block(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        let state_store = StateStore::new();
+        let mut state = state_store.load(view);
+        let store = EntryStore::new();
+
+        let mut queued = Vec::new();
+        for id in state.queued {
+            let entry = match store.get(id, view) {
+                Some(e) => e,
+                None => continue,
+            };
+            if entry.unlock_height > ctx.height() {
+                queued.push(id);
+                continue;
+            }
+
+            // Undo the changes of a failed message
+            let checkpoint = view.checkpoint();
+            let success = match self.execute(ctx, &entry, view) {
+                Ok(()) => true,
+                Err(err) => {
+                    view.rollback_to(checkpoint);
+                    ctx.logger()
+                        .warn(format!("timelock {} for {} failed: {}", id, entry.app, err));
+                    false
+                }
+            };
+            store.remove(id, view);
+            ctx.emit(ExecuteEvent { id, success })?;
+        }
+        state.queued = queued;
+        state_store.save(state, view);
+        Ok(())
+    }
+
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        QueryRouter::new()
+            .add("/entry/:id", |params, view| {
+                let id: u64 = params.require("id")?.parse()?;
+                Ok(EntryStore::new().query(id, view))
+            })
+            .add("/queued", |_, view| {
+                let state = StateStore::new()
+                    .query(STATE_KEY.into(), view)
+                    .unwrap_or_default();
+                let entries: Vec<TimelockEntry> = state
+                    .queued
+                    .into_iter()
+                    .filter_map(|id| EntryStore::new().query(id, view))
+                    .collect();
+                Ok(Some(entries))
+            })
+            .route(path, key, view)
+    }
+
+    fn query_paths(&self) -> Vec<String> {
+        vec!["/entry/:id".into(), "/queued".into()]
+    }
+}
+
+impl TimelockMsgsHandler for TimelockModule {
+    fn queue(
+        &self,
+        ctx: &Context,
+        view: &mut StoreView,
+        app: String,
+        msg: Vec<u8>,
+        unlock_height: i64,
+    ) -> Result<(), anyhow::Error> {
+        ensure!(
+            unlock_height > ctx.height(),
+            "unlock height must be a future height"
+        );
+        ensure!(
+            app != TIMELOCK_APP_NAME,
+            "cannot queue a message to the timelock"
+        );
+
+        let state_store = StateStore::new();
+        let mut state = state_store.load(view);
+        let id = state.next_id;
+        state.next_id += 1;
+        state.queued.push(id);
+        state_store.save(state, view);
+
+        let entry = TimelockEntry {
+            id,
+            owner: Owner::from_context(ctx),
+            app: app.clone(),
+            msg,
+            unlock_height,
+        };
+        EntryStore::new().put(id, entry, view);
+        ctx.emit(QueueEvent {
+            id,
+            app,
+            unlock_height,
+        })
+    }
+
+    fn cancel(&self, ctx: &Context, view: &mut StoreView, id: u64) -> Result<(), anyhow::Error> {
+        let store = EntryStore::new();
+        let entry = match store.get(id, view) {
+            Some(e) => e,
+            None => bail!("timelock {} not found", id),
+        };
+        ensure!(
+            entry.owner == Owner::from_context(ctx),
+            "only the owner can cancel"
+        );
+
+        store.remove(id, view);
+        let state_store = StateStore::new();
+        let mut state = state_store.load(view);
+        state.queued.retain(|i| *i != id);
+        state_store.save(state, view);
+        ctx.emit(CancelEvent { id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{AppBuilder, SignedTransaction, TestKit, TxBuilder};
+
+    fn timelock_tx(sender: &str, msg: TimelockMsgs) -> SignedTransaction {
+        TxBuilder::new(TIMELOCK_APP_NAME)
+            .sender(sender)
+            .msg(msg)
+            .build()
+            .unwrap()
+    }
+
+    fn queued(tester: &mut TestKit) -> Vec<TimelockEntry> {
+        tester.query_as("rapido.timelock/queued", vec![]).unwrap()
+    }
+
+    // Counts the mints dispatched by an AppModule with the capability 'minter::mint'
+    struct MinterStore;
+    impl Store for MinterStore {
+        type Key = String;
+        type Value = u64;
+
+        fn name(&self) -> String {
+            "minter.store".into()
+        }
+    }
+
+    struct Minter;
+    impl AppModule for Minter {
+        fn name(&self) -> String {
+            "minter".into()
+        }
+
+        fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+            ctx.require_capability("mint")?;
+            let minted = MinterStore.get("minted".into(), view).unwrap_or_default();
+            MinterStore.put("minted".into(), minted + 1, view);
+            Ok(())
+        }
+
+        fn handle_query(
+            &self,
+            _path: &str,
+            _key: Vec<u8>,
+            _view: &QueryView,
+        ) -> Result<QueryResponse, anyhow::Error> {
+            bail!("invalid query")
+        }
+    }
+
+    // Queues a mint with the timelock for the next height
+    struct Queuer(&'static str);
+    impl AppModule for Queuer {
+        fn name(&self) -> String {
+            self.0.into()
+        }
+
+        fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+            let queue = TimelockMsgs::Queue {
+                app: "minter".into(),
+                msg: vec![],
+                unlock_height: ctx.height() + 1,
+            };
+            ctx.dispatch(TIMELOCK_APP_NAME, queue, view)
+        }
+
+        fn handle_query(
+            &self,
+            _path: &str,
+            _key: Vec<u8>,
+            _view: &QueryView,
+        ) -> Result<QueryResponse, anyhow::Error> {
+            bail!("invalid query")
+        }
+    }
+
+    // Counts the call and then fails
+    struct Flaky;
+    impl AppModule for Flaky {
+        fn name(&self) -> String {
+            "flaky".into()
+        }
+
+        fn handle_tx(&self, _ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+            MinterStore.put("flaky".into(), 1, view);
+            bail!("flaky failed")
+        }
+
+        fn handle_query(
+            &self,
+            _path: &str,
+            _key: Vec<u8>,
+            _view: &QueryView,
+        ) -> Result<QueryResponse, anyhow::Error> {
+            bail!("invalid query")
+        }
+    }
+
+    #[test]
+    fn test_timelock_failed_msg_discarded() {
+        let app = AppBuilder::new()
+            .with_app(TimelockModule {})
+            .with_app(Flaky)
+            .grant(TIMELOCK_APP_NAME, "flaky::dispatch_as");
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let queue = TimelockMsgs::Queue {
+            app: "flaky".into(),
+            msg: vec![],
+            unlock_height: 2,
+        };
+        tester.produce_block(&[&timelock_tx("bob", queue)]).unwrap();
+        assert_eq!(1, queued(&mut tester).len());
+
+        // The msg fails: its write is undone and the entry is removed
+        tester.produce_block(&[]).unwrap();
+        assert!(queued(&mut tester).is_empty());
+        assert_eq!(None, tester.read_store(&MinterStore, "flaky".into()));
+    }
+
+    #[test]
+    fn test_timelock_module_owner() {
+        let app = AppBuilder::new()
+            .with_app(TimelockModule {})
+            .with_app(Minter)
+            .with_app(Queuer("granted"))
+            .with_app(Queuer("other"))
+            .grant("granted", "minter::mint")
+            .grant(TIMELOCK_APP_NAME, "minter::dispatch_as_module");
+        let mut tester = TestKit::create(app);
+        tester.start();
+        let minted = |tester: &TestKit| tester.read_store(&MinterStore, "minted".into());
+
+        let queue = |app: &str| TxBuilder::new(app).sender("bob").msg(0u8).build().unwrap();
+        tester
+            .produce_block(&[&queue("granted"), &queue("other")])
+            .unwrap();
+        assert_eq!(
+            Owner::Module("granted".into()),
+            queued(&mut tester)[0].owner
+        );
+
+        // Dispatched with the owner's capabilities, not the timelock's
+        tester.produce_block(&[]).unwrap();
+        assert!(queued(&mut tester).is_empty());
+        assert_eq!(Some(1), minted(&tester));
+    }
+
+    #[test]
+    fn test_timelock_queue_and_cancel() {
+        let app = AppBuilder::new().with_app(TimelockModule {});
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        let queue = TimelockMsgs::Queue {
+            app: "other".into(),
+            msg: vec![1, 2],
+            unlock_height: 10,
+        };
+        assert!(tester
+            .commit_tx(&[
+                &timelock_tx("bob", queue.clone()),
+                &timelock_tx("bob", queue)
+            ])
+            .is_ok());
+
+        let entries = queued(&mut tester);
+        assert_eq!(2, entries.len());
+        assert_eq!(Owner::Account(b"bob".to_vec()), entries[0].owner);
+
+        // Only the owner can cancel
+        let cancel = TimelockMsgs::Cancel { id: 0 };
+        assert!(tester
+            .commit_tx(&[&timelock_tx("alice", cancel.clone())])
+            .is_err());
+        assert!(tester.commit_tx(&[&timelock_tx("bob", cancel)]).is_ok());
+
+        let entries = queued(&mut tester);
+        assert_eq!(1, entries.len());
+        assert_eq!(1, entries[0].id);
+
+        // Must be a future height
+        let past = TimelockMsgs::Queue {
+            app: "other".into(),
+            msg: vec![],
+            unlock_height: 0,
+        };
+        assert!(tester.commit_tx(&[&timelock_tx("bob", past)]).is_err());
+    }
+}
@@ -0,0 +1,420 @@
+//!
+//! Run AppModules compiled to WebAssembly. The code is kept in state and executed with
+//! the wasmi interpreter, so the logic can be upgraded on-chain by the module's
+//! authority without restarting validators. See the README for the module ABI.
+//!
+use anyhow::{anyhow, bail, ensure};
+use borsh::{BorshDeserialize, BorshSerialize};
+use rapido_core::{AccountId, AppModule, Context, QueryResponse, QueryView, Store, StoreView};
+use wasmi::core::Trap;
+use wasmi::{Caller, Config, Engine, Extern, Linker, Memory, Module, Store as WasmStore};
+
+const WASM_CODE_STORE_NAME: &str = "rapido.wasm.code";
+const WASM_DATA_STORE_NAME: &str = "rapido.wasm.data";
+
+/// Fuel available to a query
+pub const QUERY_FUEL: u64 = 10_000_000;
+
+/// Code of each wasm AppModule keyed by name
+pub(crate) struct CodeStore;
+impl Store for CodeStore {
+    type Key = String;
+    type Value = Vec<u8>;
+
+    fn name(&self) -> String {
+        WASM_CODE_STORE_NAME.into()
+    }
+}
+
+/// Data of each wasm AppModule keyed by (name, key)
+pub(crate) struct DataStore;
+impl Store for DataStore {
+    type Key = (String, Vec<u8>);
+    type Value = Vec<u8>;
+
+    fn name(&self) -> String {
+        WASM_DATA_STORE_NAME.into()
+    }
+}
+
+/// Messages to a `WasmModule`
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum WasmMsgs {
+    /// Pass the message to the module's `handle_tx`
+    Call(Vec<u8>),
+    /// Replace the module's code. Only accepted from the authority
+    Upgrade(Vec<u8>),
+}
+
+/// AppModule that runs wasm code
+pub struct WasmModule {
+    name: String,
+    authority: AccountId,
+    code: Vec<u8>,
+    engine: Engine,
+}
+
+impl WasmModule {
+    /// Create the module with its initial code. `authority` can upgrade the code.
+    pub fn new(name: &str, authority: impl Into<AccountId>, code: Vec<u8>) -> Self {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        Self {
+            name: name.into(),
+            authority: authority.into(),
+            code,
+            engine: Engine::new(&config),
+        }
+    }
+
+    fn compile(&self, code: &[u8]) -> anyhow::Result<Module, anyhow::Error> {
+        Module::new(&self.engine, code).map_err(|e| anyhow!("invalid wasm code: {}", e))
+    }
+
+    fn load_code(&self, view: &StoreView) -> anyhow::Result<Module, anyhow::Error> {
+        let code = CodeStore {}.get(self.name.clone(), view);
+        match code {
+            Some(code) => self.compile(&code),
+            None => bail!("no wasm code for {}", self.name),
+        }
+    }
+
+    // Instantiate the module, write the inputs to its memory and call the export
+    fn run(
+        &self,
+        module: &Module,
+        host: Host,
+        fuel: u64,
+        export: &str,
+        inputs: &[&[u8]],
+    ) -> anyhow::Result<(i32, Host, u64), anyhow::Error> {
+        let mut store = WasmStore::new(&self.engine, host);
+        store.add_fuel(fuel).map_err(|e| anyhow!("{}", e))?;
+        let instance = link(&self.engine)?
+            .instantiate(&mut store, module)?
+            .start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow!("wasm module doesn't export memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+
+        let mut args = Vec::new();
+        for input in inputs {
+            let ptr = alloc.call(&mut store, input.len() as i32)?;
+            memory.write(&mut store, ptr as usize, input)?;
+            args.push(ptr);
+            args.push(input.len() as i32);
+        }
+
+        let result = match args.len() {
+            2 => instance
+                .get_typed_func::<(i32, i32), i32>(&store, export)?
+                .call(&mut store, (args[0], args[1])),
+            4 => instance
+                .get_typed_func::<(i32, i32, i32, i32), i32>(&store, export)?
+                .call(&mut store, (args[0], args[1], args[2], args[3])),
+            _ => bail!("unsupported number of inputs"),
+        };
+        let used = store.fuel_consumed().unwrap_or_default();
+        let code = result.map_err(|e| anyhow!("wasm {} failed: {}", export, e))?;
+        Ok((code, store.into_data(), used))
+    }
+}
+
+impl AppModule for WasmModule {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn initialize(&self, _genesis: &[u8], view: &mut StoreView) -> Result<(), anyhow::Error> {
+        self.compile(&self.code)?;
+        CodeStore {}.put(self.name.clone(), self.code.clone(), view);
+        Ok(())
+    }
+
+    fn handle_tx(&self, ctx: &Context, view: &mut StoreView) -> Result<(), anyhow::Error> {
+        match ctx.decode_msg()? {
+            WasmMsgs::Upgrade(code) => {
+                ensure!(
+                    ctx.caller().is_none() && ctx.sender() == self.authority,
+                    "only the authority can upgrade the code"
+                );
+                self.compile(&code)?;
+                CodeStore {}.put(self.name.clone(), code, view);
+                Ok(())
+            }
+            WasmMsgs::Call(msg) => {
+                let module = self.load_code(view)?;
+                let fuel = ctx.gas_meter().remaining();
+                let host = Host::new(&self.name, HostView::write(view), Some(ctx));
+                let (code, host, used) = self.run(&module, host, fuel, "handle_tx", &[&msg])?;
+                ctx.gas_meter().consume(used, "wasm handle_tx")?;
+                ensure!(
+                    code == 0,
+                    "wasm handle_tx returned {}: {}",
+                    code,
+                    String::from_utf8_lossy(&host.result)
+                );
+                Ok(())
+            }
+        }
+    }
+
+    // The value is set by the module via `set_result`
+    fn handle_query(
+        &self,
+        path: &str,
+        key: Vec<u8>,
+        view: &QueryView,
+    ) -> Result<QueryResponse, anyhow::Error> {
+        let module = self.load_code(view)?;
+        let host = Host::new(&self.name, HostView::read(view), None);
+        let (code, host, _) = self.run(
+            &module,
+            host,
+            QUERY_FUEL,
+            "handle_query",
+            &[path.as_bytes(), &key],
+        )?;
+        ensure!(
+            code == 0,
+            "wasm handle_query returned {}: {}",
+            code,
+            String::from_utf8_lossy(&host.result)
+        );
+        Ok(host.result.into())
+    }
+}
+
+// The StoreView available to the module. Queries only get read access
+enum HostView {
+    Write(*mut StoreView<'static>),
+    Read(*const StoreView<'static>),
+}
+
+impl HostView {
+    fn write(view: &mut StoreView) -> Self {
+        HostView::Write(view as *mut StoreView<'_> as *mut StoreView<'static>)
+    }
+
+    fn read(view: &StoreView) -> Self {
+        HostView::Read(view as *const StoreView<'_> as *const StoreView<'static>)
+    }
+}
+
+// State of the host functions. wasmi requires 'static state, so the view and
+// context borrowed by `handle_tx`/`handle_query` are held as raw pointers.
+// They're only dereferenced while `WasmModule::run` executes, which is within
+// the lifetime of the borrows.
+struct Host {
+    app: String,
+    view: HostView,
+    ctx: Option<*const Context>,
+    result: Vec<u8>,
+}
+
+impl Host {
+    fn new(app: &str, view: HostView, ctx: Option<&Context>) -> Self {
+        Self {
+            app: app.into(),
+            view,
+            ctx: ctx.map(|c| c as *const Context),
+            result: Vec::new(),
+        }
+    }
+
+    fn view(&self) -> &StoreView<'static> {
+        // See the note on `Host`
+        unsafe {
+            match self.view {
+                HostView::Write(v) => &*v,
+                HostView::Read(v) => &*v,
+            }
+        }
+    }
+
+    fn view_mut(&mut self) -> Result<&mut StoreView<'static>, Trap> {
+        match self.view {
+            // See the note on `Host`
+            HostView::Write(v) => Ok(unsafe { &mut *v }),
+            HostView::Read(_) => Err(Trap::new("the store is read-only in queries")),
+        }
+    }
+
+    fn ctx(&self) -> Result<&Context, Trap> {
+        match self.ctx {
+            // See the note on `Host`
+            Some(c) => Ok(unsafe { &*c }),
+            None => Err(Trap::new("no context in queries")),
+        }
+    }
+}
+
+fn memory(caller: &Caller<'_, Host>) -> Result<Memory, Trap> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("wasm module doesn't export memory"))
+}
+
+fn read(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+    let mut buf = vec![0u8; len.max(0) as usize];
+    memory(caller)?
+        .read(caller, ptr as usize, &mut buf)
+        .map_err(|e| Trap::new(e.to_string()))?;
+    Ok(buf)
+}
+
+// Copy up to `cap` bytes of the value and return its length
+fn write(caller: &mut Caller<'_, Host>, value: &[u8], ptr: i32, cap: i32) -> Result<i32, Trap> {
+    let n = value.len().min(cap.max(0) as usize);
+    memory(caller)?
+        .write(caller, ptr as usize, &value[..n])
+        .map_err(|e| Trap::new(e.to_string()))?;
+    Ok(value.len() as i32)
+}
+
+// Host functions available to the module in 'env'. See the README
+fn link(engine: &Engine) -> anyhow::Result<Linker<Host>, anyhow::Error> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(
+        "env",
+        "store_get",
+        |mut caller: Caller<'_, Host>, key_ptr: i32, key_len: i32, out_ptr: i32, out_cap: i32| {
+            let key = read(&caller, key_ptr, key_len)?;
+            let host = caller.data();
+            let value = DataStore {}.get((host.app.clone(), key), host.view());
+            match value {
+                Some(value) => write(&mut caller, &value, out_ptr, out_cap),
+                None => Ok(-1),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "store_set",
+        |mut caller: Caller<'_, Host>, key_ptr: i32, key_len: i32, val_ptr: i32, val_len: i32| {
+            let key = read(&caller, key_ptr, key_len)?;
+            let value = read(&caller, val_ptr, val_len)?;
+            let host = caller.data_mut();
+            let app = host.app.clone();
+            DataStore {}.put((app, key), value, host.view_mut()?);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "store_remove",
+        |mut caller: Caller<'_, Host>, key_ptr: i32, key_len: i32| {
+            let key = read(&caller, key_ptr, key_len)?;
+            let host = caller.data_mut();
+            let app = host.app.clone();
+            DataStore {}.remove((app, key), host.view_mut()?);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "sender",
+        |mut caller: Caller<'_, Host>, out_ptr: i32, out_cap: i32| {
+            let sender = caller.data().ctx()?.sender();
+            write(&mut caller, &sender, out_ptr, out_cap)
+        },
+    )?;
+    linker.func_wrap("env", "height", |caller: Caller<'_, Host>| {
+        Ok(caller.data().ctx()?.height())
+    })?;
+    linker.func_wrap(
+        "env",
+        "emit_event",
+        |caller: Caller<'_, Host>, type_ptr: i32, type_len: i32, attrs_ptr: i32, attrs_len: i32| {
+            let event_type = String::from_utf8(read(&caller, type_ptr, type_len)?);
+            let attrs =
+                <Vec<(String, String)>>::try_from_slice(&read(&caller, attrs_ptr, attrs_len)?);
+            let (event_type, attrs) = match (event_type, attrs) {
+                (Ok(t), Ok(a)) => (t, a),
+                _ => return Ok(-1),
+            };
+            let pairs: Vec<(&str, &str)> = attrs
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect();
+            match caller.data().ctx()?.dispatch_event(event_type, &pairs) {
+                Ok(()) => Ok(0),
+                Err(_) => Ok(-1),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "set_result",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let value = read(&caller, ptr, len)?;
+            caller.data_mut().result = value;
+            Ok(())
+        },
+    )?;
+    Ok(linker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{AppBuilder, TestKit, TxBuilder};
+
+    // Saves the last message and returns it from queries
+    const LAST_MSG: &str = r#"
+    (module
+      (import "env" "store_set" (func $store_set (param i32 i32 i32 i32)))
+      (import "env" "store_get" (func $store_get (param i32 i32 i32 i32) (result i32)))
+      (import "env" "set_result" (func $set_result (param i32 i32)))
+      (memory (export "memory") 1)
+      (data (i32.const 0) "last")
+      (global $next (mut i32) (i32.const 1024))
+      (func (export "alloc") (param $len i32) (result i32)
+        (local $p i32)
+        (local.set $p (global.get $next))
+        (global.set $next (i32.add (global.get $next) (local.get $len)))
+        (local.get $p))
+      (func (export "handle_tx") (param $ptr i32) (param $len i32) (result i32)
+        (call $store_set (i32.const 0) (i32.const 4) (local.get $ptr) (local.get $len))
+        (i32.const 0))
+      (func (export "handle_query") (param i32 i32 i32 i32) (result i32)
+        (local $n i32)
+        (local.set $n (call $store_get (i32.const 0) (i32.const 4) (i32.const 512) (i32.const 256)))
+        (if (i32.lt_s (local.get $n) (i32.const 0)) (then (return (i32.const 1))))
+        (call $set_result (i32.const 512) (local.get $n))
+        (i32.const 0)))
+    "#;
+
+    #[test]
+    fn test_wasm_module() {
+        let code = wat::parse_str(LAST_MSG).unwrap();
+        let app = AppBuilder::new().with_app(WasmModule::new("wasm.app", "admin", code.clone()));
+        let mut tester = TestKit::create(app);
+        tester.start();
+
+        // Nothing saved yet
+        assert!(tester.query("wasm.app", vec![]).is_err());
+
+        let call = TxBuilder::new("wasm.app")
+            .sender("bob")
+            .msg(WasmMsgs::Call(vec![1, 2, 3]))
+            .build()
+            .unwrap();
+        assert!(tester.commit_tx(&[&call]).is_ok());
+        assert_eq!(vec![1, 2, 3], tester.query("wasm.app", vec![]).unwrap());
+
+        // Only the authority can upgrade
+        let upgrade = |sender: &str, code: Vec<u8>| {
+            TxBuilder::new("wasm.app")
+                .sender(sender)
+                .msg(WasmMsgs::Upgrade(code))
+                .build()
+                .unwrap()
+        };
+        assert!(tester.commit_tx(&[&upgrade("bob", code.clone())]).is_err());
+        assert!(tester.commit_tx(&[&upgrade("admin", vec![0, 1])]).is_err());
+        assert!(tester.commit_tx(&[&upgrade("admin", code)]).is_ok());
+    }
+}
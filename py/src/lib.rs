@@ -0,0 +1,283 @@
+//! Python bindings (pyo3) for scripting against a rapido chain: build and sign
+//! txs, query apps, and decode the Borsh responses of the common modules.
+//! Build with maturin (see the README):
+//! ```text
+//! import rapido
+//!
+//! pk, sk = rapido.generate_keypair()
+//! tx = rapido.Transaction("rapido.bank", rapido.account_id(pk), msg, nonce=1, chain_id="my-chain")
+//! tx.sign(sk)
+//! client = rapido.Client("tcp://127.0.0.1:26657")
+//! client.send(tx)
+//! balance = rapido.decode_u64(client.query("rapido.bank", "/", b"bob"))
+//! ```
+use borsh::BorshDeserialize;
+use exonum_crypto::{gen_keypair, PublicKey, SecretKey};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use pyo3::wrap_pyfunction;
+use tendermint_rpc::HttpClient;
+use tokio::runtime::{Builder, Runtime};
+
+use rapido_account::Account;
+use rapido_client::{broadcast, query, BroadcastMode, ClientError};
+use rapido_core::{ChainState, ModuleInfo, SignedTransaction, TxBuilder};
+
+fn value_error<E: ToString>(err: E) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+fn client_error(err: ClientError) -> PyErr {
+    match err {
+        ClientError::InvalidInput(_) => value_error(err),
+        _ => PyRuntimeError::new_err(err.to_string()),
+    }
+}
+
+fn secret_key(raw: &[u8]) -> PyResult<SecretKey> {
+    match SecretKey::from_slice(raw) {
+        Some(sk) => Ok(sk),
+        None => Err(value_error("expected a secret key of 64 bytes")),
+    }
+}
+
+/// Generate an ed25519 keypair. Returns (public key, secret key)
+#[pyfunction]
+fn generate_keypair(py: Python) -> (PyObject, PyObject) {
+    let (pk, sk) = gen_keypair();
+    (
+        PyBytes::new(py, pk.as_ref()).into(),
+        PyBytes::new(py, sk.as_ref()).into(),
+    )
+}
+
+/// The account id of a public key, as created by the account module
+#[pyfunction]
+fn account_id<'p>(py: Python<'p>, public_key: &[u8]) -> PyResult<&'p PyBytes> {
+    let pk = match PublicKey::from_slice(public_key) {
+        Some(pk) => pk,
+        None => return Err(value_error("expected a public key of 32 bytes")),
+    };
+    Ok(PyBytes::new(py, &Account::create(&pk, false).id()))
+}
+
+/// A transaction to an app.  `msg` is the app's message, Borsh encoded
+#[pyclass]
+struct Transaction {
+    app: String,
+    sender: Vec<u8>,
+    msg: Vec<u8>,
+    nonce: u64,
+    lane: u8,
+    chain_id: String,
+    fee_payer: Option<Vec<u8>>,
+    signature: Option<Vec<u8>>,
+}
+
+impl Transaction {
+    // Build the tx with its signature, if signed
+    fn build(&self) -> PyResult<SignedTransaction> {
+        let mut builder = TxBuilder::new(self.app.clone())
+            .sender(self.sender.clone())
+            .encoded_msg(self.msg.clone())
+            .nonce(self.nonce)
+            .lane(self.lane)
+            .chain_id(self.chain_id.clone());
+        if let Some(payer) = &self.fee_payer {
+            builder = builder.fee_payer(payer.clone());
+        }
+        let mut tx = builder.build().map_err(value_error)?;
+        if let Some(signature) = &self.signature {
+            tx.attach_signature(signature).map_err(value_error)?;
+        }
+        Ok(tx)
+    }
+}
+
+#[pymethods]
+impl Transaction {
+    #[new]
+    #[args(nonce = "0", lane = "0", chain_id = "String::new()")]
+    fn new(
+        app: String,
+        sender: Vec<u8>,
+        msg: Vec<u8>,
+        nonce: u64,
+        lane: u8,
+        chain_id: String,
+    ) -> Self {
+        Self {
+            app,
+            sender,
+            msg,
+            nonce,
+            lane,
+            chain_id,
+            fee_payer: None,
+            signature: None,
+        }
+    }
+
+    /// Set an account to pay the fees for the sender. Clears the signature
+    fn set_fee_payer(&mut self, payer: Vec<u8>) {
+        self.fee_payer = Some(payer);
+        self.signature = None;
+    }
+
+    /// Sign with an ed25519 secret key (64 bytes). The chain id must be set
+    fn sign(&mut self, secret_key_bytes: &[u8]) -> PyResult<()> {
+        let sk = secret_key(secret_key_bytes)?;
+        if self.chain_id.is_empty() {
+            return Err(value_error("a chain id is required to sign a transaction"));
+        }
+        self.signature = None;
+        let mut tx = self.build()?;
+        tx.sign(&sk).map_err(value_error)?;
+        self.signature = Some(tx.signature());
+        Ok(())
+    }
+
+    /// The bytes to sign outside of the process: sign their SHA-256 hash
+    fn sign_bytes<'p>(&self, py: Python<'p>) -> PyResult<&'p PyBytes> {
+        Ok(PyBytes::new(py, &self.build()?.sign_bytes()))
+    }
+
+    /// Add a signature produced outside of the process
+    fn attach_signature(&mut self, signature: Vec<u8>) -> PyResult<()> {
+        let mut tx = self.build()?;
+        tx.attach_signature(&signature).map_err(value_error)?;
+        self.signature = Some(signature);
+        Ok(())
+    }
+
+    /// The id of the tx, hex
+    fn id(&self) -> PyResult<String> {
+        Ok(self.build()?.id_hex())
+    }
+
+    /// Encode the tx with Borsh
+    fn encode<'p>(&self, py: Python<'p>) -> PyResult<&'p PyBytes> {
+        Ok(PyBytes::new(py, &self.build()?.encode()))
+    }
+
+    /// Encode the tx as hex, prefixed with 0x
+    fn to_hex(&self) -> PyResult<String> {
+        Ok(self.build()?.to_hex())
+    }
+}
+
+/// A connection to a node's RPC, e.g. 'tcp://127.0.0.1:26657'
+#[pyclass(unsendable)]
+struct Client {
+    runtime: Runtime,
+    client: HttpClient,
+}
+
+#[pymethods]
+impl Client {
+    #[new]
+    fn new(address: &str) -> PyResult<Self> {
+        let runtime = Builder::new()
+            .basic_scheduler()
+            .enable_all()
+            .build()
+            .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+        let address = address.parse().map_err(value_error)?;
+        let client = HttpClient::new(address).map_err(value_error)?;
+        Ok(Self { runtime, client })
+    }
+
+    /// Query the path of an app. Returns the encoded value
+    #[args(path = "\"/\"", key = "Vec::new()")]
+    fn query<'p>(
+        &mut self,
+        py: Python<'p>,
+        app: &str,
+        path: &str,
+        key: Vec<u8>,
+    ) -> PyResult<&'p PyBytes> {
+        let app_path = match path.trim_start_matches('/') {
+            "" => app.to_string(),
+            rest => format!("{}/{}", app, rest),
+        };
+        let client = &self.client;
+        let value = self
+            .runtime
+            .block_on(query(&app_path, key, client))
+            .map_err(client_error)?;
+        Ok(PyBytes::new(py, &value))
+    }
+
+    /// Send the tx and wait for it to be committed.  Returns a dict of the
+    /// hash, code (0 if the tx succeeded), log and height
+    fn send<'p>(&mut self, py: Python<'p>, tx: &Transaction) -> PyResult<&'p PyDict> {
+        let tx = tx.build()?;
+        let client = &self.client;
+        let result = self
+            .runtime
+            .block_on(broadcast(&tx, BroadcastMode::Commit, client))
+            .map_err(client_error)?;
+        let dict = PyDict::new(py);
+        dict.set_item("hash", result.hash)?;
+        dict.set_item("code", result.code)?;
+        dict.set_item("log", result.log)?;
+        dict.set_item("height", result.height)?;
+        Ok(dict)
+    }
+}
+
+/// Decode a Borsh u64, e.g. a bank balance or supply, or an account nonce
+#[pyfunction]
+fn decode_u64(raw: &[u8]) -> PyResult<u64> {
+    u64::try_from_slice(raw).map_err(value_error)
+}
+
+/// Decode an account of the account module
+#[pyfunction]
+fn decode_account<'p>(py: Python<'p>, raw: &[u8]) -> PyResult<&'p PyDict> {
+    let account = Account::try_from_slice(raw).map_err(value_error)?;
+    let dict = PyDict::new(py);
+    dict.set_item("id", PyBytes::new(py, &account.id))?;
+    dict.set_item("pubkey", PyBytes::new(py, &account.pubkey))?;
+    dict.set_item("trust_anchor", account.is_trust_anchor())?;
+    Ok(dict)
+}
+
+/// Decode the `rapido/apphash` query
+#[pyfunction]
+fn decode_chain_state<'p>(py: Python<'p>, raw: &[u8]) -> PyResult<&'p PyDict> {
+    let state = ChainState::try_from_slice(raw).map_err(value_error)?;
+    let dict = PyDict::new(py);
+    dict.set_item("height", state.height)?;
+    dict.set_item("apphash", PyBytes::new(py, &state.apphash))?;
+    Ok(dict)
+}
+
+/// Decode the `rapido/modules` query
+#[pyfunction]
+fn decode_modules<'p>(py: Python<'p>, raw: &[u8]) -> PyResult<Vec<&'p PyDict>> {
+    let modules = Vec::<ModuleInfo>::try_from_slice(raw).map_err(value_error)?;
+    let mut decoded = Vec::with_capacity(modules.len());
+    for module in modules {
+        let dict = PyDict::new(py);
+        dict.set_item("name", module.name)?;
+        dict.set_item("version", module.version)?;
+        dict.set_item("query_paths", module.query_paths)?;
+        decoded.push(dict);
+    }
+    Ok(decoded)
+}
+
+#[pymodule]
+fn rapido(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Transaction>()?;
+    m.add_class::<Client>()?;
+    m.add_function(wrap_pyfunction!(generate_keypair, m)?)?;
+    m.add_function(wrap_pyfunction!(account_id, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_u64, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_account, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_chain_state, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_modules, m)?)?;
+    Ok(())
+}
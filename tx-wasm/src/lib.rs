@@ -0,0 +1,235 @@
+//! Build and sign Rapido transactions in the browser.  Compiled with wasm-bindgen,
+//! so wallets get txs that are encoded, hashed and signed exactly like
+//! `rapido_core::SignedTransaction` without reimplementing the Borsh layout.
+//! The core crate can't be compiled to wasm (it depends on RocksDB), so the tx
+//! is mirrored here and the tests check both agree.
+//! From TypeScript, after `wasm-pack build --target web`:
+//! ```text
+//! import init, { Transaction } from "rapido-tx-wasm";
+//!
+//! await init();
+//! const tx = new Transaction("counter", sender, msg, 1n);
+//! tx.setChainId("my-chain");
+//! tx.sign(secretKey);
+//! await fetch(`${node}/broadcast_tx_commit?tx=${tx.toHex()}`);
+//! ```
+use borsh::{BorshDeserialize, BorshSerialize};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer, SECRET_KEY_LENGTH};
+use sha2::{Digest, Sha256};
+use wasm_bindgen::prelude::*;
+
+// Same fields, in the same order, as `rapido_core::SignedTransaction`
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+struct RawTransaction {
+    sender: Vec<u8>,
+    app: String,
+    msg: Vec<u8>,
+    nonce: u64,
+    lane: u8,
+    unsigned: bool,
+    fee_payer: Option<Vec<u8>>,
+    chain_id: String,
+    signature: Vec<u8>,
+}
+
+impl RawTransaction {
+    // Same as `SignedTransaction::sign_bytes`
+    fn sign_bytes(&self) -> Vec<u8> {
+        vec![
+            self.sender.clone(),
+            self.app.as_bytes().to_vec(),
+            self.msg.clone(),
+            self.nonce.to_le_bytes().to_vec(),
+            vec![self.lane],
+            vec![self.unsigned as u8],
+            match &self.fee_payer {
+                Some(payer) => [&[1], &(payer.len() as u32).to_le_bytes()[..], payer].concat(),
+                None => vec![0],
+            },
+            self.chain_id.as_bytes().to_vec(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    fn hash(&self) -> Vec<u8> {
+        Sha256::digest(&self.sign_bytes()).to_vec()
+    }
+}
+
+// A keypair from a 32 byte seed or a 64 byte (seed + public key) secret key,
+// as used by exonum-crypto
+fn keypair(secret_key: &[u8]) -> Result<Keypair, JsValue> {
+    if secret_key.len() != SECRET_KEY_LENGTH && secret_key.len() != 2 * SECRET_KEY_LENGTH {
+        return Err(JsValue::from_str("expected a secret key of 32 or 64 bytes"));
+    }
+    let secret = match SecretKey::from_bytes(&secret_key[..SECRET_KEY_LENGTH]) {
+        Ok(secret) => secret,
+        Err(_) => return Err(JsValue::from_str("bad secret key")),
+    };
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+/// Return the ed25519 public key of a secret key
+#[wasm_bindgen(js_name = publicKey)]
+pub fn public_key(secret_key: &[u8]) -> Result<Vec<u8>, JsValue> {
+    Ok(keypair(secret_key)?.public.to_bytes().to_vec())
+}
+
+/// A transaction, encoded and signed like `rapido_core::SignedTransaction`
+#[wasm_bindgen]
+pub struct Transaction {
+    inner: RawTransaction,
+}
+
+#[wasm_bindgen]
+impl Transaction {
+    /// Create a tx to the app.  `msg` is the app's message, Borsh encoded
+    #[wasm_bindgen(constructor)]
+    pub fn new(app: String, sender: Vec<u8>, msg: Vec<u8>, nonce: u64) -> Transaction {
+        Transaction {
+            inner: RawTransaction {
+                sender,
+                app,
+                msg,
+                nonce,
+                lane: 0,
+                unsigned: false,
+                fee_payer: None,
+                chain_id: String::new(),
+                signature: vec![],
+            },
+        }
+    }
+
+    /// Set the nonce lane. Defaults to 0
+    #[wasm_bindgen(js_name = setLane)]
+    pub fn set_lane(&mut self, lane: u8) {
+        self.inner.lane = lane;
+    }
+
+    /// Set the chain id the tx is intended for
+    #[wasm_bindgen(js_name = setChainId)]
+    pub fn set_chain_id(&mut self, chain_id: String) {
+        self.inner.chain_id = chain_id;
+    }
+
+    /// Set an account to pay the fees for the sender
+    #[wasm_bindgen(js_name = setFeePayer)]
+    pub fn set_fee_payer(&mut self, payer: Vec<u8>) {
+        self.inner.fee_payer = Some(payer);
+    }
+
+    /// Mark the tx as unsigned
+    #[wasm_bindgen(js_name = setUnsigned)]
+    pub fn set_unsigned(&mut self) {
+        self.inner.unsigned = true;
+    }
+
+    /// The bytes hashed by `sign`, for signing outside of the wasm module
+    #[wasm_bindgen(js_name = signBytes)]
+    pub fn sign_bytes(&self) -> Vec<u8> {
+        self.inner.sign_bytes()
+    }
+
+    /// Sign the tx with an ed25519 secret key (32 or 64 bytes). The chain id
+    /// must be set
+    pub fn sign(&mut self, secret_key: &[u8]) -> Result<(), JsValue> {
+        if self.inner.unsigned {
+            return Err(JsValue::from_str(
+                "cannot sign a transaction marked as unsigned",
+            ));
+        }
+        if self.inner.chain_id.is_empty() {
+            return Err(JsValue::from_str(
+                "a chain id is required to sign a transaction",
+            ));
+        }
+        let keypair = keypair(secret_key)?;
+        self.inner.signature = keypair.sign(&self.inner.hash()).to_bytes().to_vec();
+        Ok(())
+    }
+
+    /// Add a signature of the SHA-256 hash of `signBytes()`
+    #[wasm_bindgen(js_name = attachSignature)]
+    pub fn attach_signature(&mut self, signature: Vec<u8>) -> Result<(), JsValue> {
+        if signature.len() != ed25519_dalek::SIGNATURE_LENGTH {
+            return Err(JsValue::from_str("expected a signature of 64 bytes"));
+        }
+        self.inner.signature = signature;
+        Ok(())
+    }
+
+    /// The id of the tx, hex. Same as `SignedTransaction::id_hex`
+    pub fn id(&self) -> String {
+        hex::encode(self.inner.hash())
+    }
+
+    /// Encode the tx with Borsh
+    pub fn encode(&self) -> Vec<u8> {
+        self.inner
+            .try_to_vec()
+            .expect("encoding signed transaction")
+    }
+
+    /// Encode the tx as hex, prefixed with 0x. Same as `SignedTransaction::to_hex`
+    #[wasm_bindgen(js_name = toHex)]
+    pub fn to_hex(&self) -> String {
+        format!("0x{}", hex::encode(self.encode()))
+    }
+
+    /// Decode a Borsh encoded tx
+    pub fn decode(raw: &[u8]) -> Result<Transaction, JsValue> {
+        match RawTransaction::try_from_slice(raw) {
+            Ok(inner) => Ok(Transaction { inner }),
+            Err(_) => Err(JsValue::from_str("problem decoding the signed tx")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapido_core::{verify_tx_signature, SignedTransaction, TxBuilder};
+
+    #[test]
+    fn test_same_as_core() {
+        let (pk, sk) = exonum_crypto::gen_keypair();
+        let core_tx = TxBuilder::new("counter")
+            .sender("bob")
+            .msg(5u16)
+            .nonce(3)
+            .lane(1)
+            .fee_payer("alice")
+            .chain_id("test-chain")
+            .sign(&sk)
+            .unwrap();
+
+        let mut tx = Transaction::new(
+            "counter".into(),
+            b"bob".to_vec(),
+            5u16.try_to_vec().unwrap(),
+            3,
+        );
+        tx.set_lane(1);
+        tx.set_fee_payer(b"alice".to_vec());
+        tx.set_chain_id("test-chain".into());
+        assert_eq!(core_tx.sign_bytes(), tx.sign_bytes());
+        assert_eq!(core_tx.id_hex(), tx.id());
+
+        // Signed with the 64 byte exonum secret key or its seed
+        tx.sign(sk.as_ref()).unwrap();
+        assert_eq!(core_tx.encode(), tx.encode());
+        assert_eq!(core_tx.to_hex(), tx.to_hex());
+        tx.sign(&sk.as_ref()[..32]).unwrap();
+        assert_eq!(core_tx.encode(), tx.encode());
+        assert_eq!(pk.as_ref(), &public_key(sk.as_ref()).unwrap()[..]);
+
+        let decoded = SignedTransaction::decode(&tx.encode()).unwrap();
+        assert!(verify_tx_signature(&decoded, &pk));
+        let back = Transaction::decode(&core_tx.encode()).unwrap();
+        assert_eq!(tx.inner, back.inner);
+    }
+}